@@ -2,9 +2,9 @@ extern crate unrust;
 #[macro_use]
 extern crate unrust_derive;
 
-use unrust::actors::ShadowPass;
-use unrust::engine::{AssetError, AssetSystem, Camera, DirectionalLight, GameObject, Material,
-                     Mesh, ObjMaterial, Prefab, RenderQueue, TextureWrap};
+use unrust::actors::{ShadowPass, SsaoPass};
+use unrust::engine::{AmbientOcclusion, AssetError, AssetSystem, Camera, DirectionalLight,
+                     GameObject, Material, Mesh, ObjMaterial, Prefab, RenderQueue, TextureWrap};
 use unrust::math::*;
 use unrust::world::events::*;
 use unrust::world::{Actor, Handle, World, WorldBuilder};
@@ -37,6 +37,7 @@ impl Actor for MainScene {
             let go = world.new_game_object();
             let mut cam = Camera::default();
             cam.zfar = 300.0;
+            cam.ambient_occlusion = Some(AmbientOcclusion::default());
             go.borrow_mut().add_component(cam);
         }
 
@@ -143,10 +144,7 @@ impl Actor for MainScene {
 pub struct WaveObjActor {}
 
 fn build_material(asys: &AssetSystem, obj_mat: ObjMaterial) -> Rc<Material> {
-    let shader_program = match obj_mat.normal_map {
-        Some(_) => asys.new_program("obj_nm"),
-        None => asys.new_program("obj"),
-    };
+    let shader_program = asys.new_program("obj");
 
     let mut material = Material::new(shader_program);
     material.set(
@@ -182,14 +180,23 @@ fn build_material(asys: &AssetSystem, obj_mat: ObjMaterial) -> Rc<Material> {
         n_tex.wrap_v.set(TextureWrap::Repeat);
 
         material.set("uMaterial.normal_map", n_tex);
+        material.enable_keyword("NORMAL_MAP");
     });
 
+    material.enable_keyword("SHADOWS");
+
     match obj_mat.alpha_mask {
         Some(ref f) => material.set("uMaterial.mask_tex", asys.new_texture(&f)),
         None => material.set("uMaterial.mask_tex", asys.new_texture("default_white")),
     }
 
-    if obj_mat.alpha.unwrap_or(1.0) < 0.9999 || obj_mat.alpha_mask.is_some() {
+    // Masked materials are cut out, only translucent ones need blending
+    if obj_mat.alpha_mask.is_some() {
+        material.enable_keyword("ALPHA_TEST");
+        material.set("uAlphaCutoff", 0.5);
+    }
+
+    if obj_mat.alpha.unwrap_or(1.0) < 0.9999 {
         material.render_queue = RenderQueue::Transparent;
     }
 
@@ -245,6 +252,7 @@ pub fn main() {
         .with_size((800, 600))
         .with_stats(true)
         .with_processor::<ShadowPass>()
+        .with_processor::<SsaoPass>()
         .build();
 
     // Add the main scene as component of scene game object
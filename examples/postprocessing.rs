@@ -128,12 +128,8 @@ impl Crt {
 
 impl Actor for Crt {
     fn start(&mut self, go: &mut GameObject, world: &mut World) {
-        // add main camera to scene
-        {
-            let go = world.new_game_object();
-            go.borrow_mut().add_component(Camera::default());
-        }
-
+        // The scene is rendered into the texture by the main camera of MainScene,
+        // a camera of our own would draw the scene on screen a second time
         {
             let db = &mut world.asset_system();
 
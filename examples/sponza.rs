@@ -248,10 +248,7 @@ impl WaveObjActor {
 }
 
 fn build_material(asys: &AssetSystem, obj_mat: ObjMaterial) -> Rc<Material> {
-    let shader_program = match obj_mat.normal_map {
-        Some(_) => asys.new_program("obj_nm"),
-        None => asys.new_program("obj"),
-    };
+    let shader_program = asys.new_program("obj");
 
     let mut material = Material::new(shader_program);
 
@@ -288,14 +285,23 @@ fn build_material(asys: &AssetSystem, obj_mat: ObjMaterial) -> Rc<Material> {
         n_tex.wrap_v.set(TextureWrap::Repeat);
 
         material.set("uMaterial.normal_map", n_tex);
+        material.enable_keyword("NORMAL_MAP");
     });
 
+    material.enable_keyword("SHADOWS");
+
     match obj_mat.alpha_mask {
         Some(ref f) => material.set("uMaterial.mask_tex", asys.new_texture(&f)),
         None => material.set("uMaterial.mask_tex", asys.new_texture("default_white")),
     }
 
-    if obj_mat.alpha.unwrap_or(1.0) < 0.9999 || obj_mat.alpha_mask.is_some() {
+    // Masked materials are cut out, only translucent ones need blending
+    if obj_mat.alpha_mask.is_some() {
+        material.enable_keyword("ALPHA_TEST");
+        material.set("uAlphaCutoff", 0.5);
+    }
+
+    if obj_mat.alpha.unwrap_or(1.0) < 0.9999 {
         material.render_queue = RenderQueue::Transparent;
     }
 
@@ -0,0 +1,498 @@
+use engine::{GameObject, Light, Mesh, MAX_MORPH_TARGETS};
+use fnv::FnvHashMap;
+
+use super::curve::Curve;
+
+use std::rc::Rc;
+
+use math::*;
+
+/// Float property of a game object animated by a clip
+#[derive(Clone)]
+pub enum FloatProperty {
+    /// Param of all surfaces of the `Mesh`, see `MeshSurface::set_property`
+    MaterialParam(String),
+    /// Weight of the morph target of this index on all surfaces of the `Mesh`,
+    /// see `MeshSurface::set_morph_weight`
+    MorphWeight(usize),
+    /// Multiplier of the diffuse and specular colors of the `Light`
+    LightIntensity,
+    /// Any other property
+    Custom(Rc<Fn(&mut GameObject, f32)>),
+}
+
+/// Identity of a property, to blend the clips which animate the same one
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum FloatKey {
+    MaterialParam(String),
+    MorphWeight(usize),
+    LightIntensity,
+    Custom(usize),
+}
+
+impl FloatProperty {
+    fn key(&self) -> FloatKey {
+        match *self {
+            FloatProperty::MaterialParam(ref name) => FloatKey::MaterialParam(name.clone()),
+            FloatProperty::MorphWeight(i) => FloatKey::MorphWeight(i),
+            FloatProperty::LightIntensity => FloatKey::LightIntensity,
+            FloatProperty::Custom(ref f) => {
+                FloatKey::Custom(&**f as *const Fn(&mut GameObject, f32) as *const u8 as usize)
+            }
+        }
+    }
+
+    /// Value where clips do not fully cover the property
+    fn rest(&self) -> f32 {
+        match *self {
+            FloatProperty::LightIntensity => 1.0,
+            _ => 0.0,
+        }
+    }
+}
+
+pub enum TrackProperty {
+    /// Local translation of the transform
+    Translation(Curve<Vector3f>),
+    /// Local rotation of the transform, interpolated linearly and normalized
+    Rotation(Curve<Quaternion<f32>>),
+    /// Local scale of the transform
+    Scale(Curve<Vector3f>),
+    Float(FloatProperty, Curve<f32>),
+}
+
+/// Animated property of the target of index `target` in `Animator::new`
+pub struct Track {
+    pub target: usize,
+    pub property: TrackProperty,
+}
+
+/// Reason the tracks of a clip were rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClipError {
+    /// Clips start at 0
+    NegativeTime { target: usize, time: f32 },
+    /// Rotation keys need to be non-zero quaternions
+    InvalidRotation { target: usize, time: f32 },
+    /// Only `MAX_MORPH_TARGETS` morph targets are blended by the shaders
+    InvalidMorphTarget { target: usize, index: usize },
+}
+
+/// Keyframed tracks played by an `Animator`, times are in seconds
+pub struct AnimationClip {
+    pub tracks: Vec<Track>,
+    /// Time of the last key of all tracks
+    pub duration: f32,
+}
+
+impl AnimationClip {
+    pub fn new(mut tracks: Vec<Track>) -> Result<AnimationClip, ClipError> {
+        let mut duration: f32 = 0.0;
+
+        for track in tracks.iter_mut() {
+            let target = track.target;
+            let (first, last) = match track.property {
+                TrackProperty::Translation(ref c) | TrackProperty::Scale(ref c) => {
+                    (c.keys()[0].0, c.end_time())
+                }
+                TrackProperty::Rotation(ref mut c) => {
+                    let keys = c.keys_mut();
+                    if let Some(k) = keys.iter().find(|k| !k.1.magnitude2().is_normal()) {
+                        return Err(ClipError::InvalidRotation { target, time: k.0 });
+                    }
+
+                    // Interpolate along the shortest path
+                    for i in 1..keys.len() {
+                        if keys[i - 1].1.dot(keys[i].1) < 0.0 {
+                            keys[i].1 = -keys[i].1;
+                        }
+                    }
+                    (keys[0].0, keys[keys.len() - 1].0)
+                }
+                TrackProperty::Float(FloatProperty::MorphWeight(index), _)
+                    if index >= MAX_MORPH_TARGETS =>
+                {
+                    return Err(ClipError::InvalidMorphTarget { target, index });
+                }
+                TrackProperty::Float(_, ref c) => (c.keys()[0].0, c.end_time()),
+            };
+
+            if first < 0.0 {
+                return Err(ClipError::NegativeTime { target, time: first });
+            }
+            duration = duration.max(last);
+        }
+
+        Ok(AnimationClip { tracks, duration })
+    }
+
+    /// Blend the values at `time` into `pose` with `weight`
+    pub(crate) fn sample(&self, time: f32, weight: f32, pose: &mut Pose) {
+        if weight <= 0.0 {
+            return;
+        }
+
+        for track in self.tracks.iter() {
+            match track.property {
+                TrackProperty::Translation(ref c) => {
+                    pose.transform(track.target)
+                        .translation
+                        .accumulate(c.evaluate(time), weight);
+                }
+                TrackProperty::Rotation(ref c) => {
+                    pose.transform(track.target)
+                        .rotation
+                        .accumulate(c.evaluate(time).normalize(), weight);
+                }
+                TrackProperty::Scale(ref c) => {
+                    pose.transform(track.target)
+                        .scale
+                        .accumulate(Scale(c.evaluate(time)), weight);
+                }
+                TrackProperty::Float(ref property, ref c) => {
+                    pose.floats
+                        .entry((track.target, property.key()))
+                        .or_insert_with(|| (property.clone(), Channel::default()))
+                        .1
+                        .accumulate(c.evaluate(time), weight);
+                }
+            }
+        }
+    }
+}
+
+/// Value which can be blended between poses
+pub(crate) trait Blend: Copy {
+    fn identity() -> Self;
+
+    fn blend(self, other: Self, f: f32) -> Self;
+
+    /// Delta from `reference` to `self`, for additive layers
+    fn difference(self, reference: Self) -> Self;
+
+    /// `self` with `f` times the delta added
+    fn add(self, delta: Self, f: f32) -> Self;
+}
+
+impl Blend for f32 {
+    fn identity() -> f32 {
+        0.0
+    }
+
+    fn blend(self, other: f32, f: f32) -> f32 {
+        self + (other - self) * f
+    }
+
+    fn difference(self, reference: f32) -> f32 {
+        self - reference
+    }
+
+    fn add(self, delta: f32, f: f32) -> f32 {
+        self + delta * f
+    }
+}
+
+impl Blend for Vector3f {
+    fn identity() -> Vector3f {
+        Vector3f::zero()
+    }
+
+    fn blend(self, other: Vector3f, f: f32) -> Vector3f {
+        self.lerp(other, f)
+    }
+
+    fn difference(self, reference: Vector3f) -> Vector3f {
+        self - reference
+    }
+
+    fn add(self, delta: Vector3f, f: f32) -> Vector3f {
+        self + delta * f
+    }
+}
+
+impl Blend for Quaternion<f32> {
+    fn identity() -> Quaternion<f32> {
+        Quaternion::one()
+    }
+
+    fn blend(self, other: Quaternion<f32>, f: f32) -> Quaternion<f32> {
+        let other = if self.dot(other) < 0.0 { -other } else { other };
+        self.nlerp(other, f)
+    }
+
+    fn difference(self, reference: Quaternion<f32>) -> Quaternion<f32> {
+        reference.conjugate() * self
+    }
+
+    fn add(self, delta: Quaternion<f32>, f: f32) -> Quaternion<f32> {
+        (self * Quaternion::one().blend(delta, f)).normalize()
+    }
+}
+
+/// Scales are added by multiplication
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Scale(pub Vector3f);
+
+impl Blend for Scale {
+    fn identity() -> Scale {
+        Scale(Vector3::new(1.0, 1.0, 1.0))
+    }
+
+    fn blend(self, other: Scale, f: f32) -> Scale {
+        Scale(self.0.lerp(other.0, f))
+    }
+
+    fn difference(self, reference: Scale) -> Scale {
+        let div = |a: f32, b: f32| if b != 0.0 { a / b } else { 1.0 };
+        let (s, r) = (self.0, reference.0);
+
+        Scale(Vector3::new(div(s.x, r.x), div(s.y, r.y), div(s.z, r.z)))
+    }
+
+    fn add(self, delta: Scale, f: f32) -> Scale {
+        let d = Vector3::new(1.0, 1.0, 1.0).lerp(delta.0, f);
+        Scale(Vector3::new(self.0.x * d.x, self.0.y * d.y, self.0.z * d.z))
+    }
+}
+
+/// Blended value of a property
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Channel<T> {
+    /// The value covers `weight` of the property, the rest is the rest pose
+    weight: f32,
+    value: Option<T>,
+    /// Added on top by additive layers
+    delta: Option<T>,
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Channel<T> {
+        Channel {
+            weight: 0.0,
+            value: None,
+            delta: None,
+        }
+    }
+}
+
+impl<T: Blend> Channel<T> {
+    /// Weighted average with the values accumulated so far
+    fn accumulate(&mut self, value: T, weight: f32) {
+        let total = self.weight + weight;
+
+        self.value = Some(match self.value {
+            Some(v) if total > 0.0 => v.blend(value, weight / total),
+            _ => value,
+        });
+        self.weight = total;
+    }
+
+    /// Blend `other` over this channel with `f`
+    fn blend_over(&mut self, other: &Channel<T>, f: f32) {
+        let value = match other.value {
+            Some(v) => v,
+            None => return,
+        };
+
+        let f = other.weight.min(1.0) * f;
+        let weight = 1.0 - (1.0 - f) * (1.0 - self.weight.min(1.0));
+
+        self.value = Some(match self.value {
+            Some(v) if weight > 0.0 => v.blend(value, f / weight),
+            _ => value,
+        });
+        self.weight = weight;
+    }
+
+    /// Add the delta of `other` from `reference` with `f`
+    fn add_over(&mut self, other: &Channel<T>, reference: &Channel<T>, f: f32) {
+        let delta = match (other.value, reference.value) {
+            (Some(v), Some(r)) => v.difference(r),
+            _ => return,
+        };
+
+        let f = other.weight.min(1.0) * f;
+        self.delta = Some(self.delta.unwrap_or(T::identity()).add(delta, f));
+    }
+
+    /// Final value, blended from `rest`
+    fn resolve(&self, rest: T) -> Option<T> {
+        let value = match self.value {
+            Some(v) => rest.blend(v, self.weight.min(1.0)),
+            None if self.delta.is_some() => rest,
+            None => return None,
+        };
+
+        Some(match self.delta {
+            Some(d) => value.add(d, 1.0),
+            None => value,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct TransformPose {
+    translation: Channel<Vector3f>,
+    rotation: Channel<Quaternion<f32>>,
+    scale: Channel<Scale>,
+}
+
+/// Local transform of a target before it is animated
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct RestTransform {
+    pub translation: Vector3f,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3f,
+}
+
+impl RestTransform {
+    pub fn new(go: &GameObject) -> RestTransform {
+        let local = go.transform.local();
+
+        RestTransform {
+            translation: local.disp,
+            rotation: local.rot,
+            scale: go.transform.local_scale(),
+        }
+    }
+}
+
+/// Blended values of the properties of all targets
+#[derive(Default)]
+pub(crate) struct Pose {
+    transforms: FnvHashMap<usize, TransformPose>,
+    floats: FnvHashMap<(usize, FloatKey), (FloatProperty, Channel<f32>)>,
+}
+
+impl Pose {
+    fn transform(&mut self, target: usize) -> &mut TransformPose {
+        self.transforms.entry(target).or_insert_with(Default::default)
+    }
+
+    /// Override this pose with `layer` blended by `weight`
+    pub fn blend_over(&mut self, layer: &Pose, weight: f32) {
+        for (&target, t) in layer.transforms.iter() {
+            let dst = self.transform(target);
+
+            dst.translation.blend_over(&t.translation, weight);
+            dst.rotation.blend_over(&t.rotation, weight);
+            dst.scale.blend_over(&t.scale, weight);
+        }
+
+        for (key, &(ref property, ref c)) in layer.floats.iter() {
+            self.floats
+                .entry(key.clone())
+                .or_insert_with(|| (property.clone(), Channel::default()))
+                .1
+                .blend_over(c, weight);
+        }
+    }
+
+    /// Add the difference of `layer` from `reference` to this pose, scaled by `weight`
+    pub fn add_over(&mut self, layer: &Pose, reference: &Pose, weight: f32) {
+        let no_transform = TransformPose::default();
+        let no_float = Channel::default();
+
+        for (&target, t) in layer.transforms.iter() {
+            let r = reference.transforms.get(&target).unwrap_or(&no_transform);
+            let dst = self.transform(target);
+
+            dst.translation
+                .add_over(&t.translation, &r.translation, weight);
+            dst.rotation.add_over(&t.rotation, &r.rotation, weight);
+            dst.scale.add_over(&t.scale, &r.scale, weight);
+        }
+
+        for (key, &(ref property, ref c)) in layer.floats.iter() {
+            let r = reference.floats.get(key).map_or(&no_float, |f| &f.1);
+
+            self.floats
+                .entry(key.clone())
+                .or_insert_with(|| (property.clone(), Channel::default()))
+                .1
+                .add_over(c, r, weight);
+        }
+    }
+
+    /// Write the transforms of the pose to `go`, the target of index `target`
+    pub fn apply_transform(&self, target: usize, go: &mut GameObject, rest: &RestTransform) {
+        let t = match self.transforms.get(&target) {
+            Some(t) => t,
+            None => return,
+        };
+
+        let translation = t.translation.resolve(rest.translation);
+        let rotation = t.rotation.resolve(rest.rotation);
+        if translation.is_some() || rotation.is_some() {
+            let mut local = go.transform.local();
+            local.disp = translation.unwrap_or(local.disp);
+            local.rot = rotation.unwrap_or(local.rot);
+
+            go.transform.set_local(local);
+        }
+
+        if let Some(scale) = t.scale.resolve(Scale(rest.scale)) {
+            go.transform.set_local_scale(scale.0);
+        }
+    }
+
+    /// Write the float properties of the pose to `go`, the target of index `target`
+    ///
+    /// `light_base` is the (diffuse, specular) colors of its light before any animation.
+    pub fn apply_floats(
+        &self,
+        target: usize,
+        go: &mut GameObject,
+        light_base: &mut Option<(Vector3f, Vector3f)>,
+    ) {
+        for (key, &(ref property, ref c)) in self.floats.iter() {
+            if key.0 != target {
+                continue;
+            }
+
+            let value = match c.resolve(property.rest()) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            match *property {
+                FloatProperty::MaterialParam(ref name) => {
+                    set_surface_property(go, name.clone(), value);
+                }
+                FloatProperty::MorphWeight(i) => {
+                    if let Some((mesh, _)) = go.find_component::<Mesh>() {
+                        for surface in mesh.surfaces.iter() {
+                            surface.set_morph_weight(i, value);
+                        }
+                    }
+                }
+                FloatProperty::LightIntensity => {
+                    if let Some((mut light, _)) = go.find_component_mut::<Light>() {
+                        let (diffuse, specular) = light_colors_mut(&mut light);
+                        let base = light_base.get_or_insert((*diffuse, *specular));
+
+                        *diffuse = base.0 * value;
+                        *specular = base.1 * value;
+                    }
+                }
+                FloatProperty::Custom(ref f) => f(go, value),
+            }
+        }
+    }
+}
+
+fn set_surface_property(go: &GameObject, name: String, value: f32) {
+    if let Some((mesh, _)) = go.find_component::<Mesh>() {
+        for surface in mesh.surfaces.iter() {
+            surface.set_property(name.clone(), value);
+        }
+    }
+}
+
+fn light_colors_mut(light: &mut Light) -> (&mut Vector3f, &mut Vector3f) {
+    match *light {
+        Light::Directional(ref mut l) => (&mut l.diffuse, &mut l.specular),
+        Light::Point(ref mut l) => (&mut l.diffuse, &mut l.specular),
+        Light::Spot(ref mut l) => (&mut l.diffuse, &mut l.specular),
+    }
+}
@@ -0,0 +1,420 @@
+use world::{Actor, Handle, World};
+use engine::GameObject;
+use fnv::FnvHashMap;
+
+use super::animation_clip::{AnimationClip, Pose, RestTransform};
+
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use math::*;
+
+/// Value of a parameter of the state machine
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Parameter {
+    Float(f32),
+    Bool(bool),
+    /// Bool which is reset when a transition consumes it
+    Trigger(bool),
+}
+
+/// Condition on a parameter for a transition, missing parameters never pass
+#[derive(Clone, Debug)]
+pub enum Condition {
+    Greater(String, f32),
+    Less(String, f32),
+    If(String),
+    IfNot(String),
+    Trigger(String),
+}
+
+impl Condition {
+    fn check(&self, parameters: &FnvHashMap<String, Parameter>) -> bool {
+        match *self {
+            Condition::Greater(ref name, v) => match parameters.get(name) {
+                Some(&Parameter::Float(p)) => p > v,
+                _ => false,
+            },
+            Condition::Less(ref name, v) => match parameters.get(name) {
+                Some(&Parameter::Float(p)) => p < v,
+                _ => false,
+            },
+            Condition::If(ref name) => parameters.get(name) == Some(&Parameter::Bool(true)),
+            Condition::IfNot(ref name) => parameters.get(name) == Some(&Parameter::Bool(false)),
+            Condition::Trigger(ref name) => {
+                parameters.get(name) == Some(&Parameter::Trigger(true))
+            }
+        }
+    }
+}
+
+/// Transition to the state named `to` when all conditions pass
+#[derive(Clone, Debug)]
+pub struct Transition {
+    pub to: String,
+    pub conditions: Vec<Condition>,
+    /// Cross-fade time in seconds
+    pub duration: f32,
+    /// Normalized time of the clip after which the transition can happen
+    pub exit_time: Option<f32>,
+}
+
+impl Transition {
+    pub fn new(to: &str, duration: f32) -> Transition {
+        Transition {
+            to: to.to_string(),
+            conditions: Vec::new(),
+            duration,
+            exit_time: None,
+        }
+    }
+}
+
+pub struct AnimationState {
+    pub name: String,
+    pub clip: Rc<AnimationClip>,
+    /// Multiplier of the playback speed
+    pub speed: f32,
+    /// Clips which do not loop hold their last keys
+    pub looping: bool,
+    /// Checked in order, the first one which passes is taken
+    pub transitions: Vec<Transition>,
+}
+
+impl AnimationState {
+    pub fn new(name: &str, clip: Rc<AnimationClip>) -> AnimationState {
+        AnimationState {
+            name: name.to_string(),
+            clip,
+            speed: 1.0,
+            looping: true,
+            transitions: Vec::new(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LayerBlend {
+    /// Replace the layers below by `weight`
+    Override,
+    /// Add the difference of the clips from their first keys, scaled by `weight`
+    ///
+    /// Additive layers are applied on top of all override layers.
+    Additive,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Playback {
+    state: usize,
+    /// In seconds of the clip
+    time: f32,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Fade {
+    from: Playback,
+    elapsed: f32,
+    duration: f32,
+}
+
+/// State machine playing one clip at a time, cross-fading between them
+pub struct AnimationLayer {
+    /// The first one is played when the animator starts
+    pub states: Vec<AnimationState>,
+    pub weight: f32,
+    pub blend: LayerBlend,
+
+    current: Option<Playback>,
+    fade: Option<Fade>,
+}
+
+impl AnimationLayer {
+    pub fn new(states: Vec<AnimationState>) -> AnimationLayer {
+        AnimationLayer {
+            states,
+            weight: 1.0,
+            blend: LayerBlend::Override,
+            current: None,
+            fade: None,
+        }
+    }
+
+    /// Name of the state which is playing or fading in
+    pub fn current_state(&self) -> Option<&str> {
+        self.current.map(|p| self.states[p.state].name.as_str())
+    }
+
+    /// Normalized time of the current state
+    pub fn normalized_time(&self) -> Option<f32> {
+        self.current.map(|p| self.normalized(&p))
+    }
+
+    fn normalized(&self, p: &Playback) -> f32 {
+        let duration = self.states[p.state].clip.duration;
+        if duration > 0.0 {
+            p.time / duration
+        } else {
+            1.0
+        }
+    }
+
+    fn find_state(&self, name: &str) -> Option<usize> {
+        self.states.iter().position(|s| s.name == name)
+    }
+
+    fn cross_fade(&mut self, state: usize, duration: f32) {
+        self.fade = match self.current.take() {
+            Some(from) if duration > 0.0 => Some(Fade {
+                from,
+                elapsed: 0.0,
+                duration,
+            }),
+            _ => None,
+        };
+        self.current = Some(Playback { state, time: 0.0 });
+    }
+
+    /// Normalized time before looping, so an exit time of 1 is reached by looping states
+    fn advance_playback(&self, p: &mut Playback, dt: f32) -> f32 {
+        let state = &self.states[p.state];
+        let duration = state.clip.duration;
+
+        p.time += dt * state.speed;
+        let normalized = self.normalized(p);
+
+        p.time = if state.looping && duration > 0.0 {
+            let t = p.time % duration;
+            if t < 0.0 {
+                t + duration
+            } else {
+                t
+            }
+        } else {
+            p.time.max(0.0).min(duration)
+        };
+
+        normalized
+    }
+
+    fn advance(&mut self, dt: f32, parameters: &mut FnvHashMap<String, Parameter>) {
+        if self.current.is_none() && !self.states.is_empty() {
+            self.current = Some(Playback {
+                state: 0,
+                time: 0.0,
+            });
+        }
+
+        let mut current = match self.current {
+            Some(p) => p,
+            None => return,
+        };
+        let normalized = self.advance_playback(&mut current, dt);
+        self.current = Some(current);
+
+        if let Some(mut fade) = self.fade {
+            self.advance_playback(&mut fade.from, dt);
+            fade.elapsed += dt;
+
+            self.fade = if fade.elapsed < fade.duration {
+                Some(fade)
+            } else {
+                None
+            };
+        }
+
+        let taken = self.states[current.state].transitions.iter().find(|t| {
+            t.exit_time.map_or(true, |exit| normalized >= exit)
+                && t.conditions.iter().all(|c| c.check(parameters))
+        });
+
+        let (to, duration) = match taken {
+            Some(t) => {
+                for c in t.conditions.iter() {
+                    if let Condition::Trigger(ref name) = *c {
+                        parameters.insert(name.clone(), Parameter::Trigger(false));
+                    }
+                }
+
+                match self.find_state(&t.to) {
+                    Some(to) => (to, t.duration),
+                    None => return,
+                }
+            }
+            None => return,
+        };
+
+        self.cross_fade(to, duration);
+    }
+
+    /// Blend the clips of the layer into `pose`, and their first keys into `reference`
+    fn sample(&self, pose: &mut Pose, reference: Option<&mut Pose>) {
+        let current = match self.current {
+            Some(p) => p,
+            None => return,
+        };
+
+        let mut playbacks = vec![(current, 1.0)];
+        if let Some(fade) = self.fade {
+            let f = fade.elapsed / fade.duration;
+            playbacks = vec![(fade.from, 1.0 - f), (current, f)];
+        }
+
+        for &(p, weight) in playbacks.iter() {
+            self.states[p.state].clip.sample(p.time, weight, pose);
+        }
+
+        if let Some(reference) = reference {
+            for &(p, weight) in playbacks.iter() {
+                self.states[p.state].clip.sample(0.0, weight, reference);
+            }
+        }
+    }
+}
+
+/// Plays animation clips on game objects of the `SceneTree`
+///
+/// Register it with `WorldBuilder::with_actor::<Animator>()`, it is evaluated in the world
+/// step before render. Layers are blended from first to last, and the parameters of their
+/// state machines are set from other actors with `find_component_mut::<Animator>()`.
+///
+/// Properties which are not animated are left untouched. The rest pose blended with
+/// partial weights is the local transform of each target when it is first animated.
+#[derive(Component)]
+pub struct Animator {
+    pub layers: Vec<AnimationLayer>,
+    /// Multiplier of the playback speed of all layers
+    pub speed: f32,
+
+    parameters: FnvHashMap<String, Parameter>,
+    targets: Vec<Weak<RefCell<GameObject>>>,
+    rest: Vec<Option<RestTransform>>,
+    /// Colors of the lights of the targets before `FloatProperty::LightIntensity`
+    light_bases: Vec<Option<(Vector3f, Vector3f)>>,
+}
+
+impl Animator {
+    /// Tracks of the clips refer to `targets` by index
+    pub fn new(targets: &[Handle<GameObject>]) -> Animator {
+        Animator {
+            layers: Vec::new(),
+            speed: 1.0,
+
+            parameters: FnvHashMap::default(),
+            targets: targets.iter().map(Rc::downgrade).collect(),
+            rest: vec![None; targets.len()],
+            light_bases: vec![None; targets.len()],
+        }
+    }
+
+    pub fn add_layer(&mut self, layer: AnimationLayer) -> usize {
+        self.layers.push(layer);
+        self.layers.len() - 1
+    }
+
+    pub fn set_float(&mut self, name: &str, v: f32) {
+        self.parameters.insert(name.to_string(), Parameter::Float(v));
+    }
+
+    pub fn set_bool(&mut self, name: &str, v: bool) {
+        self.parameters.insert(name.to_string(), Parameter::Bool(v));
+    }
+
+    pub fn set_trigger(&mut self, name: &str) {
+        self.parameters
+            .insert(name.to_string(), Parameter::Trigger(true));
+    }
+
+    pub fn reset_trigger(&mut self, name: &str) {
+        self.parameters
+            .insert(name.to_string(), Parameter::Trigger(false));
+    }
+
+    pub fn float(&self, name: &str) -> Option<f32> {
+        match self.parameters.get(name) {
+            Some(&Parameter::Float(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn bool(&self, name: &str) -> Option<bool> {
+        match self.parameters.get(name) {
+            Some(&Parameter::Bool(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Jump to the state named `state` of `layer`
+    pub fn play(&mut self, layer: usize, state: &str) {
+        self.cross_fade(layer, state, 0.0);
+    }
+
+    /// Fade from the current state of `layer` to the state named `state` in `duration` seconds
+    pub fn cross_fade(&mut self, layer: usize, state: &str, duration: f32) {
+        if let Some(layer) = self.layers.get_mut(layer) {
+            if let Some(state) = layer.find_state(state) {
+                layer.cross_fade(state, duration);
+            }
+        }
+    }
+
+    /// Advance the layers by `dt` seconds times `speed`, and write the pose to the targets
+    ///
+    /// Called by the world step, or directly to drive the animator by hand.
+    pub fn advance(&mut self, dt: f32) {
+        let dt = dt * self.speed;
+        self.evaluate(dt);
+    }
+
+    fn evaluate(&mut self, dt: f32) {
+        for layer in self.layers.iter_mut() {
+            layer.advance(dt, &mut self.parameters);
+        }
+
+        let mut pose = Pose::default();
+        let mut additive = Vec::new();
+
+        for layer in self.layers.iter() {
+            let mut layer_pose = Pose::default();
+
+            match layer.blend {
+                LayerBlend::Override => {
+                    layer.sample(&mut layer_pose, None);
+                    pose.blend_over(&layer_pose, layer.weight);
+                }
+                LayerBlend::Additive => {
+                    let mut reference = Pose::default();
+                    layer.sample(&mut layer_pose, Some(&mut reference));
+                    additive.push((layer_pose, reference, layer.weight));
+                }
+            }
+        }
+
+        for &(ref layer_pose, ref reference, weight) in additive.iter() {
+            pose.add_over(layer_pose, reference, weight);
+        }
+
+        for (i, target) in self.targets.iter().enumerate() {
+            let target = match target.upgrade() {
+                Some(target) => target,
+                None => continue,
+            };
+
+            // Skip targets borrowed elsewhere, like a custom property setter
+            let mut go = match target.try_borrow_mut() {
+                Ok(go) => go,
+                Err(_) => continue,
+            };
+
+            let rest = self.rest[i].get_or_insert_with(|| RestTransform::new(&go));
+            pose.apply_transform(i, &mut go, rest);
+            pose.apply_floats(i, &mut go, &mut self.light_bases[i]);
+        }
+    }
+}
+
+impl Actor for Animator {
+    fn update_rc(&mut self, _go: Handle<GameObject>, world: &mut World) {
+        // Targets are borrowed one by one, and may include the game object of the animator
+        self.advance(world.delta_time() as f32);
+    }
+}
@@ -0,0 +1,80 @@
+use std::cmp::Ordering;
+use std::ops::{Add, Mul};
+
+/// Reason the keys of a curve were rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum CurveError {
+    /// A curve needs at least one key
+    NoKeys,
+    /// Key times need to be finite
+    InvalidTime(f32),
+}
+
+/// Value over time, linearly interpolated between keys
+///
+/// Used for particles over their normalized lifetime and for animation clips in seconds.
+#[derive(Clone, Debug)]
+pub struct Curve<T> {
+    /// (time, value), sorted by time, never empty
+    keys: Vec<(f32, T)>,
+}
+
+impl<T> Curve<T>
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    /// Curve through `keys` of (time, value), in any order
+    pub fn new(mut keys: Vec<(f32, T)>) -> Result<Curve<T>, CurveError> {
+        if keys.is_empty() {
+            return Err(CurveError::NoKeys);
+        }
+
+        if let Some(k) = keys.iter().find(|k| !k.0.is_finite()) {
+            return Err(CurveError::InvalidTime(k.0));
+        }
+
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        Ok(Curve { keys })
+    }
+
+    pub fn constant(v: T) -> Curve<T> {
+        Curve { keys: vec![(0.0, v)] }
+    }
+
+    pub fn linear(from: T, to: T) -> Curve<T> {
+        Curve {
+            keys: vec![(0.0, from), (1.0, to)],
+        }
+    }
+
+    /// (time, value), sorted by time
+    pub fn keys(&self) -> &[(f32, T)] {
+        &self.keys
+    }
+
+    /// Only the values may change, the times need to stay sorted
+    pub(crate) fn keys_mut(&mut self) -> &mut [(f32, T)] {
+        &mut self.keys
+    }
+
+    /// Time of the last key
+    pub fn end_time(&self) -> f32 {
+        self.keys[self.keys.len() - 1].0
+    }
+
+    pub fn evaluate(&self, t: f32) -> T {
+        let next = self.keys.iter().position(|&(kt, _)| kt > t);
+
+        match next {
+            None => self.keys[self.keys.len() - 1].1,
+            Some(0) => self.keys[0].1,
+            Some(i) => {
+                let (t0, v0) = self.keys[i - 1];
+                let (t1, v1) = self.keys[i];
+                let f = (t - t0) / (t1 - t0);
+
+                v0 * (1.0 - f) + v1 * f
+            }
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use world::{Actor, World};
+use engine::{Aabb, Asset, BlendState, Component, CullMode, GameObject, Material, Mesh, MeshBuffer,
+             MeshData, RenderQueue, RenderTexture, Resource, Texture, TextureAsset,
+             TextureAttachment, TextureImage, TextureWrap};
+
+use super::particle_emitter::{EmitterShape, ParticleEmitter, Random, SimulationSpace};
+use super::particle_system::{ParticleRenderMode, ParticleSystem};
+use super::screen_pass::ScreenPass;
+
+use image::{Rgba, RgbaImage};
+use std::rc::Rc;
+use std::sync::Arc;
+use uni_app;
+use uni_gl::{Parameter, WebGLRenderingContext};
+
+use math::*;
+
+/// State textures are at most 256 x 256
+const MAX_GPU_PARTICLES: usize = 65536;
+/// Samples of the color and size curves, same as RAMP_SIZE in gpu_particle_vs.glsl
+const RAMP_SIZE: u32 = 64;
+/// uPositions, uVelocities and uRamp are fetched in gpu_particle_vs.glsl
+const VERTEX_TEXTURES: i32 = 3;
+
+/// Ping-pong pair of float render textures
+struct StateTextures {
+    /// (position, age)
+    positions: [Rc<RenderTexture>; 2],
+    /// (velocity, lifetime)
+    velocities: [Rc<RenderTexture>; 2],
+}
+
+impl StateTextures {
+    fn new(side: u32) -> StateTextures {
+        let rt = || Rc::new(RenderTexture::new(side, side, TextureAttachment::Color0Float));
+
+        StateTextures {
+            positions: [rt(), rt()],
+            velocities: [rt(), rt()],
+        }
+    }
+}
+
+/// Particles simulated on the gpu, for effects with tens of thousands of particles
+///
+/// The state of each particle is a texel of two float render textures, updated every frame
+/// by "unrust/gpu_particle_sim" through a `ScreenPass`. A single quad is drawn with one instance
+/// per particle, which fetches that state in "unrust/gpu_particle_vs.glsl".
+///
+/// The emitter is the same description as for `ParticleSystem`, but:
+/// * `max_particles` is read at start, up to 65536.
+/// * Particles are emitted in a ring, the oldest ones are replaced when it is full.
+/// * `EmitterShape::MeshSurface` emits from the origin like `EmitterShape::Point`.
+///
+/// Particles are not sorted, so additive blending works best.
+/// Without float render textures, instancing or texture fetches in the vertex shader,
+/// see `is_supported`, the particles are simulated by a `ParticleSystem` instead.
+#[derive(Actor)]
+pub struct GpuParticleSystem {
+    pub emitter: ParticleEmitter,
+    pub render_mode: ParticleRenderMode,
+    /// Multiplied by the particle color, particles are soft discs without texture
+    pub texture: Option<Rc<Texture>>,
+    /// Read when the system starts
+    pub blend: BlendState,
+    /// Existing particles live on when emitting stops
+    pub emitting: bool,
+    /// Box around all particles in the space of the game object, used for culling
+    pub bounds: Aabb,
+
+    max_particles: usize,
+    side: u32,
+    state: Option<StateTextures>,
+    /// Index of the state textures written last
+    current: usize,
+
+    time: f32,
+    emit_accumulator: f32,
+    emit_start: usize,
+    rng: Random,
+
+    velocity_material: Option<Rc<Material>>,
+    position_material: Option<Rc<Material>>,
+    render_material: Option<Rc<Material>>,
+    screen_pass: ScreenPass,
+
+    /// Color and size curve keys baked in the ramp texture
+    ramp_keys: Option<(Vec<(f32, Vector4<f32>)>, Vec<(f32, f32)>)>,
+    /// Bounds baked in the mesh buffer
+    mesh_bounds: Option<(Vector3f, Vector3f)>,
+    mesh_buffer: Option<Rc<MeshBuffer>>,
+    mesh: Option<Arc<Component>>,
+
+    /// Simulates the particles when the gpu path is not supported
+    fallback: Option<ParticleSystem>,
+    reported_mesh_shape: bool,
+}
+
+impl GpuParticleSystem {
+    pub fn new(emitter: ParticleEmitter) -> GpuParticleSystem {
+        GpuParticleSystem {
+            emitter,
+            render_mode: ParticleRenderMode::Billboard,
+            texture: None,
+            blend: BlendState::additive(),
+            emitting: true,
+            bounds: Aabb {
+                min: Vector3::new(-5.0, -5.0, -5.0),
+                max: Vector3::new(5.0, 5.0, 5.0),
+            },
+
+            max_particles: 0,
+            side: 0,
+            state: None,
+            current: 0,
+
+            time: 0.0,
+            emit_accumulator: 0.0,
+            emit_start: 0,
+            rng: Random::new(0x85eb_ca6b),
+
+            velocity_material: None,
+            position_material: None,
+            render_material: None,
+            screen_pass: ScreenPass::new(),
+
+            ramp_keys: None,
+            mesh_bounds: None,
+            mesh_buffer: None,
+            mesh: None,
+
+            fallback: None,
+            reported_mesh_shape: false,
+        }
+    }
+
+    /// Whether the particles can be simulated and drawn on the gpu
+    pub fn is_supported(gl: &WebGLRenderingContext) -> bool {
+        TextureAttachment::Color0Float.is_supported(gl) && MeshBuffer::supports_instancing(gl)
+            && gl.get_parameter_i32(Parameter::MaxVertexTextureImageUnits) >= VERTEX_TEXTURES
+    }
+
+    /// Same particles simulated on the cpu
+    fn new_fallback(&self) -> ParticleSystem {
+        let mut fallback = ParticleSystem::new(self.emitter.clone());
+        fallback.render_mode = self.render_mode;
+        fallback.texture = self.texture.clone();
+        fallback.blend = self.blend;
+        fallback.emitting = self.emitting;
+        // Like the gpu particles, which are not sorted
+        fallback.sort_by_distance = false;
+
+        fallback
+    }
+
+    fn report_mesh_shape(&mut self) {
+        let mesh_shape = match self.emitter.shape {
+            EmitterShape::MeshSurface(_) => true,
+            _ => false,
+        };
+
+        if !mesh_shape || self.reported_mesh_shape {
+            return;
+        }
+
+        uni_app::App::print(
+            "GpuParticleSystem does not support EmitterShape::MeshSurface, \
+             particles are emitted from the origin like EmitterShape::Point\n",
+        );
+        self.reported_mesh_shape = true;
+    }
+
+    /// Number of particles emitted this frame
+    fn emit_count(&mut self, dt: f32) -> usize {
+        let mut count = 0.0;
+
+        if self.time == 0.0 {
+            count += self.emitter.burst as f32;
+        }
+
+        self.time += dt;
+
+        let active = match self.emitter.duration {
+            Some(duration) => self.time <= duration,
+            None => true,
+        };
+
+        if self.emitting && active {
+            self.emit_accumulator += self.emitter.rate * dt;
+            let n = self.emit_accumulator.floor();
+            self.emit_accumulator -= n;
+
+            count += n;
+        }
+
+        (count as usize).min(self.max_particles)
+    }
+
+    fn apply_emitter(&self, material: &Material, dt: f32, model: &Matrix4f) {
+        let (shape, params) = match self.emitter.shape {
+            EmitterShape::Point | EmitterShape::MeshSurface(_) => (0, Vector2::new(0.0, 0.0)),
+            EmitterShape::Sphere { radius } => (1, Vector2::new(radius, 0.0)),
+            EmitterShape::Cone { angle, radius } => {
+                (2, Vector2::new(Rad::from(angle).0.cos(), radius))
+            }
+        };
+
+        let (spawn_matrix, gravity) = match self.emitter.space {
+            SimulationSpace::World => (*model, self.emitter.gravity),
+            SimulationSpace::Local => (
+                Matrix4::identity(),
+                model
+                    .inverse_transform()
+                    .map(|inv| inv.transform_vector(self.emitter.gravity))
+                    .unwrap_or(self.emitter.gravity),
+            ),
+        };
+
+        material.set("uSide", self.side as f32);
+        material.set("uMaxParticles", self.max_particles as f32);
+        material.set("uDeltaTime", dt);
+        material.set("uShape", shape);
+        material.set("uShapeParams", params);
+        material.set("uSpeed", Vector2::new(self.emitter.speed.0, self.emitter.speed.1));
+        material.set(
+            "uLifetime",
+            Vector2::new(self.emitter.lifetime.0, self.emitter.lifetime.1),
+        );
+        material.set("uGravity", gravity);
+        material.set("uDrag", self.emitter.drag);
+        material.set("uSpawnMatrix", spawn_matrix);
+    }
+
+    fn simulate(&mut self, world: &mut World, dt: f32, model: &Matrix4f) {
+        let (vel_material, pos_material) = match (&self.velocity_material, &self.position_material)
+        {
+            (&Some(ref a), &Some(ref b)) => (a.clone(), b.clone()),
+            _ => return,
+        };
+
+        let reset = self.time == 0.0;
+        let count = self.emit_count(dt);
+        let seed = self.rng.next();
+
+        let (prev, next) = (self.current, 1 - self.current);
+        let size = (self.side, self.side);
+
+        for material in [&vel_material, &pos_material].iter() {
+            self.apply_emitter(material, dt, model);
+
+            material.set("uReset", reset);
+            material.set("uSeed", seed);
+            material.set("uEmitStart", self.emit_start as f32);
+            material.set("uEmitCount", count as f32);
+        }
+
+        let (pos, vel) = match self.state {
+            Some(ref s) => (s.positions.clone(), s.velocities.clone()),
+            None => return,
+        };
+
+        vel_material.set("uPositions", pos[prev].as_texture());
+        vel_material.set("uVelocities", vel[prev].as_texture());
+        self.screen_pass.render(world, &vel_material, &vel[next], size);
+
+        pos_material.set("uPositions", pos[prev].as_texture());
+        pos_material.set("uNewVelocities", vel[next].as_texture());
+        self.screen_pass.render(world, &pos_material, &pos[next], size);
+
+        self.current = next;
+        self.emit_start = (self.emit_start + count) % self.max_particles;
+    }
+
+    /// Color and size curves sampled over the normalized age, see "unrust/gpu_particle_vs.glsl"
+    fn update_ramp(&mut self, material: &Material) {
+        let keys = (self.emitter.color.keys().to_vec(), self.emitter.size.keys().to_vec());
+        if self.ramp_keys.as_ref() == Some(&keys) {
+            return;
+        }
+
+        let max_size = keys.1.iter().fold(0.0001f32, |m, &(_, s)| m.max(s));
+        let emitter = &self.emitter;
+
+        let img = RgbaImage::from_fn(RAMP_SIZE, 2, |x, y| {
+            let t = x as f32 / (RAMP_SIZE - 1) as f32;
+            let to_u8 = |f: f32| (f.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+
+            if y == 0 {
+                let c = emitter.color.evaluate(t);
+                Rgba([to_u8(c.x), to_u8(c.y), to_u8(c.z), to_u8(c.w)])
+            } else {
+                let s = to_u8(emitter.size.evaluate(t) / max_size);
+                Rgba([s, s, s, 0xff])
+            }
+        });
+
+        let ramp = Texture::new_from_resource(TextureAsset::Single(Resource::new(
+            TextureImage::Rgba(img),
+        )));
+        ramp.mipmaps.set(false);
+        ramp.wrap_u.set(TextureWrap::ClampToEdge);
+        ramp.wrap_v.set(TextureWrap::ClampToEdge);
+
+        material.set("uRamp", ramp);
+        material.set("uMaxSize", max_size);
+
+        self.ramp_keys = Some(keys);
+    }
+
+    /// Quad drawn for each particle, with the min and max of `bounds` as vertex positions
+    /// such that the mesh bounds used for culling enclose all particles
+    fn build_mesh_data(bounds: &Aabb) -> MeshData {
+        let (min, max) = (bounds.min, bounds.max);
+        let positions = [min, max, min, max];
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+
+        let mut vertices = Vec::with_capacity(12);
+        let mut normals = Vec::with_capacity(12);
+
+        for (p, &(x, y)) in positions.iter().zip(corners.iter()) {
+            vertices.extend_from_slice(&[p.x, p.y, p.z]);
+            normals.extend_from_slice(&[x, y, 0.0]);
+        }
+
+        MeshData {
+            vertices,
+            uvs: None,
+            normals: Some(normals),
+            tangents: None,
+            bitangents: None,
+            colors: None,
+            joints: None,
+            weights: None,
+            morph_targets: Vec::new(),
+            indices: vec![0, 1, 2, 0, 2, 3],
+        }
+    }
+
+    fn update_bounds(&mut self) {
+        let bounds = (self.bounds.min, self.bounds.max);
+        if self.mesh_bounds == Some(bounds) {
+            return;
+        }
+
+        if let Some(ref mesh_buffer) = self.mesh_buffer {
+            mesh_buffer.update_mesh_data(Self::build_mesh_data(&self.bounds));
+        }
+
+        if let Some(ref mesh) = self.mesh {
+            mesh.try_as::<Mesh>().unwrap().borrow().invalidate_bounds();
+        }
+
+        self.mesh_bounds = Some(bounds);
+    }
+
+    fn apply_render(&self, material: &Material, view: &Matrix4f) {
+        let cam_right = Vector3::new(view.x.x, view.y.x, view.z.x);
+        let cam_up = Vector3::new(view.x.y, view.y.y, view.z.y);
+
+        if let Some(ref s) = self.state {
+            material.set("uPositions", s.positions[self.current].as_texture());
+            material.set("uVelocities", s.velocities[self.current].as_texture());
+        }
+
+        material.set("uSide", self.side as f32);
+        material.set("uMaxParticles", self.max_particles as f32);
+        material.set("uWorldSpace", self.emitter.space == SimulationSpace::World);
+        material.set("uCameraRight", cam_right);
+        material.set("uCameraUp", cam_up);
+
+        match self.render_mode {
+            ParticleRenderMode::Billboard => material.set("uStretched", false),
+            ParticleRenderMode::Stretched {
+                length_scale,
+                speed_scale,
+            } => {
+                material.set("uStretched", true);
+                material.set("uStretch", Vector2::new(length_scale, speed_scale));
+            }
+        }
+
+        let sheet = match (&self.texture, &self.emitter.texture_sheet) {
+            (&Some(_), &Some(ref sheet)) => {
+                Vector3::new(sheet.columns() as f32, sheet.rows() as f32, sheet.cycles)
+            }
+            _ => Vector3::new(1.0, 1.0, 0.0),
+        };
+        material.set("uSheet", sheet);
+
+        material.set("uHasTexture", self.texture.is_some());
+        if let Some(ref texture) = self.texture {
+            material.set("uTexture", texture.clone());
+        }
+    }
+}
+
+impl Actor for GpuParticleSystem {
+    fn start(&mut self, go: &mut GameObject, world: &mut World) {
+        if !Self::is_supported(&world.engine().gl) {
+            uni_app::App::print(
+                "GpuParticleSystem is not supported, particles are simulated on the cpu\n",
+            );
+
+            let mut fallback = self.new_fallback();
+            fallback.start(go, world);
+            self.fallback = Some(fallback);
+            return;
+        }
+
+        self.screen_pass.start(world);
+
+        self.max_particles = self.emitter.max_particles.max(1).min(MAX_GPU_PARTICLES);
+        self.side = (self.max_particles as f32).sqrt().ceil() as u32;
+        self.state = Some(StateTextures::new(self.side));
+
+        let db = &mut world.asset_system();
+
+        let velocity_material = Material::new(db.new_program("unrust/gpu_particle_sim"));
+        velocity_material.enable_keyword("SIM_VELOCITY");
+        self.velocity_material = Some(Rc::new(velocity_material));
+        self.position_material = Some(Rc::new(Material::new(
+            db.new_program("unrust/gpu_particle_sim"),
+        )));
+
+        let mut material = Material::new(db.new_program("unrust/gpu_particle"));
+        material.render_queue = RenderQueue::Transparent;
+        material.states.blend = Some(self.blend);
+        material.states.cull = Some(CullMode::Off);
+        let material = Rc::new(material);
+
+        let mesh_buffer = MeshBuffer::new(Self::build_mesh_data(&self.bounds));
+        mesh_buffer.set_instances(self.max_particles);
+
+        let mut mesh = Mesh::new();
+        mesh.add_surface(mesh_buffer.clone(), material.clone());
+
+        self.mesh_bounds = Some((self.bounds.min, self.bounds.max));
+        self.mesh = Some(go.add_component(mesh));
+        self.mesh_buffer = Some(mesh_buffer);
+        self.render_material = Some(material);
+    }
+
+    fn update(&mut self, go: &mut GameObject, world: &mut World) {
+        if let Some(ref mut fallback) = self.fallback {
+            fallback.emitter = self.emitter.clone();
+            fallback.render_mode = self.render_mode;
+            fallback.texture = self.texture.clone();
+            fallback.emitting = self.emitting;
+
+            fallback.update(go, world);
+            return;
+        }
+
+        self.report_mesh_shape();
+
+        let model = go.transform.as_global_matrix();
+        let dt = world.delta_time() as f32;
+
+        self.simulate(world, dt, &model);
+        self.update_bounds();
+
+        let view = match world.current_camera() {
+            Some(cam) => cam.borrow().v,
+            None => return,
+        };
+
+        if let Some(material) = self.render_material.clone() {
+            self.update_ramp(&material);
+            self.apply_render(&material, &view);
+        }
+    }
+}
@@ -1,7 +1,28 @@
 mod skybox;
+mod procedural_sky;
 mod shadow_pass;
+mod shadow_atlas;
+mod shadow_vsm;
+mod screen_pass;
+mod ssao_pass;
+mod curve;
+mod particle_emitter;
+mod particle_system;
+mod gpu_particle_system;
+mod animation_clip;
+mod animator;
 mod first_person_camera;
 
 pub use self::skybox::SkyBox;
-pub use self::shadow_pass::ShadowPass;
+pub use self::procedural_sky::ProceduralSky;
+pub use self::shadow_pass::{ShadowFilter, ShadowPass};
+pub use self::ssao_pass::SsaoPass;
+pub use self::curve::{Curve, CurveError};
+pub use self::particle_emitter::{EmitterMesh, EmitterShape, ParticleEmitter, SimulationSpace,
+                                 TextureSheet};
+pub use self::particle_system::{ParticleRenderMode, ParticleSystem};
+pub use self::gpu_particle_system::GpuParticleSystem;
+pub use self::animation_clip::{AnimationClip, ClipError, FloatProperty, Track, TrackProperty};
+pub use self::animator::{AnimationLayer, AnimationState, Animator, Condition, LayerBlend,
+                         Parameter, Transition};
 pub use self::first_person_camera::FirstPersonCamera;
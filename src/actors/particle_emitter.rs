@@ -0,0 +1,265 @@
+use engine::MeshData;
+
+use super::curve::Curve;
+
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use math::*;
+
+/// Triangles of a mesh with their accumulated areas, to emit from random points on its surface
+#[derive(Debug)]
+pub struct EmitterMesh {
+    triangles: Vec<[Vector3f; 3]>,
+    /// Accumulated area up to and including each triangle
+    areas: Vec<f32>,
+}
+
+impl EmitterMesh {
+    pub fn new(data: &MeshData) -> EmitterMesh {
+        let vertex = |i: u16| {
+            let i = i as usize * 3;
+            Vector3::new(data.vertices[i], data.vertices[i + 1], data.vertices[i + 2])
+        };
+
+        let mut triangles = Vec::new();
+        let mut areas = Vec::new();
+        let mut total = 0.0;
+
+        for tri in data.indices.chunks(3).filter(|tri| tri.len() == 3) {
+            let (a, b, c) = (vertex(tri[0]), vertex(tri[1]), vertex(tri[2]));
+
+            total += (b - a).cross(c - a).magnitude() * 0.5;
+            triangles.push([a, b, c]);
+            areas.push(total);
+        }
+
+        EmitterMesh { triangles, areas }
+    }
+
+    /// Random point on the surface and the normal of its triangle
+    fn sample(&self, rng: &mut Random) -> (Vector3f, Vector3f) {
+        let total = match self.areas.last() {
+            Some(&total) => total,
+            None => return (Vector3f::zero(), Vector3::unit_y()),
+        };
+
+        let r = rng.next() * total;
+        let i = self.areas
+            .iter()
+            .position(|&a| a >= r)
+            .unwrap_or(self.areas.len() - 1);
+        let (a, b, c) = (self.triangles[i][0], self.triangles[i][1], self.triangles[i][2]);
+
+        let (mut u, mut v) = (rng.next(), rng.next());
+        if u + v > 1.0 {
+            u = 1.0 - u;
+            v = 1.0 - v;
+        }
+
+        let normal = (b - a).cross(c - a);
+        let normal = if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            Vector3::unit_y()
+        };
+
+        (a + (b - a) * u + (c - a) * v, normal)
+    }
+}
+
+/// Shape from which particles are emitted, in the space of the game object
+#[derive(Clone, Debug)]
+pub enum EmitterShape {
+    /// From the origin, in all directions
+    Point,
+    /// From inside a sphere, away from its center
+    Sphere { radius: f32 },
+    /// From a disc of `radius` on the xz plane, within `angle` of the +y axis
+    Cone { angle: Deg<f32>, radius: f32 },
+    /// From the triangles of a mesh, along their normals
+    MeshSurface(Rc<EmitterMesh>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SimulationSpace {
+    /// Particles move with the game object
+    Local,
+    /// Particles stay where they were emitted when the game object moves
+    World,
+}
+
+/// Animation of the particle texture as a grid of frames, row by row from the top left
+#[derive(Copy, Clone, Debug)]
+pub struct TextureSheet {
+    columns: u32,
+    rows: u32,
+    /// How many times the frames are played over the lifetime of a particle
+    pub cycles: f32,
+}
+
+impl TextureSheet {
+    /// Sheet of `columns` x `rows` frames played once over the lifetime
+    ///
+    /// Panics if `columns` or `rows` is 0.
+    pub fn new(columns: u32, rows: u32) -> TextureSheet {
+        assert!(
+            columns > 0 && rows > 0,
+            "Texture sheet of {}x{} frames is empty",
+            columns,
+            rows
+        );
+
+        TextureSheet {
+            columns,
+            rows,
+            cycles: 1.0,
+        }
+    }
+
+    pub fn columns(&self) -> u32 {
+        self.columns
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    /// (u, v) offset and size of the frame at normalized age `t`
+    pub fn frame_rect(&self, t: f32) -> (Vector2f, Vector2f) {
+        let frames = self.columns * self.rows;
+        let frame = (t * self.cycles * frames as f32) as u32 % frames;
+
+        let size = Vector2::new(1.0 / self.columns as f32, 1.0 / self.rows as f32);
+        let col = frame % self.columns;
+        // Loaded images are flipped, so the first row is at the top of the uv space
+        let row = self.rows - 1 - frame / self.columns;
+
+        (Vector2::new(col as f32 * size.x, row as f32 * size.y), size)
+    }
+}
+
+/// Description of how particles are emitted and evolve,
+/// shared by `ParticleSystem` and `GpuParticleSystem`
+#[derive(Clone, Debug)]
+pub struct ParticleEmitter {
+    pub shape: EmitterShape,
+    pub space: SimulationSpace,
+
+    /// Particles emitted per second
+    pub rate: f32,
+    /// Particles emitted at once when the emitter starts
+    pub burst: u32,
+    /// Stop emitting after this many seconds, or loop forever
+    pub duration: Option<f32>,
+    pub max_particles: usize,
+
+    /// Lifetime in seconds, random between min and max
+    pub lifetime: (f32, f32),
+    /// Initial speed along the emission direction, random between min and max
+    pub speed: (f32, f32),
+    /// Acceleration in world space
+    pub gravity: Vector3f,
+    /// Fraction of the velocity lost per second
+    pub drag: f32,
+
+    /// rgba over the normalized lifetime
+    pub color: Curve<Vector4<f32>>,
+    /// Size in world units over the normalized lifetime
+    pub size: Curve<f32>,
+    pub texture_sheet: Option<TextureSheet>,
+}
+
+impl Default for ParticleEmitter {
+    fn default() -> ParticleEmitter {
+        ParticleEmitter {
+            shape: EmitterShape::Cone {
+                angle: Deg(25.0),
+                radius: 0.1,
+            },
+            space: SimulationSpace::World,
+            rate: 20.0,
+            burst: 0,
+            duration: None,
+            max_particles: 1000,
+            lifetime: (1.5, 2.5),
+            speed: (1.0, 2.0),
+            gravity: Vector3::new(0.0, -1.0, 0.0),
+            drag: 0.0,
+            color: Curve::linear(
+                Vector4::new(1.0, 1.0, 1.0, 1.0),
+                Vector4::new(1.0, 1.0, 1.0, 0.0),
+            ),
+            size: Curve::linear(0.2, 0.5),
+            texture_sheet: None,
+        }
+    }
+}
+
+impl ParticleEmitter {
+    /// Initial position and velocity of a new particle, in the space of the game object
+    pub(crate) fn spawn(&self, rng: &mut Random) -> (Vector3f, Vector3f) {
+        let (position, direction) = match self.shape {
+            EmitterShape::Point => (Vector3f::zero(), rng.unit_vector()),
+            EmitterShape::Sphere { radius } => {
+                let dir = rng.unit_vector();
+                (dir * radius * rng.next().cbrt(), dir)
+            }
+            EmitterShape::Cone { angle, radius } => {
+                let cos_angle = Rad::from(angle).0.cos();
+                let cos_t = 1.0 - rng.next() * (1.0 - cos_angle);
+                let sin_t = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+                let phi = rng.next() * 2.0 * PI;
+
+                let r = radius * rng.next().sqrt();
+                let disc_phi = rng.next() * 2.0 * PI;
+
+                (
+                    Vector3::new(r * disc_phi.cos(), 0.0, r * disc_phi.sin()),
+                    Vector3::new(sin_t * phi.cos(), cos_t, sin_t * phi.sin()),
+                )
+            }
+            EmitterShape::MeshSurface(ref mesh) => mesh.sample(rng),
+        };
+
+        let speed = rng.range(self.speed.0, self.speed.1);
+        (position, direction * speed)
+    }
+
+    pub(crate) fn random_lifetime(&self, rng: &mut Random) -> f32 {
+        rng.range(self.lifetime.0, self.lifetime.1).max(0.001)
+    }
+}
+
+/// Small xorshift generator, particles do not need a good one
+#[derive(Clone, Debug)]
+pub(crate) struct Random(u32);
+
+impl Random {
+    pub fn new(seed: u32) -> Random {
+        Random(seed.max(1))
+    }
+
+    /// Uniform in [0, 1)
+    pub fn next(&mut self) -> f32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+
+        (x >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next()
+    }
+
+    pub fn unit_vector(&mut self) -> Vector3f {
+        let z = self.next() * 2.0 - 1.0;
+        let phi = self.next() * 2.0 * PI;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+
+        Vector3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+}
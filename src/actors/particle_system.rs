@@ -0,0 +1,313 @@
+use world::{Actor, World};
+use engine::{Asset, BlendState, Component, CullMode, GameObject, Material, Mesh, MeshBuffer,
+             MeshData, RenderQueue, Texture};
+
+use super::particle_emitter::{ParticleEmitter, Random, SimulationSpace};
+
+use std::cmp::Ordering;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use math::*;
+
+/// Each particle is a quad of 4 vertices indexed by u16
+const MAX_CPU_PARTICLES: usize = 16384;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParticleRenderMode {
+    /// Quads facing the camera
+    Billboard,
+    /// Quads facing the camera, stretched backwards along the velocity
+    Stretched {
+        /// Stretch in sizes of the particle
+        length_scale: f32,
+        /// Stretch in seconds of movement
+        speed_scale: f32,
+    },
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Particle {
+    /// In the simulation space of the emitter
+    position: Vector3f,
+    velocity: Vector3f,
+    age: f32,
+    lifetime: f32,
+}
+
+/// Particles simulated on the cpu, rendered as quads in the transparent queue
+///
+/// The quads are rebuilt every frame into a dynamic mesh of the game object.
+#[derive(Actor)]
+pub struct ParticleSystem {
+    pub emitter: ParticleEmitter,
+    pub render_mode: ParticleRenderMode,
+    /// Multiplied by the particle color, particles are soft discs without texture
+    pub texture: Option<Rc<Texture>>,
+    /// Read when the system starts
+    pub blend: BlendState,
+    /// Particles are drawn back to front from the camera
+    pub sort_by_distance: bool,
+    /// Existing particles live on when emitting stops
+    pub emitting: bool,
+
+    particles: Vec<Particle>,
+    time: f32,
+    emit_accumulator: f32,
+    rng: Random,
+
+    mesh_buffer: Option<Rc<MeshBuffer>>,
+    mesh: Option<Arc<Component>>,
+    material: Option<Rc<Material>>,
+}
+
+impl ParticleSystem {
+    pub fn new(emitter: ParticleEmitter) -> ParticleSystem {
+        ParticleSystem {
+            emitter,
+            render_mode: ParticleRenderMode::Billboard,
+            texture: None,
+            blend: BlendState::alpha(),
+            sort_by_distance: true,
+            emitting: true,
+
+            particles: Vec::new(),
+            time: 0.0,
+            emit_accumulator: 0.0,
+            rng: Random::new(0x9e37_79b9),
+
+            mesh_buffer: None,
+            mesh: None,
+            material: None,
+        }
+    }
+
+    fn max_particles(&self) -> usize {
+        self.emitter.max_particles.min(MAX_CPU_PARTICLES)
+    }
+
+    fn emit(&mut self, count: usize, model: &Matrix4f) {
+        let count = count.min(self.max_particles().saturating_sub(self.particles.len()));
+
+        for _ in 0..count {
+            let (mut position, mut velocity) = self.emitter.spawn(&mut self.rng);
+            let lifetime = self.emitter.random_lifetime(&mut self.rng);
+
+            if self.emitter.space == SimulationSpace::World {
+                position = model.transform_point(Point3::from_vec(position)).to_vec();
+                velocity = model.transform_vector(velocity);
+            }
+
+            self.particles.push(Particle {
+                position,
+                velocity,
+                age: 0.0,
+                lifetime,
+            });
+        }
+    }
+
+    fn simulate(&mut self, dt: f32, model: &Matrix4f) {
+        if self.time == 0.0 {
+            let burst = self.emitter.burst as usize;
+            self.emit(burst, model);
+        }
+
+        self.time += dt;
+
+        let active = match self.emitter.duration {
+            Some(duration) => self.time <= duration,
+            None => true,
+        };
+
+        if self.emitting && active {
+            self.emit_accumulator += self.emitter.rate * dt;
+            let count = self.emit_accumulator.floor();
+            self.emit_accumulator -= count;
+
+            self.emit(count as usize, model);
+        }
+
+        let gravity = match self.emitter.space {
+            SimulationSpace::World => self.emitter.gravity,
+            SimulationSpace::Local => model
+                .inverse_transform()
+                .map(|inv| inv.transform_vector(self.emitter.gravity))
+                .unwrap_or(self.emitter.gravity),
+        };
+        let drag = (1.0 - self.emitter.drag * dt).max(0.0);
+
+        for p in self.particles.iter_mut() {
+            p.age += dt;
+            p.velocity = (p.velocity + gravity * dt) * drag;
+            p.position += p.velocity * dt;
+        }
+
+        self.particles.retain(|p| p.age < p.lifetime);
+    }
+
+    fn apply_texture(&self, material: &Material) {
+        material.set("uHasTexture", self.texture.is_some());
+        if let Some(ref texture) = self.texture {
+            material.set("uTexture", texture.clone());
+        }
+    }
+
+    /// Quads of all particles in the space of the game object
+    fn build_mesh_data(&self, eye: Vector3f, view: &Matrix4f, model: &Matrix4f) -> MeshData {
+        let inv_model = model.inverse_transform().unwrap_or(Matrix4::identity());
+
+        // Positions and velocities in world space
+        let world_particles: Vec<(Vector3f, Vector3f)> = self.particles
+            .iter()
+            .map(|p| match self.emitter.space {
+                SimulationSpace::World => (p.position, p.velocity),
+                SimulationSpace::Local => (
+                    model.transform_point(Point3::from_vec(p.position)).to_vec(),
+                    model.transform_vector(p.velocity),
+                ),
+            })
+            .collect();
+
+        let mut order: Vec<usize> = (0..self.particles.len()).collect();
+        if self.sort_by_distance {
+            let dist: Vec<f32> = world_particles
+                .iter()
+                .map(|&(pos, _)| (pos - eye).magnitude2())
+                .collect();
+
+            order.sort_by(|&a, &b| dist[b].partial_cmp(&dist[a]).unwrap_or(Ordering::Equal));
+        }
+
+        let cam_right = Vector3::new(view.x.x, view.y.x, view.z.x);
+        let cam_up = Vector3::new(view.x.y, view.y.y, view.z.y);
+
+        let n = order.len();
+        let mut vertices = Vec::with_capacity(n * 12);
+        let mut uvs = Vec::with_capacity(n * 8);
+        let mut colors = Vec::with_capacity(n * 16);
+        let mut indices = Vec::with_capacity(n * 6);
+
+        for (i, &pi) in order.iter().enumerate() {
+            let p = &self.particles[pi];
+            let (center, velocity) = world_particles[pi];
+
+            let t = p.age / p.lifetime;
+            let half = self.emitter.size.evaluate(t) * 0.5;
+            let color = self.emitter.color.evaluate(t);
+
+            // (side, back, front) axes of the quad in world space
+            let (side, back, front) = match self.render_mode {
+                ParticleRenderMode::Billboard => (cam_right * half, -cam_up * half, cam_up * half),
+                ParticleRenderMode::Stretched {
+                    length_scale,
+                    speed_scale,
+                } => {
+                    let speed = velocity.magnitude();
+                    let axis = if speed > 0.0001 { velocity / speed } else { cam_up };
+
+                    let to_eye = eye - center;
+                    let side = axis.cross(to_eye);
+                    let side = if side.magnitude2() > 0.0 {
+                        side.normalize()
+                    } else {
+                        cam_right
+                    };
+
+                    let length = half * length_scale + speed * speed_scale;
+                    (side * half, -axis * length, axis * half)
+                }
+            };
+
+            let corners = [
+                center - side + back,
+                center + side + back,
+                center + side + front,
+                center - side + front,
+            ];
+
+            let (uv_offset, uv_size) = match (&self.texture, &self.emitter.texture_sheet) {
+                (&Some(_), &Some(ref sheet)) => sheet.frame_rect(t),
+                _ => (Vector2::new(0.0, 0.0), Vector2::new(1.0, 1.0)),
+            };
+            let corner_uvs = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+
+            for (c, &(u, v)) in corners.iter().zip(corner_uvs.iter()) {
+                let c = inv_model.transform_point(Point3::from_vec(*c));
+                vertices.extend_from_slice(&[c.x, c.y, c.z]);
+                uvs.extend_from_slice(&[uv_offset.x + u * uv_size.x, uv_offset.y + v * uv_size.y]);
+                colors.extend_from_slice(&[color.x, color.y, color.z, color.w]);
+            }
+
+            let base = (i * 4) as u16;
+            indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+
+        MeshData {
+            vertices,
+            uvs: Some(uvs),
+            normals: None,
+            tangents: None,
+            bitangents: None,
+            colors: Some(colors),
+            joints: None,
+            weights: None,
+            morph_targets: Vec::new(),
+            indices,
+        }
+    }
+}
+
+impl Actor for ParticleSystem {
+    fn start(&mut self, go: &mut GameObject, world: &mut World) {
+        let db = &mut world.asset_system();
+
+        let mut material = Material::new(db.new_program("unrust/particle"));
+        material.render_queue = RenderQueue::Transparent;
+        material.states.blend = Some(self.blend);
+        material.states.cull = Some(CullMode::Off);
+        let material = Rc::new(material);
+
+        let mesh_buffer = MeshBuffer::new(MeshData {
+            uvs: Some(Vec::new()),
+            colors: Some(Vec::new()),
+            ..Default::default()
+        });
+
+        let mut mesh = Mesh::new();
+        mesh.add_surface(mesh_buffer.clone(), material.clone());
+
+        self.mesh = Some(go.add_component(mesh));
+        self.mesh_buffer = Some(mesh_buffer);
+        self.material = Some(material);
+    }
+
+    fn update(&mut self, go: &mut GameObject, world: &mut World) {
+        let model = go.transform.as_global_matrix();
+        let dt = world.delta_time() as f32;
+
+        self.simulate(dt, &model);
+
+        let (eye, view) = match world.current_camera() {
+            Some(cam) => {
+                let cam = cam.borrow();
+                (cam.eye(), cam.v)
+            }
+            None => return,
+        };
+
+        if let Some(ref material) = self.material {
+            self.apply_texture(material);
+        }
+
+        let data = self.build_mesh_data(eye, &view, &model);
+
+        if let Some(ref mesh_buffer) = self.mesh_buffer {
+            mesh_buffer.update_mesh_data(data);
+        }
+
+        if let Some(ref mesh) = self.mesh {
+            mesh.try_as::<Mesh>().unwrap().borrow().invalidate_bounds();
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use world::{Actor, Processor, World};
+use engine::{Asset, GameObject, Light, Material, Mesh, RenderQueue, Resource, Texture,
+             TextureAsset, TextureImage};
+
+use image::{Rgb, RgbImage};
+use std::rc::Rc;
+use std::f32::consts::PI;
+
+use math::*;
+
+/// Analytic daylight sky (Preetham) lit by the main directional light
+///
+/// Rendered in place of `SkyBox`. When `time_of_day` is set, the sun position is computed
+/// from it and written back to the main light, otherwise the sun follows the light.
+#[derive(Component)]
+pub struct ProceduralSky {
+    /// Atmosphere haziness, from 2 for a clear sky to 10 for a hazy one
+    pub turbidity: f32,
+    /// Angular diameter of the sun disk
+    pub sun_size: Deg<f32>,
+    pub sun_intensity: f32,
+    pub exposure: f32,
+
+    /// Hours from 0 to 24, the sun rises at 6 and sets at 18
+    pub time_of_day: Option<f32>,
+    /// Tilt of the sun path from the zenith, towards +z
+    pub latitude: Deg<f32>,
+
+    pub fog_color: Vector3f,
+    /// Opacity of the fog at the horizon, 0 disables the fog
+    pub fog_density: f32,
+    /// How fast the fog thins out above the horizon
+    pub fog_height_falloff: f32,
+
+    sun_direction: Vector3f,
+    material: Option<Rc<Material>>,
+}
+
+impl ProceduralSky {
+    /// Normalized direction to the sun
+    pub fn sun_direction(&self) -> Vector3f {
+        self.sun_direction
+    }
+
+    fn time_of_day_direction(&self, hours: f32) -> Vector3f {
+        let a = (hours - 6.0) / 12.0 * PI;
+        let tilt = Rad::from(self.latitude).0;
+
+        Vector3::new(a.cos(), a.sin() * tilt.cos(), a.sin() * tilt.sin()).normalize()
+    }
+
+    fn apply(&self, material: &Material) {
+        let model = SkyModel::new(self.turbidity, self.sun_direction);
+        for (i, p) in model.perez.iter().enumerate() {
+            material.set(format!("uPerez[{}]", i), *p);
+        }
+        material.set("uSkyZenith", model.zenith);
+
+        material.set("uSunDirection", self.sun_direction);
+        material.set("uSunRadius", Rad::from(self.sun_size).0 * 0.5);
+        material.set("uSunIntensity", self.sun_intensity);
+        material.set("uExposure", self.exposure);
+        material.set("uFogColor", self.fog_color);
+        material.set("uFogDensity", self.fog_density);
+        material.set("uFogHeightFalloff", self.fog_height_falloff);
+    }
+
+    /// Bakes the current sky into a cubemap, e.g. for reflections or ambient lighting
+    ///
+    /// The sky is evaluated on the cpu, so it should not be called every frame.
+    pub fn bake_cubemap(&self, size: u32) -> Rc<Texture> {
+        // Face order and orientation of GL_TEXTURE_CUBE_MAP_POSITIVE_X ..
+        let faces: [fn(f32, f32) -> Vector3f; 6] = [
+            |u, v| Vector3::new(1.0, -v, -u),
+            |u, v| Vector3::new(-1.0, -v, u),
+            |u, v| Vector3::new(u, 1.0, v),
+            |u, v| Vector3::new(u, -1.0, -v),
+            |u, v| Vector3::new(u, -v, 1.0),
+            |u, v| Vector3::new(-u, -v, -1.0),
+        ];
+
+        let model = SkyModel::new(self.turbidity, self.sun_direction);
+
+        let bake = |face: fn(f32, f32) -> Vector3f| -> Resource<TextureImage> {
+            let img = RgbImage::from_fn(size, size, |x, y| {
+                let u = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                // Loaded images are flipped, keep the same row order
+                let v = 1.0 - (y as f32 + 0.5) / size as f32 * 2.0;
+
+                let c = self.evaluate(&model, face(u, v).normalize());
+                let to_u8 = |f: f32| (f.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+
+                Rgb([to_u8(c.x), to_u8(c.y), to_u8(c.z)])
+            });
+
+            Resource::new(TextureImage::Rgb(img))
+        };
+
+        Texture::new_from_resource(TextureAsset::Cube([
+            bake(faces[0]),
+            bake(faces[1]),
+            bake(faces[2]),
+            bake(faces[3]),
+            bake(faces[4]),
+            bake(faces[5]),
+        ]))
+    }
+
+    /// Same as main() in procedural_sky_fs.glsl
+    fn evaluate(&self, model: &SkyModel, dir: Vector3f) -> Vector3f {
+        let sun = self.sun_direction;
+
+        let cos_theta = dir.y.max(0.01);
+        let cos_gamma = dir.dot(sun);
+
+        let rgb = yxy_to_rgb(model.yxy(cos_theta, cos_gamma));
+        let mut color = Vector3::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0));
+
+        // Fades out from the edge of the disk to 1.2 times its radius
+        let sun_radius = Rad::from(self.sun_size).0 * 0.5;
+        let disk = smoothstep((sun_radius * 1.2).cos(), sun_radius.cos(), cos_gamma);
+        let sun_color = Vector3::new(1.0, 0.4, 0.1).lerp(
+            Vector3::new(1.0, 0.95, 0.85),
+            smoothstep(0.0, 0.3, sun.y),
+        );
+        color += sun_color * disk * self.sun_intensity;
+
+        color *= smoothstep(-0.1, 0.05, sun.y);
+
+        let tonemap = |c: f32| (1.0 - (-c * self.exposure).exp()).powf(1.0 / 2.2);
+        let color = Vector3::new(tonemap(color.x), tonemap(color.y), tonemap(color.z));
+
+        let fog = (self.fog_density * (-dir.y.max(0.0) * self.fog_height_falloff).exp())
+            .max(0.0)
+            .min(1.0);
+
+        color.lerp(self.fog_color, fog)
+    }
+}
+
+/// Preetham, Shirley, Smits: "A Practical Analytic Model for Daylight"
+///
+/// The coefficients only depend on the sun and the turbidity, so they are computed once
+/// here and passed to procedural_sky_fs.glsl, which only evaluates the distribution.
+struct SkyModel {
+    /// Perez distribution coefficients A to E, of Y, x and y
+    perez: [Vector3f; 5],
+    /// Zenith Yxy divided by the distribution at the zenith
+    zenith: Vector3f,
+}
+
+impl SkyModel {
+    fn new(t: f32, sun_direction: Vector3f) -> SkyModel {
+        let perez = [
+            Vector3::new(0.1787 * t - 1.4630, -0.0193 * t - 0.2592, -0.0167 * t - 0.2608),
+            Vector3::new(-0.3554 * t + 0.4275, -0.0665 * t + 0.0008, -0.0950 * t + 0.0092),
+            Vector3::new(-0.0227 * t + 5.3251, -0.0004 * t + 0.2125, -0.0079 * t + 0.2102),
+            Vector3::new(0.1206 * t - 2.5771, -0.0641 * t - 0.8989, -0.0441 * t - 1.6537),
+            Vector3::new(-0.0670 * t + 0.3703, -0.0033 * t + 0.0452, -0.0109 * t + 0.0529),
+        ];
+
+        // The model is only valid for a sun above the horizon
+        let theta_s = sun_direction.y.max(0.0).min(1.0).acos();
+        let theta_s2 = theta_s * theta_s;
+        let theta_s3 = theta_s2 * theta_s;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let yz = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let xz = t * t * (0.00166 * theta_s3 - 0.00375 * theta_s2 + 0.00209 * theta_s)
+            + t * (-0.02903 * theta_s3 + 0.06377 * theta_s2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta_s3 - 0.21196 * theta_s2 + 0.06052 * theta_s + 0.25886);
+
+        let yz_chroma = t * t * (0.00275 * theta_s3 - 0.00610 * theta_s2 + 0.00317 * theta_s)
+            + t * (-0.04214 * theta_s3 + 0.08970 * theta_s2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta_s3 - 0.26756 * theta_s2 + 0.06670 * theta_s + 0.26688);
+
+        let mut model = SkyModel {
+            perez,
+            zenith: Vector3::new(1.0, 1.0, 1.0),
+        };
+
+        let f0 = model.perez_at(1.0, theta_s.cos());
+        model.zenith = Vector3::new(yz / f0.x, xz / f0.y, yz_chroma / f0.z);
+        model
+    }
+
+    /// Perez distribution in a direction of view zenith angle theta and sun angle gamma,
+    /// same as Perez() in procedural_sky_fs.glsl
+    fn perez_at(&self, cos_theta: f32, cos_gamma: f32) -> Vector3f {
+        let p = &self.perez;
+        let gamma = cos_gamma.max(-1.0).min(1.0).acos();
+
+        let f = |i: usize| {
+            (1.0 + p[0][i] * (p[1][i] / cos_theta).exp())
+                * (1.0 + p[2][i] * (p[3][i] * gamma).exp() + p[4][i] * cos_gamma * cos_gamma)
+        };
+        Vector3::new(f(0), f(1), f(2))
+    }
+
+    fn yxy(&self, cos_theta: f32, cos_gamma: f32) -> Vector3f {
+        let f = self.perez_at(cos_theta, cos_gamma);
+        Vector3::new(f.x * self.zenith.x, f.y * self.zenith.y, f.z * self.zenith.z)
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).max(0.0).min(1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn yxy_to_rgb(yxy: Vector3f) -> Vector3f {
+    let y = yxy.x;
+    let x = yxy.y / yxy.z * y;
+    let z = (1.0 - yxy.y - yxy.z) / yxy.z * y;
+
+    Vector3::new(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+impl Actor for ProceduralSky {
+    fn start(&mut self, go: &mut GameObject, world: &mut World) {
+        let db = &mut world.asset_system();
+
+        let mut material = Material::new(db.new_program("unrust/procedural_sky"));
+        material.render_queue = RenderQueue::Skybox;
+        let material = Rc::new(material);
+
+        let mut mesh = Mesh::new();
+        mesh.add_surface(db.new_mesh_buffer("skybox"), material.clone());
+        go.add_component(mesh);
+
+        self.material = Some(material);
+    }
+
+    fn update(&mut self, _go: &mut GameObject, world: &mut World) {
+        if let Some(main_light) = world.engine().find_main_light() {
+            let light = main_light.try_as::<Light>().unwrap();
+            let mut light = light.borrow_mut();
+
+            if let Some(l) = light.directional_mut() {
+                match self.time_of_day {
+                    Some(hours) => {
+                        self.sun_direction = self.time_of_day_direction(hours);
+                        l.direction = -self.sun_direction;
+                    }
+                    // The light direction points away from the sun
+                    None => self.sun_direction = -l.world_space_direction.normalize(),
+                }
+            }
+        } else if let Some(hours) = self.time_of_day {
+            self.sun_direction = self.time_of_day_direction(hours);
+        }
+
+        if let Some(ref material) = self.material {
+            self.apply(material);
+        }
+    }
+}
+
+impl Processor for ProceduralSky {
+    fn new() -> ProceduralSky {
+        ProceduralSky {
+            turbidity: 3.0,
+            sun_size: Deg(0.53),
+            sun_intensity: 20.0,
+            exposure: 0.1,
+            time_of_day: None,
+            latitude: Deg(30.0),
+            fog_color: Vector3::new(0.7, 0.75, 0.8),
+            fog_density: 0.0,
+            fog_height_falloff: 8.0,
+            sun_direction: Vector3::new(0.0, 1.0, 0.0),
+            material: None,
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use world::{Handle, World};
+use engine::{Camera, ClearOption, GameObject, Material, Mesh, RenderTexture};
+
+use std::rc::Rc;
+
+/// Layer of the screen quad, so no other camera renders it
+const SCREEN_PASS_LAYER: u32 = 1 << 31;
+
+/// Draws a screen quad with a material into a render texture, for image effects
+pub struct ScreenPass {
+    quad: Handle<GameObject>,
+    camera: Camera,
+}
+
+impl ScreenPass {
+    pub fn new() -> ScreenPass {
+        ScreenPass {
+            quad: GameObject::empty(),
+            camera: Camera::new(),
+        }
+    }
+
+    pub fn start(&mut self, world: &mut World) {
+        self.quad = world.new_game_object();
+
+        let db = world.asset_system();
+        let mut go = self.quad.borrow_mut();
+        let mut mesh = Mesh::new();
+
+        // Only drawn with the material given to render
+        mesh.add_surface(
+            db.new_mesh_buffer("screen_quad"),
+            Material::new(db.new_program("unrust/shadow_display")),
+        );
+        go.add_component(mesh);
+        go.layer = SCREEN_PASS_LAYER;
+        go.active = false;
+
+        self.camera.enable_frustum_culling = false;
+        self.camera.culling_mask = SCREEN_PASS_LAYER;
+    }
+
+    /// Fill `target` of `size` pixels with `material`
+    pub fn render(
+        &mut self,
+        world: &mut World,
+        material: &Rc<Material>,
+        target: &Rc<RenderTexture>,
+        size: (u32, u32),
+    ) {
+        self.camera.render_texture = Some(target.clone());
+        self.camera.rect = Some(((0, 0), size));
+
+        self.quad.borrow_mut().active = true;
+        world.engine_mut().render_pass_with_material(
+            &self.camera,
+            Some(material),
+            ClearOption::default(),
+        );
+        self.quad.borrow_mut().active = false;
+    }
+}
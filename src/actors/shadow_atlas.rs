@@ -0,0 +1,214 @@
+use world::World;
+use engine::{Camera, ClearOption, Component, Light, Material, MaterialParamMap, RenderTexture,
+             ShadowSettings, TextureAttachment};
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+use math::*;
+
+const ATLAS_SIZE: u32 = 2048;
+const TILE_SIZE: u32 = 512;
+const MAX_FACES: usize = 16;
+const MAX_LIGHTS: usize = 4;
+const SHADOW_ZNEAR: f32 = 0.05;
+
+/// Cube face directions and up vectors, ordered as `CubeFace` in shadow_utils.glsl
+const CUBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+/// Shadow maps of point and spot lights, packed as tiles of a single depth texture
+///
+/// A spot light uses one tile and a point light uses 6 tiles, one per cube face.
+/// Lights which do not fit in the atlas anymore are not shadowed.
+pub struct ShadowAtlas {
+    rt: Rc<RenderTexture>,
+    material: Option<Rc<Material>>,
+    material_params: MaterialParamMap,
+    num_faces: usize,
+}
+
+fn perspective(fovy: Deg<f32>, far: f32) -> Matrix4f {
+    PerspectiveFov {
+        fovy: fovy.into(),
+        aspect: 1.0,
+        near: SHADOW_ZNEAR,
+        far: far.max(SHADOW_ZNEAR * 2.0),
+    }.into()
+}
+
+/// The light matrices of all the faces of a light, empty if it does not cast shadows
+fn light_matrices(light: &Light) -> Vec<Matrix4f> {
+    if !light.casts_shadows() {
+        return Vec::new();
+    }
+
+    if let Some(point) = light.point() {
+        let eye = Point3::from_vec(point.world_space_position);
+        let proj = perspective(Deg(90.0), point.range());
+
+        return CUBE_FACES
+            .iter()
+            .map(|&(dir, up)| {
+                let view = Matrix4::look_at(eye, eye + Vector3::from(dir), Vector3::from(up));
+                proj * view
+            })
+            .collect();
+    }
+
+    if let Some(spot) = light.spot() {
+        let eye = Point3::from_vec(spot.world_space_position);
+        let dir = spot.world_space_direction;
+        let mut up = Vector3::unit_y();
+
+        if up.dot(dir).abs() > 0.9999 {
+            up = Vector3f::unit_z();
+        }
+
+        let fovy = Deg((spot.outer_cutoff.0 * 2.0).min(170.0));
+        let view = Matrix4::look_at(eye, eye + dir, up);
+
+        return vec![perspective(fovy, spot.range()) * view];
+    }
+
+    Vec::new()
+}
+
+/// Set the `ShadowSettings` glsl struct named `name`
+pub fn insert_shadow_settings(params: &mut MaterialParamMap, name: &str, s: &ShadowSettings) {
+    params.insert((name.to_owned() + ".depth_bias").into(), s.depth_bias.into());
+    params.insert((name.to_owned() + ".normal_bias").into(), s.normal_bias.into());
+    params.insert((name.to_owned() + ".strength").into(), s.strength.into());
+}
+
+impl ShadowAtlas {
+    pub fn new() -> ShadowAtlas {
+        ShadowAtlas {
+            rt: Rc::new(RenderTexture::new(
+                ATLAS_SIZE,
+                ATLAS_SIZE,
+                TextureAttachment::Depth,
+            )),
+            material: None,
+            material_params: MaterialParamMap::default(),
+            num_faces: 0,
+        }
+    }
+
+    pub fn start(&mut self, world: &mut World) {
+        let db = world.asset_system();
+
+        let material = Material::new(db.new_program("unrust/shadow"));
+        material.enable_keyword("LOCAL_SHADOW");
+        self.material = Some(Rc::new(material));
+    }
+
+    fn tile_viewport(face: usize) -> ((i32, i32), (u32, u32)) {
+        let per_row = (ATLAS_SIZE / TILE_SIZE) as usize;
+        let x = (face % per_row) as u32 * TILE_SIZE;
+        let y = (face / per_row) as u32 * TILE_SIZE;
+
+        ((x as i32, y as i32), (TILE_SIZE, TILE_SIZE))
+    }
+
+    /// Render the shadow maps of the lights bound to `uPointLights`
+    pub fn render(&mut self, world: &mut World, light_cam: &mut Camera) {
+        let material = match self.material {
+            Some(ref m) => m.clone(),
+            None => return,
+        };
+
+        let lights: Vec<Arc<Component>> = world.engine().find_point_lights();
+        let mut face = 0;
+
+        self.material_params.clear();
+
+        for i in 0..MAX_LIGHTS {
+            let name = format!("uLocalShadows[{}]", i);
+
+            let (matrices, mask, settings) = match lights.get(i) {
+                Some(com) => {
+                    let light = com.try_as::<Light>().unwrap().borrow();
+                    (
+                        light_matrices(&light),
+                        light.culling_mask(),
+                        light.shadow_settings(),
+                    )
+                }
+                None => (Vec::new(), 0, ShadowSettings::default()),
+            };
+
+            let num_faces = if face + matrices.len() <= MAX_FACES {
+                matrices.len()
+            } else {
+                0
+            };
+
+            self.material_params
+                .insert((name.clone() + ".first_face").into(), (face as i32).into());
+            self.material_params
+                .insert((name.clone() + ".num_faces").into(), (num_faces as i32).into());
+            insert_shadow_settings(&mut self.material_params, &(name + ".settings"), &settings);
+
+            // Only objects lit by the light cast shadows
+            light_cam.culling_mask = mask;
+            light_cam.render_texture = Some(self.rt.clone());
+
+            for m in matrices.into_iter().take(num_faces) {
+                let viewport = Self::tile_viewport(face);
+                let face_name = format!("uLocalShadowFaces[{}]", face);
+
+                light_cam.rect = Some(viewport);
+                material.set("uShadowMatrix", m);
+
+                // The clear is scissored to the viewport, so each face clears its own tile
+                world.engine_mut().render_pass_with_material(
+                    light_cam,
+                    Some(&material),
+                    ClearOption::default(),
+                );
+
+                let size = ATLAS_SIZE as f32;
+                self.material_params
+                    .insert((face_name.clone() + ".light_matrix").into(), m.into());
+                self.material_params.insert(
+                    (face_name + ".tile").into(),
+                    Vector4::new(
+                        (viewport.0).0 as f32 / size,
+                        (viewport.0).1 as f32 / size,
+                        TILE_SIZE as f32 / size,
+                        TILE_SIZE as f32 / size,
+                    ).into(),
+                );
+
+                face += 1;
+            }
+        }
+
+        self.num_faces = face;
+    }
+
+    /// Faces rendered in the last frame
+    pub fn num_faces(&self) -> usize {
+        self.num_faces
+    }
+
+    pub fn texture(&self) -> &Rc<RenderTexture> {
+        &self.rt
+    }
+
+    pub fn apply(&self, material: &Material) {
+        material.set("uLocalShadowTexture", self.rt.as_texture());
+        material.set(
+            "uLocalShadowTexelSize",
+            Vector2f::new(1.0 / ATLAS_SIZE as f32, 1.0 / ATLAS_SIZE as f32),
+        );
+        material.set("LocalShadowParams", self.material_params.clone());
+    }
+}
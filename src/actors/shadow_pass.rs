@@ -1,16 +1,88 @@
 use world::{Actor, Handle, World};
-use engine::{Asset, Camera, ClearOption, Component, CullMode, GameObject, Light,
+use engine::{Asset, Camera, ClearOption, Component, CullMode, DepthTest, GameObject, Light,
              Material, MaterialParamMap, Mesh, MeshBuffer, MeshData, RenderQueue, RenderTexture,
              TextureAttachment};
 use engine::mesh_util::*;
 
 use world::Processor;
 
+use super::shadow_atlas::{insert_shadow_settings, ShadowAtlas};
+use super::shadow_vsm::VarianceShadowMap;
+
+use uni_app;
+use uni_gl;
+
 use std::rc::Rc;
 use std::sync::Arc;
 
 use math::*;
 
+/// How the shadow maps are filtered when they are sampled
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShadowFilter {
+    /// A single bilinear 2x2 depth comparison done by the hardware, on desktop GL and WebGL2,
+    /// WebGL1 uses a single unfiltered comparison
+    Hardware,
+    /// Poisson disk PCF with `samples` taps (up to 16) in a `radius` in texels
+    Pcf { samples: u32, radius: f32 },
+    /// Percentage-closer soft shadows of a light of `light_size` in uv of a cascade,
+    /// with `samples` taps (up to 16) for both the blocker search and the filtering.
+    /// Local lights use a 3x3 grid PCF
+    Pcss { samples: u32, light_size: f32 },
+    /// Variance shadow maps blurred in a `blur` radius in texels (up to 8),
+    /// `light_bleeding` from 0 to 1 darkens the light leaking through overlapping occluders.
+    /// Local lights use a 3x3 grid PCF, as only their depth is rendered
+    Vsm { blur: u32, light_bleeding: f32 },
+}
+
+impl Default for ShadowFilter {
+    fn default() -> ShadowFilter {
+        ShadowFilter::Pcf {
+            samples: 16,
+            radius: 1.5,
+        }
+    }
+}
+
+impl ShadowFilter {
+    fn keyword(&self) -> &'static str {
+        match *self {
+            ShadowFilter::Hardware => "SHADOW_HARDWARE",
+            ShadowFilter::Pcf { .. } => "SHADOW_PCF",
+            ShadowFilter::Pcss { .. } => "SHADOW_PCSS",
+            ShadowFilter::Vsm { .. } => "SHADOW_VSM",
+        }
+    }
+}
+
+const SHADOW_FILTER_KEYWORDS: [&str; 4] =
+    ["SHADOW_HARDWARE", "SHADOW_PCF", "SHADOW_PCSS", "SHADOW_VSM"];
+
+const MAX_SHADOW_SAMPLES: u32 = 16;
+/// Fraction of a cascade blended with the next one
+const DEFAULT_CASCADE_BLEND: f32 = 0.1;
+const VSM_MIN_VARIANCE: f32 = 0.00002;
+
+// https://developer.download.nvidia.com/whitepapers/2008/PCSS_Integration.pdf
+const POISSON_DISK: [[f32; 2]; 16] = [
+    [-0.94201624, -0.39906216],
+    [0.94558609, -0.76890725],
+    [-0.094184101, -0.92938870],
+    [0.34495938, 0.29387760],
+    [-0.91588581, 0.45771432],
+    [-0.81544232, -0.87912464],
+    [-0.38277543, 0.27676845],
+    [0.97484398, 0.75648379],
+    [0.44323325, -0.97511554],
+    [0.53742981, -0.47373420],
+    [-0.26496911, -0.41893023],
+    [0.79197514, 0.19090188],
+    [-0.24188840, 0.99706507],
+    [-0.81409955, 0.91437590],
+    [0.19984126, 0.78641367],
+    [0.14383161, -0.14100790],
+];
+
 struct ShadowMap {
     name: String,
     rt: Rc<RenderTexture>,
@@ -33,6 +105,24 @@ pub struct ShadowPass {
     debug_mode: bool,
 
     use_scene_aabb: bool,
+
+    /// Whether the main light casts shadows this frame
+    main_light_shadows: bool,
+    local_shadows: ShadowAtlas,
+
+    pub filter: ShadowFilter,
+    vsm: VarianceShadowMap,
+    /// Whether shadow samplers are available, on desktop GL and WebGL2
+    hardware_compare: bool,
+    /// The filter local lights were reported to not support
+    reported_local_filter: Option<ShadowFilter>,
+
+    /// Fraction at the end of each cascade blended with the next one, 0 to disable
+    pub cascade_blend: f32,
+
+    /// Bounds of the shadow casters, with the bounds generation and culling mask
+    /// they were computed with
+    scene_bounds: Option<(u64, u32, Option<Aabb>)>,
 }
 
 #[repr(usize)]
@@ -145,14 +235,14 @@ struct LightMatrixContext {
 
 impl LightMatrixContext {
     fn new(
-        light_cam: &Camera,
+        scene_bound: &Aabb,
         light_com: &Arc<Component>,
         world: &World,
-    ) -> Option<LightMatrixContext> {
+    ) -> LightMatrixContext {
         let cam_borrow = world.current_camera().unwrap();
         let cam = cam_borrow.borrow();
 
-        let p = cam.perspective(world.engine().screen_size);
+        let p = cam.projection_matrix(world.engine().screen_size);
         let v = cam.v;
         let inv_pv = (p * v).inverse_transform().unwrap();
 
@@ -168,14 +258,7 @@ impl LightMatrixContext {
 
         let view = Matrix4::look_at(Point3::new(0.0, 0.0, 0.0), light_target, up);
 
-        // Compute scene bound light space aabb.
-        // Todo: it is very expensive...
-        let scene_bound = world.engine().get_bounds(&light_cam);
-        if scene_bound.is_none() {
-            return None;
-        }
-
-        let light_space_scene_aabb = scene_bound.unwrap().corners().iter().fold(
+        let light_space_scene_aabb = scene_bound.corners().iter().fold(
             Aabb::empty(),
             |mut acc, p| {
                 acc.merge_point(&view.transform_point(Point3::from_vec(*p)).to_vec());
@@ -183,13 +266,13 @@ impl LightMatrixContext {
             },
         );
 
-        Some(LightMatrixContext {
+        LightMatrixContext {
             cam_znear: cam.znear,
             cam_zfar: cam.zfar,
             inv_pv,
             view,
             light_space_scene_aabb,
-        })
+        }
     }
 }
 
@@ -210,6 +293,7 @@ fn compute_light_matrix(
     world: &mut World,
     z_range: &(f32, f32),
     use_scene_aabb: bool,
+    resolution: u32,
     debug: Option<&mut Vec<Handle<GameObject>>>,
 ) -> (Matrix4<f32>, (f32, f32)) {
     let bound_m = ctx.view * ctx.inv_pv;
@@ -255,13 +339,28 @@ fn compute_light_matrix(
             bound_m.transform_point(Point3::new(-1.0, 1.0, farz)),
         ];
 
-        // light local space aabb
-        //let mut aabb = ctx.light_space_scene_aabb;
+        // Fit a sphere around the slice in light space, unlike a box
+        // its size does not change when the camera rotates.
+        let center = corners
+            .iter()
+            .fold(Vector3f::zero(), |acc, c| acc + c.to_vec()) / 8.0;
+        let radius = corners
+            .iter()
+            .map(|c| (c.to_vec() - center).magnitude())
+            .fold(0.0, f32::max);
+        let radius = (radius * 16.0).ceil() / 16.0;
+
+        // Move the center by whole texels, such that static objects
+        // are rasterized the same way in each frame
+        let texel = 2.0 * radius / resolution as f32;
+        let center = Vector3f::new(
+            (center.x / texel).floor() * texel,
+            (center.y / texel).floor() * texel,
+            center.z,
+        );
 
         let mut aabb = Aabb::empty();
-        for c in corners.into_iter() {
-            aabb.merge_point(&c.to_vec())
-        }
+        aabb.merge_sphere(&center, radius);
 
         let max_z = ctx.light_space_scene_aabb.max.z;
 
@@ -360,6 +459,7 @@ impl ShadowMap {
         }
 
         let partition = (last_partition_z, self.partition_z);
+        let resolution = (self.viewport.1).0;
 
         let (lm, r) = match debug {
             Some(debug_gameobjects) => {
@@ -373,11 +473,19 @@ impl ShadowMap {
                     world,
                     &partition,
                     use_scene_aabb,
+                    resolution,
                     Some(debug_gameobjects),
                 )
             }
 
-            None => compute_light_matrix(&ctx, world, &partition, use_scene_aabb, None),
+            None => compute_light_matrix(
+                &ctx,
+                world,
+                &partition,
+                use_scene_aabb,
+                resolution,
+                None,
+            ),
         };
 
         self.light_matrix = lm;
@@ -430,10 +538,112 @@ impl ShadowPass {
         self.shadow_maps[3].partition_z = partitions[3];
     }
 
+    /// Compute the scene bounds again in the next update, e.g. after objects were
+    /// activated or moved to another layer, which does not change the bounds generation
+    pub fn invalidate_scene_bounds(&mut self) {
+        self.scene_bounds = None;
+    }
+
+    /// Bounds of the shadow casters, cached until the scene changes as computing them
+    /// walks the whole scene
+    fn scene_bounds(&mut self, world: &World) -> Option<Aabb> {
+        let engine = world.engine();
+        let generation = engine.bounds_generation(&world.root().tree());
+        let mask = self.light_camera.culling_mask;
+
+        if let Some((cached_generation, cached_mask, bounds)) = self.scene_bounds {
+            if cached_generation == generation && cached_mask == mask {
+                return bounds;
+            }
+        }
+
+        let bounds = engine.get_scene_bounds(&self.light_camera);
+        self.scene_bounds = if bounds.dynamic {
+            None
+        } else {
+            Some((generation, mask, bounds.aabb))
+        };
+
+        bounds.aabb
+    }
+
     fn apply(&self, material: &Material) {
-        material.set("uShadowEnabled", true);
+        material.set("uShadowEnabled", self.main_light_shadows);
         material.set("uShadowMapTexture", self.rt.as_texture());
         material.set("ShadowMapParams", self.material_params.clone());
+        material.set("uShadowCascadeBlend", self.cascade_blend);
+
+        self.apply_filter(material);
+        self.local_shadows.apply(material);
+    }
+
+    fn apply_filter(&self, material: &Material) {
+        let keyword = self.filter.keyword();
+
+        for k in SHADOW_FILTER_KEYWORDS.iter() {
+            if *k == keyword {
+                material.enable_keyword(*k);
+            } else {
+                material.disable_keyword(k);
+            }
+        }
+
+        match self.filter {
+            ShadowFilter::Hardware => (),
+            ShadowFilter::Pcf { samples, radius } => {
+                material.set("uShadowSamples", samples.min(MAX_SHADOW_SAMPLES) as i32);
+                material.set("uShadowFilterRadius", radius);
+            }
+            ShadowFilter::Pcss {
+                samples,
+                light_size,
+            } => {
+                material.set("uShadowSamples", samples.min(MAX_SHADOW_SAMPLES) as i32);
+                material.set("uShadowLightSize", light_size);
+            }
+            ShadowFilter::Vsm { light_bleeding, .. } => {
+                if self.vsm.packed() {
+                    material.enable_keyword("VSM_PACKED");
+                } else {
+                    material.disable_keyword("VSM_PACKED");
+                }
+
+                material.set("uShadowMomentsTexture", self.vsm.texture());
+                material.set("uShadowMinVariance", VSM_MIN_VARIANCE);
+                material.set("uShadowLightBleeding", light_bleeding.max(0.0).min(0.99));
+            }
+        }
+    }
+
+    /// Hardware comparison is only enabled on depth textures sampled by shadow samplers
+    fn update_depth_compare(&self) {
+        let compare = match self.filter {
+            ShadowFilter::Hardware if self.hardware_compare => Some(DepthTest::LessEqual),
+            _ => None,
+        };
+
+        self.rt.as_texture().compare.set(compare);
+        self.local_shadows.texture().as_texture().compare.set(compare);
+    }
+
+    /// Report once per filter that local lights fall back to the 3x3 grid PCF
+    fn report_local_filter(&mut self) {
+        let unsupported = match self.filter {
+            ShadowFilter::Pcss { .. } | ShadowFilter::Vsm { .. } => true,
+            _ => false,
+        };
+
+        if !unsupported || self.local_shadows.num_faces() == 0
+            || self.reported_local_filter == Some(self.filter)
+        {
+            return;
+        }
+
+        uni_app::App::print(format!(
+            "Local light shadows do not support {:?}, a 3x3 grid PCF is used\n",
+            self.filter
+        ));
+        self.reported_local_filter = Some(self.filter);
     }
 }
 
@@ -452,6 +662,12 @@ impl Actor for ShadowPass {
             .as_mut()
             .unwrap()
             .insert(RenderQueue::Opaque);
+
+        self.local_shadows.start(world);
+        self.vsm.start(world);
+
+        // Shaders including unrust/shadow_utils.glsl are GLSL ES 3.00 on WebGL2
+        self.hardware_compare = !uni_gl::IS_GL_ES || world.engine().gl.is_webgl2;
     }
 
     fn update(&mut self, _go: &mut GameObject, world: &mut World) {
@@ -473,15 +689,41 @@ impl Actor for ShadowPass {
             }
         }
 
+        self.update_depth_compare();
+        self.local_shadows.render(world, &mut self.light_camera);
+        self.report_local_filter();
+
         // update light
         let main_light = match world.engine().find_main_light() {
             Some(l) => l,
             None => {
+                self.main_light_shadows = false;
                 return;
             }
         };
 
-        let ctx = LightMatrixContext::new(&self.light_camera, &main_light, world);
+        let (casts_shadows, settings) = {
+            let light = main_light.try_as::<Light>().unwrap();
+            let light = light.borrow();
+            (light.casts_shadows(), light.shadow_settings())
+        };
+
+        self.main_light_shadows = casts_shadows;
+        insert_shadow_settings(&mut self.material_params, "uShadowSettings", &settings);
+
+        if !self.main_light_shadows {
+            return;
+        }
+
+        // Only objects lit by the main light cast shadows
+        self.light_camera.culling_mask = {
+            let light = main_light.try_as::<Light>().unwrap();
+            let mask = light.borrow().culling_mask();
+            mask
+        };
+
+        let ctx = self.scene_bounds(world)
+            .map(|bounds| LightMatrixContext::new(&bounds, &main_light, world));
 
         if let Some(ctx) = ctx {
             if self.use_scene_aabb {
@@ -529,6 +771,11 @@ impl Actor for ShadowPass {
             }
         }
 
+        if let ShadowFilter::Vsm { blur, .. } = self.filter {
+            let tiles = if self.use_scene_aabb { 1 } else { 2 };
+            self.vsm.render(world, &self.rt, tiles, blur);
+        }
+
         // update material params
         {
             for map in self.shadow_maps.iter_mut() {
@@ -540,6 +787,11 @@ impl Actor for ShadowPass {
         use imgui;
         use imgui::Metric::*;
 
+        // Depth textures with hardware comparison can not be displayed
+        if self.rt.as_texture().compare.get().is_some() {
+            return;
+        }
+
         imgui::pivot((0.0, 1.0));
         let mut mat = Material::new(world.asset_system().new_program("unrust/shadow_display"));
         mat.set("uDepthMap", self.rt.as_texture());
@@ -559,9 +811,17 @@ impl Processor for ShadowPass {
             TextureAttachment::Depth,
         ));
 
+        let mut material_params = MaterialParamMap::default();
+        for (i, p) in POISSON_DISK.iter().enumerate() {
+            material_params.insert(
+                format!("uPoissonDisk[{}]", i).into(),
+                Vector2f::new(p[0], p[1]).into(),
+            );
+        }
+
         ShadowPass {
             rt: rt.clone(),
-            material_params: MaterialParamMap::default(),
+            material_params,
             use_scene_aabb: false,
             shadow_maps: [
                 ShadowMap {
@@ -604,6 +864,14 @@ impl Processor for ShadowPass {
             light_camera: Camera::new(),
             debug_gameobjects: Vec::new(),
             debug_mode: false,
+            main_light_shadows: true,
+            local_shadows: ShadowAtlas::new(),
+            filter: ShadowFilter::default(),
+            vsm: VarianceShadowMap::new(texture_size),
+            hardware_compare: !uni_gl::IS_GL_ES,
+            reported_local_filter: None,
+            cascade_blend: DEFAULT_CASCADE_BLEND,
+            scene_bounds: None,
         }
     }
 
@@ -0,0 +1,124 @@
+use world::World;
+use engine::{Material, RenderTexture, Texture, TextureAttachment, TextureFiltering};
+
+use super::screen_pass::ScreenPass;
+
+use std::rc::Rc;
+
+use math::*;
+
+const MAX_BLUR_RADIUS: u32 = 8;
+
+/// Moments of a shadow depth map, blurred by two separable passes on a screen quad
+pub struct VarianceShadowMap {
+    size: u32,
+    /// Horizontal blur output
+    temp: Rc<RenderTexture>,
+    moments: Rc<RenderTexture>,
+    /// Moments packed in RGBA8, as the context can not render to half floats
+    packed: bool,
+
+    screen_pass: ScreenPass,
+    from_depth_material: Option<Rc<Material>>,
+    blur_material: Option<Rc<Material>>,
+}
+
+fn moments_texture(size: u32, packed: bool) -> Rc<RenderTexture> {
+    if !packed {
+        return Rc::new(RenderTexture::new(size, size, TextureAttachment::Color0HalfFloat));
+    }
+
+    let rt = RenderTexture::new(size, size, TextureAttachment::Color0);
+
+    // Packed moments can not be interpolated
+    rt.as_texture().filtering.set(TextureFiltering::Nearest);
+    Rc::new(rt)
+}
+
+impl VarianceShadowMap {
+    pub fn new(size: u32) -> VarianceShadowMap {
+        VarianceShadowMap {
+            size,
+            temp: moments_texture(size, true),
+            moments: moments_texture(size, true),
+            packed: true,
+            screen_pass: ScreenPass::new(),
+            from_depth_material: None,
+            blur_material: None,
+        }
+    }
+
+    pub fn start(&mut self, world: &mut World) {
+        self.screen_pass.start(world);
+
+        if TextureAttachment::Color0HalfFloat.is_supported(&world.engine().gl) {
+            self.temp = moments_texture(self.size, false);
+            self.moments = moments_texture(self.size, false);
+            self.packed = false;
+        }
+
+        let db = world.asset_system();
+
+        let from_depth = Material::new(db.new_program("unrust/shadow_vsm"));
+        from_depth.enable_keyword("VSM_FROM_DEPTH");
+        let blur = Material::new(db.new_program("unrust/shadow_vsm"));
+
+        if self.packed {
+            from_depth.enable_keyword("VSM_PACKED");
+            blur.enable_keyword("VSM_PACKED");
+        }
+
+        self.from_depth_material = Some(Rc::new(from_depth));
+        self.blur_material = Some(Rc::new(blur));
+    }
+
+    /// Compute the blurred moments of `depth`, made of `tiles` x `tiles` shadow maps
+    pub fn render(
+        &mut self,
+        world: &mut World,
+        depth: &Rc<RenderTexture>,
+        tiles: u32,
+        blur: u32,
+    ) {
+        let (from_depth, blur_material) = match (&self.from_depth_material, &self.blur_material) {
+            (&Some(ref a), &Some(ref b)) => (a.clone(), b.clone()),
+            _ => return,
+        };
+
+        let texel = 1.0 / self.size as f32;
+        let radius = blur.min(MAX_BLUR_RADIUS) as i32;
+
+        let passes = [
+            (
+                from_depth,
+                depth.clone(),
+                self.temp.clone(),
+                Vector2f::new(texel, 0.0),
+            ),
+            (
+                blur_material,
+                self.temp.clone(),
+                self.moments.clone(),
+                Vector2f::new(0.0, texel),
+            ),
+        ];
+
+        for &(ref material, ref source, ref target, step) in passes.iter() {
+            material.set("uSource", source.as_texture());
+            material.set("uBlurStep", step);
+            material.set("uBlurRadius", radius);
+            material.set("uTiles", tiles as f32);
+
+            self.screen_pass.render(world, material, target, (self.size, self.size));
+        }
+    }
+
+    pub fn texture(&self) -> Rc<Texture> {
+        self.moments.as_texture()
+    }
+
+    /// Whether the moments are packed, see `VSM_PACKED` in unrust/shadow_vsm.glsl
+    pub fn packed(&self) -> bool {
+        self.packed
+    }
+}
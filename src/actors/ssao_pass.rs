@@ -0,0 +1,240 @@
+use world::{Actor, Processor, World};
+use engine::{AmbientOcclusion, Camera, ClearOption, GameObject, Material, RenderQueue,
+             RenderTexture, TextureAttachment};
+
+use super::screen_pass::ScreenPass;
+
+use std::rc::Rc;
+
+use math::*;
+
+const MAX_SSAO_SAMPLES: u32 = 32;
+const MAX_BLUR_RADIUS: u32 = 4;
+
+/// Screen space ambient occlusion of the current camera, enabled by its `ambient_occlusion`
+///
+/// The occlusion texture is multiplied into the ambient lighting of the phong programs,
+/// only when they are rendered by that camera.
+#[derive(Component)]
+pub struct SsaoPass {
+    size: (u32, u32),
+    depth: Option<Rc<RenderTexture>>,
+    /// Occlusion, also the vertical blur output
+    ao: Option<Rc<RenderTexture>>,
+    /// Horizontal blur output
+    temp: Option<Rc<RenderTexture>>,
+
+    depth_material: Option<Rc<Material>>,
+    ssao_material: Option<Rc<Material>>,
+    blur_material: Option<Rc<Material>>,
+    screen_pass: ScreenPass,
+
+    viewport: ((i32, i32), (u32, u32)),
+}
+
+fn radical_inverse(mut i: u32, base: u32) -> f32 {
+    let inv_base = 1.0 / base as f32;
+    let mut f = inv_base;
+    let mut r = 0.0;
+
+    while i > 0 {
+        r += f * (i % base) as f32;
+        i /= base;
+        f *= inv_base;
+    }
+
+    r
+}
+
+/// Samples in the hemisphere around +z, any prefix of the kernel is well distributed
+fn ssao_kernel() -> Vec<Vector3f> {
+    (0..MAX_SSAO_SAMPLES)
+        .map(|i| {
+            let z = 1.0 - radical_inverse(i, 2);
+            let phi = i as f32 * 2.399963; // golden angle
+            let r = (1.0 - z * z).sqrt();
+
+            // More samples close to the center
+            let t = radical_inverse(i, 3);
+            let scale = 0.1 + 0.9 * t * t;
+
+            Vector3f::new(r * phi.cos(), r * phi.sin(), z) * scale
+        })
+        .collect()
+}
+
+impl SsaoPass {
+    fn resize(&mut self, size: (u32, u32)) {
+        if self.size == size && self.ao.is_some() {
+            return;
+        }
+
+        let (w, h) = size;
+        self.size = size;
+        self.depth = Some(Rc::new(RenderTexture::new(w, h, TextureAttachment::Depth)));
+        self.ao = Some(Rc::new(RenderTexture::new(w, h, TextureAttachment::Color0)));
+        self.temp = Some(Rc::new(RenderTexture::new(w, h, TextureAttachment::Color0)));
+    }
+
+    fn render(&mut self, world: &mut World, settings: &AmbientOcclusion, proj: Matrix4f) {
+        let (ssao_material, blur_material) = match (&self.ssao_material, &self.blur_material) {
+            (&Some(ref a), &Some(ref b)) => (a.clone(), b.clone()),
+            _ => return,
+        };
+
+        let depth = self.depth.clone().unwrap();
+        let ao = self.ao.clone().unwrap();
+        let temp = self.temp.clone().unwrap();
+        let texel = Vector2f::new(1.0 / self.size.0 as f32, 1.0 / self.size.1 as f32);
+
+        ssao_material.set("uDepthMap", depth.as_texture());
+        ssao_material.set("uTexelSize", texel);
+        ssao_material.set("uProjection", proj);
+        ssao_material.set("uInvProjection", proj.invert().unwrap_or(Matrix4f::identity()));
+        ssao_material.set("uSsaoSamples", settings.samples.min(MAX_SSAO_SAMPLES) as i32);
+        ssao_material.set("uSsaoRadius", settings.radius);
+        ssao_material.set("uSsaoBias", settings.bias);
+        ssao_material.set("uSsaoIntensity", settings.intensity);
+
+        self.screen_pass.render(world, &ssao_material, &ao, self.size);
+
+        if settings.blur == 0 {
+            return;
+        }
+
+        // Separable blur, from ao to temp and back
+        let radius = settings.blur.min(MAX_BLUR_RADIUS) as i32;
+        let passes = [
+            (ao.clone(), temp.clone(), Vector2f::new(texel.x, 0.0)),
+            (temp.clone(), ao.clone(), Vector2f::new(0.0, texel.y)),
+        ];
+
+        blur_material.set("uBlurRadius", radius);
+
+        for &(ref source, ref target, step) in passes.iter() {
+            blur_material.set("uSource", source.as_texture());
+            blur_material.set("uBlurStep", step);
+
+            self.screen_pass.render(world, &blur_material, target, self.size);
+        }
+    }
+
+    fn apply(&self, material: &Material) {
+        let ((x, y), (w, h)) = self.viewport;
+
+        material.set(
+            "uSsaoViewport",
+            Vector4::new(x as f32, y as f32, w as f32, h as f32),
+        );
+
+        if let Some(ref ao) = self.ao {
+            material.set("uSsaoTexture", ao.as_texture());
+        }
+    }
+}
+
+impl Actor for SsaoPass {
+    fn start(&mut self, _go: &mut GameObject, world: &mut World) {
+        self.screen_pass.start(world);
+
+        let db = world.asset_system();
+
+        self.depth_material = Some(Rc::new(Material::new(db.new_program("unrust/depth"))));
+        self.blur_material = Some(Rc::new(Material::new(db.new_program("unrust/ssao_blur"))));
+
+        let ssao_material = Material::new(db.new_program("unrust/ssao"));
+        for (i, k) in ssao_kernel().into_iter().enumerate() {
+            ssao_material.set(format!("uSsaoKernel[{}]", i), k);
+        }
+        self.ssao_material = Some(Rc::new(ssao_material));
+    }
+
+    fn update(&mut self, _go: &mut GameObject, world: &mut World) {
+        for c in world.engine().cameras() {
+            c.try_as::<Camera>().unwrap().borrow_mut().ssao_bound = false;
+        }
+
+        let cam_borrow = match world.current_camera() {
+            Some(c) => c,
+            None => return,
+        };
+
+        let settings = match cam_borrow.borrow().ambient_occlusion {
+            Some(s) => s,
+            None => return,
+        };
+
+        let depth_material = match self.depth_material {
+            Some(ref m) => m.clone(),
+            None => return,
+        };
+
+        let screen_size = world.engine().screen_size;
+        let (viewport, proj) = {
+            let cam = cam_borrow.borrow();
+            (cam.viewport(screen_size), cam.projection_matrix(screen_size))
+        };
+
+        self.resize(viewport.1);
+        self.viewport = viewport;
+
+        // Depth of the opaque objects seen by the camera
+        {
+            let mut cam = cam_borrow.borrow_mut();
+
+            let render_texture = cam.render_texture.take();
+            let rect = cam.rect;
+            let queues = cam.included_render_queues.take();
+
+            cam.render_texture = self.depth.clone();
+            cam.rect = Some(((0, 0), self.size));
+            cam.included_render_queues = Some(Default::default());
+            cam.included_render_queues
+                .as_mut()
+                .unwrap()
+                .insert(RenderQueue::Opaque);
+
+            world.engine_mut().render_pass_with_material(
+                &cam,
+                Some(&depth_material),
+                ClearOption::default(),
+            );
+
+            cam.render_texture = render_texture;
+            cam.rect = rect;
+            cam.included_render_queues = queues;
+        }
+
+        self.render(world, &settings, proj);
+        cam_borrow.borrow_mut().ssao_bound = true;
+    }
+}
+
+impl Processor for SsaoPass {
+    fn new() -> SsaoPass {
+        SsaoPass {
+            size: (0, 0),
+            depth: None,
+            ao: None,
+            temp: None,
+            depth_material: None,
+            ssao_material: None,
+            blur_material: None,
+            screen_pass: ScreenPass::new(),
+            viewport: ((0, 0), (0, 0)),
+        }
+    }
+
+    fn apply_materials(&self, materials: &Vec<Rc<Material>>) {
+        for m in materials.iter() {
+            self.apply(&m);
+        }
+    }
+
+    fn watch_material() -> bool
+    where
+        Self: Sized,
+    {
+        return true;
+    }
+}
@@ -8,14 +8,15 @@ use engine::asset::fs;
 use engine::asset::loader;
 use engine::asset::Resource;
 
-use engine::{Material, MeshBuffer, ShaderFs, ShaderProgram, ShaderVs, Texture, TextureFiltering,
-             TextureImage};
+use engine::{CompressedFormat, Material, MeshBuffer, ShaderFs, ShaderKind, ShaderProgram,
+             ShaderVs, Texture, TextureFiltering, TextureImage};
 use std::fmt::Debug;
 use std::ops::Deref;
-use futures::{Async, Future};
+use futures::{future, Async, Future};
 use std::boxed::FnBox;
 
 use image;
+use uni_app;
 use image::ImageBuffer;
 
 #[derive(Debug)]
@@ -28,6 +29,17 @@ pub enum AssetError {
         reason: String,
     },
     FileIoError(fs::FileIoError),
+    /// `stage` is `None` for link errors
+    ShaderCompile {
+        program: String,
+        stage: Option<ShaderKind>,
+        log: String,
+    },
+    /// A compressed format the GL context can not sample and which has no CPU decoder
+    UnsupportedFormat {
+        path: String,
+        format: CompressedFormat,
+    },
 }
 
 pub type AssetResult<T> = Result<T, AssetError>;
@@ -51,6 +63,14 @@ pub trait AssetSystem {
 
     fn new_prefab(&self, name: &str, mh: MaterialHandler, f: PrefabHandler);
 
+    /// Compressed texture variant substituted for `*` in texture names
+    fn texture_variant(&self) -> String {
+        "dxt".to_owned()
+    }
+
+    /// Ignored by default, which keeps the DXT variant
+    fn set_texture_variant(&self, _variant: &str) {}
+
     fn reset(&mut self);
 
     fn step(&mut self);
@@ -74,13 +94,18 @@ pub trait Asset {
 }
 
 pub trait LoadableAsset: Asset {
+    /// `files` are the files returned by `gather` for the same `name`
     fn load<T: AssetSystem + Clone + 'static>(
         asys: &T,
+        name: &str,
         files: Vec<fs::FileFuture>,
     ) -> Self::Resource;
 
     fn gather<T: AssetSystem>(asys: &T, fname: &str) -> Vec<fs::FileFuture>;
 
+    /// Swap in a resource loaded again from the same files, keeping the `Rc` identity
+    fn reload(&self, r: Self::Resource);
+
     fn load_resource<U, A>(asys: A, f: fs::FileFuture) -> Resource<U>
     where
         U: loader::Loadable + Debug + 'static,
@@ -92,6 +117,14 @@ pub trait LoadableAsset: Asset {
 
 type PrefabFuture = Box<Future<Item = loader::Prefab, Error = AssetError>>;
 
+/// An asset to reload when one of its files is modified
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum WatchedAsset {
+    Program(String),
+    Texture(String),
+    MeshBuffer(String),
+}
+
 pub struct AssetDatabaseContext<FS> {
     fs: FS,
     path: String,
@@ -99,6 +132,12 @@ pub struct AssetDatabaseContext<FS> {
     mesh_buffers: RefCell<HashMap<String, Rc<MeshBuffer>>>,
     programs: RefCell<HashMap<String, Rc<ShaderProgram>>>,
 
+    /// Assets by the files they are loaded from
+    watched_assets: RefCell<HashMap<String, Vec<WatchedAsset>>>,
+    /// Files opened while gathering the files of an asset
+    recording: RefCell<Option<Vec<String>>>,
+    texture_variant: RefCell<String>,
+
     pending_prefabs: RefCell<Vec<(PrefabHandler, PrefabFuture)>>,
     pending_tasks: RefCell<Vec<AssetTask>>,
 }
@@ -141,28 +180,37 @@ where
     F: fs::File + 'static,
 {
     fn new_file(&self, name: &str) -> fs::FileFuture {
-        self.fs.open(&self.get_filename(name))
+        let filename = self.get_filename(name);
+
+        if let Some(ref mut files) = *self.recording.borrow_mut() {
+            files.push(filename.clone());
+        }
+
+        self.fs.open(&filename)
     }
 
     fn new_program(&self, name: &str) -> Rc<ShaderProgram> {
         let mut a = self.programs.borrow_mut();
-        self.new_asset(&mut a, name)
+        self.new_asset(&mut a, name, WatchedAsset::Program(name.into()))
     }
 
     fn new_texture(&self, name: &str) -> Rc<Texture> {
         let mut a = self.textures.borrow_mut();
-        self.new_asset(&mut a, name)
+        let tex = self.new_asset(&mut a, name, WatchedAsset::Texture(name.into()));
+        tex.set_path(&name.replace('*', &self.texture_variant()));
+        tex
     }
 
     fn new_mesh_buffer(&self, name: &str) -> Rc<MeshBuffer> {
         let mut a = self.mesh_buffers.borrow_mut();
-        self.new_asset(&mut a, name)
+        self.new_asset(&mut a, name, WatchedAsset::MeshBuffer(name.into()))
     }
 
     fn reset(&mut self) {
         self.textures.borrow_mut().clear();
         self.mesh_buffers.borrow_mut().clear();
         self.programs.borrow_mut().clear();
+        self.watched_assets.borrow_mut().clear();
 
         self.setup();
     }
@@ -172,6 +220,14 @@ where
         self.pending_prefabs.borrow_mut().push((f, prefab));
     }
 
+    fn texture_variant(&self) -> String {
+        self.texture_variant.borrow().clone()
+    }
+
+    fn set_texture_variant(&self, variant: &str) {
+        *self.texture_variant.borrow_mut() = variant.to_owned();
+    }
+
     fn execute(&self, task: AssetTask) {
         self.pending_tasks.borrow_mut().push(task);
     }
@@ -184,6 +240,9 @@ where
                 textures: RefCell::new(HashMap::new()),
                 mesh_buffers: RefCell::new(HashMap::new()),
                 programs: RefCell::new(HashMap::new()),
+                watched_assets: RefCell::new(HashMap::new()),
+                recording: RefCell::new(None),
+                texture_variant: RefCell::new("dxt".to_owned()),
                 pending_prefabs: RefCell::new(Vec::new()),
                 pending_tasks: RefCell::new(Vec::new()),
             }),
@@ -194,6 +253,8 @@ where
     }
 
     fn step(&mut self) {
+        self.reload_modified();
+
         {
             let pending_prefabs = self.pending_prefabs
                 .borrow_mut()
@@ -249,20 +310,111 @@ where
     FS: fs::FileSystem<File = F> + 'static,
     F: fs::File + 'static,
 {
-    fn new_asset<R>(&self, hm: &mut HashMap<String, Rc<R>>, name: &str) -> Rc<R>
+    fn new_asset<R>(
+        &self,
+        hm: &mut HashMap<String, Rc<R>>,
+        name: &str,
+        watched: WatchedAsset,
+    ) -> Rc<R>
     where
         R: LoadableAsset,
     {
         match hm.get(name) {
             Some(asset) => asset.clone(),
             None => {
-                let asset = R::new(R::load(self, R::gather(self, name)));
+                *self.recording.borrow_mut() = Some(Vec::new());
+                let files = R::gather(self, name);
+                let opened = self.recording.borrow_mut().take().unwrap_or_default();
+
+                let mut watched_assets = self.watched_assets.borrow_mut();
+                for f in opened {
+                    watched_assets
+                        .entry(f)
+                        .or_insert_with(Vec::new)
+                        .push(watched.clone());
+                }
+
+                let asset = R::new(R::load(self, name, files));
                 hm.insert(name.into(), asset.clone());
                 asset
             }
         }
     }
 
+    fn reload_asset<R>(&self, hm: &RefCell<HashMap<String, Rc<R>>>, name: &str)
+    where
+        R: LoadableAsset,
+    {
+        let asset = match hm.borrow().get(name) {
+            Some(asset) => asset.clone(),
+            None => return,
+        };
+
+        uni_app::App::print(format!("Reloading {}\n", name));
+        asset.reload(R::load(self, name, R::gather(self, name)));
+    }
+
+    /// Load again the assets whose files were modified
+    fn reload_modified(&self) {
+        let modified = self.fs.modified_files();
+        if modified.is_empty() {
+            return;
+        }
+
+        let mut reloads = Vec::new();
+        {
+            let watched_assets = self.watched_assets.borrow();
+
+            for file in modified.iter() {
+                match watched_assets.get(file) {
+                    Some(assets) => reloads.extend(assets.iter().cloned()),
+                    // Files included by shaders are found once they are loaded
+                    None => if file.ends_with(".glsl") {
+                        reloads.extend(
+                            watched_assets
+                                .values()
+                                .flat_map(|assets| assets.iter())
+                                .filter(|a| match **a {
+                                    WatchedAsset::Program(ref name) => {
+                                        self.program_includes(name, file)
+                                    }
+                                    _ => false,
+                                })
+                                .cloned(),
+                        );
+                    },
+                }
+            }
+        }
+
+        reloads.sort();
+        reloads.dedup();
+
+        for asset in reloads {
+            match asset {
+                WatchedAsset::Program(ref name) => self.reload_asset(&self.programs, name),
+                WatchedAsset::Texture(ref name) => self.reload_asset(&self.textures, name),
+                WatchedAsset::MeshBuffer(ref name) => {
+                    self.reload_asset(&self.mesh_buffers, name)
+                }
+            }
+        }
+    }
+
+    /// Whether the program `name` includes `file`,
+    /// programs which did not load are reloaded with any shader file as their includes are unknown
+    fn program_includes(&self, name: &str, file: &str) -> bool {
+        let prog = match self.programs.borrow().get(name) {
+            Some(prog) => prog.clone(),
+            None => return false,
+        };
+
+        match prog.includes() {
+            Some(includes) => includes.iter().any(|f| self.get_filename(f) == file),
+            None => true,
+        }
+    }
+
     fn setup(&mut self) {
         {
             let mut hm = self.mesh_buffers.borrow_mut();
@@ -315,6 +467,7 @@ where
             let mut hm = self.programs.borrow_mut();
             hm.insert("default".into(), Self::new_default_program());
             hm.insert("default_ui".into(), Self::new_default_ui_program());
+            hm.insert("default_error".into(), Self::new_default_error_program());
         }
     }
 
@@ -362,18 +515,39 @@ where
         })))
     }
 
-    pub fn new_default_program() -> Rc<ShaderProgram> {
-        let vs = ShaderVs::new("phong_vs.glsl", DEFAULT_VS);
-        let fs = ShaderFs::new("phong_fs.glsl", DEFAULT_FS);
+    fn new_builtin_program(
+        (vs_name, vs): (&str, &str),
+        (fs_name, fs): (&str, &str),
+    ) -> Rc<ShaderProgram> {
+        let includes: HashMap<String, String> = BUILTIN_INCLUDES
+            .iter()
+            .map(|&(name, src)| (name.to_string(), src.to_string()))
+            .collect();
+
+        // Errors are reported when the program is prepared
+        let vs = ShaderVs::new_with_includes(vs_name, vs, &includes);
+        let fs = ShaderFs::new_with_includes(fs_name, fs, &includes);
 
-        ShaderProgram::new((Resource::new(vs), Resource::new(fs)))
+        let vs = Resource::new_future(future::result(vs));
+        let fs = Resource::new_future(future::result(fs));
+
+        ShaderProgram::new((vs, fs))
+    }
+
+    pub fn new_default_program() -> Rc<ShaderProgram> {
+        Self::new_builtin_program(("phong_vs.glsl", DEFAULT_VS), ("phong_fs.glsl", DEFAULT_FS))
     }
 
     pub fn new_default_ui_program() -> Rc<ShaderProgram> {
-        let vs = ShaderVs::new("ui_vs.glsl", DEFAULT_UI_VS);
-        let fs = ShaderFs::new("ui_fs.glsl", DEFAULT_UI_FS);
+        Self::new_builtin_program(("ui_vs.glsl", DEFAULT_UI_VS), ("ui_fs.glsl", DEFAULT_UI_FS))
+    }
 
-        ShaderProgram::new((Resource::new(vs), Resource::new(fs)))
+    /// Magenta program used in place of programs which fail to compile
+    pub fn new_default_error_program() -> Rc<ShaderProgram> {
+        Self::new_builtin_program(
+            ("error_vs.glsl", DEFAULT_ERROR_VS),
+            ("error_fs.glsl", DEFAULT_ERROR_FS),
+        )
     }
 
     pub fn get_filename(&self, name: &str) -> String {
@@ -387,3 +561,26 @@ const DEFAULT_FS: &'static str = include_str!("phong_fs.glsl");
 
 const DEFAULT_UI_VS: &'static str = include_str!("ui_vs.glsl");
 const DEFAULT_UI_FS: &'static str = include_str!("ui_fs.glsl");
+
+const DEFAULT_ERROR_VS: &'static str = include_str!("error_vs.glsl");
+const DEFAULT_ERROR_FS: &'static str = include_str!("error_fs.glsl");
+
+// Engine includes which the builtin programs can use without loading files
+const BUILTIN_INCLUDES: &[(&'static str, &'static str)] = &[
+    (
+        "unrust/frame_data.glsl",
+        include_str!("../../../static/unrust/frame_data.glsl"),
+    ),
+    (
+        "unrust/phong_light.glsl",
+        include_str!("../../../static/unrust/phong_light.glsl"),
+    ),
+    (
+        "unrust/skinning.glsl",
+        include_str!("../../../static/unrust/skinning.glsl"),
+    ),
+    (
+        "unrust/morph.glsl",
+        include_str!("../../../static/unrust/morph.glsl"),
+    ),
+];
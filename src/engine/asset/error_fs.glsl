@@ -0,0 +1,8 @@
+#ifndef GL_ES
+#define gl_FragColor FragColor
+out vec4 FragColor;
+#endif
+
+void main(void) {
+    gl_FragColor = vec4(1.0, 0.0, 1.0, 1.0);
+}
@@ -10,6 +10,11 @@ pub trait FileSystem: Default {
     fn open(&self, filename: &str) -> FileFuture;
 
     fn loading_files(&self) -> Vec<String>;
+
+    /// Files opened before which were modified since, used for hot reloading
+    fn modified_files(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 pub trait File {
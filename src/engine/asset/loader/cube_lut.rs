@@ -0,0 +1,98 @@
+use engine::asset::loader::{Loadable, Loader};
+use engine::asset::{AssetError, AssetResult, AssetSystem, File};
+use engine::render::TextureVolume;
+
+use std::str;
+
+/// Loader of Adobe / Resolve `.cube` 3D LUT files
+pub struct CubeLutLoader {}
+
+fn parse_rgb<'a, I>(tokens: I) -> Option<[f32; 3]>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let v: Vec<f32> = tokens.filter_map(|t| t.parse().ok()).collect();
+
+    if v.len() == 3 {
+        Some([v[0], v[1], v[2]])
+    } else {
+        None
+    }
+}
+
+impl Loader<TextureVolume> for CubeLutLoader {
+    fn load<A>(_asys: A, mut file: Box<File>) -> AssetResult<TextureVolume>
+    where
+        A: AssetSystem + Clone,
+    {
+        let name = file.name();
+        let buf = file.read_binary()
+            .map_err(|_| AssetError::ReadBufferFail(name.clone()))?;
+        let invalid = |reason: String| AssetError::InvalidFormat {
+            path: name.clone(),
+            len: buf.len(),
+            reason,
+        };
+
+        let s = str::from_utf8(&buf).map_err(|e| invalid(format!("{:?}", e)))?;
+
+        let mut size = None;
+        let mut domain_min = [0.0, 0.0, 0.0];
+        let mut domain_max = [1.0, 1.0, 1.0];
+        let mut data = Vec::new();
+
+        for line in s.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut tokens = line.split_whitespace();
+            let key = tokens.next().unwrap();
+
+            match key {
+                "TITLE" | "LUT_3D_INPUT_RANGE" => (),
+                "LUT_1D_SIZE" => return Err(invalid("1D LUTs are not supported".to_owned())),
+                "LUT_3D_SIZE" => size = tokens.next().and_then(|t| t.parse::<u32>().ok()),
+                "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                    let v = parse_rgb(tokens)
+                        .ok_or_else(|| invalid(format!("Invalid {}", key)))?;
+
+                    if key == "DOMAIN_MIN" {
+                        domain_min = v;
+                    } else {
+                        domain_max = v;
+                    }
+                }
+                _ => {
+                    let rgb = parse_rgb(line.split_whitespace())
+                        .ok_or_else(|| invalid(format!("Invalid line: {}", line)))?;
+
+                    for i in 0..3 {
+                        data.push((rgb[i] - domain_min[i]) / (domain_max[i] - domain_min[i]));
+                    }
+                }
+            }
+        }
+
+        let size = size.ok_or_else(|| invalid("Missing LUT_3D_SIZE".to_owned()))?;
+        if data.len() != (size * size * size * 3) as usize {
+            return Err(invalid(format!(
+                "Expected {} entries, found {}",
+                size * size * size,
+                data.len() / 3
+            )));
+        }
+
+        // red varies fastest, as x in a 3D texture
+        Ok(TextureVolume {
+            width: size,
+            height: size,
+            depth: size,
+            data,
+        })
+    }
+}
+
+impl Loadable for TextureVolume {
+    type Loader = CubeLutLoader;
+}
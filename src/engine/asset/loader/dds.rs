@@ -9,6 +9,9 @@ pub struct DDSReader {}
 pub enum DDSFormat {
     DXT1,
     DXT5,
+    BC4,
+    BC5,
+    BC7,
 }
 
 #[derive(Debug, Clone)]
@@ -57,6 +60,17 @@ struct DDSHeader {
     dw_reserved2: u32, // offset 31
 }
 
+/// Extended header following `DDSHeader` when the FourCC is `DX10`
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+struct DDSHeaderDX10 {
+    dxgi_format: u32,
+    resource_dimension: u32,
+    misc_flag: u32,
+    array_size: u32,
+    misc_flags2: u32,
+}
+
 const DDPF_FOURCC: u32 = 0x4;
 const DDSD_MIPMAPCOUNT: u32 = 0x20000;
 const DDPF_ALPHAPIXELS: u32 = 0x1;
@@ -90,30 +104,62 @@ impl DDSReader {
             });
         }
 
-        let format: DDSFormat;
-        let block_bytes: u32;
-        let mut mipmap_count = 1;
-
-        match header.dd_spf.dw_four_cc {
-            c if c.starts_with(b"DXT1") => {
-                format = DDSFormat::DXT1;
-                block_bytes = 8;
-            }
-            c if c.starts_with(b"DXT5") => {
-                format = DDSFormat::DXT5;
-                block_bytes = 16;
-            }
-            _ => {
-                return Err(AssetError::InvalidFormat {
-                    len: buffer.len(),
-                    path: file_name.clone(),
-                    reason: format!(
-                        "Unsupported Format, only support DXT1, DXT5 (current: {:?})",
-                        header.dd_spf.dw_four_cc
-                    ),
-                })
+        let mut data_offset: usize = (header.dw_size + 4) as usize;
+        let four_cc = header.dd_spf.dw_four_cc;
+
+        let format = match &four_cc {
+            b"DXT1" => Some(DDSFormat::DXT1),
+            b"DXT5" => Some(DDSFormat::DXT5),
+            b"ATI1" | b"BC4U" => Some(DDSFormat::BC4),
+            b"ATI2" | b"BC5U" => Some(DDSFormat::BC5),
+            b"DX10" => {
+                let mut header10: DDSHeaderDX10 = unsafe { mem::zeroed() };
+
+                unsafe {
+                    let header_slice = slice::from_raw_parts_mut(
+                        &mut header10 as *mut _ as *mut u8,
+                        mem::size_of::<DDSHeaderDX10>(),
+                    );
+
+                    buffer
+                        .read_exact(header_slice)
+                        .map_err(|_| AssetError::InvalidFormat {
+                            len: buffer.len(),
+                            path: file_name.clone(),
+                            reason: "Invalid DDS DX10 Header Format".to_owned(),
+                        })?;
+                }
+
+                data_offset += mem::size_of::<DDSHeaderDX10>();
+
+                // DXGI_FORMAT, the UNORM and UNORM_SRGB variants
+                match header10.dxgi_format {
+                    71 | 72 => Some(DDSFormat::DXT1),
+                    77 | 78 => Some(DDSFormat::DXT5),
+                    80 => Some(DDSFormat::BC4),
+                    83 => Some(DDSFormat::BC5),
+                    98 | 99 => Some(DDSFormat::BC7),
+                    _ => None,
+                }
             }
-        }
+            _ => None,
+        };
+
+        let format = format.ok_or_else(|| AssetError::InvalidFormat {
+            len: buffer.len(),
+            path: file_name.clone(),
+            reason: format!(
+                "Unsupported Format, only support DXT1, DXT5, BC4, BC5, BC7 (current: {:?})",
+                four_cc
+            ),
+        })?;
+
+        let block_bytes: u32 = match format {
+            DDSFormat::DXT1 | DDSFormat::BC4 => 8,
+            DDSFormat::DXT5 | DDSFormat::BC5 | DDSFormat::BC7 => 16,
+        };
+
+        let mut mipmap_count = 1;
 
         if header.dw_flags & DDSD_MIPMAPCOUNT != 0 {
             mipmap_count = 1.max(header.dw_mipmapcount);
@@ -123,12 +169,19 @@ impl DDSReader {
 
         let mut width = header.dw_width;
         let mut height = header.dw_height;
-        let mut data_offset: usize = (header.dw_size + 4) as usize;
 
         let mut images = Vec::new();
 
         for _ in 0..mipmap_count {
-            let data_length = 4.max(width) / 4 * 4.max(height) / 4 * block_bytes;
+            let data_length = (width + 3) / 4 * ((height + 3) / 4) * block_bytes;
+            if data_offset + data_length as usize > buff.len() {
+                return Err(AssetError::InvalidFormat {
+                    len: buff.len(),
+                    path: file_name.clone(),
+                    reason: "DDS data is shorter than its mipmaps".to_owned(),
+                });
+            }
+
             let byte_array = buff[data_offset..data_offset + data_length as usize].to_vec();
 
             images.push(DDSImage {
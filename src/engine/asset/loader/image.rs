@@ -1,6 +1,6 @@
 use engine::asset::loader::{Loadable, Loader};
 use engine::asset::{AssetError, AssetResult, AssetSystem, File, FileFuture};
-use engine::TextureImage;
+use engine::{CompressedFormat, CompressedImage, TextureImage};
 use image::png;
 use image::tga;
 use image;
@@ -13,7 +13,8 @@ use std::fmt::Debug;
 use uni_app;
 use std::path::Path;
 
-use super::dds::{DDSFormat, DDSReader};
+use super::dds::{DDSFormat, DDSImage, DDSReader};
+use super::ktx::{KTXReader, KTX2_MAGIC_BYTES, KTX_MAGIC_BYTES};
 
 pub struct ImageLoader {}
 
@@ -307,12 +308,19 @@ where
         DDSReader::read(whole_buf, &file_name).map(|dds| match dds.format {
             DDSFormat::DXT1 => TextureImage::DXT1(dds),
             DDSFormat::DXT5 => TextureImage::DXT5(dds),
+            DDSFormat::BC4 => compressed_dds(CompressedFormat::Bc4, dds.images),
+            DDSFormat::BC5 => compressed_dds(CompressedFormat::Bc5, dds.images),
+            DDSFormat::BC7 => compressed_dds(CompressedFormat::Bc7, dds.images),
         })
     });
 
     Box::new(img)
 }
 
+fn compressed_dds(format: CompressedFormat, images: Vec<DDSImage>) -> TextureImage {
+    TextureImage::Compressed(CompressedImage { format, images })
+}
+
 impl Loadable for TextureImage {
     type Loader = ImageLoader;
 
@@ -335,6 +343,11 @@ impl Loadable for TextureImage {
                 return load_future_dds(future::result(Ok((whole_buf, file_name))));
             }
 
+            if whole_buf.starts_with(KTX_MAGIC_BYTES) || whole_buf.starts_with(KTX2_MAGIC_BYTES) {
+                let img = KTXReader::read(whole_buf, &file_name).map(TextureImage::Compressed);
+                return Box::new(future::result(img));
+            }
+
             load_future_uncompressed(future::result(Ok((whole_buf, file_name))))
         }))
     }
@@ -0,0 +1,233 @@
+use engine::asset::{AssetError, AssetResult};
+use engine::render::{CompressedFormat, CompressedImage};
+
+use super::dds::DDSImage;
+
+pub struct KTXReader {}
+
+pub static KTX_MAGIC_BYTES: &'static [u8] = b"\xABKTX 11\xBB\r\n\x1A\n";
+pub static KTX2_MAGIC_BYTES: &'static [u8] = b"\xABKTX 20\xBB\r\n\x1A\n";
+
+const KTX_ENDIANNESS: u32 = 0x04030201;
+
+/// ASTC block sizes in the order of their GL and Vulkan formats
+const ASTC_BLOCKS: [(u8, u8); 14] = [
+    (4, 4),
+    (5, 4),
+    (5, 5),
+    (6, 5),
+    (6, 6),
+    (8, 5),
+    (8, 6),
+    (8, 8),
+    (10, 5),
+    (10, 6),
+    (10, 8),
+    (10, 10),
+    (12, 10),
+    (12, 12),
+];
+
+fn gl_format(internal_format: u32) -> Option<CompressedFormat> {
+    match internal_format {
+        0x83F0 => Some(CompressedFormat::Dxt1),
+        0x83F1 => Some(CompressedFormat::Dxt1Alpha),
+        0x83F3 => Some(CompressedFormat::Dxt5),
+        0x8DBB => Some(CompressedFormat::Bc4),
+        0x8DBD => Some(CompressedFormat::Bc5),
+        0x8E8C => Some(CompressedFormat::Bc7),
+        0x9274 | 0x9275 => Some(CompressedFormat::Etc2Rgb),
+        0x9276 | 0x9277 => Some(CompressedFormat::Etc2RgbA1),
+        0x9278 | 0x9279 => Some(CompressedFormat::Etc2Rgba),
+        // RGBA_ASTC and SRGB8_ALPHA8_ASTC
+        f @ 0x93B0...0x93BD => Some(astc_format((f - 0x93B0) as usize)),
+        f @ 0x93D0...0x93DD => Some(astc_format((f - 0x93D0) as usize)),
+        _ => None,
+    }
+}
+
+fn vk_format(vk_format: u32) -> Option<CompressedFormat> {
+    // UNORM formats are followed by their SRGB variant
+    match vk_format {
+        131 | 132 => Some(CompressedFormat::Dxt1),
+        133 | 134 => Some(CompressedFormat::Dxt1Alpha),
+        137 | 138 => Some(CompressedFormat::Dxt5),
+        139 => Some(CompressedFormat::Bc4),
+        141 => Some(CompressedFormat::Bc5),
+        145 | 146 => Some(CompressedFormat::Bc7),
+        147 | 148 => Some(CompressedFormat::Etc2Rgb),
+        149 | 150 => Some(CompressedFormat::Etc2RgbA1),
+        151 | 152 => Some(CompressedFormat::Etc2Rgba),
+        f @ 157...184 => Some(astc_format(((f - 157) / 2) as usize)),
+        _ => None,
+    }
+}
+
+fn astc_format(i: usize) -> CompressedFormat {
+    let (w, h) = ASTC_BLOCKS[i];
+    CompressedFormat::Astc(w, h)
+}
+
+struct KTXBuffer<'a> {
+    buff: &'a [u8],
+    file_name: &'a String,
+    swap: bool,
+}
+
+impl<'a> KTXBuffer<'a> {
+    fn error(&self, reason: &str) -> AssetError {
+        AssetError::InvalidFormat {
+            len: self.buff.len(),
+            path: self.file_name.clone(),
+            reason: reason.to_owned(),
+        }
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> AssetResult<&'a [u8]> {
+        if offset + len > self.buff.len() {
+            return Err(self.error("Unexpected end of KTX file"));
+        }
+
+        Ok(&self.buff[offset..offset + len])
+    }
+
+    fn u32(&self, offset: usize) -> AssetResult<u32> {
+        let b = self.bytes(offset, 4)?;
+        let v = b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24;
+
+        Ok(if self.swap { v.swap_bytes() } else { v })
+    }
+
+    fn check_levels(
+        &self,
+        format: CompressedFormat,
+        images: Vec<DDSImage>,
+    ) -> AssetResult<CompressedImage> {
+        for img in images.iter() {
+            if img.data.len() < format.level_size(img.width, img.height) {
+                return Err(self.error("KTX level data is smaller than its dimensions"));
+            }
+        }
+
+        Ok(CompressedImage { format, images })
+    }
+
+    fn u64(&self, offset: usize) -> AssetResult<u64> {
+        let lo = self.u32(offset)? as u64;
+        let hi = self.u32(offset + 4)? as u64;
+        Ok(lo | hi << 32)
+    }
+}
+
+impl KTXReader {
+    /// Read a 2D compressed texture from a KTX or KTX2 file
+    pub fn read(buff: Vec<u8>, file_name: &String) -> AssetResult<CompressedImage> {
+        if buff.starts_with(KTX2_MAGIC_BYTES) {
+            Self::read_ktx2(&buff, file_name)
+        } else {
+            Self::read_ktx(&buff, file_name)
+        }
+    }
+
+    fn read_ktx(buff: &[u8], file_name: &String) -> AssetResult<CompressedImage> {
+        let mut r = KTXBuffer {
+            buff,
+            file_name,
+            swap: false,
+        };
+
+        let header = KTX_MAGIC_BYTES.len();
+        r.swap = r.u32(header)? != KTX_ENDIANNESS;
+
+        let gl_type = r.u32(header + 4)?;
+        let internal_format = r.u32(header + 16)?;
+        let width = r.u32(header + 24)?;
+        let height = r.u32(header + 28)?;
+        let depth = r.u32(header + 32)?;
+        let array_len = r.u32(header + 36)?;
+        let faces = r.u32(header + 40)?;
+        let levels = 1.max(r.u32(header + 44)?);
+        let kv_len = r.u32(header + 48)? as usize;
+
+        if gl_type != 0 {
+            return Err(r.error("Only compressed KTX textures are supported"));
+        }
+
+        if depth > 1 || array_len > 0 || faces != 1 {
+            return Err(r.error("Only 2D KTX textures are supported"));
+        }
+
+        let format = gl_format(internal_format).ok_or_else(|| {
+            r.error(&format!(
+                "Unsupported KTX format (glInternalFormat: {:#x})",
+                internal_format
+            ))
+        })?;
+
+        let mut offset = header + 52 + kv_len;
+        let mut images = Vec::new();
+
+        for lvl in 0..levels {
+            let size = r.u32(offset)? as usize;
+            let data = r.bytes(offset + 4, size)?;
+
+            images.push(DDSImage {
+                width: 1.max(width >> lvl),
+                height: 1.max(height >> lvl),
+                data: data.to_vec(),
+            });
+
+            // image data is padded to 4 bytes
+            offset += 4 + (size + 3) / 4 * 4;
+        }
+
+        r.check_levels(format, images)
+    }
+
+    fn read_ktx2(buff: &[u8], file_name: &String) -> AssetResult<CompressedImage> {
+        let r = KTXBuffer {
+            buff,
+            file_name,
+            swap: false,
+        };
+
+        let header = KTX2_MAGIC_BYTES.len();
+        let vk = r.u32(header)?;
+        let width = r.u32(header + 8)?;
+        let height = r.u32(header + 12)?;
+        let depth = r.u32(header + 16)?;
+        let layers = r.u32(header + 20)?;
+        let faces = r.u32(header + 24)?;
+        let levels = 1.max(r.u32(header + 28)?);
+        let supercompression = r.u32(header + 32)?;
+
+        if supercompression != 0 {
+            return Err(r.error("Supercompressed KTX2 textures are not supported"));
+        }
+
+        if depth > 1 || layers > 0 || faces != 1 {
+            return Err(r.error("Only 2D KTX2 textures are supported"));
+        }
+
+        let format = vk_format(vk)
+            .ok_or_else(|| r.error(&format!("Unsupported KTX2 format (vkFormat: {})", vk)))?;
+
+        // level index follows the 36 bytes header and the 32 bytes of dfd, kvd and sgd
+        let index = header + 36 + 32;
+        let mut images = Vec::new();
+
+        for lvl in 0..levels {
+            let entry = index + lvl as usize * 24;
+            let offset = r.u64(entry)? as usize;
+            let size = r.u64(entry + 8)? as usize;
+
+            images.push(DDSImage {
+                width: 1.max(width >> lvl),
+                height: 1.max(height >> lvl),
+                data: r.bytes(offset, size)?.to_vec(),
+            });
+        }
+
+        r.check_levels(format, images)
+    }
+}
@@ -53,6 +53,10 @@ impl Loader<MeshData> for MeshDataLoader {
             normals: Some(normals),
             tangents: None,
             bitangents: None,
+            colors: None,
+            joints: None,
+            weights: None,
+            morph_targets: Vec::new(),
         })
     }
 }
@@ -4,9 +4,13 @@ mod shader;
 mod mesh_data;
 mod prefab;
 mod dds;
+mod ktx;
+mod cube_lut;
 
 pub use self::loader::{Loadable, Loader};
 pub use self::image::ImageLoader;
+pub use self::cube_lut::CubeLutLoader;
 pub use self::shader::{ShaderFSLoader, ShaderVSLoader};
 pub use self::prefab::{ObjMaterial, Prefab, PrefabLoader};
-pub use self::dds::DDS;
+pub use self::dds::{DDSImage, DDS};
+pub use self::ktx::KTXReader;
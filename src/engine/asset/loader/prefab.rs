@@ -371,6 +371,10 @@ impl PrefabLoader {
                     uvs: uv_array,
                     tangents: tangent_space.tangents,
                     bitangents: tangent_space.bitangents,
+                    colors: None,
+                    joints: None,
+                    weights: None,
+                    morph_targets: Vec::new(),
                     normals: n_array,
                 };
 
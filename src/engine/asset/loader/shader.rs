@@ -1,7 +1,7 @@
 use engine::asset::loader::{Loadable, Loader};
 use engine::asset::{AssetError, AssetResult, AssetSystem, File, FileFuture};
-use engine::render::{PreprocessedShaderCode, Shader, ShaderKind, ShaderKindFs, ShaderKindProvider,
-                     ShaderKindVs};
+use engine::render::{preprocess_error, PreprocessedShaderCode, Shader, ShaderKind, ShaderKindFs,
+                     ShaderKindProvider, ShaderKindVs};
 
 use std::str;
 use std::marker::PhantomData;
@@ -28,7 +28,8 @@ where
             reason: format!("{:?}", e),
         })?;
 
-        let code = PreprocessedShaderCode::new(T::kind(), s, &HashMap::new()).unwrap();
+        let code = PreprocessedShaderCode::new(T::kind(), s, &HashMap::new())
+            .map_err(|e| preprocess_error(T::kind(), &file.name(), e))?;
         Ok(Shader::<T>::from_preprocessed(&file.name(), code))
     }
 }
@@ -152,11 +153,7 @@ where
         extern_files: HashMap::new(),
     };
 
-    Box::new(pcode_future.map_err(move |e| AssetError::InvalidFormat {
-        path: filename,
-        len: buf.len(),
-        reason: format!("{:?}", e),
-    }))
+    Box::new(pcode_future.map_err(move |e| preprocess_error(kind, &filename, e)))
 }
 
 impl<T: ShaderKindProvider> Loadable for Shader<T> {
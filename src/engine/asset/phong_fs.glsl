@@ -3,69 +3,123 @@
 #define gl_FragColor FragColor
 out vec4 FragColor;
 #define texture2D texture
+#define textureCube texture
 #endif
 
 #define UNI_POINT_LIGHTS 4
 
-struct DirectionalLight {
-    vec3 direction;
-  
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-};
-
-struct PointLight {
-    vec3 position;
-    
-    float constant;
-    float linear;
-    float quadratic;
-	
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-
-    float rate;
-};
+#include "unrust/frame_data.glsl"
 
 struct Material {
     sampler2D diffuse;
     float shininess;
 };
 
-uniform vec3 uViewPos;
 uniform Material uMaterial;
 
+// With the ALPHA_TEST keyword, fragments with a diffuse alpha under uAlphaCutoff are discarded
+#ifdef ALPHA_TEST
+uniform float uAlphaCutoff;
+#endif
+
 varying vec3 vFragPos;
 varying vec2 vTexCoords;       
 varying vec3 vNormal;                       
 
-// Lights
-uniform DirectionalLight uDirectionalLight;
-uniform PointLight uPointLights[UNI_POINT_LIGHTS];
+// Screen space ambient occlusion, set by SsaoPass
+uniform bool uSsaoEnabled;
+uniform sampler2D uSsaoTexture;
+uniform vec4 uSsaoViewport;
+
+float AmbientOcclusion()
+{
+    if (!uSsaoEnabled) {
+        return 1.0;
+    }
+
+    return texture2D(uSsaoTexture, (gl_FragCoord.xy - uSsaoViewport.xy) / uSsaoViewport.zw).r;
+}
+
+// Scene-wide fog, uFog is in the per-frame data, see "unrust/fog.glsl"
+uniform samplerCube uFogSky;
+
+vec3 FogColor(vec3 viewDir)
+{
+    if (uFog.sky) {
+        return textureCube(uFogSky, viewDir).rgb;
+    }
+
+    return uFog.color;
+}
+
+// Average density along the ray from the eye, relative to the density at uFog.height
+float FogHeightFactor(vec3 eye, vec3 fragPos)
+{
+    if (uFog.height_falloff <= 0.0) {
+        return 1.0;
+    }
+
+    float k = uFog.height_falloff * (fragPos.y - eye.y);
+    float average = abs(k) > 0.001 ? (1.0 - exp(-k)) / k : 1.0;
+
+    return exp(-uFog.height_falloff * (eye.y - uFog.height)) * average;
+}
 
-vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir);
-vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir);
+float FogFactor(vec3 eye, vec3 fragPos)
+{
+    float d = length(fragPos - eye) * FogHeightFactor(eye, fragPos);
+
+    if (uFog.mode == 1) {
+        return clamp((d - uFog.start) / max(uFog.end - uFog.start, 0.0001), 0.0, 1.0);
+    } else if (uFog.mode == 2) {
+        return 1.0 - exp(-uFog.density * d);
+    } else if (uFog.mode == 3) {
+        float dd = uFog.density * d;
+        return 1.0 - exp(-dd * dd);
+    }
+
+    return 0.0;
+}
+
+vec3 ApplyFog(vec3 color, vec3 eye, vec3 fragPos)
+{
+    if (uFog.mode == 0) {
+        return color;
+    }
+
+    vec3 viewDir = normalize(fragPos - eye);
+    return mix(color, FogColor(viewDir), FogFactor(eye, fragPos));
+}
+
+vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir, float lit);
+vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir, float lit);
 
 void main(void) {
+#ifdef ALPHA_TEST
+    if (texture2D(uMaterial.diffuse, vTexCoords).a < uAlphaCutoff) {
+        discard;
+    }
+#endif
+
     vec3 norm = normalize(vNormal);
     vec3 viewDir = normalize(uViewPos - vFragPos);
 
     // Directional Light
-    vec3 result = CalcDirectionalLight(uDirectionalLight, norm, viewDir);
+    vec3 result = CalcDirectionalLight(uDirectionalLight, norm, viewDir, LightFactor(0));
     
     // Point Lights
     for(int i = 0; i < UNI_POINT_LIGHTS; i++)
-        result += CalcPointLight(uPointLights[i], norm, vFragPos, viewDir);
+        result += CalcPointLight(uPointLights[i], norm, vFragPos, viewDir, LightFactor(i + 1));
+
+    result = ApplyFog(result, uViewPos, vFragPos);
 
     gl_FragColor = vec4(result, 1.0);           
 }
 
-vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir)
+vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir, float lit)
 {
     // diffuse
-    vec3 ambient = light.ambient * vec3(texture2D(uMaterial.diffuse, vTexCoords));
+    vec3 ambient = light.ambient * vec3(texture2D(uMaterial.diffuse, vTexCoords)) * AmbientOcclusion();
 
     vec3 lightDir = normalize(-light.direction);  
     float diff = max(dot(normal, lightDir), 0.0);
@@ -76,10 +130,10 @@ vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir)
     float spec = pow(max(dot(viewDir, reflectDir), 0.0), uMaterial.shininess);
     vec3 specular = light.specular * spec; 
 
-    return ambient + diffuse + specular;
+    return ambient + (diffuse + specular) * lit;
 }
 
-vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir)
+vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir, float lit)
 {
     vec3 lightDir = normalize(light.position - fragPos);
     
@@ -92,10 +146,10 @@ vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir)
     // attenuation
     float distance = length(light.position - fragPos);
     float d = (light.constant + light.linear * distance + light.quadratic * (distance * distance));
-    float attenuation = 1.0 / max(d, 0.001);
+    float attenuation = SpotFactor(light, fragPos) / max(d, 0.001);
     
     // combine results
-    vec3 ambient = light.ambient * vec3(texture2D(uMaterial.diffuse, vTexCoords));
+    vec3 ambient = light.ambient * vec3(texture2D(uMaterial.diffuse, vTexCoords)) * AmbientOcclusion();
     vec3 diffuse = light.diffuse * diff * vec3(texture2D(uMaterial.diffuse, vTexCoords));
     vec3 specular = light.specular * spec;
     
@@ -103,5 +157,5 @@ vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir)
     diffuse *= attenuation;
     specular *= attenuation;
     
-    return (ambient + diffuse + specular) * light.rate;        
+    return (ambient + (diffuse + specular) * lit) * light.rate;        
 }
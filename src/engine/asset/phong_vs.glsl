@@ -7,19 +7,26 @@ attribute vec3 aVertexPosition;
 attribute vec3 aVertexNormal;
 attribute vec2 aTextureCoord;
 
+#include "unrust/frame_data.glsl"
+
 uniform mat4 uMVMatrix;
-uniform mat4 uPMatrix;
 uniform mat4 uNMatrix;
 uniform mat4 uMMatrix;
 
+#include "unrust/skinning.glsl"
+#include "unrust/morph.glsl"
+
 varying vec3 vFragPos;
 varying vec3 vNormal;
 varying vec2 vTexCoords;
 
 void main(void) {
-    vFragPos = vec3(uMMatrix * vec4(aVertexPosition, 1.0));            
-    vNormal = mat3(uNMatrix) * aVertexNormal;
+    mat4 skin = SkinMatrix();
+    vec4 position = skin * vec4(MorphPosition(aVertexPosition), 1.0);
+
+    vFragPos = vec3(uMMatrix * position);
+    vNormal = mat3(uNMatrix) * mat3(skin) * aVertexNormal;
     vTexCoords = aTextureCoord;
 
-    gl_Position = uPMatrix * uMVMatrix * vec4(aVertexPosition, 1.0);
+    gl_Position = uPMatrix * uMVMatrix * position;
 }
@@ -123,6 +123,10 @@ impl CubeMesh {
             indices: indices,
             tangents: None,
             bitangents: None,
+            colors: None,
+            joints: None,
+            weights: None,
+            morph_targets: Vec::new(),
         }
     }
 }
@@ -164,6 +168,10 @@ impl PlaneMesh {
             indices: indices,
             tangents: None,
             bitangents: None,
+            colors: None,
+            joints: None,
+            weights: None,
+            morph_targets: Vec::new(),
         }
     }
 }
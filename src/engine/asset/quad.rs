@@ -30,6 +30,10 @@ impl QuadMesh {
             indices: indices,
             tangents: None,
             bitangents: None,
+            colors: None,
+            joints: None,
+            weights: None,
+            morph_targets: Vec::new(),
         }
     }
 }
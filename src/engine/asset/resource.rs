@@ -2,8 +2,10 @@ use futures::{Async, Future};
 use std::cell::{Ref, RefCell};
 use std::fmt;
 use std::fmt::Debug;
+use std::io;
 use std::mem;
 
+use engine::asset::fs::FileIoError;
 use engine::asset::loader;
 use engine::asset::{AssetError, AssetResult};
 
@@ -16,6 +18,7 @@ where
             &ResourceKind::Consumed => write!(f, "ResourceKind::Consumed"),
             &ResourceKind::Data(ref t) => write!(f, "ResourceKind::Data({:?})", *t),
             &ResourceKind::Future(_) => write!(f, "ResourceKind::Future"),
+            &ResourceKind::Failed(ref e) => write!(f, "ResourceKind::Failed({:?})", e),
         }
     }
 }
@@ -24,6 +27,8 @@ enum ResourceKind<T: Debug> {
     Consumed,
     Data(T),
     Future(Box<Future<Item = T, Error = AssetError>>),
+    /// The future failed, the error is kept as a finished future must not be polled again
+    Failed(AssetError),
 }
 
 impl<T: Debug> ResourceKind<T> {
@@ -33,13 +38,6 @@ impl<T: Debug> ResourceKind<T> {
 }
 
 impl<T: Debug> ResourceKind<T> {
-    fn try_into_data(self) -> Option<T> {
-        match self {
-            ResourceKind::Data(d) => Some(d),
-            _ => None,
-        }
-    }
-
     fn try_as_data(&self) -> Option<&T> {
         match self {
             &ResourceKind::Data(ref d) => Some(d),
@@ -63,50 +61,101 @@ impl<T: Debug + loader::Loadable> Resource<T> {
         Resource(RefCell::new(ResourceKind::Data(f)))
     }
 
-    pub fn try_into(&self) -> AssetResult<T> {
-        match &mut *self.0.borrow_mut() {
-            &mut ResourceKind::Future(ref mut f) => {
-                return match f.poll() {
-                    Err(e) => Err(e),
-                    Ok(Async::NotReady) => Err(AssetError::NotReady),
-                    Ok(Async::Ready(i)) => Ok(i),
-                };
-            }
+    /// Resource of the result of a loader, an error is returned by each access
+    pub fn from_result(r: AssetResult<T>) -> Self {
+        match r {
+            Ok(t) => Resource::new(t),
+            Err(e) => Resource(RefCell::new(ResourceKind::Failed(e))),
+        }
+    }
 
-            img @ &mut ResourceKind::Data(_) => {
-                let r = img.replace(ResourceKind::Consumed);
-                Ok(r.try_into_data().unwrap())
-            }
+    pub fn try_into(&self) -> AssetResult<T> {
+        self.poll()?;
 
+        match self.0.borrow_mut().replace(ResourceKind::Consumed) {
+            ResourceKind::Data(d) => Ok(d),
             _ => unreachable!(),
         }
     }
 
     pub fn try_borrow(&self) -> AssetResult<Ref<T>> {
-        let mut data = None;
-
-        if let &mut ResourceKind::Future(ref mut f) = &mut *self.0.borrow_mut() {
-            match f.poll() {
-                Err(e) => return Err(e),
-                Ok(Async::NotReady) => return Err(AssetError::NotReady),
-                Ok(Async::Ready(i)) => {
-                    data = Some(i);
-                }
-            }
-        }
-
-        if let Some(i) = data {
-            let kind: &mut ResourceKind<T> = &mut self.0.borrow_mut();
-            kind.replace(ResourceKind::Data(i));
-        }
+        self.poll()?;
 
         let b0 = self.0.borrow();
         return Ok(Ref::map(b0, |t| t.try_as_data().unwrap()));
     }
 
+    /// Poll the future into data, a failure is kept and returned again on next polls
+    fn poll(&self) -> AssetResult<()> {
+        let kind: &mut ResourceKind<T> = &mut self.0.borrow_mut();
+
+        let r = match *kind {
+            ResourceKind::Future(ref mut f) => f.poll(),
+            ResourceKind::Failed(ref e) => return Err(copy_error(e)),
+            _ => return Ok(()),
+        };
+
+        match r {
+            Ok(Async::NotReady) => Err(AssetError::NotReady),
+            Ok(Async::Ready(i)) => {
+                kind.replace(ResourceKind::Data(i));
+                Ok(())
+            }
+            Err(AssetError::NotReady) => Err(AssetError::NotReady),
+            Err(e) => {
+                let copy = copy_error(&e);
+                kind.replace(ResourceKind::Failed(e));
+                Err(copy)
+            }
+        }
+    }
+
     pub fn replace(&self, t: T) {
         self.0.borrow_mut().replace(ResourceKind::Data(t));
     }
+
+    /// Take the content of another resource, e.g. a future reloading the same file
+    pub fn reload(&self, other: Resource<T>) {
+        *self.0.borrow_mut() = other.0.into_inner();
+    }
+}
+
+/// AssetError is not Clone as it may carry an io error
+fn copy_error(e: &AssetError) -> AssetError {
+    match *e {
+        AssetError::NotReady => AssetError::NotReady,
+        AssetError::ReadBufferFail(ref s) => AssetError::ReadBufferFail(s.clone()),
+        AssetError::InvalidFormat {
+            ref path,
+            len,
+            ref reason,
+        } => AssetError::InvalidFormat {
+            path: path.clone(),
+            len,
+            reason: reason.clone(),
+        },
+        AssetError::FileIoError(ref e) => AssetError::FileIoError(match *e {
+            FileIoError::NotReady => FileIoError::NotReady,
+            FileIoError::NoSuchFile(ref s) => FileIoError::NoSuchFile(s.clone()),
+            FileIoError::IoError(ref e) => {
+                FileIoError::IoError(io::Error::new(e.kind(), e.to_string()))
+            }
+            FileIoError::Unknown(ref s) => FileIoError::Unknown(s.clone()),
+        }),
+        AssetError::ShaderCompile {
+            ref program,
+            stage,
+            ref log,
+        } => AssetError::ShaderCompile {
+            program: program.clone(),
+            stage,
+            log: log.clone(),
+        },
+        AssetError::UnsupportedFormat { ref path, format } => AssetError::UnsupportedFormat {
+            path: path.clone(),
+            format,
+        },
+    }
 }
 
 impl<T: Debug + loader::Loadable> From<T> for Resource<T> {
@@ -1,15 +1,16 @@
 use engine::asset::AssetResult;
 use engine::core::Component;
 use engine::engine::EngineStats;
-use engine::render::{CullMode, DepthTest, Material, MaterialState, MeshBuffer, ShaderProgram,
-                     Texture};
+use engine::render::{BlendEquation, BlendFactor, BlendState, CullMode, DepthTest, Material,
+                     MaterialState, MeshBuffer, PolygonOffset, Scissor, ShaderProgram, StencilOp,
+                     StencilState, Texture};
 use std::collections::VecDeque;
 use std::rc::{Rc, Weak};
 use std::sync::Arc;
 use uni_gl;
 use uni_gl::{Culling, Flag, WebGLRenderingContext};
 
-trait ToGLState<T> {
+pub(crate) trait ToGLState<T> {
     fn as_gl_state(&self) -> T;
 }
 
@@ -28,6 +29,48 @@ impl ToGLState<uni_gl::DepthTest> for DepthTest {
     }
 }
 
+impl ToGLState<uni_gl::BlendMode> for BlendFactor {
+    fn as_gl_state(&self) -> uni_gl::BlendMode {
+        match self {
+            &BlendFactor::Zero => uni_gl::BlendMode::Zero,
+            &BlendFactor::One => uni_gl::BlendMode::One,
+            &BlendFactor::SrcColor => uni_gl::BlendMode::SrcColor,
+            &BlendFactor::OneMinusSrcColor => uni_gl::BlendMode::OneMinusSrcColor,
+            &BlendFactor::DstColor => uni_gl::BlendMode::DstColor,
+            &BlendFactor::OneMinusDstColor => uni_gl::BlendMode::OneMinusDstColor,
+            &BlendFactor::SrcAlpha => uni_gl::BlendMode::SrcAlpha,
+            &BlendFactor::OneMinusSrcAlpha => uni_gl::BlendMode::OneMinusSrcAlpha,
+            &BlendFactor::DstAlpha => uni_gl::BlendMode::DstAlpha,
+            &BlendFactor::OneMinusDstAlpha => uni_gl::BlendMode::OneMinusDstAlpha,
+        }
+    }
+}
+
+impl ToGLState<uni_gl::BlendEquation> for BlendEquation {
+    fn as_gl_state(&self) -> uni_gl::BlendEquation {
+        match self {
+            &BlendEquation::Add => uni_gl::BlendEquation::FuncAdd,
+            &BlendEquation::Subtract => uni_gl::BlendEquation::FuncSubtract,
+            &BlendEquation::ReverseSubtract => uni_gl::BlendEquation::FuncReverseSubtract,
+        }
+    }
+}
+
+impl ToGLState<uni_gl::StencilAction> for StencilOp {
+    fn as_gl_state(&self) -> uni_gl::StencilAction {
+        match self {
+            &StencilOp::Keep => uni_gl::StencilAction::Keep,
+            &StencilOp::Zero => uni_gl::StencilAction::Zero,
+            &StencilOp::Replace => uni_gl::StencilAction::Replace,
+            &StencilOp::Increment => uni_gl::StencilAction::Incr,
+            &StencilOp::IncrementWrap => uni_gl::StencilAction::IncrWrap,
+            &StencilOp::Decrement => uni_gl::StencilAction::Decr,
+            &StencilOp::DecrementWrap => uni_gl::StencilAction::DecrWrap,
+            &StencilOp::Invert => uni_gl::StencilAction::Invert,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct StateCache {
     state: MaterialState,
@@ -40,7 +83,12 @@ impl StateCache {
             cull: Some(CullMode::Back),
             depth_test: Some(DepthTest::Less),
             alpha_blending: Some(false),
+            blend: Some(BlendState::alpha()),
+            color_mask: Some((true, true, true, true)),
             depth_write: Some(true),
+            stencil: Some(StencilState::default()),
+            polygon_offset: Some(PolygonOffset::default()),
+            scissor: Some(Scissor::Off),
         }
     }
 
@@ -50,6 +98,18 @@ impl StateCache {
         ms.depth_write.map(|s| self.curr.depth_write = Some(s));
         ms.alpha_blending
             .map(|s| self.curr.alpha_blending = Some(s));
+        ms.blend.map(|s| self.curr.blend = Some(s));
+        ms.color_mask.map(|s| self.curr.color_mask = Some(s));
+        ms.stencil.map(|s| self.curr.stencil = Some(s));
+        ms.polygon_offset
+            .map(|s| self.curr.polygon_offset = Some(s));
+        ms.scissor.map(|s| self.curr.scissor = Some(s));
+    }
+
+    /// Set the scissor right away, used to restrict the clearing to a camera rect
+    pub fn set_scissor(&mut self, gl: &WebGLRenderingContext, sc: Scissor) {
+        self.curr.scissor = Some(sc);
+        self.apply_scissor(gl, &sc);
     }
 
     pub fn commit(&mut self, gl: &WebGLRenderingContext) {
@@ -59,6 +119,99 @@ impl StateCache {
         self.curr
             .alpha_blending
             .map(|s| self.apply_alpha_blending(gl, s));
+        self.curr.blend.map(|s| self.apply_blend(gl, &s));
+        self.curr.color_mask.map(|s| self.apply_color_mask(gl, s));
+        self.curr.stencil.map(|s| self.apply_stencil(gl, &s));
+        self.curr
+            .polygon_offset
+            .map(|s| self.apply_polygon_offset(gl, &s));
+        self.curr.scissor.map(|s| self.apply_scissor(gl, &s));
+    }
+
+    fn apply_blend(&mut self, gl: &WebGLRenderingContext, b: &BlendState) {
+        if let Some(s) = self.state.blend {
+            if s == *b {
+                return;
+            }
+        }
+
+        gl.blend_equation(b.equation.as_gl_state());
+        gl.blend_func(b.src.as_gl_state(), b.dst.as_gl_state());
+
+        self.state.blend = Some(*b);
+    }
+
+    fn apply_color_mask(&mut self, gl: &WebGLRenderingContext, m: (bool, bool, bool, bool)) {
+        if let Some(s) = self.state.color_mask {
+            if s == m {
+                return;
+            }
+        }
+
+        gl.color_mask(m.0, m.1, m.2, m.3);
+        self.state.color_mask = Some(m);
+    }
+
+    fn apply_stencil(&mut self, gl: &WebGLRenderingContext, st: &StencilState) {
+        if let Some(s) = self.state.stencil {
+            if s == *st {
+                return;
+            }
+        }
+
+        if st.is_enabled() {
+            gl.enable(Flag::StencilTest as i32);
+            gl.stencil_func(st.test.as_gl_state(), st.reference, st.read_mask);
+            gl.stencil_op(
+                st.fail.as_gl_state(),
+                st.depth_fail.as_gl_state(),
+                st.pass.as_gl_state(),
+            );
+        } else {
+            gl.disable(Flag::StencilTest as i32);
+        }
+
+        // the write mask also affects clearing, so always set it
+        gl.stencil_mask(st.write_mask);
+
+        self.state.stencil = Some(*st);
+    }
+
+    fn apply_polygon_offset(&mut self, gl: &WebGLRenderingContext, po: &PolygonOffset) {
+        if let Some(s) = self.state.polygon_offset {
+            if s == *po {
+                return;
+            }
+        }
+
+        if po.is_enabled() {
+            gl.enable(Flag::PolygonOffsetFill as i32);
+            gl.polygon_offset(po.factor, po.units);
+        } else {
+            gl.disable(Flag::PolygonOffsetFill as i32);
+        }
+
+        self.state.polygon_offset = Some(*po);
+    }
+
+    fn apply_scissor(&mut self, gl: &WebGLRenderingContext, sc: &Scissor) {
+        if let Some(s) = self.state.scissor {
+            if s == *sc {
+                return;
+            }
+        }
+
+        match sc {
+            &Scissor::Off => {
+                gl.disable(Flag::ScissorTest as i32);
+            }
+            &Scissor::Rect((x, y), (w, h)) => {
+                gl.enable(Flag::ScissorTest as i32);
+                gl.scissor(x, y, w, h);
+            }
+        }
+
+        self.state.scissor = Some(*sc);
     }
 
     fn apply_depth_write(&mut self, gl: &WebGLRenderingContext, b: bool) {
@@ -141,6 +294,7 @@ pub struct EngineContext {
 
     pub main_light: Option<Arc<Component>>,
     pub point_lights: Vec<Arc<Component>>,
+    pub fog: Option<Arc<Component>>,
 
     pub switch_mesh: u32,
     pub switch_prog: u32,
@@ -150,7 +304,15 @@ pub struct EngineContext {
     pub states: StateCache,
 
     pub last_light_bound: Option<Weak<ShaderProgram>>,
+    /// Which lights were enabled in the last light binding, bit 0 is the main light
+    pub last_light_mask: u32,
     pub last_material_bound: Option<Weak<Material>>,
+    /// Cubemap bound to `FOG_SKY_UNIT` for the pass
+    pub fog_sky: Weak<Texture>,
+    /// Whether `fog_sky` is the sky of the fog, and not the default black one
+    pub fog_sky_ready: bool,
+    /// Last program which got the per-frame uniforms without the uniform block
+    pub last_frame_bound: Option<Weak<ShaderProgram>>,
 }
 
 impl EngineContext {
@@ -162,6 +324,7 @@ impl EngineContext {
 
             main_light: Default::default(),
             point_lights: Default::default(),
+            fog: Default::default(),
 
             switch_mesh: 0,
             switch_prog: 0,
@@ -171,7 +334,11 @@ impl EngineContext {
 
             states: Default::default(),
             last_light_bound: None,
+            last_light_mask: 0,
             last_material_bound: None,
+            fog_sky: Weak::new(),
+            fog_sky_ready: false,
+            last_frame_bound: None,
         }
     }
 }
@@ -193,7 +360,11 @@ pub trait EngineCacher {
 impl_cacher!(prog, ShaderProgram);
 impl_cacher!(mesh_buffer, MeshBuffer);
 
-const MAX_TEXTURE_UNITS: u32 = 8;
+/// Units cached for the material textures
+const MAX_TEXTURE_UNITS: u32 = 7;
+
+/// Unit reserved for the sky of the fog, bound once for each pass
+pub const FOG_SKY_UNIT: u32 = 7;
 
 impl EngineContext {
     #[cfg_attr(feature = "flame_it", flame)]
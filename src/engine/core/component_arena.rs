@@ -1,5 +1,5 @@
 use std::any::{Any, TypeId};
-use std::cell::{Ref, RefCell};
+use std::cell::{Cell, Ref, RefCell};
 use std::collections::HashMap;
 use std::rc::Rc;
 use typed_arena::Arena;
@@ -64,6 +64,7 @@ impl<T> ComponentContainer<T> {
 
 pub struct ComponentArena {
     arenas: RefCell<HashMap<TypeId, Box<Any>>>,
+    generation: Cell<u64>,
 }
 
 impl ComponentArena {
@@ -113,9 +114,20 @@ impl ComponentArena {
         self.container().as_vec()
     }
 
+    /// Changes whenever a component is added to or removed from a game object,
+    /// such that caches built from the components of the scene can be invalidated
+    pub fn generation(&self) -> u64 {
+        self.generation.get()
+    }
+
+    pub(crate) fn touch(&self) {
+        self.generation.set(self.generation.get() + 1);
+    }
+
     pub fn new() -> ComponentArena {
         ComponentArena {
             arenas: Default::default(),
+            generation: Cell::new(0),
         }
     }
 }
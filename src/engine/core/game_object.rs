@@ -12,6 +12,12 @@ use super::scene_tree::{ComponentEvent, NodeTransform, SceneTree};
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
 
+/// Layer mask of game objects which are not assigned to any specific layer
+pub const DEFAULT_LAYER: u32 = 1;
+
+/// Culling mask which includes every layer
+pub const ALL_LAYERS: u32 = !0;
+
 fn next_component_id() -> u64 {
     static CURR_COMPONENT_COUNTER: AtomicU32 = AtomicU32::new(1);;
 
@@ -123,6 +129,7 @@ impl GameObjectUtil {
             transform: Transform::new(node_id, tree),
             arena: Rc::downgrade(arena),
             active: true,
+            layer: DEFAULT_LAYER,
             components: vec![],
         }
     }
@@ -225,6 +232,9 @@ impl Transform {
 pub struct GameObject {
     pub transform: Transform,
     pub active: bool,
+    /// Bit mask of the layers this object belongs to,
+    /// it is only rendered by cameras whose culling mask contains one of them.
+    pub layer: u32,
     components: Vec<Arc<Component>>,
     arena: rc::Weak<ComponentArena>,
 }
@@ -235,6 +245,7 @@ impl GameObject {
         Rc::new(RefCell::new(GameObject {
             transform: Transform::new(0, rc::Weak::new()),
             active: true,
+            layer: DEFAULT_LAYER,
             arena: rc::Weak::new(),
             components: vec![],
         }))
@@ -278,8 +289,10 @@ impl GameObject {
     where
         T: IntoComponentPtr,
     {
-        let p: Arc<Component> = c.into_component_ptr(&self.arena.upgrade().unwrap());
+        let arena = self.arena.upgrade().unwrap();
+        let p: Arc<Component> = c.into_component_ptr(&arena);
         self.components.push(p.clone());
+        arena.touch();
 
         self.tree()
             .notifiy_component(ComponentEvent::Add, self.transform.node_id, p.clone());
@@ -289,6 +302,7 @@ impl GameObject {
 
     pub fn remove_component(&mut self, c: Arc<Component>) {
         self.components.retain(|cc| !Arc::ptr_eq(&cc, &c));
+        self.arena.upgrade().map(|a| a.touch());
 
         self.tree()
             .notifiy_component(ComponentEvent::Remove, self.transform.node_id, c.clone());
@@ -297,6 +311,7 @@ impl GameObject {
     pub fn clear_components(&mut self) {
         let mut coms = Vec::new();
         coms.append(&mut self.components);
+        self.arena.upgrade().map(|a| a.touch());
 
         for c in coms.into_iter() {
             self.tree().notifiy_component(
@@ -1,5 +1,4 @@
-use math::InnerSpace;
-use math::Vector3f;
+use math::{EuclideanSpace, InnerSpace, Matrix4f, Point3, Transform, Vector3f};
 use std::default::Default;
 
 #[derive(Copy, Clone, Debug)]
@@ -69,4 +68,40 @@ impl Aabb {
 
         (center, (self.max - center).magnitude())
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn center(&self) -> Vector3f {
+        (self.max + self.min) * 0.5
+    }
+
+    /// Half size of the box along each axis
+    pub fn extents(&self) -> Vector3f {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Compute the aabb which encloses this box after transformed by `m`
+    ///
+    /// Uses Arvo's method, so rotations and non-uniform scales of
+    /// all parents are taken into account.
+    pub fn transform(&self, m: &Matrix4f) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+
+        let center = m.transform_point(Point3::from_vec(self.center())).to_vec();
+        let e = self.extents();
+
+        let mut ext = Vector3f::new(0.0, 0.0, 0.0);
+        for i in 0..3 {
+            ext[i] = m.x[i].abs() * e.x + m.y[i].abs() * e.y + m.z[i].abs() * e.z;
+        }
+
+        Aabb {
+            min: center - ext,
+            max: center + ext,
+        }
+    }
 }
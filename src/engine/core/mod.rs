@@ -4,7 +4,8 @@ mod math;
 mod scene_tree;
 
 pub use self::component_arena::ComponentArena;
-pub use self::game_object::{Component, ComponentBased, ComponentType, GameObject, IntoComponentPtr};
+pub use self::game_object::{Component, ComponentBased, ComponentType, GameObject, IntoComponentPtr,
+                            ALL_LAYERS, DEFAULT_LAYER};
 pub use self::math::*;
 pub use self::scene_tree::{ComponentEvent, SceneTree};
 
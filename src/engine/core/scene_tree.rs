@@ -40,6 +40,7 @@ pub struct SceneTree {
     root: Rc<RefCell<GameObject>>,
     nodes: RefCell<BTreeMap<u64, Node>>,
     curr_id: Cell<u64>,
+    generation: Cell<u64>,
     weak_self: RefCell<Weak<SceneTree>>,
 
     component_watcher:
@@ -60,6 +61,7 @@ impl SceneTree {
             root: GameObject::empty(),
             weak_self: RefCell::new(Weak::new()),
             curr_id: Cell::new(1),
+            generation: Cell::new(0),
             component_watcher: Default::default(),
         };
 
@@ -110,6 +112,7 @@ impl SceneTree {
         )));
 
         self.curr_id.set(id + 1);
+        self.touch();
         let parent_id = GameObjectUtil::node_id(parent_go);
 
         // Not root
@@ -145,11 +148,17 @@ impl SceneTree {
         parent_node.children.retain(|&x| x != node_id);
         drop(parent_node);
 
-        for child_id in children_id {
-            let child_node = nodes.get_mut(&child_id).unwrap();
+        for child_id in children_id.iter() {
+            let child_node = nodes.get_mut(child_id).unwrap();
             // Root adapted.
             child_node.parent = 0;
         }
+        drop(nodes);
+
+        self.touch();
+        for child_id in children_id.into_iter() {
+            self.set_dirty(child_id);
+        }
     }
 
     pub fn add_child(&self, parent_id: u64, child_id: u64) -> Rc<RefCell<GameObject>> {
@@ -168,7 +177,11 @@ impl SceneTree {
         let parent_node = nodes.get_mut(&old_parent_id).unwrap();
         parent_node.children.retain(|&x| x != child_id);
 
-        parent_node.go.upgrade().unwrap_or(self.root.clone())
+        let old_parent = parent_node.go.upgrade().unwrap_or(self.root.clone());
+        drop(nodes);
+
+        self.set_dirty(child_id);
+        old_parent
     }
 
     pub fn set_local_transform(&self, node_id: u64, t: NodeTransform) {
@@ -176,26 +189,39 @@ impl SceneTree {
         let n = nodes.get_mut(&node_id).unwrap();
 
         n.transform = t;
-        n.dirty = true;
         drop(nodes);
 
         // set all child
         self.set_dirty(node_id);
     }
 
+    /// Invalidate the cached global matrix of a node and all its descendants
     pub fn set_dirty(&self, node_id: u64) {
-        let mut nodes = self.nodes.borrow_mut();
-        let n = nodes.get_mut(&node_id).unwrap();
+        self.touch();
 
-        if n.dirty {
-            return;
-        }
+        let children = {
+            let mut nodes = self.nodes.borrow_mut();
+            let n = nodes.get_mut(&node_id).unwrap();
+
+            n.dirty = true;
+            n.children.clone()
+        };
 
-        for c in n.children.iter() {
-            self.set_dirty(*c);
+        for c in children.into_iter() {
+            self.set_dirty(c);
         }
     }
 
+    /// Changes whenever a node is added, removed, reparented or moved,
+    /// such that caches built from the global transforms can be invalidated
+    pub fn generation(&self) -> u64 {
+        self.generation.get()
+    }
+
+    fn touch(&self) {
+        self.generation.set(self.generation.get() + 1);
+    }
+
     pub fn get_local_transform(&self, node_id: u64) -> NodeTransform {
         let nodes = self.nodes.borrow();
         nodes.get(&node_id).unwrap().transform
@@ -1,18 +1,23 @@
 use math::*;
 use uni_gl::*;
 
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell};
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::rc::{Rc, Weak};
-use std::sync::Arc;
+use std::sync::{self, Arc};
 
 use engine::asset::{AssetError, AssetResult, AssetSystem};
-use engine::context::EngineContext;
+use engine::context::{EngineContext, FOG_SKY_UNIT};
 use engine::core::{Component, ComponentArena, ComponentBased, GameObject, SceneTree};
-use engine::render::Camera;
-use engine::render::{DepthTest, DirectionalLight, Light, Material, MaterialState, Mesh,
-                     MeshSurface, ShaderProgram};
-use engine::render::{Frustum, RenderQueue};
+use engine::render::{Camera, CompressedFormat};
+use engine::render::{default_render_queues, find_render_queue_desc};
+use engine::render::{DirectionalLight, Light, Material, MaterialParamMap, Mesh, MeshSurface,
+                     ShaderProgram};
+use engine::render::{Fog, SkinnedMesh, Texture, JOINT_MATRIX_UNIFORMS};
+use engine::render::{Frustum, Intersection, Ray, RenderQueue, RenderQueueDesc, RenderQueueSort};
+use engine::render::{PointLight, Scissor, Std140Writer, UniformBuffer, FRAME_BLOCK_BINDING,
+                     FRAME_BLOCK_NAME};
+use uni_app;
 use image;
 use math::Aabb;
 
@@ -43,6 +48,16 @@ pub struct EngineStats {
     pub total_transparent_count: u32,
 }
 
+impl EngineStats {
+    fn accumulate(&mut self, other: &EngineStats) {
+        self.surfaces_count += other.surfaces_count;
+        self.opaque_count += other.opaque_count;
+        self.transparent_count += other.transparent_count;
+        self.total_opaque_count += other.total_opaque_count;
+        self.total_transparent_count += other.total_transparent_count;
+    }
+}
+
 pub struct Engine<A>
 where
     A: AssetSystem,
@@ -56,6 +71,15 @@ where
     pub current_camera: RefCell<Option<Arc<Component>>>,
     pub gui_context: Rc<RefCell<imgui::Context>>,
     pub arena: Rc<ComponentArena>,
+    pub render_queues: BTreeMap<RenderQueue, RenderQueueDesc>,
+
+    frame_uniforms: UniformBuffer,
+    start_time: f64,
+    /// Incremented by `begin`, to compute the per-frame data once for all passes
+    frame_index: u64,
+    /// Bound to `uFogSky` when the fog has no sky
+    default_fog_sky: Rc<Texture>,
+    camera_cache: RefCell<CameraCache>,
 
     pub stats: EngineStats,
 }
@@ -64,11 +88,66 @@ struct RenderCommand {
     pub surface: Rc<MeshSurface>,
     pub model_m: Matrix4<f32>,
     pub cam_distance: f32,
+    pub layer: u32,
+    /// `SkinnedMesh` of the game object
+    pub skin: Option<Arc<Component>>,
 }
 
+struct CachedCamera {
+    go: Weak<RefCell<GameObject>>,
+    camera: sync::Weak<Component>,
+    depth: i32,
+}
+
+impl CachedCamera {
+    fn active(&self) -> Option<Arc<Component>> {
+        let go = self.go.upgrade()?;
+        if !go.try_borrow().map(|o| o.active).unwrap_or(false) {
+            return None;
+        }
+
+        self.camera.upgrade()
+    }
+}
+
+/// Cameras of all game objects sorted by depth, including the inactive ones
+/// such that activating a camera does not need a rebuild
 #[derive(Default)]
+struct CameraCache {
+    generation: u64,
+    cameras: Vec<CachedCamera>,
+}
+
+impl CameraCache {
+    /// False when a component was added or removed, a camera was dropped
+    /// or its depth changed since the cache was built
+    fn is_valid(&self, generation: u64) -> bool {
+        self.generation == generation && self.cameras.iter().all(|c| {
+            match (c.go.upgrade(), c.camera.upgrade()) {
+                (Some(_), Some(com)) => com.try_as::<Camera>().unwrap().borrow().depth == c.depth,
+                _ => false,
+            }
+        })
+    }
+}
+
+/// Result of a raycast query
+pub struct RaycastHit {
+    pub game_object: Rc<RefCell<GameObject>>,
+    pub distance: f32,
+    pub point: Vector3<f32>,
+}
+
+/// World space bounds of the objects rendered by a camera
+pub struct SceneBounds {
+    pub aabb: Option<Aabb>,
+    /// Whether skinned or still loading meshes were found, as their bounds
+    /// change without bumping `bounds_generation`
+    pub dynamic: bool,
+}
+
 struct RenderQueueState {
-    states: MaterialState,
+    desc: RenderQueueDesc,
     commands: Vec<RenderCommand>,
 }
 
@@ -108,42 +187,53 @@ impl RenderQueueState {
 
         self
     }
+
+    fn sort_by_sort_key(&mut self) -> &mut Self {
+        // stable sort, such that the order from other sorts is kept for same keys
+        self.commands
+            .sort_by_key(|cmd| cmd.surface.material.sort_key);
+
+        self
+    }
+
+    fn sort(&mut self) {
+        match self.desc.sort {
+            RenderQueueSort::None => self,
+            RenderQueueSort::FrontToBack => self.sort_by_cam_distance_reverse().sort_by_material(),
+            RenderQueueSort::BackToFront => self.sort_by_cam_distance(),
+        }.sort_by_sort_key();
+    }
 }
 
-#[derive(Default)]
 struct RenderQueueList {
-    aabb: Option<Aabb>,
+    descs: BTreeMap<RenderQueue, RenderQueueDesc>,
     queues: BTreeMap<RenderQueue, RenderQueueState>,
 }
 
 impl RenderQueueList {
-    pub fn new() -> RenderQueueList {
-        let mut qlist = RenderQueueList::default();
-
-        // Opaque Queue
-        let mut state = RenderQueueState::default();
-        state.states.alpha_blending = Some(false);
-        qlist.queues.insert(RenderQueue::Opaque, state);
-
-        // Skybox Queue
-        let mut state = RenderQueueState::default();
-        state.states.depth_write = Some(false);
-        state.states.alpha_blending = Some(false);
-        state.states.depth_test = Some(DepthTest::LessEqual);
-        qlist.queues.insert(RenderQueue::Skybox, state);
+    pub fn new(descs: &BTreeMap<RenderQueue, RenderQueueDesc>) -> RenderQueueList {
+        RenderQueueList {
+            descs: descs.clone(),
+            queues: BTreeMap::new(),
+        }
+    }
 
-        // Transparent Queue
-        let mut state = RenderQueueState::default();
-        state.states.alpha_blending = Some(true);
-        state.states.depth_write = Some(false);
-        qlist.queues.insert(RenderQueue::Transparent, state);
+    fn queue_mut(&mut self, queue: RenderQueue) -> &mut RenderQueueState {
+        let descs = &self.descs;
 
-        // UI Queue
-        let mut state = RenderQueueState::default();
-        state.states.alpha_blending = Some(true);
-        qlist.queues.insert(RenderQueue::UI, state);
+        self.queues
+            .entry(queue)
+            .or_insert_with(|| RenderQueueState {
+                desc: find_render_queue_desc(descs, queue),
+                commands: Vec::new(),
+            })
+    }
 
-        qlist
+    fn surface_count_in(&self, from: RenderQueue, to: RenderQueue) -> usize {
+        self.queues
+            .range(from..to)
+            .map(|(_, q)| q.commands.len())
+            .sum()
     }
 
     fn surface_count(&self) -> usize {
@@ -159,6 +249,7 @@ fn compute_model_m(object: &GameObject) -> Matrix4<f32> {
     object.transform.as_global_matrix()
 }
 
+#[derive(Copy, Clone, Debug)]
 pub struct ClearOption {
     pub color: Option<(f32, f32, f32, f32)>,
     pub clear_color: bool,
@@ -177,10 +268,6 @@ impl Default for ClearOption {
     }
 }
 
-fn get_max_scale(s: &Vector3<f32>) -> f32 {
-    s[0].max(s[1]).max(s[2])
-}
-
 impl<A> Engine<A>
 where
     A: AssetSystem,
@@ -206,12 +293,29 @@ where
         }
     }
 
+    /// Register a custom render queue, or override the settings of an existing one
+    pub fn register_render_queue(&mut self, queue: RenderQueue, desc: RenderQueueDesc) {
+        self.render_queues.insert(queue, desc);
+    }
+
     pub fn resize(&mut self, size: (u32, u32)) {
         self.screen_size = size;
 
         self.gui_context.borrow_mut().reset();
     }
 
+    fn bind_program(
+        &self,
+        ctx: &mut EngineContext,
+        program: &Rc<ShaderProgram>,
+    ) -> AssetResult<()> {
+        ctx.prepare_cache(program, |ctx| {
+            program.bind(&self.gl)?;
+            ctx.switch_prog += 1;
+            Ok(())
+        })
+    }
+
     #[cfg_attr(feature = "flame_it", flame)]
     fn setup_material(&self, ctx: &mut EngineContext, material: &Rc<Material>) -> AssetResult<()> {
         if let Some(ref last_material) = ctx.last_material_bound {
@@ -222,11 +326,22 @@ where
             }
         }
 
-        ctx.prepare_cache(&material.program, |ctx| {
-            material.program.bind(&self.gl)?;
-            ctx.switch_prog += 1;
-            Ok(())
-        })?;
+        let r = material
+            .shader_program()
+            .and_then(|program| self.bind_program(ctx, &program));
+
+        match r {
+            Err(AssetError::ShaderCompile { .. }) => {
+                // A broken shader should not stop the frame, render it with the error program
+                self.report_compile_error(material);
+                let program = self.asset_system.new_program("default_error");
+                self.bind_program(ctx, &program)?;
+
+                ctx.last_material_bound = Some(Rc::downgrade(&material));
+                return Ok(());
+            }
+            r => r?,
+        }
 
         material.bind(|tex| {
             ctx.prepare_cache_tex(tex, |ctx, unit| {
@@ -238,68 +353,270 @@ where
             })
         })?;
 
-        self.setup_light(ctx);
+        material.bind_uniform_blocks(&self.gl)?;
 
         ctx.last_material_bound = Some(Rc::downgrade(&material));
 
         Ok(())
     }
 
+    /// Fill the per-frame uniform block, which is shared by all programs in this pass
+    fn update_frame_uniforms(&self, ctx: &EngineContext, camera: &Camera) {
+        if !self.gl.is_webgl2 {
+            return;
+        }
+
+        let projection = camera.projection_matrix(self.screen_size);
+
+        let mut w = Std140Writer::new();
+        w.write_mat4(projection * camera.v)
+            .write_mat4(camera.v)
+            .write_mat4(projection)
+            .write_vec3(camera.eye())
+            .write_f32((uni_app::now() - self.start_time) as f32);
+
+        let light_com = ctx.main_light.as_ref().unwrap();
+        light_com
+            .try_as::<Light>()
+            .unwrap()
+            .borrow()
+            .write_std140(&mut w);
+
+        for i in 0..4 {
+            match ctx.point_lights.get(i) {
+                Some(plight_com) => plight_com
+                    .try_as::<Light>()
+                    .unwrap()
+                    .borrow()
+                    .write_std140(&mut w),
+                None => PointLight::default().write_std140(&mut w, 0.0),
+            }
+        }
+
+        match ctx.fog {
+            Some(ref fog_com) => fog_com
+                .try_as::<Fog>()
+                .unwrap()
+                .borrow()
+                .write_std140(&mut w, ctx.fog_sky_ready),
+            None => Fog::write_disabled_std140(&mut w),
+        }
+
+        self.frame_uniforms.update(w);
+        self.frame_uniforms.bind(&self.gl);
+    }
+
     #[cfg_attr(feature = "flame_it", flame)]
-    fn setup_camera(&self, ctx: &mut EngineContext, modelm: Matrix4<f32>, camera: &Camera) {
+    fn setup_properties(
+        &self,
+        ctx: &mut EngineContext,
+        material: &Rc<Material>,
+        surface: &MeshSurface,
+    ) -> AssetResult<()> {
+        let properties = surface.properties();
+        if properties.is_empty() {
+            return Ok(());
+        }
+
+        self.bind_surface_params(ctx, material, &properties)
+    }
+
+    /// Bind back the params overridden by `setup_properties` once the surface is drawn,
+    /// such that they do not leak to the next surfaces using the same program
+    fn restore_properties(
+        &self,
+        ctx: &mut EngineContext,
+        material: &Rc<Material>,
+        surface: &MeshSurface,
+    ) -> AssetResult<()> {
+        let properties = surface.properties();
+        if properties.is_empty() {
+            return Ok(());
+        }
+
+        let restored = material.restored_properties(&properties);
+        self.bind_surface_params(ctx, material, &restored)
+    }
+
+    fn bind_surface_params(
+        &self,
+        ctx: &mut EngineContext,
+        material: &Rc<Material>,
+        params: &MaterialParamMap,
+    ) -> AssetResult<()> {
+        let r = material.bind_properties(params, |tex| {
+            ctx.prepare_cache_tex(tex, |ctx, unit| {
+                tex.bind(&self.gl, unit)?;
+
+                ctx.switch_tex += 1;
+                Ok(())
+            })
+        });
+
+        match r {
+            // Rendered with the error program, see setup_material
+            Err(AssetError::ShaderCompile { .. }) => {
+                self.report_compile_error(material);
+                Ok(())
+            }
+            r => r,
+        }
+    }
+
+    /// Log the compile error of the program variant used by `material`, once per variant
+    fn report_compile_error(&self, material: &Material) {
+        if let Ok(prog) = material.shader_program() {
+            prog.report_compile_error(&material.keywords());
+        }
+    }
+
+    /// Fallback of the per-frame uniform block, set once per program in each pass
+    #[cfg_attr(feature = "flame_it", flame)]
+    fn setup_frame(&self, ctx: &mut EngineContext, camera: &Camera) {
         let prog = ctx.prog.upgrade().unwrap();
-        // setup_camera
-        let perspective = camera.perspective(self.screen_size);
 
-        prog.set("uMVMatrix", camera.v * modelm);
-        prog.set("uPMatrix", perspective);
+        if let Some(ref last_prog) = ctx.last_frame_bound {
+            if let Some(last_prog) = last_prog.upgrade() {
+                if Rc::ptr_eq(&prog, &last_prog) {
+                    return;
+                }
+            }
+        }
 
-        let skybox_v: Matrix3<_> = Matrix3::from_cols(
-            camera.v.x.truncate(),
-            camera.v.y.truncate(),
-            camera.v.z.truncate(),
-        );
+        ctx.last_frame_bound = Some(ctx.prog.clone());
+
+        // The occlusion texture is only valid for the camera it was computed for
+        prog.set("uSsaoEnabled", camera.ssao_bound);
+
+        // The sky of the fog stays on its reserved unit for the whole pass
+        prog.set("uFogSky", (ctx.fog_sky.clone(), FOG_SKY_UNIT));
+
+        if prog.uses_frame_block(&self.gl) {
+            return;
+        }
+
+        let projection = camera.projection_matrix(self.screen_size);
 
-        prog.set("uPVMatrix", perspective * camera.v);
-        prog.set("uPVSkyboxMatrix", perspective * Matrix4::from(skybox_v));
+        prog.set("uPVMatrix", projection * camera.v);
+        prog.set("uVMatrix", camera.v);
+        prog.set("uPMatrix", projection);
+        prog.set("uViewPos", camera.eye());
+        prog.set("uTime", (uni_app::now() - self.start_time) as f32);
+
+        match ctx.fog {
+            Some(ref fog_com) => fog_com.try_as::<Fog>().unwrap().borrow().bind("uFog", &prog),
+            None => Fog::bind_disabled("uFog", &prog),
+        }
+        prog.set("uFog.sky", ctx.fog_sky_ready);
+    }
+
+    #[cfg_attr(feature = "flame_it", flame)]
+    fn setup_camera(&self, ctx: &mut EngineContext, modelm: Matrix4<f32>, camera: &Camera) {
+        let prog = ctx.prog.upgrade().unwrap();
 
+        prog.set("uMVMatrix", camera.v * modelm);
         prog.set("uNMatrix", modelm.inverse_transform().unwrap().transpose());
         prog.set("uMMatrix", modelm);
-        prog.set("uViewPos", camera.eye());
     }
 
     #[cfg_attr(feature = "flame_it", flame)]
-    fn setup_light(&self, ctx: &mut EngineContext) {
+    fn setup_light(&self, ctx: &mut EngineContext, layer: u32) {
         // Setup light
         let prog = ctx.prog.upgrade().unwrap();
 
+        let lit = |c: &Arc<Component>| {
+            let light = c.try_as::<Light>().unwrap();
+            light.borrow().culling_mask() & layer != 0
+        };
+
+        // bit 0 for the main light, and following bits for point lights
+        let mut light_mask = lit(ctx.main_light.as_ref().unwrap()) as u32;
+        for (i, plight_com) in ctx.point_lights.iter().enumerate() {
+            light_mask |= (lit(plight_com) as u32) << (i + 1);
+        }
+
+        // Lights are in the per-frame uniform block, only the mask changes per object
+        if prog.uses_frame_block(&self.gl) {
+            prog.set("uLightMask", light_mask as i32);
+            return;
+        }
+
         if let Some(ref last_prog) = ctx.last_light_bound {
             if let Some(last_prog) = last_prog.upgrade() {
-                if Rc::ptr_eq(&prog, &last_prog) {
+                if Rc::ptr_eq(&prog, &last_prog) && ctx.last_light_mask == light_mask {
                     return;
                 }
             }
         }
 
         ctx.last_light_bound = Some(ctx.prog.clone());
+        ctx.last_light_mask = light_mask;
+
+        let bind = |light: &Light, name: &str, enabled: bool| {
+            if enabled {
+                light.bind(name, &prog);
+            } else {
+                light.bind_unlit(name, &prog);
+            }
+        };
 
         let light_com = ctx.main_light.as_ref().unwrap();
         let light = light_com.try_as::<Light>().unwrap();
+        let enabled = light_mask & 1 != 0;
 
-        light.borrow().bind("uDirectionalLight", &prog);
+        bind(&light.borrow(), "uDirectionalLight", enabled);
         // So shader needs to have a vs stage light
-        light.borrow().bind("uDirectionalLightVS", &prog);
+        bind(&light.borrow(), "uDirectionalLightVS", enabled);
 
         for (i, plight_com) in ctx.point_lights.iter().enumerate() {
             let plight = plight_com.try_as::<Light>().unwrap();
+            let enabled = light_mask & (1 << (i + 1)) != 0;
+
             let name = format!("uPointLights[{}]", i);
-            plight.borrow().bind(&name, &prog);
+            bind(&plight.borrow(), &name, enabled);
 
             let name = format!("uPointLightsVS[{}]", i);
-            plight.borrow().bind(&name, &prog);
+            bind(&plight.borrow(), &name, enabled);
         }
     }
 
+    #[cfg_attr(feature = "flame_it", flame)]
+    fn setup_skin(&self, ctx: &EngineContext, skin: &Option<Arc<Component>>) {
+        let prog = ctx.prog.upgrade().unwrap();
+
+        let skin = match *skin {
+            Some(ref skin) => skin.try_as::<SkinnedMesh>().unwrap(),
+            None => {
+                prog.set("uSkinned", false);
+                return;
+            }
+        };
+
+        prog.set("uSkinned", true);
+        for (name, m) in JOINT_MATRIX_UNIFORMS.iter().zip(skin.borrow().joint_matrices()) {
+            prog.set(name.as_str(), *m);
+        }
+    }
+
+    /// Bind the sky of the fog to its reserved unit, once for each pass
+    fn bind_fog_sky(&self, ctx: &mut EngineContext) {
+        let sky = ctx.fog
+            .as_ref()
+            .and_then(|fog_com| fog_com.try_as::<Fog>().unwrap().borrow().sky.clone());
+
+        let sky = sky.and_then(|sky| sky.bind(&self.gl, FOG_SKY_UNIT).ok().map(|_| sky));
+        ctx.fog_sky_ready = sky.is_some();
+
+        // A cube texture is always bound, such that no program samples an empty unit
+        let sky = sky.unwrap_or_else(|| {
+            self.default_fog_sky.bind(&self.gl, FOG_SKY_UNIT).ok();
+            self.default_fog_sky.clone()
+        });
+
+        ctx.fog_sky = Rc::downgrade(&sky);
+        ctx.switch_tex += 1;
+    }
+
     #[cfg_attr(feature = "flame_it", flame)]
     fn render_commands(
         &self,
@@ -317,18 +634,24 @@ where
             };
 
             ctx.states.apply_defaults();
-            ctx.states.apply(&q.states);
+            ctx.states.apply(&q.desc.states);
             ctx.states.apply(&mat.states);
             ctx.states.commit(gl);
 
-            if let Err(err) = self.setup_material(ctx, mat) {
+            if let Err(err) = self.setup_material(ctx, mat)
+                .and_then(|_| self.setup_properties(ctx, mat, &cmd.surface))
+            {
                 if let AssetError::NotReady = err {
+                    // Some properties may be bound before a texture which is not ready
+                    self.restore_properties(ctx, mat, &cmd.surface).ok();
                     continue;
                 }
 
                 panic!(format!("Failed to load material, reason {:?}", err));
             }
 
+            self.setup_light(ctx, cmd.layer);
+
             let prog = ctx.prog.upgrade().unwrap();
 
             let r = ctx.prepare_cache(&cmd.surface.buffer, |ctx| {
@@ -339,7 +662,9 @@ where
 
             match r {
                 Ok(_) => {
+                    self.setup_frame(ctx, camera);
                     self.setup_camera(ctx, cmd.model_m, camera);
+                    self.setup_skin(ctx, &cmd.skin);
                     prog.commit(gl);
                     // if let RenderQueue::UI = mat.render_queue
                     {
@@ -353,6 +678,11 @@ where
                     _ => panic!(format!("Failed to load mesh, reason {:?}", err)),
                 },
             }
+
+            match self.restore_properties(ctx, mat, &cmd.surface) {
+                Ok(_) | Err(AssetError::NotReady) => (),
+                Err(err) => panic!(format!("Failed to load material, reason {:?}", err)),
+            }
         }
     }
 
@@ -431,139 +761,253 @@ where
 
         ctx.main_light = Some(main_light);
 
-        ctx.point_lights = self.find_all_components::<Light>()
-                .into_iter()
-                .filter(|c| {
-                    let light_com = c.try_as::<Light>().unwrap();
-                    match *light_com.borrow() {
-                        Light::Point(_) => true,
-                        _ => false,
-                    }
-                })
-                .take(4)            // only take 4 points light.
-                .map(
-                    |c| c.clone()
-                )
-                .collect();
+        ctx.point_lights = self.find_point_lights();
+
+        ctx.fog = self.find_component::<Fog>();
+
+        // Pose of the skinned meshes, before their bounds are used for culling
+        self.map_component::<SkinnedMesh, _>(|obj, c| {
+            let modelm = obj.borrow().transform.as_global_matrix();
+
+            c.try_as::<SkinnedMesh>()
+                .unwrap()
+                .borrow_mut()
+                .update(&modelm, self.frame_index);
+            true
+        });
+    }
+
+    /// Point and spot lights in the order of their `uPointLights` slots
+    pub fn find_point_lights(&self) -> Vec<Arc<Component>> {
+        self.find_all_components::<Light>()
+            .into_iter()
+            .filter(|c| c.try_as::<Light>().unwrap().borrow().is_local())
+            .take(4) // only take 4 points light.
+            .collect()
     }
 
     fn gather_render_commands(
         &self,
         object: &GameObject,
         cam_pos: &Vector3<f32>,
-        update_bounds_only: bool,
         frustum_opt: &Option<Frustum>,
         render_q: &mut RenderQueueList,
         included_render_queues: &Option<BTreeSet<RenderQueue>>,
+        culling_mask: u32,
         eng_stats: &mut Option<&mut EngineStats>,
     ) {
-        if !object.active {
+        if !object.active || object.layer & culling_mask == 0 {
             return;
         }
 
         let result = object.find_component::<Mesh>();
         if let Some((mesh, _)) = result {
             let m = compute_model_m(&*object);
-            use math::*;
 
-            // TODO: local scale only ?? should be using global scale??
-            let scale = get_max_scale(&object.transform.local_scale());
+            // Skinned meshes use the bounds of their animated pose for all surfaces
+            let skin = object.find_component::<SkinnedMesh>();
+            let skin_bounds = skin.as_ref().and_then(|&(ref skin, _)| skin.bounds());
+            let skin = skin.map(|(_, c)| c.clone());
+
+            // Test the whole object first using its cached world space bounds,
+            // such that surfaces only need to be tested when it is partially visible.
+            let world_bounds = match skin_bounds {
+                Some(aabb) => Some(aabb.transform(&m)),
+                None => mesh.world_bounds(&m),
+            };
+            let object_test = match (frustum_opt, world_bounds) {
+                (&Some(ref frustum), Some(ref aabb)) => frustum.collide_aabb(aabb),
+                _ => Intersection::Intersect,
+            };
 
             for surface in mesh.surfaces.iter() {
+                let queue = surface.material.render_queue;
+
                 if let &Some(ref included) = included_render_queues {
-                    if included.get(&surface.material.render_queue).is_none() {
+                    if included.get(&queue).is_none() {
                         continue;
                     }
                 }
 
                 if let &mut Some(ref mut stats) = eng_stats {
-                    match surface.material.render_queue {
-                        RenderQueue::Transparent => stats.total_transparent_count += 1,
-                        RenderQueue::Opaque => stats.total_opaque_count += 1,
-                        _ => (),
+                    if queue >= RenderQueue::Transparent && queue < RenderQueue::UI {
+                        stats.total_transparent_count += 1;
+                    } else if queue >= RenderQueue::Opaque && queue < RenderQueue::Skybox {
+                        stats.total_opaque_count += 1;
                     }
                 }
 
-                // TODO: should use a material flag to skip
+                let frustum_culling = render_q.queue_mut(queue).desc.frustum_culling;
+
                 if let &Some(ref frustum) = frustum_opt {
-                    match surface.material.render_queue {
-                        RenderQueue::Skybox | RenderQueue::UI => (),
-                        _ => {
-                            let bounds = surface.buffer.bounds();
-                            if bounds.is_none() {
-                                continue;
+                    if frustum_culling {
+                        let local_aabb = match skin_bounds {
+                            Some(aabb) => aabb,
+                            None => match surface.buffer.bounds() {
+                                Some(bounds) => bounds.local_aabb(),
+                                None => continue,
+                            },
+                        };
+                        let visible = match object_test {
+                            Intersection::Outside => false,
+                            Intersection::Inside => true,
+                            Intersection::Intersect => {
+                                frustum.collide_obb(&local_aabb, &m) != Intersection::Outside
                             }
+                        };
 
-                            let bounds = bounds.unwrap();
-                            let (center, r) = bounds.local_aabb().sphere();
+                        if !visible {
+                            continue;
+                        }
+                    }
+                }
 
-                            let scaled_r = r * scale;
-                            let p = m.transform_point(Point3::from_vec(center));
+                let q = render_q.queue_mut(queue);
 
-                            if !frustum.collide_sphere(&p.to_vec(), scaled_r) {
-                                continue;
-                            }
+                let cam_dist = (cam_pos - object.transform.global().disp).magnitude();
 
-                            if render_q.aabb.is_none() {
-                                render_q.aabb = Some(Aabb::empty());
-                            }
+                q.commands.push(RenderCommand {
+                    surface: surface.clone(),
+                    model_m: m,
+                    cam_distance: cam_dist,
+                    layer: object.layer,
+                    skin: skin.clone(),
+                })
+            }
+        }
+    }
 
-                            render_q
-                                .aabb
-                                .as_mut()
-                                .unwrap()
-                                .merge_sphere(&p.to_vec(), scaled_r);
-                        }
-                    }
-                } else {
-                    let bounds = surface.buffer.bounds();
-                    if let Some(bounds) = bounds {
-                        let (center, r) = bounds.local_aabb().sphere();
-                        let p = m.transform_point(Point3::from_vec(center));
-
-                        if render_q.aabb.is_none() {
-                            render_q.aabb = Some(Aabb::empty());
-                        }
+    /// Find the nearest game object whose mesh bounds are hit by the ray,
+    /// only objects with a layer in `culling_mask` are tested.
+    pub fn raycast(&self, ray: &Ray, culling_mask: u32) -> Option<RaycastHit> {
+        let mut result: Option<RaycastHit> = None;
 
-                        render_q
-                            .aabb
-                            .as_mut()
-                            .unwrap()
-                            .merge_sphere(&p.to_vec(), r * scale);
-                    }
-                }
+        for obj in self.objects.iter() {
+            let obj = match obj.upgrade() {
+                Some(obj) => obj,
+                None => continue,
+            };
+
+            let distance = {
+                let object = match obj.try_borrow() {
+                    Ok(object) => object,
+                    Err(_) => continue,
+                };
 
-                if !update_bounds_only {
-                    let q = render_q
-                        .queues
-                        .get_mut(&surface.material.render_queue)
-                        .unwrap();
+                if !object.active || object.layer & culling_mask == 0 {
+                    continue;
+                }
 
-                    let cam_dist = (cam_pos - object.transform.global().disp).magnitude();
+                let bounds = object.find_component::<Mesh>().and_then(|(mesh, _)| {
+                    mesh.world_bounds(&compute_model_m(&object))
+                });
 
-                    q.commands.push(RenderCommand {
-                        surface: surface.clone(),
-                        model_m: m,
-                        cam_distance: cam_dist,
-                    })
+                match bounds.and_then(|aabb| ray.intersect_aabb(&aabb)) {
+                    Some(d) => d,
+                    None => continue,
                 }
+            };
+
+            if result.as_ref().map(|hit| distance < hit.distance).unwrap_or(true) {
+                result = Some(RaycastHit {
+                    game_object: obj.clone(),
+                    distance,
+                    point: ray.at(distance),
+                });
             }
         }
+
+        result
     }
 
+    /// World space bounds of the objects rendered by `camera`, merged from the cached
+    /// bounds of their meshes
     pub fn get_bounds(&self, camera: &Camera) -> Option<Aabb> {
-        let render_q = self.gather_all_render_commands(camera, true, None);
+        self.get_scene_bounds(camera).aabb
+    }
 
-        return render_q.aabb;
+    /// Changes whenever the static bounds returned by `get_scene_bounds` could change:
+    /// a node of `tree` moved, a component was added or removed, or the bounds of
+    /// a mesh were invalidated
+    pub fn bounds_generation(&self, tree: &SceneTree) -> u64 {
+        // Sum of increasing counters, which changes whenever one of them does
+        tree.generation() + self.arena.generation() + Mesh::bounds_generation()
+    }
+
+    /// Like `get_bounds`, also reporting whether the bounds can be cached
+    /// until `bounds_generation` changes
+    pub fn get_scene_bounds(&self, camera: &Camera) -> SceneBounds {
+        let frustum = if camera.enable_frustum_culling {
+            Some(camera.calc_frustum(self.screen_size))
+        } else {
+            None
+        };
+
+        let mut result = SceneBounds {
+            aabb: None,
+            dynamic: false,
+        };
+
+        for obj in self.objects.iter() {
+            let obj = match obj.upgrade() {
+                Some(obj) => obj,
+                None => continue,
+            };
+
+            let object = match obj.try_borrow() {
+                Ok(object) => object,
+                Err(_) => continue,
+            };
+
+            if !object.active || object.layer & camera.culling_mask == 0 {
+                continue;
+            }
+
+            let mesh = match object.find_component::<Mesh>() {
+                Some((mesh, _)) => mesh,
+                None => continue,
+            };
+
+            if let Some(ref included) = camera.included_render_queues {
+                let mut queues = mesh.surfaces.iter().map(|s| s.material.render_queue);
+                if !queues.any(|q| included.contains(&q)) {
+                    continue;
+                }
+            }
+
+            let m = compute_model_m(&*object);
+            let skin = object.find_component::<SkinnedMesh>();
+            let skin_bounds = skin.as_ref().and_then(|&(ref skin, _)| skin.bounds());
+
+            let bounds = match skin_bounds {
+                Some(aabb) => Some(aabb.transform(&m)),
+                None => mesh.world_bounds(&m),
+            };
+
+            if skin.is_some() || bounds.is_none() {
+                result.dynamic = true;
+            }
+
+            if let Some(aabb) = bounds {
+                if let Some(ref frustum) = frustum {
+                    if frustum.collide_aabb(&aabb) == Intersection::Outside {
+                        continue;
+                    }
+                }
+
+                result.aabb.get_or_insert_with(Aabb::empty).merge(&aabb);
+            }
+        }
+
+        result
     }
 
     fn gather_all_render_commands(
         &self,
         camera: &Camera,
-        update_bounds_only: bool,
         mut eng_stats: Option<&mut EngineStats>,
     ) -> RenderQueueList {
-        let mut render_q = RenderQueueList::new();
+        let mut render_q = RenderQueueList::new(&self.render_queues);
         let objects = &self.objects;
 
         let frustum = if camera.enable_frustum_culling {
@@ -578,10 +1022,10 @@ where
                     self.gather_render_commands(
                         &object,
                         &camera.eye(),
-                        update_bounds_only,
                         &frustum,
                         &mut render_q,
                         &camera.included_render_queues,
+                        camera.culling_mask,
                         &mut eng_stats,
                     )
                 }
@@ -604,56 +1048,44 @@ where
             rt.bind_frame_buffer(&self.gl);
         }
 
-        match camera.rect {
-            Some(((x, y), (w, h))) => {
-                self.gl.viewport(x, y, w, h);
-            }
-            None => {
-                self.gl
-                    .viewport(0, 0, self.screen_size.0, self.screen_size.1);
-            }
-        }
+        let ((x, y), (w, h)) = camera.viewport(self.screen_size);
+        self.gl.viewport(x, y, w, h);
 
-        self.clear(clear_option);
+        if camera.rect.is_some() {
+            // Only clear the region covered by this camera
+            ctx.states.set_scissor(&self.gl, Scissor::Rect((x, y), (w, h)));
+            self.clear(clear_option);
+            ctx.states.set_scissor(&self.gl, Scissor::Off);
+        } else {
+            self.clear(clear_option);
+        }
 
         self.prepare_ctx(&mut ctx);
+        self.bind_fog_sky(&mut ctx);
+        self.update_frame_uniforms(&ctx, camera);
 
         // gather commands
-        let mut render_q = self.gather_all_render_commands(&camera, false, Some(&mut ctx.stats));
+        let mut render_q = self.gather_all_render_commands(&camera, Some(&mut ctx.stats));
 
-        // Sort the opaque queue
-        render_q
-            .queues
-            .get_mut(&RenderQueue::Opaque)
-            .unwrap()
-            .sort_by_cam_distance_reverse()
-            .sort_by_material();
-
-        // Sort the transparent queue
-        render_q
-            .queues
-            .get_mut(&RenderQueue::Transparent)
-            .unwrap()
-            .sort_by_cam_distance();
+        for (_, q) in render_q.queues.iter_mut() {
+            q.sort();
+        }
 
         ctx.stats.surfaces_count = render_q.surface_count() as u32;
-        ctx.stats.transparent_count = render_q
-            .queues
-            .get(&RenderQueue::Transparent)
-            .unwrap()
-            .commands
-            .len() as u32;
-        ctx.stats.opaque_count = render_q
-            .queues
-            .get(&RenderQueue::Opaque)
-            .unwrap()
-            .commands
-            .len() as u32;
+        ctx.stats.transparent_count =
+            render_q.surface_count_in(RenderQueue::Transparent, RenderQueue::UI) as u32;
+        ctx.stats.opaque_count =
+            render_q.surface_count_in(RenderQueue::Opaque, RenderQueue::Skybox) as u32;
 
         for (_, q) in render_q.queues.iter() {
             self.render_commands(&mut ctx, &q, camera, material);
         }
 
+        // Restore the default states, such that color and stencil masks
+        // left by materials won't affect the clearing of next pass
+        ctx.states.apply_defaults();
+        ctx.states.commit(&self.gl);
+
         if let Some(ref rt) = camera.render_texture {
             rt.unbind_frame_buffer(&self.gl);
         }
@@ -666,20 +1098,54 @@ where
         self.render_pass_with_material(camera, None, clear_option)
     }
 
+    /// Active camera with the lowest depth, the first one rendered by `render_cameras`
     pub fn main_camera(&self) -> Option<Arc<Component>> {
-        let mut found = self.current_camera.borrow_mut();
-        match *found {
-            None => *found = self.find_component::<Camera>().map(|c| c.clone()),
-            _ => (),
-        }
+        let found = self.camera_cache()
+            .cameras
+            .iter()
+            .filter_map(|c| c.active())
+            .next();
+        *self.current_camera.borrow_mut() = found.clone();
+
+        found
+    }
+
+    /// All cameras in active game objects, sorted by their depth
+    pub fn cameras(&self) -> Vec<Arc<Component>> {
+        self.camera_cache()
+            .cameras
+            .iter()
+            .filter_map(|c| c.active())
+            .collect()
+    }
 
-        if let Some(ref c) = *found {
-            return Some(c.clone());
+    fn camera_cache(&self) -> Ref<CameraCache> {
+        let generation = self.arena.generation();
+        if self.camera_cache.borrow().is_valid(generation) {
+            return self.camera_cache.borrow();
         }
 
-        None
+        let mut cameras = Vec::new();
+        self.map_component::<Camera, _>(|obj, c| {
+            cameras.push(CachedCamera {
+                go: Rc::downgrade(&obj),
+                camera: Arc::downgrade(&c),
+                depth: c.try_as::<Camera>().unwrap().borrow().depth,
+            });
+            true
+        });
+
+        // sort_by_key is stable, cameras with same depth keep their creation order
+        cameras.sort_by_key(|c| c.depth);
+        *self.camera_cache.borrow_mut() = CameraCache {
+            generation,
+            cameras,
+        };
+
+        self.camera_cache.borrow()
     }
 
+    /// Render the main camera only, clearing with `clear_option`
     #[cfg_attr(feature = "flame_it", flame)]
     pub fn render(&mut self, clear_option: ClearOption) {
         imgui::pre_render(self);
@@ -693,6 +1159,30 @@ where
         }
     }
 
+    /// Render every active camera in order of depth, each with its own `clear_option`
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn render_cameras(&mut self) {
+        imgui::pre_render(self);
+
+        let cameras = self.cameras();
+        if cameras.is_empty() {
+            // We dont have any camera here, just clean the screen.
+            self.clear(ClearOption::default());
+            return;
+        }
+
+        let mut stats = EngineStats::default();
+
+        for camera in cameras.iter() {
+            let camera = camera.try_as::<Camera>().unwrap().borrow();
+            let clear_option = camera.clear_option;
+
+            stats.accumulate(&self.render_pass(&camera, clear_option));
+        }
+
+        self.stats = stats;
+    }
+
     pub fn new(webgl_ctx: WebGLContext, size: (u32, u32), hidpi: f32) -> Engine<A> {
         let gl = WebGLRenderingContext::new(webgl_ctx);
 
@@ -717,21 +1207,31 @@ where
 
         let gui_tree = SceneTree::new();
 
+        let asset_system = A::new();
+        asset_system.set_texture_variant(CompressedFormat::texture_variant(&gl));
+
         Engine {
             gl: gl,
             objects: vec![],
             program_cache: RefCell::new(HashMap::new()),
-            asset_system: Box::new(A::new()),
+            asset_system: Box::new(asset_system),
             gui_context: Rc::new(RefCell::new(imgui::Context::new(gui_tree))),
             screen_size: size,
             hidpi: hidpi,
             current_camera: RefCell::new(None),
             stats: Default::default(),
             arena: Rc::new(ComponentArena::new()),
+            render_queues: default_render_queues(),
+            frame_uniforms: UniformBuffer::new(FRAME_BLOCK_NAME, FRAME_BLOCK_BINDING),
+            start_time: uni_app::now(),
+            frame_index: 0,
+            default_fog_sky: Fog::default_sky(),
+            camera_cache: Default::default(),
         }
     }
 
     pub fn begin(&mut self) {
+        self.frame_index += 1;
         imgui::begin();
 
         self.asset_system_mut().step();
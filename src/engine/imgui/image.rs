@@ -39,6 +39,10 @@ fn make_quad_mesh_data(ndc_size: (f32, f32)) -> MeshData {
         indices: indices,
         tangents: None,
         bitangents: None,
+        colors: None,
+        joints: None,
+        weights: None,
+        morph_targets: Vec::new(),
     }
 }
 
@@ -122,6 +122,10 @@ fn make_text_mesh_data(text_data: TextData) -> MeshData {
         indices: indices,
         tangents: None,
         bitangents: None,
+        colors: None,
+        joints: None,
+        weights: None,
+        morph_targets: Vec::new(),
     }
 }
 
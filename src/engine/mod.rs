@@ -12,10 +12,10 @@ pub use self::imgui::Metric;
 pub use self::asset::*;
 pub use self::core::Aabb;
 pub use self::core::{Component, ComponentArena, ComponentBased, ComponentEvent, ComponentType,
-                     GameObject, IntoComponentPtr, SceneTree};
+                     GameObject, IntoComponentPtr, SceneTree, ALL_LAYERS, DEFAULT_LAYER};
 pub use self::render::*;
 
-pub use self::engine::{ClearOption, IEngine};
+pub use self::engine::{ClearOption, IEngine, RaycastHit, SceneBounds};
 
 pub use self::sound::{SoundHandle, SoundSystem};
 
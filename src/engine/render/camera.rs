@@ -1,6 +1,9 @@
+use engine::core::{Aabb, ALL_LAYERS};
 use engine::render::{RenderQueue, RenderTexture};
+use engine::ClearOption;
 use math::*;
 use std::collections::BTreeSet;
+use std::f32;
 use std::rc::Rc;
 
 pub struct Plane {
@@ -9,20 +12,52 @@ pub struct Plane {
 }
 
 impl Plane {
-    /// Make a plane from 3 points (anti-clockwise)
-    pub fn from_3_points(p0: &Vector3<f32>, p1: &Vector3<f32>, p2: &Vector3<f32>) -> Plane {
-        let n = (p1 - p0).cross(p2 - p1).normalize();
-        let offset = n.dot(*p0);
+    /// Make a plane from the equation a*x + b*y + c*z + d = 0, packed as (a, b, c, d)
+    fn from_equation(eq: Vector4<f32>) -> Plane {
+        let len = eq.truncate().magnitude();
 
-        Plane { n, offset }
+        Plane {
+            n: eq.truncate() / len,
+            offset: -eq.w / len,
+        }
     }
 }
 
+/// Result of testing a volume against a frustum
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Intersection {
+    Outside,
+    Intersect,
+    Inside,
+}
+
 pub struct Frustum {
     planes: [Plane; 6],
 }
 
 impl Frustum {
+    /// Extract the frustum planes from a projection * view matrix
+    ///
+    /// Reference: Gribb & Hartmann, "Fast Extraction of Viewing Frustum Planes
+    /// from the World-View-Projection Matrix"
+    pub fn from_matrix(pv: &Matrix4<f32>) -> Frustum {
+        let r0 = pv.row(0);
+        let r1 = pv.row(1);
+        let r2 = pv.row(2);
+        let r3 = pv.row(3);
+
+        Frustum {
+            planes: [
+                Plane::from_equation(r3 + r0), // left
+                Plane::from_equation(r3 - r0), // right
+                Plane::from_equation(r3 - r1), // top
+                Plane::from_equation(r3 + r1), // bottom
+                Plane::from_equation(r3 + r2), // near
+                Plane::from_equation(r3 - r2), // far
+            ],
+        }
+    }
+
     pub fn collide_sphere(&self, p: &Vector3<f32>, r: f32) -> bool {
         for plane in self.planes.iter() {
             // Distance = (A*x0+B*y0+C*z0+D)/Sqrt(A*A+B*B+C*C)
@@ -33,12 +68,162 @@ impl Frustum {
 
         true
     }
+
+    /// Test a world space aabb against the frustum
+    pub fn collide_aabb(&self, aabb: &Aabb) -> Intersection {
+        let e = aabb.extents();
+
+        self.collide_box(&aabb.center(), |n| {
+            e.x * n.x.abs() + e.y * n.y.abs() + e.z * n.z.abs()
+        })
+    }
+
+    /// Test a local space aabb transformed by `m` (an oriented box) against the frustum
+    pub fn collide_obb(&self, local: &Aabb, m: &Matrix4<f32>) -> Intersection {
+        let center = m.transform_point(Point3::from_vec(local.center()));
+        let e = local.extents();
+
+        // The axes of the oriented box, scaled by the transform
+        let ax = m.x.truncate() * e.x;
+        let ay = m.y.truncate() * e.y;
+        let az = m.z.truncate() * e.z;
+
+        self.collide_box(&center.to_vec(), |n| {
+            n.dot(ax).abs() + n.dot(ay).abs() + n.dot(az).abs()
+        })
+    }
+
+    fn collide_box<F>(&self, center: &Vector3<f32>, projected_radius: F) -> Intersection
+    where
+        F: Fn(&Vector3<f32>) -> f32,
+    {
+        let mut result = Intersection::Inside;
+
+        for plane in self.planes.iter() {
+            let d = plane.n.dot(*center) - plane.offset;
+            let r = projected_radius(&plane.n);
+
+            if d < -r {
+                return Intersection::Outside;
+            }
+
+            if d < r {
+                result = Intersection::Intersect;
+            }
+        }
+
+        result
+    }
+}
+
+/// A ray in world space, `direction` is normalized
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn at(&self, t: f32) -> Vector3<f32> {
+        self.origin + self.direction * t
+    }
+
+    /// Distance along the ray to the first hit with the aabb, using the slab method
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut tmin = f32::MIN;
+        let mut tmax = f32::MAX;
+
+        for i in 0..3 {
+            // Parallel to the slab, 0 * inf would be NaN
+            if self.direction[i] == 0.0 {
+                if self.origin[i] < aabb.min[i] || self.origin[i] > aabb.max[i] {
+                    return None;
+                }
+                continue;
+            }
+
+            let inv_d = 1.0 / self.direction[i];
+            let mut t0 = (aabb.min[i] - self.origin[i]) * inv_d;
+            let mut t1 = (aabb.max[i] - self.origin[i]) * inv_d;
+
+            if inv_d < 0.0 {
+                ::std::mem::swap(&mut t0, &mut t1);
+            }
+
+            tmin = tmin.max(t0);
+            tmax = tmax.min(t1);
+
+            if tmax < tmin {
+                return None;
+            }
+        }
+
+        if tmax < 0.0 {
+            return None;
+        }
+
+        Some(tmin.max(0.0))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// Perspective projection with vertical field of view
+    Perspective { fovy: Rad<f32> },
+    /// Orthographic projection, `size` is half of the vertical view volume
+    Orthographic { size: f32 },
+    /// User defined projection matrix, znear, zfar and aspect are ignored
+    Custom(Matrix4<f32>),
+}
+
+impl Default for Projection {
+    fn default() -> Projection {
+        Projection::Perspective {
+            fovy: Rad(f32::consts::PI / 4.0),
+        }
+    }
+}
+
+/// Screen space ambient occlusion of a camera, computed by the `SsaoPass` actor
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AmbientOcclusion {
+    /// Radius of the sampled hemisphere in world units
+    pub radius: f32,
+    /// Depth difference ignored to avoid self occlusion, in world units
+    pub bias: f32,
+    /// 1.0 for the sampled occlusion, higher values darken it
+    pub intensity: f32,
+    /// Samples per pixel, up to 32
+    pub samples: u32,
+    /// Blur radius in pixels, up to 4, 0 to disable the blur
+    pub blur: u32,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> AmbientOcclusion {
+        AmbientOcclusion {
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.0,
+            samples: 16,
+            blur: 2,
+        }
+    }
 }
 
 #[derive(Component)]
 pub struct Camera {
     pub v: Matrix4<f32>,
 
+    pub projection: Projection,
+
+    /// Cameras are rendered in ascending order of depth,
+    /// cameras with higher depth are drawn on top of lower ones.
+    pub depth: i32,
+
+    /// How the target is cleared before this camera renders
+    pub clear_option: ClearOption,
+
     pub enable_frustum_culling: bool,
 
     /// Optional viewport of this camera,  (pos, size) in pixels
@@ -49,6 +234,14 @@ pub struct Camera {
 
     pub included_render_queues: Option<BTreeSet<RenderQueue>>,
 
+    /// Only game objects with a layer in this mask are rendered
+    pub culling_mask: u32,
+
+    /// Darken the ambient lighting of occluded areas, `None` to disable
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    /// Whether the occlusion texture bound by SsaoPass was computed for this camera
+    pub(crate) ssao_bound: bool,
+
     eye: Point3<f32>,
 
     pub render_texture: Option<Rc<RenderTexture>>,
@@ -66,16 +259,6 @@ fn extract_forward(m: &Matrix4<f32>) -> Vector3<f32> {
     -m.row(2).truncate()
 }
 
-fn extract_up(m: &Matrix4<f32>) -> Vector3<f32> {
-    //Vector3::new(m.data[1], m.data[1 + 1 * 4], m.data[1 + 2 * 4])
-    m.row(1).truncate()
-}
-
-fn extract_right(m: &Matrix4<f32>) -> Vector3<f32> {
-    //Vector3::new(m.data[0], m.data[0 + 1 * 4], m.data[0 + 2 * 4])
-    m.row(0).truncate()
-}
-
 impl Camera {
     pub fn forward(&self) -> Vector3<f32> {
         extract_forward(&self.v)
@@ -117,28 +300,56 @@ impl Camera {
         aspect
     }
 
-    pub fn perspective(&self, screen_size: (u32, u32)) -> Matrix4<f32> {
-        use math::*;
-
+    pub fn projection_matrix(&self, screen_size: (u32, u32)) -> Matrix4<f32> {
         let aspect = self.calc_aspect(screen_size).max(0.001);
 
-        PerspectiveFov {
-            fovy: Rad(3.1415 / 4.0),
-            aspect,
-            near: self.znear,
-            far: self.zfar,
-        }.into()
+        match self.projection {
+            Projection::Perspective { fovy } => PerspectiveFov {
+                fovy,
+                aspect,
+                near: self.znear,
+                far: self.zfar,
+            }.into(),
+            Projection::Orthographic { size } => ortho(
+                -size * aspect,
+                size * aspect,
+                -size,
+                size,
+                self.znear,
+                self.zfar,
+            ),
+            Projection::Custom(m) => m,
+        }
+    }
+
+    pub fn set_perspective(&mut self, fovy: Rad<f32>) {
+        self.projection = Projection::Perspective { fovy };
+    }
+
+    pub fn set_orthographic(&mut self, size: f32) {
+        self.projection = Projection::Orthographic { size };
+    }
+
+    /// The viewport of this camera in pixels, the whole screen if `rect` is not set
+    pub fn viewport(&self, screen_size: (u32, u32)) -> ((i32, i32), (u32, u32)) {
+        self.rect.unwrap_or(((0, 0), screen_size))
     }
 
     pub fn new() -> Camera {
         Camera {
             v: Matrix4::identity(),
+            projection: Projection::default(),
+            depth: 0,
+            clear_option: ClearOption::default(),
             eye: Point3::new(0.0, 0.0, 0.0),
             rect: None,
             znear: 0.03,
             zfar: 1000.0,
             enable_frustum_culling: true,
             included_render_queues: None,
+            culling_mask: ALL_LAYERS,
+            ambient_occlusion: None,
+            ssao_bound: false,
             render_texture: None,
         }
     }
@@ -148,48 +359,59 @@ impl Camera {
     }
 
     pub fn calc_frustum(&self, screen_size: (u32, u32)) -> Frustum {
-        let forward = extract_forward(&self.v);
-        let up = extract_up(&self.v);
-        let right = extract_right(&self.v);
-        let aspect = self.calc_aspect(screen_size);
-
-        let near_center = self.eye.to_vec() + forward * self.znear;
-        let far_center = self.eye.to_vec() + forward * self.zfar;
-
-        let fovy: f32 = 3.1415 / 4.0;
-
-        let near_height = 2.0 * (fovy * 0.5).tan() * self.znear;
-        let far_height = 2.0 * (fovy * 0.5).tan() * self.zfar;
-        let near_width = near_height * aspect;
-        let far_width = far_height * aspect;
-
-        let far_top_left = far_center + up * (far_height * 0.5) - right * (far_width * 0.5);
-        let far_top_right = far_center + up * (far_height * 0.5) + right * (far_width * 0.5);
-        let far_bottom_left = far_center - up * (far_height * 0.5) - right * (far_width * 0.5);
-        let far_bottom_right = far_center - up * (far_height * 0.5) + right * (far_width * 0.5);
-
-        let near_top_left = near_center + up * (near_height * 0.5) - right * (near_width * 0.5);
-        let near_top_right = near_center + up * (near_height * 0.5) + right * (near_width * 0.5);
-        let near_bottom_left = near_center - up * (near_height * 0.5) - right * (near_width * 0.5);
-        let near_bottom_right = near_center - up * (near_height * 0.5) + right * (near_width * 0.5);
-
-        let plane_left = Plane::from_3_points(&near_bottom_left, &far_bottom_left, &far_top_left);
-        let plane_right = Plane::from_3_points(&near_bottom_right, &near_top_right, &far_top_right);
-        let plane_top = Plane::from_3_points(&near_top_right, &near_top_left, &far_top_left);
-        let plane_bottom =
-            Plane::from_3_points(&near_bottom_left, &near_bottom_right, &far_bottom_right);
-        let plane_near = Plane::from_3_points(&near_bottom_left, &near_top_left, &near_top_right);
-        let plane_far = Plane::from_3_points(&far_bottom_left, &far_bottom_right, &far_top_right);
+        Frustum::from_matrix(&(self.projection_matrix(screen_size) * self.v))
+    }
 
-        Frustum {
-            planes: [
-                plane_left,
-                plane_right,
-                plane_top,
-                plane_bottom,
-                plane_near,
-                plane_far,
-            ],
+    /// Make a world space ray from a screen position in pixels,
+    /// (0, 0) is the left top corner of the screen.
+    pub fn screen_to_ray(&self, pos: (f32, f32), screen_size: (u32, u32)) -> Ray {
+        let ((x, y), (w, h)) = self.viewport(screen_size);
+
+        // viewport origin is at the left bottom, same as opengl
+        let ndc_x = (pos.0 - x as f32) / w as f32 * 2.0 - 1.0;
+        let ndc_y = (screen_size.1 as f32 - pos.1 - y as f32) / h as f32 * 2.0 - 1.0;
+
+        let inv_pv = (self.projection_matrix(screen_size) * self.v)
+            .invert()
+            .unwrap_or(Matrix4::identity());
+
+        let unproject = |z: f32| {
+            let p = inv_pv * Vector4::new(ndc_x, ndc_y, z, 1.0);
+            p.truncate() / p.w
+        };
+
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+
+        Ray {
+            origin: near,
+            direction: (far - near).normalize(),
         }
     }
+
+    /// Project a world space point to the screen,
+    /// returns (x, y) in pixels with (0, 0) at the left top corner of the screen
+    /// and z as the depth from 0 (near) to 1 (far).
+    ///
+    /// Returns None if the point is behind the camera.
+    pub fn world_to_screen(
+        &self,
+        p: &Vector3<f32>,
+        screen_size: (u32, u32),
+    ) -> Option<Vector3<f32>> {
+        let ((x, y), (w, h)) = self.viewport(screen_size);
+
+        let clip = self.projection_matrix(screen_size) * self.v * p.extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+
+        let ndc = clip.truncate() / clip.w;
+
+        Some(Vector3::new(
+            x as f32 + (ndc.x + 1.0) * 0.5 * w as f32,
+            screen_size.1 as f32 - (y as f32 + (ndc.y + 1.0) * 0.5 * h as f32),
+            (ndc.z + 1.0) * 0.5,
+        ))
+    }
 }
@@ -0,0 +1,138 @@
+use engine::asset::{Asset, Resource};
+use engine::render::{ShaderProgram, Std140Writer, Texture, TextureAsset, TextureImage};
+use image::{ImageBuffer, Rgba};
+use math::*;
+use std::rc::Rc;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FogMode {
+    /// Fades in from `start` to `end` distance
+    Linear { start: f32, end: f32 },
+    Exponential { density: f32 },
+    ExponentialSquared { density: f32 },
+}
+
+impl FogMode {
+    fn index(&self) -> i32 {
+        match *self {
+            FogMode::Linear { .. } => 1,
+            FogMode::Exponential { .. } => 2,
+            FogMode::ExponentialSquared { .. } => 3,
+        }
+    }
+}
+
+/// Scene-wide distance and height fog, see "unrust/fog.glsl"
+///
+/// The first fog found in the scene is part of the per-frame data set by the engine.
+#[derive(Component)]
+pub struct Fog {
+    pub mode: FogMode,
+    pub color: Vector3f,
+    /// Cubemap sampled in the view direction instead of `color`, e.g. a baked sky
+    pub sky: Option<Rc<Texture>>,
+
+    /// World height below which the fog has its full density
+    pub height: f32,
+    /// How fast the fog thins out above `height`, 0 for a fog of uniform density
+    pub height_falloff: f32,
+
+    /// How much the skybox is fogged at the horizon, it fades out towards the zenith
+    pub skybox: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Fog {
+        Fog {
+            mode: FogMode::Exponential { density: 0.02 },
+            color: Vector3::new(0.5, 0.6, 0.7),
+            sky: None,
+            height: 0.0,
+            height_falloff: 0.0,
+            skybox: 1.0,
+        }
+    }
+}
+
+impl Fog {
+    /// Parameters of the mode, as `(start, end, density)`
+    fn mode_params(&self) -> (f32, f32, f32) {
+        match self.mode {
+            FogMode::Linear { start, end } => (start, end, 0.0),
+            FogMode::Exponential { density } | FogMode::ExponentialSquared { density } => {
+                (0.0, 0.0, density)
+            }
+        }
+    }
+
+    pub fn bind(&self, fogname: &str, prog: &ShaderProgram) {
+        let (start, end, density) = self.mode_params();
+
+        prog.set(fogname.to_string() + ".mode", self.mode.index());
+        prog.set(fogname.to_string() + ".color", self.color);
+        prog.set(fogname.to_string() + ".start", start);
+        prog.set(fogname.to_string() + ".end", end);
+        prog.set(fogname.to_string() + ".density", density);
+        prog.set(fogname.to_string() + ".height", self.height);
+        prog.set(fogname.to_string() + ".height_falloff", self.height_falloff);
+        prog.set(fogname.to_string() + ".skybox", self.skybox);
+    }
+
+    /// Disable the fog in the program
+    pub fn bind_disabled(fogname: &str, prog: &ShaderProgram) {
+        prog.set(fogname.to_string() + ".mode", 0);
+    }
+
+    /// Write the `Fog` struct of the per-frame uniform block,
+    /// `sky` is whether the sky is bound to `uFogSky`
+    pub fn write_std140(&self, w: &mut Std140Writer, sky: bool) {
+        let (start, end, density) = self.mode_params();
+
+        w.write_struct(|w| {
+            w.write_i32(self.mode.index())
+                .write_vec3(self.color)
+                .write_f32(start)
+                .write_f32(end)
+                .write_f32(density)
+                .write_f32(self.height)
+                .write_f32(self.height_falloff)
+                .write_f32(self.skybox)
+                .write_bool(sky);
+        });
+    }
+
+    /// Write a disabled fog in the per-frame uniform block
+    pub fn write_disabled_std140(w: &mut Std140Writer) {
+        w.write_struct(|w| {
+            w.write_i32(0)
+                .write_vec3(Vector3::new(0.0, 0.0, 0.0))
+                .write_f32(0.0)
+                .write_f32(0.0)
+                .write_f32(0.0)
+                .write_f32(0.0)
+                .write_f32(0.0)
+                .write_f32(0.0)
+                .write_bool(false);
+        });
+    }
+
+    /// Black cubemap bound to the sky sampler of the fog when it has no sky
+    pub(crate) fn default_sky() -> Rc<Texture> {
+        let face = || {
+            let black = ImageBuffer::from_pixel(1, 1, Rgba([0, 0, 0, 0xff]));
+            Resource::new(TextureImage::Rgba(black))
+        };
+
+        let tex = Texture::new_from_resource(TextureAsset::Cube([
+            face(),
+            face(),
+            face(),
+            face(),
+            face(),
+            face(),
+        ]));
+        tex.mipmaps.set(false);
+
+        tex
+    }
+}
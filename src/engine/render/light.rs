@@ -1,13 +1,15 @@
 use super::ShaderProgram;
+use super::uniform_buffer::Std140Writer;
 use math::*;
 use std::rc::Rc;
 use std::sync::Arc;
-use unrust::engine::{Component, ComponentArena, IntoComponentPtr};
+use unrust::engine::{Component, ComponentArena, IntoComponentPtr, ALL_LAYERS};
 
 #[derive(Component)]
 pub enum Light {
     Directional(DirectionalLight),
     Point(PointLight),
+    Spot(SpotLight),
 }
 
 macro_rules! impl_light {
@@ -33,6 +35,7 @@ macro_rules! impl_light {
 impl Light {
     impl_light!(directional, directional_mut, Directional, DirectionalLight);
     impl_light!(point, point_mut, Point, PointLight);
+    impl_light!(spot, spot_mut, Spot, SpotLight);
 
     pub fn new<T>(a: T) -> Light
     where
@@ -45,6 +48,7 @@ impl Light {
         match *self {
             Light::Directional(ref mut l) => l.update(model),
             Light::Point(ref mut l) => l.update(model),
+            Light::Spot(ref mut l) => l.update(model),
         }
     }
 
@@ -52,16 +56,111 @@ impl Light {
         match *self {
             Light::Directional(ref l) => l.bind(lightname, prog),
             Light::Point(ref l) => l.bind(lightname, prog),
+            Light::Spot(ref l) => l.bind(lightname, prog),
+        }
+    }
+
+    /// Bind the light with its direct contribution turned off
+    ///
+    /// The ambient term is kept, shaders add it once from the main light.
+    pub fn bind_unlit(&self, lightname: &str, prog: &ShaderProgram) {
+        self.bind(lightname, prog);
+
+        let black = Vector3f::zero();
+        prog.set(lightname.to_string() + ".diffuse", black);
+        prog.set(lightname.to_string() + ".specular", black);
+    }
+
+    /// Write the light as its glsl struct in a std140 uniform block
+    pub fn write_std140(&self, w: &mut Std140Writer) {
+        match *self {
+            Light::Directional(ref l) => l.write_std140(w),
+            Light::Point(ref l) => l.write_std140(w, 1.0),
+            Light::Spot(ref l) => l.write_std140(w),
+        }
+    }
+
+    /// Layers of game objects which are illuminated and shadowed by this light
+    pub fn culling_mask(&self) -> u32 {
+        match *self {
+            Light::Directional(ref l) => l.culling_mask,
+            Light::Point(ref l) => l.culling_mask,
+            Light::Spot(ref l) => l.culling_mask,
+        }
+    }
+
+    pub fn casts_shadows(&self) -> bool {
+        match *self {
+            Light::Directional(ref l) => l.casts_shadows,
+            Light::Point(ref l) => l.casts_shadows,
+            Light::Spot(ref l) => l.casts_shadows,
+        }
+    }
+
+    pub fn shadow_settings(&self) -> ShadowSettings {
+        match *self {
+            Light::Directional(ref l) => l.shadow,
+            Light::Point(ref l) => l.shadow,
+            Light::Spot(ref l) => l.shadow,
+        }
+    }
+
+    /// Point and spot lights, which are bound to the `uPointLights` slots
+    pub fn is_local(&self) -> bool {
+        match *self {
+            Light::Directional(_) => false,
+            _ => true,
+        }
+    }
+}
+
+/// Bias and strength of the shadows cast by a light
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShadowSettings {
+    /// Depth offset in shadow map texels, scaled up on surfaces sloped to the light
+    pub depth_bias: f32,
+    /// Offset of the lookup along the surface normal in world units,
+    /// scaled by the distance to the light for point and spot lights
+    pub normal_bias: f32,
+    /// From 0 for no shadow to 1 for fully dark shadows
+    pub strength: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> ShadowSettings {
+        ShadowSettings {
+            depth_bias: 0.25,
+            normal_bias: 0.02,
+            strength: 1.0,
         }
     }
 }
 
+/// Distance at which the attenuation falls below 1/256
+fn attenuation_range(constant: f32, linear: f32, quadratic: f32) -> f32 {
+    let c = constant - 256.0;
+
+    if quadratic > 0.0 {
+        (-linear + (linear * linear - 4.0 * quadratic * c).sqrt()) / (2.0 * quadratic)
+    } else if linear > 0.0 {
+        -c / linear
+    } else {
+        1000.0
+    }
+}
+
 pub struct DirectionalLight {
     pub direction: Vector3<f32>,
     pub ambient: Vector3<f32>,
     pub diffuse: Vector3<f32>,
     pub specular: Vector3<f32>,
 
+    /// Layers of game objects which are illuminated and shadowed by this light
+    pub culling_mask: u32,
+    /// Rendered in the cascaded shadow maps of `ShadowPass` when it is the main light
+    pub casts_shadows: bool,
+    pub shadow: ShadowSettings,
+
     pub world_space_direction: Vector3f,
 }
 
@@ -79,6 +178,9 @@ impl Default for DirectionalLight {
             ambient: Vector3::new(0.212, 0.227, 0.259),
             diffuse: Vector3::new(1.0, 0.957, 0.839),
             specular: Vector3::new(1.0, 1.0, 1.0),
+            culling_mask: ALL_LAYERS,
+            casts_shadows: true,
+            shadow: ShadowSettings::default(),
 
             world_space_direction: light_dir,
         }
@@ -102,6 +204,15 @@ impl DirectionalLight {
         prog.set(lightname.to_string() + ".specular", self.specular);
     }
 
+    pub fn write_std140(&self, w: &mut Std140Writer) {
+        w.write_struct(|w| {
+            w.write_vec3(self.world_space_direction)
+                .write_vec3(self.ambient)
+                .write_vec3(self.diffuse)
+                .write_vec3(self.specular);
+        });
+    }
+
     fn update(&mut self, modelm: &Matrix4f) {
         let m = modelm.inverse_transform().unwrap().transpose();
         self.world_space_direction = m.transform_vector(self.direction);
@@ -119,6 +230,12 @@ pub struct PointLight {
     pub linear: f32,
     pub quadratic: f32,
 
+    /// Layers of game objects which are illuminated by this light
+    pub culling_mask: u32,
+    /// Rendered in the shadow atlas of `ShadowPass` as the 6 faces of a cube
+    pub casts_shadows: bool,
+    pub shadow: ShadowSettings,
+
     pub world_space_position: Vector3f,
 }
 
@@ -139,6 +256,9 @@ impl Default for PointLight {
             constant: 1.0,
             linear: 0.022,
             quadratic: 0.0019,
+            culling_mask: ALL_LAYERS,
+            casts_shadows: false,
+            shadow: ShadowSettings::default(),
         }
     }
 }
@@ -159,12 +279,160 @@ impl PointLight {
         prog.set(lightname.to_string() + ".quadratic", self.quadratic);
 
         prog.set(lightname.to_string() + ".rate", 1.0);
+
+        // No cone, the spot factor is always 1
+        prog.set(lightname.to_string() + ".direction", Vector3f::zero());
+        prog.set(lightname.to_string() + ".cutoff", -1.0);
+        prog.set(lightname.to_string() + ".outer_cutoff", -2.0);
+    }
+
+    /// A `rate` of 0 writes an unused light slot
+    pub fn write_std140(&self, w: &mut Std140Writer, rate: f32) {
+        w.write_struct(|w| {
+            w.write_vec3(self.world_space_position)
+                .write_f32(self.constant)
+                .write_f32(self.linear)
+                .write_f32(self.quadratic)
+                .write_vec3(self.ambient)
+                .write_vec3(self.diffuse)
+                .write_vec3(self.specular)
+                .write_f32(rate)
+                .write_vec3(Vector3f::zero())
+                .write_f32(-1.0)
+                .write_f32(-2.0);
+        });
+    }
+
+    /// Distance beyond which the light has no visible contribution
+    pub fn range(&self) -> f32 {
+        attenuation_range(self.constant, self.linear, self.quadratic)
+    }
+
+    fn update(&mut self, modelm: &Matrix4f) {
+        self.world_space_position = modelm
+            .transform_point(Point3::from_vec(self.position))
+            .to_vec();
+    }
+}
+
+/// A point light restricted to a cone, bound to the `uPointLights` slots
+pub struct SpotLight {
+    pub position: Vector3<f32>,
+    /// Axis of the cone in local space
+    pub direction: Vector3<f32>,
+
+    pub ambient: Vector3<f32>,
+    pub diffuse: Vector3<f32>,
+    pub specular: Vector3<f32>,
+
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+
+    /// Half angle of the fully lit cone
+    pub cutoff: Deg<f32>,
+    /// Half angle where the light fades out
+    pub outer_cutoff: Deg<f32>,
+
+    /// Layers of game objects which are illuminated by this light
+    pub culling_mask: u32,
+    /// Rendered in the shadow atlas of `ShadowPass` with a perspective projection
+    pub casts_shadows: bool,
+    pub shadow: ShadowSettings,
+
+    pub world_space_position: Vector3f,
+    pub world_space_direction: Vector3f,
+}
+
+impl From<SpotLight> for Light {
+    fn from(w: SpotLight) -> Light {
+        Light::Spot(w)
+    }
+}
+
+impl Default for SpotLight {
+    fn default() -> SpotLight {
+        SpotLight {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            ambient: Vector3::new(0.0, 0.0, 0.0),
+            diffuse: Vector3::new(0.8, 0.8, 0.8),
+            specular: Vector3::new(1.0, 1.0, 1.0),
+            constant: 1.0,
+            linear: 0.022,
+            quadratic: 0.0019,
+            cutoff: Deg(25.0),
+            outer_cutoff: Deg(30.0),
+            culling_mask: ALL_LAYERS,
+            casts_shadows: false,
+            shadow: ShadowSettings::default(),
+            world_space_position: Vector3f::zero(),
+            world_space_direction: Vector3::new(0.0, 0.0, -1.0),
+        }
+    }
+}
+
+impl SpotLight {
+    fn bind(&self, lightname: &str, prog: &ShaderProgram) {
+        prog.set(
+            lightname.to_string() + ".position",
+            self.world_space_position,
+        );
+
+        prog.set(lightname.to_string() + ".ambient", self.ambient);
+        prog.set(lightname.to_string() + ".diffuse", self.diffuse);
+        prog.set(lightname.to_string() + ".specular", self.specular);
+
+        prog.set(lightname.to_string() + ".constant", self.constant);
+        prog.set(lightname.to_string() + ".linear", self.linear);
+        prog.set(lightname.to_string() + ".quadratic", self.quadratic);
+
+        prog.set(lightname.to_string() + ".rate", 1.0);
+
+        prog.set(
+            lightname.to_string() + ".direction",
+            self.world_space_direction,
+        );
+        prog.set(lightname.to_string() + ".cutoff", self.cutoff.cos());
+        prog.set(
+            lightname.to_string() + ".outer_cutoff",
+            self.outer_cutoff.cos(),
+        );
+    }
+
+    pub fn write_std140(&self, w: &mut Std140Writer) {
+        w.write_struct(|w| {
+            w.write_vec3(self.world_space_position)
+                .write_f32(self.constant)
+                .write_f32(self.linear)
+                .write_f32(self.quadratic)
+                .write_vec3(self.ambient)
+                .write_vec3(self.diffuse)
+                .write_vec3(self.specular)
+                .write_f32(1.0)
+                .write_vec3(self.world_space_direction)
+                .write_f32(self.cutoff.cos())
+                .write_f32(self.outer_cutoff.cos());
+        });
+    }
+
+    /// Distance beyond which the light has no visible contribution
+    pub fn range(&self) -> f32 {
+        attenuation_range(self.constant, self.linear, self.quadratic)
     }
 
     fn update(&mut self, modelm: &Matrix4f) {
         self.world_space_position = modelm
             .transform_point(Point3::from_vec(self.position))
             .to_vec();
+        self.world_space_direction = modelm.transform_vector(self.direction).normalize();
+    }
+}
+
+impl IntoComponentPtr for SpotLight {
+    fn into_component_ptr(self, arena: &Rc<ComponentArena>) -> Arc<Component> {
+        let light: Light = self.into();
+        Component::new(light, arena)
     }
 }
 
@@ -1,11 +1,13 @@
 use engine::asset::{Asset, AssetResult};
-use engine::render::{RenderQueue, ShaderProgram, Texture};
+use engine::render::{RenderQueue, ShaderKeywords, ShaderProgram, ShaderVarType, Std140Writer,
+                     Texture, UniformBuffer, MATERIAL_BLOCK_BINDING, MATERIAL_BLOCK_NAME};
 
 use fnv::FnvHashMap;
 use math::*;
 use std::borrow::Cow;
-use std::cell::RefCell;
+use std::cell::{Cell, Ref, RefCell};
 use std::rc::Rc;
+use uni_gl::WebGLRenderingContext;
 
 #[derive(Debug, Clone)]
 pub struct TexturePtr(Rc<Texture>);
@@ -50,6 +52,100 @@ impl_from_material_param!(Vector4<f32>, Vec4);
 impl_from_material_param!(Matrix4<f32>, Matrix4);
 impl_from_material_param!(MaterialParamMap, Params);
 
+impl MaterialParam {
+    /// Whether the param can be set to an uniform of type `ty`
+    pub fn matches(&self, ty: &ShaderVarType) -> bool {
+        match (self, ty) {
+            (&MaterialParam::Texture(ref tex), ty) => tex.0.sampler_type() == *ty,
+            (&MaterialParam::Bool(_), &ShaderVarType::Bool) => true,
+            (&MaterialParam::Int(_), &ShaderVarType::Int) => true,
+            (&MaterialParam::Int(_), &ShaderVarType::Bool) => true,
+            // the texture unit of a sampler
+            (&MaterialParam::Int(_), ty) if ty.is_sampler() => true,
+            (&MaterialParam::Float(_), &ShaderVarType::Float) => true,
+            (&MaterialParam::Vec2(_), &ShaderVarType::Vec2) => true,
+            (&MaterialParam::Vec3(_), &ShaderVarType::Vec3) => true,
+            (&MaterialParam::Vec4(_), &ShaderVarType::Vec4) => true,
+            (&MaterialParam::Matrix4(_), &ShaderVarType::Mat4) => true,
+            _ => false,
+        }
+    }
+
+    /// Zero value of the same kind, textures are reset to unit 0
+    fn zero(&self) -> MaterialParam {
+        match self {
+            &MaterialParam::Texture(_) | &MaterialParam::Int(_) => MaterialParam::Int(0),
+            &MaterialParam::Float(_) => MaterialParam::Float(0.0),
+            &MaterialParam::Bool(_) => MaterialParam::Bool(false),
+            &MaterialParam::Vec2(_) => MaterialParam::Vec2(Vector2::zero()),
+            &MaterialParam::Vec3(_) => MaterialParam::Vec3(Vector3::zero()),
+            &MaterialParam::Vec4(_) => MaterialParam::Vec4(Vector4::zero()),
+            &MaterialParam::Matrix4(_) => MaterialParam::Matrix4(Matrix4::zero()),
+            &MaterialParam::Params(_) => MaterialParam::Params(MaterialParamMap::default()),
+        }
+    }
+
+    /// Zero value of an uniform of type `ty`, samplers are reset to unit 0
+    fn zero_of(ty: &ShaderVarType) -> Option<MaterialParam> {
+        match *ty {
+            ShaderVarType::Bool => Some(MaterialParam::Bool(false)),
+            ShaderVarType::Int => Some(MaterialParam::Int(0)),
+            ShaderVarType::Float => Some(MaterialParam::Float(0.0)),
+            ShaderVarType::Vec2 => Some(MaterialParam::Vec2(Vector2::zero())),
+            ShaderVarType::Vec3 => Some(MaterialParam::Vec3(Vector3::zero())),
+            ShaderVarType::Vec4 => Some(MaterialParam::Vec4(Vector4::zero())),
+            ShaderVarType::Mat4 => Some(MaterialParam::Matrix4(Matrix4::zero())),
+            ref ty if ty.is_sampler() => Some(MaterialParam::Int(0)),
+            _ => None,
+        }
+    }
+}
+
+/// Find a param in `params` and the nested params, which are flattened when bound
+fn find_param<'a>(params: &'a MaterialParamMap, name: &str) -> Option<&'a MaterialParam> {
+    if let Some(p) = params.get(name) {
+        return Some(p);
+    }
+
+    params
+        .values()
+        .filter_map(|p| match p {
+            &MaterialParam::Params(ref pm) => find_param(pm, name),
+            _ => None,
+        })
+        .next()
+}
+
+/// Write a member of the material block, zeros if the param is missing or does not match
+///
+/// Return false if values of type `ty` can not be packed.
+fn write_block_member(
+    w: &mut Std140Writer,
+    ty: &ShaderVarType,
+    param: Option<&MaterialParam>,
+) -> bool {
+    match (ty, param) {
+        (&ShaderVarType::Bool, Some(&MaterialParam::Bool(v))) => w.write_bool(v),
+        (&ShaderVarType::Bool, Some(&MaterialParam::Int(v))) => w.write_bool(v != 0),
+        (&ShaderVarType::Bool, _) => w.write_bool(false),
+        (&ShaderVarType::Int, Some(&MaterialParam::Int(v))) => w.write_i32(v),
+        (&ShaderVarType::Int, _) => w.write_i32(0),
+        (&ShaderVarType::Float, Some(&MaterialParam::Float(v))) => w.write_f32(v),
+        (&ShaderVarType::Float, _) => w.write_f32(0.0),
+        (&ShaderVarType::Vec2, Some(&MaterialParam::Vec2(v))) => w.write_vec2(v),
+        (&ShaderVarType::Vec2, _) => w.write_vec2(Vector2::zero()),
+        (&ShaderVarType::Vec3, Some(&MaterialParam::Vec3(v))) => w.write_vec3(v),
+        (&ShaderVarType::Vec3, _) => w.write_vec3(Vector3::zero()),
+        (&ShaderVarType::Vec4, Some(&MaterialParam::Vec4(v))) => w.write_vec4(v),
+        (&ShaderVarType::Vec4, _) => w.write_vec4(Vector4::zero()),
+        (&ShaderVarType::Mat4, Some(&MaterialParam::Matrix4(v))) => w.write_mat4(v),
+        (&ShaderVarType::Mat4, _) => w.write_mat4(Matrix4::zero()),
+        _ => return false,
+    };
+
+    true
+}
+
 impl From<Rc<Texture>> for MaterialParam {
     fn from(b: Rc<Texture>) -> MaterialParam {
         MaterialParam::Texture(TexturePtr(b))
@@ -83,28 +179,192 @@ impl Default for DepthTest {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Default, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum BlendEquation {
+    Add,
+    Subtract,
+    ReverseSubtract,
+}
+
+/// How the fragment color is blended with the frame buffer when `alpha_blending` is on
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BlendState {
+    pub equation: BlendEquation,
+    pub src: BlendFactor,
+    pub dst: BlendFactor,
+}
+
+impl BlendState {
+    pub fn alpha() -> BlendState {
+        BlendState {
+            equation: BlendEquation::Add,
+            src: BlendFactor::SrcAlpha,
+            dst: BlendFactor::OneMinusSrcAlpha,
+        }
+    }
+
+    pub fn premultiplied() -> BlendState {
+        BlendState {
+            equation: BlendEquation::Add,
+            src: BlendFactor::One,
+            dst: BlendFactor::OneMinusSrcAlpha,
+        }
+    }
+
+    pub fn additive() -> BlendState {
+        BlendState {
+            equation: BlendEquation::Add,
+            src: BlendFactor::SrcAlpha,
+            dst: BlendFactor::One,
+        }
+    }
+
+    pub fn multiply() -> BlendState {
+        BlendState {
+            equation: BlendEquation::Add,
+            src: BlendFactor::DstColor,
+            dst: BlendFactor::Zero,
+        }
+    }
+}
+
+impl Default for BlendState {
+    fn default() -> BlendState {
+        BlendState::alpha()
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    Increment,
+    IncrementWrap,
+    Decrement,
+    DecrementWrap,
+    Invert,
+}
+
+/// Stencil test and operations, the stencil test is disabled
+/// when it cannot affect rendering (test is `Always` and all ops are `Keep`)
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct StencilState {
+    /// Comparison between `reference` and the stored stencil value
+    pub test: DepthTest,
+    pub reference: i32,
+    pub read_mask: u32,
+    pub write_mask: u32,
+
+    /// Operation when the stencil test fails
+    pub fail: StencilOp,
+    /// Operation when the stencil test passes but the depth test fails
+    pub depth_fail: StencilOp,
+    /// Operation when both the stencil and depth tests pass
+    pub pass: StencilOp,
+}
+
+impl StencilState {
+    pub fn is_enabled(&self) -> bool {
+        self.test != DepthTest::Always || self.fail != StencilOp::Keep
+            || self.depth_fail != StencilOp::Keep || self.pass != StencilOp::Keep
+    }
+}
+
+impl Default for StencilState {
+    fn default() -> StencilState {
+        StencilState {
+            test: DepthTest::Always,
+            reference: 0,
+            read_mask: !0,
+            write_mask: !0,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+/// Depth offset, (0.0, 0.0) disables the polygon offset
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct PolygonOffset {
+    pub factor: f32,
+    pub units: f32,
+}
+
+impl PolygonOffset {
+    pub fn is_enabled(&self) -> bool {
+        self.factor != 0.0 || self.units != 0.0
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Scissor {
+    Off,
+    /// (pos, size) in pixels, same as the viewport
+    Rect((i32, i32), (u32, u32)),
+}
+
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
 pub struct MaterialState {
     pub cull: Option<CullMode>,
     pub alpha_blending: Option<bool>,
+    pub blend: Option<BlendState>,
+    /// Which of the (r, g, b, a) channels are written
+    pub color_mask: Option<(bool, bool, bool, bool)>,
     pub depth_write: Option<bool>,
     pub depth_test: Option<DepthTest>,
+    pub stencil: Option<StencilState>,
+    pub polygon_offset: Option<PolygonOffset>,
+    pub scissor: Option<Scissor>,
 }
 
 #[derive(Debug)]
 pub struct Material {
     pub program: Rc<ShaderProgram>,
     pub render_queue: RenderQueue,
+    /// Surfaces in the same render queue are rendered in ascending order of sort key
+    pub sort_key: i32,
     pub states: MaterialState,
 
     params: RefCell<MaterialParamMap>,
+    uniform_blocks: RefCell<Vec<Rc<UniformBuffer>>>,
+    /// `params` packed in the layout of the `MaterialData` block of the program
+    params_block: UniformBuffer,
+    /// Whether `params_block` has to be packed again
+    params_block_dirty: Cell<bool>,
+
+    keywords: RefCell<ShaderKeywords>,
+    /// The variant of `program` matching `keywords`, with the generation of `program`
+    variant: RefCell<Option<(u32, Rc<ShaderProgram>)>>,
 }
 
 impl PartialEq for Material {
     fn eq(&self, other: &Self) -> bool {
         Rc::ptr_eq(&self.program, &other.program) && self.render_queue == other.render_queue
-            && self.states == other.states
+            && self.sort_key == other.sort_key && self.states == other.states
             && *self.params.borrow() == *other.params.borrow()
+            && *self.keywords.borrow() == *other.keywords.borrow()
+            && self.uniform_blocks.borrow().len() == other.uniform_blocks.borrow().len()
+            && self.uniform_blocks
+                .borrow()
+                .iter()
+                .zip(other.uniform_blocks.borrow().iter())
+                .all(|(a, b)| Rc::ptr_eq(a, b))
     }
 }
 
@@ -112,8 +372,14 @@ impl Material {
     pub fn new(program: Rc<ShaderProgram>) -> Material {
         return Material {
             render_queue: RenderQueue::Opaque,
+            sort_key: 0,
             program: program,
             params: RefCell::new(FnvHashMap::default()),
+            uniform_blocks: RefCell::new(Vec::new()),
+            params_block: UniformBuffer::new(MATERIAL_BLOCK_NAME, MATERIAL_BLOCK_BINDING),
+            params_block_dirty: Cell::new(true),
+            keywords: RefCell::new(ShaderKeywords::new()),
+            variant: RefCell::new(None),
             states: MaterialState::default(),
         };
     }
@@ -124,10 +390,64 @@ impl Material {
         S: Into<Cow<'static, str>>,
     {
         self.params.borrow_mut().insert(name.into(), t.into());
+        self.params_block_dirty.set(true);
+    }
+
+    /// Enable a shader keyword, e.g. `NORMAL_MAP`, which is `#define`d in the shader variant
+    pub fn enable_keyword<S>(&self, keyword: S)
+    where
+        S: Into<String>,
+    {
+        if self.keywords.borrow_mut().insert(keyword.into()) {
+            self.variant.replace(None);
+        }
+    }
+
+    pub fn disable_keyword(&self, keyword: &str) {
+        if self.keywords.borrow_mut().remove(keyword) {
+            self.variant.replace(None);
+        }
+    }
+
+    pub fn is_keyword_enabled(&self, keyword: &str) -> bool {
+        self.keywords.borrow().contains(keyword)
+    }
+
+    pub fn keywords(&self) -> Ref<ShaderKeywords> {
+        self.keywords.borrow()
+    }
+
+    /// The program variant used to render this material
+    pub fn shader_program(&self) -> AssetResult<Rc<ShaderProgram>> {
+        let generation = self.program.generation();
+
+        if let Some((g, ref prog)) = *self.variant.borrow() {
+            // A reload of the program drops its variants
+            if g == generation {
+                return Ok(prog.clone());
+            }
+        }
+
+        let prog = ShaderProgram::variant(&self.program, &self.keywords.borrow())?;
+        self.variant.replace(Some((generation, prog.clone())));
+        // The layout of the block may differ between variants
+        self.params_block_dirty.set(true);
+
+        Ok(prog)
+    }
+
+    /// Attach a uniform block shared by the material, it is bound when the program declares it
+    ///
+    /// Blocks are ignored on GLES2 / WebGL1, where the params set by `set` are used instead.
+    /// The `MaterialData` block is packed from the params set by `set` if the program
+    /// declares it, so `MATERIAL_BLOCK_BINDING` should not be used by other blocks.
+    pub fn add_uniform_block(&self, block: Rc<UniformBuffer>) {
+        self.uniform_blocks.borrow_mut().push(block);
     }
 
     fn bind_params<F>(
         &self,
+        prog: &ShaderProgram,
         params: &MaterialParamMap,
         request_tex_unit: &mut F,
         level: u32,
@@ -136,35 +456,47 @@ impl Material {
         F: FnMut(&Rc<Texture>) -> AssetResult<u32>,
     {
         for (name, param) in params.iter() {
+            // nested params are flattened, only their members are uniforms
+            match param {
+                &MaterialParam::Params(_) => (),
+                _ => {
+                    prog.check_uniform(name, |ty| param.matches(ty));
+
+                    // Packed in the material block instead, see `bind_uniform_blocks`
+                    if prog.in_block(MATERIAL_BLOCK_NAME, name) {
+                        continue;
+                    }
+                }
+            }
+
             match param {
                 &MaterialParam::Texture(ref tex) => {
                     let new_unit = request_tex_unit(&tex.0)?;
-                    self.program
-                        .set(name.clone(), (Rc::downgrade(&tex.0), new_unit));
+                    prog.set(name.clone(), (Rc::downgrade(&tex.0), new_unit));
                 }
                 &MaterialParam::Bool(v) => {
-                    self.program.set(name.clone(), v);
+                    prog.set(name.clone(), v);
                 }
                 &MaterialParam::Float(f) => {
-                    self.program.set(name.clone(), f);
+                    prog.set(name.clone(), f);
                 }
                 &MaterialParam::Int(v) => {
-                    self.program.set(name.clone(), v);
+                    prog.set(name.clone(), v);
                 }
                 &MaterialParam::Vec2(v) => {
-                    self.program.set(name.clone(), v);
+                    prog.set(name.clone(), v);
                 }
                 &MaterialParam::Vec3(v) => {
-                    self.program.set(name.clone(), v);
+                    prog.set(name.clone(), v);
                 }
                 &MaterialParam::Vec4(v) => {
-                    self.program.set(name.clone(), v);
+                    prog.set(name.clone(), v);
                 }
                 &MaterialParam::Matrix4(v) => {
-                    self.program.set(name.clone(), v);
+                    prog.set(name.clone(), v);
                 }
                 &MaterialParam::Params(ref pm) => {
-                    self.bind_params(&pm, request_tex_unit, level + 1)?;
+                    self.bind_params(prog, &pm, request_tex_unit, level + 1)?;
                 }
             }
         }
@@ -176,10 +508,125 @@ impl Material {
     where
         F: FnMut(&Rc<Texture>) -> AssetResult<u32>,
     {
-        self.bind_params(&self.params.borrow(), &mut request_tex_unit, 0)?;
+        let prog = self.shader_program()?;
+        self.bind_params(&prog, &self.params.borrow(), &mut request_tex_unit, 0)?;
 
         Ok(())
     }
+
+    /// Bind per-instance params on top of the params of this material
+    pub fn bind_properties<F>(
+        &self,
+        properties: &MaterialParamMap,
+        mut request_tex_unit: F,
+    ) -> AssetResult<()>
+    where
+        F: FnMut(&Rc<Texture>) -> AssetResult<u32>,
+    {
+        let prog = self.shader_program()?;
+        self.bind_params(&prog, properties, &mut request_tex_unit, 0)
+    }
+
+    /// Values binding back the params overridden by `properties` once a surface is drawn
+    ///
+    /// Params which are not set by this material are reset to zero,
+    /// or to unit 0 for samplers.
+    pub fn restored_properties(&self, properties: &MaterialParamMap) -> MaterialParamMap {
+        let prog = self.shader_program().ok();
+        let mut restored = MaterialParamMap::default();
+        self.restore_params(prog.as_ref().map(|p| &**p), properties, &mut restored);
+
+        restored
+    }
+
+    fn restore_params(
+        &self,
+        prog: Option<&ShaderProgram>,
+        properties: &MaterialParamMap,
+        restored: &mut MaterialParamMap,
+    ) {
+        for (name, param) in properties.iter() {
+            if let &MaterialParam::Params(ref pm) = param {
+                self.restore_params(prog, pm, restored);
+                continue;
+            }
+
+            let value = find_param(&self.params.borrow(), name).cloned();
+            let value = value.unwrap_or_else(|| {
+                // The type of the override is used until the program is reflected
+                prog.and_then(|p| p.reflection())
+                    .and_then(|r| r.uniform(name).and_then(|v| MaterialParam::zero_of(&v.ty)))
+                    .unwrap_or_else(|| param.zero())
+            });
+
+            restored.insert(name.clone(), value);
+        }
+    }
+
+    pub fn bind_uniform_blocks(&self, gl: &WebGLRenderingContext) -> AssetResult<()> {
+        let prog = self.shader_program()?;
+
+        if prog.uniform_block(gl, MATERIAL_BLOCK_NAME, MATERIAL_BLOCK_BINDING) {
+            if self.params_block_dirty.get() {
+                self.pack_params_block(&prog);
+            }
+            self.params_block.bind(gl);
+        }
+
+        for block in self.uniform_blocks.borrow().iter() {
+            if prog.uniform_block(gl, block.name(), block.binding()) {
+                block.bind(gl);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Material {
+    /// Write the params following the members of the `MaterialData` block of `prog`
+    fn pack_params_block(&self, prog: &ShaderProgram) {
+        let reflection = match prog.reflection() {
+            Some(r) => r,
+            None => return,
+        };
+
+        let members = match reflection.blocks.get(MATERIAL_BLOCK_NAME) {
+            Some(members) => members,
+            None => return,
+        };
+
+        let params = self.params.borrow();
+        let mut w = Std140Writer::new();
+        self.params_block_dirty.set(false);
+
+        for m in members.iter() {
+            let packed = match m.array_len {
+                None => write_block_member(&mut w, &m.ty, find_param(&params, &m.name)),
+                Some(0) => false,
+                Some(n) => (0..n).all(|i| {
+                    let name = format!("{}[{}]", m.name, i);
+                    let param = find_param(&params, &name).or_else(|| match i {
+                        0 => find_param(&params, &m.name),
+                        _ => None,
+                    });
+
+                    // Array elements are aligned to vec4
+                    let mut packed = true;
+                    w.write_struct(|w| packed = write_block_member(w, &m.ty, param));
+                    packed
+                }),
+            };
+
+            // Offsets after this member would be wrong, the block is left as it was
+            if !packed {
+                prog.report_uniform(&m.name, "can not be packed in the material uniform block");
+                return;
+            }
+        }
+
+        self.params_block.update(w);
+    }
 }
 
 impl Asset for Material {
@@ -1,7 +1,21 @@
 use engine::core::Aabb;
-use engine::render::{Material, MeshBuffer};
-use std::cell::Cell;
+use engine::render::{Material, MaterialParam, MaterialParamMap, MeshBuffer, MAX_MORPH_TARGETS};
+use math::Matrix4f;
+use std::borrow::Cow;
+use std::cell::{Cell, Ref, RefCell};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Names of the weights of the morph targets, see "unrust/morph.glsl"
+const MORPH_WEIGHT_UNIFORMS: [&str; MAX_MORPH_TARGETS] = [
+    "uMorphWeights[0]",
+    "uMorphWeights[1]",
+    "uMorphWeights[2]",
+    "uMorphWeights[3]",
+];
+
+/// Bumped whenever the bounds of any mesh are invalidated
+static BOUNDS_GENERATION: AtomicU32 = AtomicU32::new(0);
 
 #[derive(Copy, Clone)]
 pub struct MeshBound {
@@ -30,12 +44,90 @@ impl MeshBound {
 pub struct MeshSurface {
     pub buffer: Rc<MeshBuffer>,
     pub material: Rc<Material>,
+
+    /// Per-instance params applied on top of the shared material params
+    properties: RefCell<MaterialParamMap>,
+}
+
+impl MeshSurface {
+    pub fn new<U, T>(buffer: U, material: T) -> MeshSurface
+    where
+        U: Into<Rc<MeshBuffer>>,
+        T: Into<Rc<Material>>,
+    {
+        MeshSurface {
+            buffer: buffer.into(),
+            material: material.into(),
+            properties: RefCell::new(MaterialParamMap::default()),
+        }
+    }
+
+    /// Override a material param for this surface only,
+    /// the material itself (and its sorting and caching) is unchanged.
+    ///
+    /// Once the surface is drawn, the param is bound back to its value in the material,
+    /// or to zero if the material does not set it.
+    pub fn set_property<T, S>(&self, name: S, t: T)
+    where
+        T: Into<MaterialParam>,
+        S: Into<Cow<'static, str>>,
+    {
+        self.properties.borrow_mut().insert(name.into(), t.into());
+    }
+
+    /// Weight of the morph target `target` of the buffer, see `MeshData::morph_targets`
+    ///
+    /// Set as a property, such that each surface sharing the material has its own weights.
+    pub fn set_morph_weight(&self, target: usize, weight: f32) {
+        if let Some(&name) = MORPH_WEIGHT_UNIFORMS.get(target) {
+            self.set_property(name, weight);
+        }
+    }
+
+    pub fn remove_property(&self, name: &str) {
+        self.properties.borrow_mut().remove(name);
+    }
+
+    pub fn clear_properties(&self) {
+        self.properties.borrow_mut().clear();
+    }
+
+    pub fn properties(&self) -> Ref<MaterialParamMap> {
+        self.properties.borrow()
+    }
 }
 
-#[derive(Component, Clone)]
+impl Clone for MeshSurface {
+    fn clone(&self) -> MeshSurface {
+        MeshSurface {
+            buffer: self.buffer.clone(),
+            material: self.material.clone(),
+            properties: RefCell::new(self.properties.borrow().clone()),
+        }
+    }
+}
+
+#[derive(Component)]
 pub struct Mesh {
     pub surfaces: Vec<Rc<MeshSurface>>,
     pub mesh_bounds: Cell<Option<MeshBound>>,
+
+    /// World space bounds cache, keyed by the model matrix used to compute it
+    world_bounds: Cell<Option<(Matrix4f, Aabb)>>,
+}
+
+/// Surfaces are copied, such that each clone has its own per-instance params
+impl Clone for Mesh {
+    fn clone(&self) -> Mesh {
+        Mesh {
+            surfaces: self.surfaces
+                .iter()
+                .map(|s| Rc::new(MeshSurface::clone(s)))
+                .collect(),
+            mesh_bounds: self.mesh_bounds.clone(),
+            world_bounds: self.world_bounds.clone(),
+        }
+    }
 }
 
 impl Mesh {
@@ -43,6 +135,7 @@ impl Mesh {
         Mesh {
             surfaces: Vec::new(),
             mesh_bounds: Cell::new(None),
+            world_bounds: Cell::new(None),
         }
     }
 
@@ -51,15 +144,29 @@ impl Mesh {
         U: Into<Rc<MeshBuffer>>,
         T: Into<Rc<Material>>,
     {
-        self.surfaces.push(Rc::new(MeshSurface {
-            buffer: buffer.into(),
-            material: material.into(),
-        }));
+        self.surfaces.push(Rc::new(MeshSurface::new(buffer, material)));
+
+        self.invalidate_bounds();
     }
 
     pub fn remove_buffer(&mut self, buffer: &Rc<MeshBuffer>) {
         self.surfaces
-            .retain(|surface| !Rc::ptr_eq(buffer, &surface.buffer))
+            .retain(|surface| !Rc::ptr_eq(buffer, &surface.buffer));
+
+        self.invalidate_bounds();
+    }
+
+    /// Compute the bounds again, e.g. after the data of a buffer was updated
+    pub fn invalidate_bounds(&self) {
+        self.mesh_bounds.set(None);
+        self.world_bounds.set(None);
+
+        BOUNDS_GENERATION.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Changes whenever the bounds of any mesh are invalidated
+    pub fn bounds_generation() -> u64 {
+        BOUNDS_GENERATION.load(Ordering::SeqCst) as u64
     }
 
     /// bounds return (vmin, vmax)
@@ -85,4 +192,20 @@ impl Mesh {
         self.mesh_bounds.set(Some(bb));
         self.mesh_bounds.get()
     }
+
+    /// World space aabb of the whole mesh transformed by model matrix `m`
+    ///
+    /// The result is cached until the mesh is drawn with another model matrix.
+    pub fn world_bounds(&self, m: &Matrix4f) -> Option<Aabb> {
+        if let Some((cached_m, aabb)) = self.world_bounds.get() {
+            if cached_m == *m {
+                return Some(aabb);
+            }
+        }
+
+        let aabb = self.bounds()?.local_aabb().transform(m);
+        self.world_bounds.set(Some((*m, aabb)));
+
+        Some(aabb)
+    }
 }
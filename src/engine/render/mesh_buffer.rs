@@ -12,7 +12,9 @@ use std::cell::Cell;
 use std::cell::RefCell;
 use std::f32::{MAX, MIN};
 use std::rc::Rc;
-use std::rc::Weak;
+
+/// Morph targets blended by the shaders, see "unrust/morph.glsl"
+pub const MAX_MORPH_TARGETS: usize = 4;
 
 trait IntoBytes {
     fn into_bytes(self) -> Vec<u8>;
@@ -34,6 +36,10 @@ enum RebindAction {
     Normal,
     Tangent,
     Bitangent,
+    Color,
+    Joints,
+    Weights,
+    MorphTarget(usize),
     Indices,
 }
 
@@ -45,6 +51,10 @@ struct MeshGLState {
     pub nb: Option<WebGLBuffer>,
     pub tb: Option<WebGLBuffer>,
     pub btb: Option<WebGLBuffer>,
+    pub cb: Option<WebGLBuffer>,
+    pub jb: Option<WebGLBuffer>,
+    pub wb: Option<WebGLBuffer>,
+    pub mtb: Vec<WebGLBuffer>,
 
     pub ib: WebGLBuffer,
     pub gl: WebGLRenderingContext,
@@ -84,6 +94,26 @@ impl MeshGLState {
                 data.bitangents.clone().unwrap().into_bytes(),
                 self.btb.as_mut().unwrap(),
             ),
+            RebindAction::Color => (
+                BufferKind::Array,
+                data.colors.clone().unwrap().into_bytes(),
+                self.cb.as_mut().unwrap(),
+            ),
+            RebindAction::Joints => (
+                BufferKind::Array,
+                data.joints.clone().unwrap().into_bytes(),
+                self.jb.as_mut().unwrap(),
+            ),
+            RebindAction::Weights => (
+                BufferKind::Array,
+                data.weights.clone().unwrap().into_bytes(),
+                self.wb.as_mut().unwrap(),
+            ),
+            RebindAction::MorphTarget(i) => (
+                BufferKind::Array,
+                data.morph_targets[i].clone().into_bytes(),
+                &mut self.mtb[i],
+            ),
             RebindAction::Indices => (
                 BufferKind::ElementArray,
                 data.indices.clone().into_bytes(),
@@ -110,6 +140,12 @@ impl Drop for MeshGLState {
         self.nb.as_ref().map(|b| self.gl.delete_buffer(&b));
         self.tb.as_ref().map(|b| self.gl.delete_buffer(&b));
         self.btb.as_ref().map(|b| self.gl.delete_buffer(&b));
+        self.cb.as_ref().map(|b| self.gl.delete_buffer(&b));
+        self.jb.as_ref().map(|b| self.gl.delete_buffer(&b));
+        self.wb.as_ref().map(|b| self.gl.delete_buffer(&b));
+        for b in self.mtb.iter() {
+            self.gl.delete_buffer(b);
+        }
         self.gl.delete_buffer(&self.ib);
 
         self.gl.delete_vertex_array(&self.vao);
@@ -125,23 +161,41 @@ pub struct MeshData {
     pub tangents: Option<Vec<f32>>,
     pub bitangents: Option<Vec<f32>>,
 
+    /// Per vertex rgba colors
+    pub colors: Option<Vec<f32>>,
+
+    /// Per vertex indices of 4 joints of a `Skeleton`, as floats
+    pub joints: Option<Vec<f32>>,
+    /// Per vertex weights of the 4 joints, summing up to 1
+    pub weights: Option<Vec<f32>>,
+
+    /// Per vertex position offsets of up to `MAX_MORPH_TARGETS` targets, blended with
+    /// the weights set by `MeshSurface::set_morph_weight`
+    pub morph_targets: Vec<Vec<f32>>,
+
     pub indices: Vec<u16>,
 }
 
 impl MeshData {
+    /// Bounds of the vertices, also displaced by each morph target with a weight of 1
     pub fn compute_bound(&self) -> MeshBound {
         let mut min = Vector3::new(MAX, MAX, MAX);
         let mut max = Vector3::new(MIN, MIN, MIN);
         let mut r: f32 = 0.0;
 
-        for (i, v) in self.vertices.iter().enumerate() {
-            min[i % 3] = v.min(min[i % 3]);
-            max[i % 3] = v.max(max[i % 3]);
-
-            if i % 3 == 0 {
-                let vs = &self.vertices[i..i + 3];
-                let d = vec3(vs[0], vs[1], vs[2]).magnitude();
-                r = r.max(d);
+        for (i, vs) in self.vertices.chunks(3).enumerate() {
+            let v = vec3(vs[0], vs[1], vs[2]);
+            let morphed = self.morph_targets
+                .iter()
+                .filter_map(|t| t.get(i * 3..i * 3 + 3))
+                .map(|d| v + vec3(d[0], d[1], d[2]));
+
+            for p in Some(v).into_iter().chain(morphed) {
+                for c in 0..3 {
+                    min[c] = p[c].min(min[c]);
+                    max[c] = p[c].max(max[c]);
+                }
+                r = r.max(p.magnitude());
             }
         }
 
@@ -162,8 +216,11 @@ pub struct MeshBuffer {
     data: Resource<MeshData>,
     gl_state: RefCell<Option<MeshGLState>>,
     bounds: Cell<Option<MeshBound>>,
+    /// Instances drawn by each render, see `set_instances`
+    instances: Cell<usize>,
 
-    bound_prog: RefCell<Weak<ShaderProgram>>,
+    /// Bit mask of the `ShaderAttrib` bound in the vertex array
+    bound_attribs: Cell<u32>,
 }
 
 impl Asset for MeshBuffer {
@@ -174,13 +231,14 @@ impl Asset for MeshBuffer {
             data: r,
             gl_state: Default::default(),
             bounds: Default::default(),
-            bound_prog: RefCell::new(Weak::new()),
+            instances: Cell::new(1),
+            bound_attribs: Cell::new(0),
         })
     }
 }
 
 impl LoadableAsset for MeshBuffer {
-    fn load<T>(asys: &T, mut files: Vec<FileFuture>) -> Self::Resource
+    fn load<T>(asys: &T, _name: &str, mut files: Vec<FileFuture>) -> Self::Resource
     where
         T: AssetSystem + Clone + 'static,
     {
@@ -190,6 +248,15 @@ impl LoadableAsset for MeshBuffer {
     fn gather<T: AssetSystem>(asys: &T, fname: &str) -> Vec<FileFuture> {
         vec![asys.new_file(fname)]
     }
+
+    fn reload(&self, r: Self::Resource) {
+        self.data.reload(r);
+
+        // Buffers are created again in next prepare
+        self.gl_state.replace(None);
+        self.bounds.set(None);
+        self.bound_attribs.set(0);
+    }
 }
 
 pub fn bind_buffer(
@@ -205,6 +272,23 @@ pub fn bind_buffer(
 }
 
 impl MeshBuffer {
+    /// Instanced draws need `gl_InstanceID`, from GLSL 1.50 or GLSL ES 3.00
+    pub fn supports_instancing(gl: &WebGLRenderingContext) -> bool {
+        !IS_GL_ES || gl.is_webgl2
+    }
+
+    /// Draw the mesh `n` times with a single draw call, each with its own `gl_InstanceID`
+    ///
+    /// Only for shaders on GLSL ES 3.00, see `supports_instancing`.
+    /// Culling uses the bounds of the mesh, which should enclose all instances.
+    pub fn set_instances(&self, n: usize) {
+        self.instances.set(n.max(1));
+    }
+
+    pub fn instances(&self) -> usize {
+        self.instances.get()
+    }
+
     pub fn update_mesh_data(&self, mesh_data: MeshData) {
         let mut actions = Vec::new();
 
@@ -229,18 +313,56 @@ impl MeshBuffer {
                     actions.push(RebindAction::Bitangent);
                 });
 
+                mesh_data.colors.as_ref().map(|_| {
+                    actions.push(RebindAction::Color);
+                });
+
+                mesh_data.joints.as_ref().map(|_| {
+                    actions.push(RebindAction::Joints);
+                });
+
+                mesh_data.weights.as_ref().map(|_| {
+                    actions.push(RebindAction::Weights);
+                });
+
+                for i in 0..mesh_data.morph_targets.len().min(MAX_MORPH_TARGETS) {
+                    actions.push(RebindAction::MorphTarget(i));
+                }
+
                 actions.push(RebindAction::Indices);
             }
         };
 
+        // Attributes missing in the old data have no buffer yet, create them all again
+        let new_attributes = match self.data.try_borrow() {
+            Ok(old) => {
+                (old.uvs.is_none() && mesh_data.uvs.is_some())
+                    || (old.normals.is_none() && mesh_data.normals.is_some())
+                    || (old.tangents.is_none() && mesh_data.tangents.is_some())
+                    || (old.bitangents.is_none() && mesh_data.bitangents.is_some())
+                    || (old.colors.is_none() && mesh_data.colors.is_some())
+                    || (old.joints.is_none() && mesh_data.joints.is_some())
+                    || (old.weights.is_none() && mesh_data.weights.is_some())
+                    || old.morph_targets.len() != mesh_data.morph_targets.len()
+            }
+            Err(_) => false,
+        };
+
         self.data.replace(mesh_data);
+        self.bounds.set(None);
+
+        if new_attributes {
+            self.gl_state.replace(None);
+            self.bound_attribs.set(0);
+            return;
+        }
 
         // check whether the state is ready
         match *self.gl_state.borrow_mut() {
             None => {}
             Some(ref mut state) => {
                 state.rebind_actions.append(&mut actions);
-                *self.bound_prog.borrow_mut() = Weak::new();
+                self.bound_attribs.set(0);
             }
         }
     }
@@ -262,15 +384,7 @@ impl MeshBuffer {
 
         let data = self.data.try_borrow()?;
 
-        self.gl_state.replace(Some(mesh_bind_buffer(
-            &data.vertices,
-            &data.uvs,
-            &data.normals,
-            &data.tangents,
-            &data.bitangents,
-            &data.indices,
-            gl,
-        )));
+        self.gl_state.replace(Some(mesh_bind_buffer(&data, gl)));
 
         Ok(())
     }
@@ -302,50 +416,51 @@ impl MeshBuffer {
         /*======= Associating shaders to buffer objects =======*/
         gl.bind_vertex_array(&state.vao);
 
-        if gl.is_webgl2 {
-            if let Some(_) = self.bound_prog.borrow().upgrade() {
-                return Ok(());
-            }
-        }
-
-        // Bind vertex buffer object
-        // "aVertexPosition"
-        bind_buffer(
-            gl,
-            &state.vb,
-            ShaderAttrib::Position as u32,
-            AttributeSize::Three,
-        );
-
-        // "aTextureCoord"
-        if let Some(ref uvb) = state.uvb {
-            bind_buffer(gl, uvb, ShaderAttrib::UV0 as u32, AttributeSize::Two);
-        }
-
-        // "aVertexNormal"
-        if let Some(ref nb) = state.nb {
-            bind_buffer(gl, nb, ShaderAttrib::Normal as u32, AttributeSize::Three);
-        }
-
-        // "aVertexTangent"
-        if let Some(ref tb) = state.tb {
-            bind_buffer(gl, tb, ShaderAttrib::Tangent as u32, AttributeSize::Three);
-        }
+        // The vertex array keeps the attributes bound for previous programs,
+        // which all use the same locations, see `ShaderAttrib`
+        let bound = if gl.is_webgl2 {
+            self.bound_attribs.get()
+        } else {
+            0
+        };
 
-        // "aVertexBitangent"
-        if let Some(ref btb) = state.btb {
-            bind_buffer(
-                gl,
-                btb,
-                ShaderAttrib::Bitangent as u32,
-                AttributeSize::Three,
-            );
+        let mut locations = program.attrib_locations(gl);
+        // Attribute 0 should always be enabled, see `ShaderProgramGLState::new`
+        locations[0] = locations[0].or(Some(ShaderAttrib::Position as u32));
+
+        let mut newly_bound = 0;
+        {
+            let mut bind = |attrib: ShaderAttrib, buffer: Option<&WebGLBuffer>, size| {
+                let bit = 1 << attrib as u32;
+                if bound & bit != 0 {
+                    return;
+                }
+
+                // Only the attributes declared by the program
+                if let (Some(buffer), Some(loc)) = (buffer, locations[attrib as usize]) {
+                    bind_buffer(gl, buffer, loc, size);
+                    newly_bound |= bit;
+                }
+            };
+
+            bind(ShaderAttrib::Position, Some(&state.vb), AttributeSize::Three);
+            bind(ShaderAttrib::UV0, state.uvb.as_ref(), AttributeSize::Two);
+            bind(ShaderAttrib::Normal, state.nb.as_ref(), AttributeSize::Three);
+            bind(ShaderAttrib::Tangent, state.tb.as_ref(), AttributeSize::Three);
+            bind(ShaderAttrib::Bitangent, state.btb.as_ref(), AttributeSize::Three);
+            bind(ShaderAttrib::Color, state.cb.as_ref(), AttributeSize::Four);
+            bind(ShaderAttrib::Joints, state.jb.as_ref(), AttributeSize::Four);
+            bind(ShaderAttrib::Weights, state.wb.as_ref(), AttributeSize::Four);
+
+            for (&attrib, buffer) in ShaderAttrib::MORPH_TARGETS.iter().zip(state.mtb.iter()) {
+                bind(attrib, Some(buffer), AttributeSize::Three);
+            }
         }
 
         // Bind index buffer object
         gl.bind_buffer(BufferKind::ElementArray, &state.ib);
 
-        *self.bound_prog.borrow_mut() = Rc::downgrade(program);
+        self.bound_attribs.set(bound | newly_bound);
 
         Ok(())
     }
@@ -354,7 +469,16 @@ impl MeshBuffer {
     pub fn render(&self, gl: &WebGLRenderingContext) {
         let data = self.data.try_borrow().unwrap();
 
-        gl.draw_elements(Primitives::Triangles, data.indices.len(), DataType::U16, 0);
+        match self.instances.get() {
+            1 => gl.draw_elements(Primitives::Triangles, data.indices.len(), DataType::U16, 0),
+            n => gl.draw_elements_instanced(
+                Primitives::Triangles,
+                data.indices.len(),
+                DataType::U16,
+                0,
+                n,
+            ),
+        }
     }
 
     pub fn unbind(&self, _gl: &WebGLRenderingContext) {
@@ -384,24 +508,24 @@ fn bind_f32_array(gl: &WebGLRenderingContext, data: &Vec<f32>) -> WebGLBuffer {
     vb
 }
 
-fn mesh_bind_buffer(
-    vertices: &Vec<f32>,
-    uvs: &Option<Vec<f32>>,
-    normals: &Option<Vec<f32>>,
-    tangents: &Option<Vec<f32>>,
-    bitangents: &Option<Vec<f32>>,
-    indices: &Vec<u16>,
-    gl: &WebGLRenderingContext,
-) -> MeshGLState {
+fn mesh_bind_buffer(data: &MeshData, gl: &WebGLRenderingContext) -> MeshGLState {
     // some opengl 3.x core profile require a VAO. See issue #11
     let vao = gl.create_vertex_array();
     gl.bind_vertex_array(&vao);
 
-    let vertex_buffer = bind_f32_array(&gl, vertices);
-    let uv_buffer = uvs.as_ref().map(|data| bind_f32_array(gl, data));
-    let normal_buffer = normals.as_ref().map(|data| bind_f32_array(gl, data));
-    let tangent_buffer = tangents.as_ref().map(|data| bind_f32_array(gl, data));
-    let bitangent_buffer = bitangents.as_ref().map(|data| bind_f32_array(gl, data));
+    let vertex_buffer = bind_f32_array(&gl, &data.vertices);
+    let uv_buffer = data.uvs.as_ref().map(|d| bind_f32_array(gl, d));
+    let normal_buffer = data.normals.as_ref().map(|d| bind_f32_array(gl, d));
+    let tangent_buffer = data.tangents.as_ref().map(|d| bind_f32_array(gl, d));
+    let bitangent_buffer = data.bitangents.as_ref().map(|d| bind_f32_array(gl, d));
+    let color_buffer = data.colors.as_ref().map(|d| bind_f32_array(gl, d));
+    let joint_buffer = data.joints.as_ref().map(|d| bind_f32_array(gl, d));
+    let weight_buffer = data.weights.as_ref().map(|d| bind_f32_array(gl, d));
+    let morph_buffers = data.morph_targets
+        .iter()
+        .take(MAX_MORPH_TARGETS)
+        .map(|d| bind_f32_array(gl, d))
+        .collect();
 
     // Create an empty buffer object to store Index buffer
     let index_buffer = gl.create_buffer();
@@ -410,7 +534,7 @@ fn mesh_bind_buffer(
         gl.bind_buffer(BufferKind::ElementArray, &index_buffer);
 
         // Pass the vertex data to the buffer
-        let ci = indices.clone();
+        let ci = data.indices.clone();
         gl.buffer_data(BufferKind::ElementArray, &ci.into_bytes(), DrawMode::Static);
 
         // Unbind the buffer
@@ -425,6 +549,10 @@ fn mesh_bind_buffer(
         nb: normal_buffer,
         tb: tangent_buffer,
         btb: bitangent_buffer,
+        cb: color_buffer,
+        jb: joint_buffer,
+        wb: weight_buffer,
+        mtb: morph_buffers,
 
         ib: index_buffer,
         gl: gl.clone(),
@@ -2,33 +2,43 @@ mod camera;
 mod mesh;
 mod shader_program;
 mod texture;
+mod texture_compression;
 mod material;
 mod light;
+mod fog;
+mod skeleton;
 mod shader;
 mod uniforms;
 mod frame_buffer;
 mod render_texture;
 mod mesh_buffer;
-
-#[derive(Hash, Eq, Ord, PartialOrd, PartialEq, Copy, Clone, Debug)]
-pub enum RenderQueue {
-    Opaque = 1000,
-    Skybox = 2000,
-    Transparent = 3000,
-    UI = 5000,
-}
+mod render_queue;
+mod uniform_buffer;
+mod shader_reflection;
 
 pub mod mesh_util;
 
-pub use self::camera::{Camera, Frustum};
-pub use self::shader::{PreprocessedShaderCode, Shader, ShaderFs, ShaderKind, ShaderKindFs,
-                       ShaderKindProvider, ShaderKindVs, ShaderVs};
+pub use self::camera::{AmbientOcclusion, Camera, Frustum, Intersection, Projection, Ray};
+pub use self::shader::{PreprocessedShaderCode, Shader, ShaderFs, ShaderKeywords, ShaderKind,
+                       ShaderKindFs, ShaderKindProvider, ShaderKindVs, ShaderVs};
 pub use self::shader_program::ShaderProgram;
+pub use self::shader_reflection::{ShaderReflection, ShaderVar, ShaderVarType};
+pub use self::texture_compression::{CompressedFormat, CompressedImage};
 pub use self::texture::{Texture, TextureAsset, TextureAttachment, TextureFiltering, TextureImage,
-                        TextureWrap};
+                        TextureVolume, TextureWrap};
 pub use self::mesh::{Mesh, MeshSurface};
-pub use self::mesh_buffer::{MeshBuffer, MeshData};
-pub use self::material::{CullMode, DepthTest, Material, MaterialParam, MaterialParamMap,
-                         MaterialState};
-pub use self::light::{DirectionalLight, Light, PointLight};
+pub use self::mesh_buffer::{MeshBuffer, MeshData, MAX_MORPH_TARGETS};
+pub use self::material::{BlendEquation, BlendFactor, BlendState, CullMode, DepthTest, Material,
+                         MaterialParam, MaterialParamMap, MaterialState, PolygonOffset, Scissor,
+                         StencilOp, StencilState};
+pub use self::light::{DirectionalLight, Light, PointLight, ShadowSettings, SpotLight};
+pub use self::fog::{Fog, FogMode};
+pub use self::skeleton::{Skeleton, SkinnedMesh, MAX_JOINTS};
+pub(crate) use self::skeleton::JOINT_MATRIX_UNIFORMS;
 pub use self::render_texture::RenderTexture;
+pub use self::render_queue::{RenderQueue, RenderQueueDesc, RenderQueueSort};
+pub use self::uniform_buffer::{Std140Writer, UniformBuffer, FRAME_BLOCK_BINDING,
+                               FRAME_BLOCK_NAME, MATERIAL_BLOCK_BINDING, MATERIAL_BLOCK_NAME};
+
+pub(crate) use self::render_queue::{default_render_queues, find_render_queue_desc};
+pub(crate) use self::shader::preprocess_error;
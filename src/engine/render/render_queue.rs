@@ -0,0 +1,129 @@
+use engine::render::{DepthTest, MaterialState};
+use std::collections::BTreeMap;
+use std::ops::{Add, Sub};
+
+/// Render queues are rendered in ascending order of their priority
+///
+/// Custom queues can be made by offseting a predefined one, e.g. `RenderQueue::Opaque + 10`,
+/// which use the settings of the closest registered queue below them.
+#[derive(Hash, Eq, Ord, PartialOrd, PartialEq, Copy, Clone, Debug)]
+pub struct RenderQueue(pub i32);
+
+#[allow(non_upper_case_globals)]
+impl RenderQueue {
+    pub const Opaque: RenderQueue = RenderQueue(1000);
+    pub const AlphaTest: RenderQueue = RenderQueue(1500);
+    pub const Skybox: RenderQueue = RenderQueue(2000);
+    pub const Transparent: RenderQueue = RenderQueue(3000);
+    pub const Overlay: RenderQueue = RenderQueue(4000);
+    pub const UI: RenderQueue = RenderQueue(5000);
+
+    pub fn priority(&self) -> i32 {
+        self.0
+    }
+}
+
+impl Add<i32> for RenderQueue {
+    type Output = RenderQueue;
+
+    fn add(self, offset: i32) -> RenderQueue {
+        RenderQueue(self.0 + offset)
+    }
+}
+
+impl Sub<i32> for RenderQueue {
+    type Output = RenderQueue;
+
+    fn sub(self, offset: i32) -> RenderQueue {
+        RenderQueue(self.0 - offset)
+    }
+}
+
+/// How commands in a render queue are ordered before rendering
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum RenderQueueSort {
+    /// Keep the gathering order
+    None,
+    /// Nearest first, grouped by material to reduce state changes
+    FrontToBack,
+    /// Farthest first, for blending
+    BackToFront,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct RenderQueueDesc {
+    /// Default states of materials rendered in this queue
+    pub states: MaterialState,
+    pub sort: RenderQueueSort,
+    pub frustum_culling: bool,
+}
+
+impl Default for RenderQueueDesc {
+    fn default() -> RenderQueueDesc {
+        RenderQueueDesc {
+            states: MaterialState::default(),
+            sort: RenderQueueSort::None,
+            frustum_culling: true,
+        }
+    }
+}
+
+/// Descriptions of all predefined render queues
+pub fn default_render_queues() -> BTreeMap<RenderQueue, RenderQueueDesc> {
+    let mut queues = BTreeMap::new();
+
+    // Opaque Queue
+    let mut desc = RenderQueueDesc::default();
+    desc.states.alpha_blending = Some(false);
+    desc.sort = RenderQueueSort::FrontToBack;
+    queues.insert(RenderQueue::Opaque, desc);
+
+    // AlphaTest Queue, the shader discards fragments instead of blending
+    let mut desc = RenderQueueDesc::default();
+    desc.states.alpha_blending = Some(false);
+    desc.sort = RenderQueueSort::FrontToBack;
+    queues.insert(RenderQueue::AlphaTest, desc);
+
+    // Skybox Queue
+    let mut desc = RenderQueueDesc::default();
+    desc.states.depth_write = Some(false);
+    desc.states.alpha_blending = Some(false);
+    desc.states.depth_test = Some(DepthTest::LessEqual);
+    desc.frustum_culling = false;
+    queues.insert(RenderQueue::Skybox, desc);
+
+    // Transparent Queue
+    let mut desc = RenderQueueDesc::default();
+    desc.states.alpha_blending = Some(true);
+    desc.states.depth_write = Some(false);
+    desc.sort = RenderQueueSort::BackToFront;
+    queues.insert(RenderQueue::Transparent, desc);
+
+    // Overlay Queue, drawn on top of the scene
+    let mut desc = RenderQueueDesc::default();
+    desc.states.alpha_blending = Some(true);
+    desc.states.depth_test = Some(DepthTest::Always);
+    desc.states.depth_write = Some(false);
+    queues.insert(RenderQueue::Overlay, desc);
+
+    // UI Queue
+    let mut desc = RenderQueueDesc::default();
+    desc.states.alpha_blending = Some(true);
+    desc.frustum_culling = false;
+    queues.insert(RenderQueue::UI, desc);
+
+    queues
+}
+
+/// Find the description of a queue, falling back to the closest registered queue below it
+pub fn find_render_queue_desc(
+    queues: &BTreeMap<RenderQueue, RenderQueueDesc>,
+    queue: RenderQueue,
+) -> RenderQueueDesc {
+    queues
+        .range(..=queue)
+        .next_back()
+        .or_else(|| queues.iter().next())
+        .map(|(_, desc)| *desc)
+        .unwrap_or_default()
+}
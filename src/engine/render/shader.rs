@@ -1,12 +1,9 @@
 use uni_glsl::preprocessor;
 use uni_glsl::preprocessor::PreprocessError;
 
-//use uni_glsl::parser;
-// use uni_glsl::TypeQualifier;
-// use uni_glsl::query::*;
-
+use engine::asset::{AssetError, AssetResult};
 use uni_gl;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::marker::PhantomData;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -35,18 +32,79 @@ impl ShaderKindProvider for ShaderKindFs {
     }
 }
 
+/// Sorted set of keywords, each one is `#define`d when compiling a shader variant
+pub type ShaderKeywords = BTreeSet<String>;
+
 #[derive(Debug)]
-pub struct PreprocessedShaderCode(String);
+pub struct PreprocessedShaderCode {
+    code: String,
+
+    // Keep the source such that variants can be preprocessed again with keywords
+    kind: ShaderKind,
+    source: String,
+    external_files: HashMap<String, String>,
+}
 
 impl PreprocessedShaderCode {
     pub fn as_string(&self) -> &String {
-        &self.0
+        &self.code
     }
 
     pub fn new(
         kind: ShaderKind,
         s: &str,
         external_files: &HashMap<String, String>,
+    ) -> Result<PreprocessedShaderCode, PreprocessError> {
+        Self::new_with_keywords(kind, s, external_files, &ShaderKeywords::new())
+    }
+
+    /// Preprocess the source of this code again with `keywords` defined
+    pub fn with_keywords(
+        &self,
+        keywords: &ShaderKeywords,
+    ) -> Result<PreprocessedShaderCode, PreprocessError> {
+        Self::new_with_keywords(self.kind, &self.source, &self.external_files, keywords)
+    }
+
+    /// Paths of the files included by the source
+    pub fn includes(&self) -> Vec<&String> {
+        self.external_files.keys().collect()
+    }
+
+    /// Rewrite the line numbers in a GL info log to the file and line of the source
+    pub fn remap_log(&self, filename: &str, log: &str) -> String {
+        log.lines()
+            .map(|line| match find_line_number(line) {
+                Some((start, end, source, n)) => format!(
+                    "{}{}{}",
+                    &line[..start],
+                    self.source_location(filename, source, n),
+                    &line[end..]
+                ),
+                None => line.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// `source` is the source string number set by the `#line` directives of `line_markers`,
+    /// 0 for the shader file and `i + 1` for the i-th include file sorted by name
+    fn source_location(&self, filename: &str, source: usize, n: usize) -> String {
+        if source == 0 {
+            return format!("{}:{}", filename, n);
+        }
+
+        match sorted_names(&self.external_files).get(source - 1) {
+            Some(name) => format!("{}:{}", name, n),
+            None => format!("{}:{} (preprocessed)", filename, n),
+        }
+    }
+
+    fn new_with_keywords(
+        kind: ShaderKind,
+        s: &str,
+        external_files: &HashMap<String, String>,
+        keywords: &ShaderKeywords,
     ) -> Result<PreprocessedShaderCode, PreprocessError> {
         let prefix = match kind {
             ShaderKind::Vertex => if !uni_gl::IS_GL_ES {
@@ -75,9 +133,109 @@ impl PreprocessedShaderCode {
             predefs.insert("GL_ES".to_string(), "".to_string());
         }
 
-        let processed = preprocessor::preprocess(&s, &predefs, external_files);
+        // GLSL 1.50 or GLSL ES 3.00, e.g. with shadow samplers
+        if !uni_gl::IS_GL_ES || s.starts_with("#define USE_GLSL_300ES") {
+            predefs.insert("UNI_GLSL3".to_string(), "".to_string());
+        }
+
+        for keyword in keywords.iter() {
+            predefs.insert(keyword.clone(), "".to_string());
+        }
+
+        // Files are numbered such that the info log lines can be mapped back to them
+        let marked_files: HashMap<String, String> = sorted_names(external_files)
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), line_markers(&external_files[name], i + 1)))
+            .collect();
+
+        let processed = preprocessor::preprocess(&line_markers(s, 0), &predefs, &marked_files);
+
+        processed.map(|code| PreprocessedShaderCode {
+            code: prefix + &code,
+            kind,
+            source: s.to_owned(),
+            external_files: external_files.clone(),
+        })
+    }
+}
+
+fn sorted_names(files: &HashMap<String, String>) -> Vec<&String> {
+    let mut names: Vec<_> = files.keys().collect();
+    names.sort();
+    names
+}
+
+/// Add a `#line` directive after each directive of `src`, such that GL reports the lines
+/// of the file numbered `source` whatever the preprocessor removed or included before them
+fn line_markers(src: &str, source: usize) -> String {
+    let mut out = format!("#line 1 {}\n", source);
+    let mut in_directive = false;
+
+    for (i, line) in src.lines().enumerate() {
+        out.push_str(line);
+        out.push('\n');
+
+        let trimmed = line.trim();
+        in_directive = in_directive || trimmed.starts_with('#');
+
+        // Directives continued on the next line are marked after their last line
+        if in_directive && !trimmed.ends_with('\\') {
+            out.push_str(&format!("#line {} {}\n", i + 2, source));
+            in_directive = false;
+        }
+    }
+
+    out
+}
+
+/// Find the source string and line numbers in a info log line,
+/// "ERROR: 1:12: ..." (ANGLE, Mesa) or "1(12) : error ..." (NVIDIA)
+fn find_line_number(line: &str) -> Option<(usize, usize, usize, usize)> {
+    let bytes = line.as_bytes();
+    let digits = |i: usize| bytes[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let n = digits(i);
+        if n == 0 {
+            i += 1;
+            continue;
+        }
+
+        let sep = i + n;
+        let word_start = i == 0 || !bytes[i - 1].is_ascii_alphanumeric();
+
+        if word_start && sep < bytes.len() && (bytes[sep] == b':' || bytes[sep] == b'(') {
+            let m = digits(sep + 1);
+
+            if m > 0 {
+                let mut end = sep + 1 + m;
+                if bytes[sep] == b'(' && bytes.get(end) == Some(&b')') {
+                    end += 1;
+                }
+
+                let source = line[i..sep].parse().ok()?;
+                let n = line[sep + 1..sep + 1 + m].parse().ok()?;
+                return Some((i, end, source, n));
+            }
+        }
+
+        i = sep;
+    }
+
+    None
+}
 
-        processed.map(|s| PreprocessedShaderCode(prefix + &s))
+pub(crate) fn preprocess_error(
+    kind: ShaderKind,
+    filename: &str,
+    e: PreprocessError,
+) -> AssetError {
+    AssetError::ShaderCompile {
+        program: filename.to_string(),
+        stage: Some(kind),
+        log: format!("Fail to preprocess: {:?}", e),
     }
 }
 
@@ -85,7 +243,6 @@ impl PreprocessedShaderCode {
 pub struct Shader<T: ShaderKindProvider> {
     pub code: PreprocessedShaderCode,
     pub filename: String,
-    //unit: parser::TranslationUnit,
     phantom: PhantomData<*const T>,
 }
 
@@ -96,32 +253,46 @@ impl<T> Shader<T>
 where
     T: ShaderKindProvider,
 {
-    pub fn new(filename: &str, s: &str) -> Shader<T> {
-        let code = PreprocessedShaderCode::new(T::kind(), s, &HashMap::new()).unwrap();
+    pub fn new(filename: &str, s: &str) -> AssetResult<Shader<T>> {
+        Self::new_with_includes(filename, s, &HashMap::new())
+    }
 
-        Shader {
-            //unit: unit,
+    /// Shader whose `#include`s are resolved from `includes`, keyed by their path
+    pub fn new_with_includes(
+        filename: &str,
+        s: &str,
+        includes: &HashMap<String, String>,
+    ) -> AssetResult<Shader<T>> {
+        let code = PreprocessedShaderCode::new(T::kind(), s, includes)
+            .map_err(|e| preprocess_error(T::kind(), filename, e))?;
+
+        Ok(Shader {
             filename: filename.to_string(),
             code,
             phantom: PhantomData,
-        }
+        })
+    }
+
+    /// The variant of this shader compiled with `keywords`
+    pub fn with_keywords(&self, keywords: &ShaderKeywords) -> AssetResult<Shader<T>> {
+        let code = self.code
+            .with_keywords(keywords)
+            .map_err(|e| preprocess_error(T::kind(), &self.filename, e))?;
+
+        Ok(Shader {
+            filename: self.filename.clone(),
+            code,
+            phantom: PhantomData,
+        })
     }
 
     pub fn from_preprocessed(filename: &str, code: PreprocessedShaderCode) -> Shader<T> {
         uni_gl::print(&format!("preprocessing {}...\n", filename));
 
         Shader {
-            //unit: unit,
             filename: filename.to_string(),
             code,
             phantom: PhantomData,
         }
     }
-
-    // pub fn has_attr(&self, s: &str) -> bool {
-    //     self.unit
-    //         .query_decl(s)
-    //         .is(TypeQualifier::Attribute)
-    //         .is_some()
-    // }
 }
@@ -1,42 +1,96 @@
-use engine::asset::{Asset, AssetResult, AssetSystem, FileFuture, LoadableAsset, Resource};
-use engine::render::shader::{ShaderFs, ShaderVs};
+use engine::asset::{Asset, AssetError, AssetResult, AssetSystem, FileFuture, LoadableAsset,
+                    Resource};
+use engine::render::shader::{Shader, ShaderFs, ShaderKeywords, ShaderKind, ShaderKindProvider,
+                             ShaderVs};
+use engine::render::mesh_buffer::MAX_MORPH_TARGETS;
+use engine::render::shader_reflection::{ShaderReflection, ShaderVarType};
+use engine::render::uniform_buffer::{FRAME_BLOCK_BINDING, FRAME_BLOCK_NAME};
 use engine::render::uniforms::*;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
-use uni_gl::{ShaderKind as WebGLShaderKind, WebGLProgram, WebGLRenderingContext};
+use uni_gl::{ShaderKind as WebGLShaderKind, ShaderParameter, WebGLProgram,
+             WebGLRenderingContext};
 
 use std::borrow::Cow;
+use std::fmt;
 
 use uni_app;
 
+/// GL_INVALID_INDEX
+const INVALID_BLOCK_INDEX: u32 = 0xFFFF_FFFF;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ShaderAttrib {
     Position = 0,
     UV0 = 1,
     Normal = 2,
     Tangent = 3,
     Bitangent = 4,
+    Color = 5,
+    Joints = 6,
+    Weights = 7,
+    MorphTarget0 = 8,
+    MorphTarget1 = 9,
+    MorphTarget2 = 10,
+    MorphTarget3 = 11,
+}
+
+impl ShaderAttrib {
+    pub const ALL: [ShaderAttrib; 12] = [
+        ShaderAttrib::Position,
+        ShaderAttrib::UV0,
+        ShaderAttrib::Normal,
+        ShaderAttrib::Tangent,
+        ShaderAttrib::Bitangent,
+        ShaderAttrib::Color,
+        ShaderAttrib::Joints,
+        ShaderAttrib::Weights,
+        ShaderAttrib::MorphTarget0,
+        ShaderAttrib::MorphTarget1,
+        ShaderAttrib::MorphTarget2,
+        ShaderAttrib::MorphTarget3,
+    ];
+
+    /// Position offsets of the morph targets, see "unrust/morph.glsl"
+    pub const MORPH_TARGETS: [ShaderAttrib; MAX_MORPH_TARGETS] = [
+        ShaderAttrib::MorphTarget0,
+        ShaderAttrib::MorphTarget1,
+        ShaderAttrib::MorphTarget2,
+        ShaderAttrib::MorphTarget3,
+    ];
+
+    /// Name of the attribute in shaders
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ShaderAttrib::Position => "aVertexPosition",
+            ShaderAttrib::UV0 => "aTextureCoord",
+            ShaderAttrib::Normal => "aVertexNormal",
+            ShaderAttrib::Tangent => "aVertexTangent",
+            ShaderAttrib::Bitangent => "aVertexBitangent",
+            ShaderAttrib::Color => "aVertexColor",
+            ShaderAttrib::Joints => "aVertexJoints",
+            ShaderAttrib::Weights => "aVertexWeights",
+            ShaderAttrib::MorphTarget0 => "aMorphTarget0",
+            ShaderAttrib::MorphTarget1 => "aMorphTarget1",
+            ShaderAttrib::MorphTarget2 => "aMorphTarget2",
+            ShaderAttrib::MorphTarget3 => "aMorphTarget3",
+        }
+    }
 }
 
 impl Asset for ShaderProgram {
     type Resource = (Resource<ShaderVs>, Resource<ShaderFs>);
 
     fn new_from_resource((vs, fs): Self::Resource) -> Rc<ShaderProgram> {
-        Rc::new(ShaderProgram {
-            gl_state: RefCell::new(None),
-
-            coord_map: Default::default(),
-            uniform_cache: Default::default(),
-
-            vs_shader: vs,
-            fs_shader: fs,
-        })
+        Rc::new(ShaderProgram::from_resources(vs, fs))
     }
 }
 
 impl LoadableAsset for ShaderProgram {
     fn load<T: AssetSystem + Clone + 'static>(
         asys: &T,
+        _name: &str,
         mut files: Vec<FileFuture>,
     ) -> Self::Resource {
         (
@@ -51,11 +105,44 @@ impl LoadableAsset for ShaderProgram {
             asys.new_file(&format!("{}_fs.glsl", fname)),
         ]
     }
+
+    fn reload(&self, (vs, fs): Self::Resource) {
+        self.vs_shader.reload(vs);
+        self.fs_shader.reload(fs);
+
+        // Compile again in next bind, materials pick their variants again
+        // as the generation changed
+        self.gl_state.replace(None);
+        self.generation.set(self.generation.get() + 1);
+        self.coord_map.borrow_mut().clear();
+        self.attrib_locations.set(None);
+        self.uniform_types.borrow_mut().clear();
+        self.block_map.borrow_mut().clear();
+        self.frame_block.set(None);
+        self.uniform_cache.clear();
+        self.variants.borrow_mut().clear();
+        self.compile_error.replace(None);
+        self.compile_error_reported.set(false);
+        self.reflection.replace(None);
+        self.reported.borrow_mut().clear();
+    }
 }
 
-#[derive(Debug)]
 pub struct ShaderProgramGLState {
     prog: WebGLProgram,
+    gl: WebGLRenderingContext,
+}
+
+impl fmt::Debug for ShaderProgramGLState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ShaderProgramGLState({:?})", self.prog)
+    }
+}
+
+impl Drop for ShaderProgramGLState {
+    fn drop(&mut self) {
+        self.gl.delete_program(&self.prog);
+    }
 }
 
 #[derive(Debug)]
@@ -63,14 +150,111 @@ pub struct ShaderProgram {
     gl_state: RefCell<Option<ShaderProgramGLState>>,
 
     coord_map: RefCell<HashMap<String, Option<u32>>>,
+    /// Locations of the declared attributes, in the order of `ShaderAttrib`
+    attrib_locations: Cell<Option<[Option<u32>; 12]>>,
+    block_map: RefCell<HashMap<&'static str, bool>>,
+    /// Whether the per-frame uniform block is declared, checked on first draw
+    frame_block: Cell<Option<bool>>,
 
     vs_shader: Resource<ShaderVs>,
     fs_shader: Resource<ShaderFs>,
 
     uniform_cache: UniformCache,
+
+    /// Kept to report `AssetError::ShaderCompile` without compiling again
+    compile_error: RefCell<Option<AssetError>>,
+    /// Whether `compile_error` was logged by `report_compile_error`
+    compile_error_reported: Cell<bool>,
+
+    /// Declarations parsed from the sources, available once the program is prepared
+    reflection: RefCell<Option<ShaderReflection>>,
+    /// Declared type of the uniforms checked by `check_uniform`, `None` if not declared
+    uniform_types: RefCell<HashMap<String, Option<ShaderVarType>>>,
+    /// Uniforms already reported by `check_uniform`
+    reported: RefCell<HashSet<String>>,
+
+    /// Programs compiled from the same sources with keywords defined
+    variants: RefCell<HashMap<ShaderKeywords, Rc<ShaderProgram>>>,
+    /// Incremented each time the program is reloaded
+    generation: Cell<u32>,
 }
 
 impl ShaderProgram {
+    fn from_resources(vs: Resource<ShaderVs>, fs: Resource<ShaderFs>) -> ShaderProgram {
+        ShaderProgram {
+            gl_state: RefCell::new(None),
+
+            coord_map: Default::default(),
+            attrib_locations: Cell::new(None),
+            block_map: Default::default(),
+            frame_block: Cell::new(None),
+            uniform_cache: Default::default(),
+            variants: Default::default(),
+            compile_error: RefCell::new(None),
+            compile_error_reported: Cell::new(false),
+            reflection: Default::default(),
+            uniform_types: Default::default(),
+            reported: Default::default(),
+            generation: Cell::new(0),
+
+            vs_shader: vs,
+            fs_shader: fs,
+        }
+    }
+
+    /// The program compiled with `keywords` defined, which is created on first use
+    ///
+    /// Return `AssetError::NotReady` until the shader sources of `prog` are loaded.
+    /// A variant failing to preprocess is kept, and reports its error when it is bound.
+    pub fn variant(
+        prog: &Rc<ShaderProgram>,
+        keywords: &ShaderKeywords,
+    ) -> AssetResult<Rc<ShaderProgram>> {
+        if keywords.is_empty() {
+            return Ok(prog.clone());
+        }
+
+        if let Some(v) = prog.variants.borrow().get(keywords) {
+            return Ok(v.clone());
+        }
+
+        let vs = prog.vs_shader.try_borrow()?;
+        let fs = prog.fs_shader.try_borrow()?;
+
+        let v = Rc::new(ShaderProgram::from_resources(
+            Resource::from_result(vs.with_keywords(keywords)),
+            Resource::from_result(fs.with_keywords(keywords)),
+        ));
+
+        prog.variants
+            .borrow_mut()
+            .insert(keywords.clone(), v.clone());
+
+        Ok(v)
+    }
+
+    /// Changes when the program is reloaded, such that variants can be picked again
+    pub fn generation(&self) -> u32 {
+        self.generation.get()
+    }
+
+    /// Files included by the shaders, `None` until they are loaded or if they failed to load
+    pub fn includes(&self) -> Option<Vec<String>> {
+        let vs = self.vs_shader.try_borrow().ok()?;
+        let fs = self.fs_shader.try_borrow().ok()?;
+
+        let mut includes: Vec<String> = vs.code
+            .includes()
+            .into_iter()
+            .chain(fs.code.includes())
+            .cloned()
+            .collect();
+        includes.sort();
+        includes.dedup();
+
+        Some(includes)
+    }
+
     pub fn bind(&self, gl: &WebGLRenderingContext) -> AssetResult<()> {
         self.prepare(gl)?;
 
@@ -89,15 +273,121 @@ impl ShaderProgram {
             return Ok(());
         }
 
+        // Failed programs are not compiled again, such that the error is reported once
+        if let Some(ref e) = *self.compile_error.borrow() {
+            return Err(copy_compile_error(e));
+        }
+
+        let state = self.vs_shader.try_borrow().and_then(|vs| {
+            let fs = self.fs_shader.try_borrow()?;
+            ShaderProgramGLState::new(gl, &vs, &fs)
+        });
+
+        let state = match state {
+            Err(e @ AssetError::ShaderCompile { .. }) => {
+                let copy = copy_compile_error(&e);
+                *self.compile_error.borrow_mut() = Some(e);
+                return Err(copy);
+            }
+            r => r?,
+        };
+
+        *self.gl_state.borrow_mut() = Some(state);
+
         let vs = self.vs_shader.try_borrow()?;
         let fs = self.fs_shader.try_borrow()?;
 
-        let state = Some(ShaderProgramGLState::new(gl, &vs, &fs));
-        *self.gl_state.borrow_mut() = state;
+        let mut reflection = ShaderReflection::parse(ShaderKind::Vertex, vs.code.as_string());
+        reflection.merge(ShaderReflection::parse(
+            ShaderKind::Fragment,
+            fs.code.as_string(),
+        ));
+        *self.reflection.borrow_mut() = Some(reflection);
 
         Ok(())
     }
 
+    /// Log the compile error of this program once, with the remapped info log
+    ///
+    /// `keywords` are the ones this variant was compiled with.
+    pub(crate) fn report_compile_error(&self, keywords: &ShaderKeywords) {
+        if self.compile_error_reported.get() {
+            return;
+        }
+
+        if let Some(AssetError::ShaderCompile {
+            ref program,
+            stage,
+            ref log,
+        }) = *self.compile_error.borrow()
+        {
+            uni_app::App::print(format!(
+                "Fail to compile shader program {} ({:?}) with keywords {:?}:\n{}\n",
+                program, stage, keywords, log
+            ));
+            self.compile_error_reported.set(true);
+        }
+    }
+
+    /// Declared attributes and uniforms, `None` until the program is prepared
+    pub fn reflection(&self) -> Option<Ref<ShaderReflection>> {
+        let r = self.reflection.borrow();
+        if r.is_none() {
+            return None;
+        }
+
+        Some(Ref::map(r, |r| r.as_ref().unwrap()))
+    }
+
+    /// Warn once if the uniform `name` is not declared or `is_valid` rejects its type
+    pub fn check_uniform<F>(&self, name: &str, is_valid: F)
+    where
+        F: FnOnce(&ShaderVarType) -> bool,
+    {
+        if self.reflection.borrow().is_none() {
+            return;
+        }
+
+        // Looked up once per name, as params are checked each time they are bound
+        let mut types = self.uniform_types.borrow_mut();
+        if !types.contains_key(name) {
+            let ty = self.reflection().unwrap().uniform(name).map(|v| v.ty.clone());
+            types.insert(name.to_string(), ty);
+        }
+
+        let problem = match types[name] {
+            None => "is not declared".to_string(),
+            Some(ref ty) => if is_valid(ty) {
+                return;
+            } else {
+                format!("is declared as {:?}", ty)
+            },
+        };
+        drop(types);
+
+        self.report_uniform(name, &problem);
+    }
+
+    /// Warn once about the material param `name`
+    pub(crate) fn report_uniform(&self, name: &str, problem: &str) {
+        if self.reported.borrow_mut().insert(name.to_string()) {
+            let vs = self.vs_shader.try_borrow().map(|vs| vs.filename.clone());
+            uni_app::App::print(format!(
+                "Warning: material param {} {} in program {}\n",
+                name,
+                problem,
+                vs.unwrap_or_default()
+            ));
+        }
+    }
+
+    /// Whether the uniform `name` is a member of the uniform block `block`
+    pub fn in_block(&self, block: &str, name: &str) -> bool {
+        self.reflection()
+            .map(|r| r.in_block(block, name))
+            .unwrap_or(false)
+    }
+
     pub fn attrib_loc(&self, gl: &WebGLRenderingContext, s: &str) -> Option<u32> {
         let mut m = self.coord_map.borrow_mut();
 
@@ -114,6 +404,86 @@ impl ShaderProgram {
         }
     }
 
+    /// Locations of the attributes declared by the program, in the order of `ShaderAttrib`
+    ///
+    /// `None` for the attributes which are not declared or not active.
+    pub fn attrib_locations(&self, gl: &WebGLRenderingContext) -> [Option<u32>; 12] {
+        if let Some(locations) = self.attrib_locations.get() {
+            return locations;
+        }
+
+        let reflection = match self.reflection() {
+            Some(r) => r,
+            // Not prepared yet, assume the locations bound before linking
+            None => {
+                let mut locations = [None; 12];
+                for (i, attrib) in ShaderAttrib::ALL.iter().enumerate() {
+                    locations[i] = Some(*attrib as u32);
+                }
+                return locations;
+            }
+        };
+
+        let mut locations = [None; 12];
+        for (i, attrib) in ShaderAttrib::ALL.iter().enumerate() {
+            if reflection.attribute(attrib.name()).is_some() {
+                locations[i] = self.attrib_loc(gl, attrib.name());
+            }
+        }
+
+        self.attrib_locations.set(Some(locations));
+        locations
+    }
+
+    /// Bind the uniform block `name` of this program to `binding`
+    ///
+    /// Return false if the program does not declare the block
+    /// or uniform blocks are not supported (GLES2 / WebGL1).
+    pub fn uniform_block(
+        &self,
+        gl: &WebGLRenderingContext,
+        name: &'static str,
+        binding: u32,
+    ) -> bool {
+        if !gl.is_webgl2 {
+            return false;
+        }
+
+        let mut m = self.block_map.borrow_mut();
+
+        let gl_state_opt = self.gl_state.borrow();
+        let gl_state = match gl_state_opt.as_ref() {
+            Some(gl_state) => gl_state,
+            None => return false,
+        };
+
+        *m.entry(name).or_insert_with(|| {
+            let index = gl.get_uniform_block_index(&gl_state.prog, name);
+            if index == INVALID_BLOCK_INDEX {
+                return false;
+            }
+
+            gl.uniform_block_binding(&gl_state.prog, index, binding);
+            true
+        })
+    }
+
+    /// Whether the program reads camera and lights from the per-frame uniform block
+    pub fn uses_frame_block(&self, gl: &WebGLRenderingContext) -> bool {
+        if let Some(used) = self.frame_block.get() {
+            return used;
+        }
+
+        // Not cached before the program is prepared
+        if self.gl_state.borrow().is_none() {
+            return false;
+        }
+
+        let used = self.uniform_block(gl, FRAME_BLOCK_NAME, FRAME_BLOCK_BINDING);
+        self.frame_block.set(Some(used));
+        used
+    }
+
     pub fn set<T, S>(&self, s: S, data: T)
     where
         T: Into<UniformAdapter>,
@@ -129,12 +499,52 @@ impl ShaderProgram {
     }
 }
 
+/// AssetError is not Clone as it may carry an io error
+fn copy_compile_error(e: &AssetError) -> AssetError {
+    match *e {
+        AssetError::ShaderCompile {
+            ref program,
+            stage,
+            ref log,
+        } => AssetError::ShaderCompile {
+            program: program.clone(),
+            stage,
+            log: log.clone(),
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// Return the remapped info log if the compile or link `status` is false
+fn check_status<T>(
+    status: bool,
+    log: Option<String>,
+    unit: &Shader<T>,
+    program: &str,
+    stage: Option<ShaderKind>,
+) -> AssetResult<()>
+where
+    T: ShaderKindProvider,
+{
+    if status {
+        return Ok(());
+    }
+
+    Err(AssetError::ShaderCompile {
+        program: program.to_string(),
+        stage,
+        log: unit.code.remap_log(&unit.filename, &log.unwrap_or_default()),
+    })
+}
+
 impl ShaderProgramGLState {
     pub fn new(
         gl: &WebGLRenderingContext,
         vs_unit: &ShaderVs,
         fs_unit: &ShaderFs,
-    ) -> ShaderProgramGLState {
+    ) -> AssetResult<ShaderProgramGLState> {
+        let program = vs_unit.filename.trim_right_matches("_vs.glsl");
+
         /*================ Shaders ====================*/
 
         // Create a vertex shader object
@@ -146,6 +556,18 @@ impl ShaderProgramGLState {
         // Compile the vertex shader
         uni_app::App::print(format!("Compiling shader file : {}\n", vs_unit.filename));
         gl.compile_shader(&vert_shader);
+        let compiled = check_status(
+            gl.get_shader_parameter(&vert_shader, ShaderParameter::CompileStatus) != 0,
+            gl.get_shader_info_log(&vert_shader),
+            vs_unit,
+            program,
+            Some(ShaderKind::Vertex),
+        );
+
+        if let Err(e) = compiled {
+            gl.delete_shader(&vert_shader);
+            return Err(e);
+        }
 
         // Create fragment shader object
         let frag_shader = gl.create_shader(WebGLShaderKind::Fragment);
@@ -156,6 +578,19 @@ impl ShaderProgramGLState {
         // Compile the fragmentt shader
         uni_app::App::print(format!("Compiling shader file : {}\n", fs_unit.filename));
         gl.compile_shader(&frag_shader);
+        let compiled = check_status(
+            gl.get_shader_parameter(&frag_shader, ShaderParameter::CompileStatus) != 0,
+            gl.get_shader_info_log(&frag_shader),
+            fs_unit,
+            program,
+            Some(ShaderKind::Fragment),
+        );
+
+        if let Err(e) = compiled {
+            gl.delete_shader(&vert_shader);
+            gl.delete_shader(&frag_shader);
+            return Err(e);
+        }
 
         // Create a shader program object to store
         // the combined shader program
@@ -176,31 +611,35 @@ impl ShaderProgramGLState {
         // This is because in desktop OpenGL, nothing gets drawn if vertex attrib 0 is not array-enabled.
         // You can use bindAttribLocation() to force a vertex attribute to use location 0,
         // and use enableVertexAttribArray() to make it array-enabled.
-        gl.bind_attrib_location(
-            &shader_program,
-            "aVertexPosition",
-            ShaderAttrib::Position as _,
-        );
-        gl.bind_attrib_location(&shader_program, "aTextureCoord", ShaderAttrib::UV0 as _);
-        gl.bind_attrib_location(&shader_program, "aVertexNormal", ShaderAttrib::Normal as _);
-        gl.bind_attrib_location(
-            &shader_program,
-            "aVertexTangent",
-            ShaderAttrib::Tangent as _,
-        );
-        gl.bind_attrib_location(
-            &shader_program,
-            "aVertexBitangent",
-            ShaderAttrib::Bitangent as _,
-        );
+        for attrib in ShaderAttrib::ALL.iter() {
+            gl.bind_attrib_location(&shader_program, attrib.name(), *attrib as _);
+        }
 
         // Link both the programs
         gl.link_program(&shader_program);
+        let linked = check_status(
+            gl.get_program_parameter(&shader_program, ShaderParameter::LinkStatus) != 0,
+            gl.get_program_info_log(&shader_program),
+            vs_unit,
+            program,
+            None,
+        );
+
+        // The shaders are only needed by the program from now on,
+        // they are deleted along with it
+        gl.delete_shader(&vert_shader);
+        gl.delete_shader(&frag_shader);
+
+        if let Err(e) = linked {
+            gl.delete_program(&shader_program);
+            return Err(e);
+        }
 
         let prog = ShaderProgramGLState {
             prog: shader_program,
+            gl: gl.clone(),
         };
 
-        prog
+        Ok(prog)
     }
 }
@@ -0,0 +1,382 @@
+// Declarations are read from the preprocessed source with a small scanner instead of the
+// uni_glsl parser, which was only used for qualifier lookups by name: reflection needs the
+// types, struct members and array sizes to flatten uniforms into their GL names.
+
+use engine::render::ShaderKind;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShaderVarType {
+    Bool,
+    Int,
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+    Mat3,
+    Mat4,
+    Sampler2D,
+    Sampler2DArray,
+    Sampler3D,
+    SamplerCube,
+    Struct(String),
+    Other(String),
+}
+
+impl ShaderVarType {
+    fn from_name(s: &str) -> ShaderVarType {
+        match s {
+            "bool" => ShaderVarType::Bool,
+            "int" => ShaderVarType::Int,
+            "float" => ShaderVarType::Float,
+            "vec2" => ShaderVarType::Vec2,
+            "vec3" => ShaderVarType::Vec3,
+            "vec4" => ShaderVarType::Vec4,
+            "mat3" => ShaderVarType::Mat3,
+            "mat4" => ShaderVarType::Mat4,
+            "sampler2D" | "sampler2DShadow" => ShaderVarType::Sampler2D,
+            "sampler2DArray" | "sampler2DArrayShadow" => ShaderVarType::Sampler2DArray,
+            "sampler3D" => ShaderVarType::Sampler3D,
+            "samplerCube" | "samplerCubeShadow" => ShaderVarType::SamplerCube,
+            _ => ShaderVarType::Other(s.to_string()),
+        }
+    }
+
+    pub fn is_sampler(&self) -> bool {
+        match *self {
+            ShaderVarType::Sampler2D
+            | ShaderVarType::Sampler2DArray
+            | ShaderVarType::Sampler3D
+            | ShaderVarType::SamplerCube => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderVar {
+    pub name: String,
+    pub ty: ShaderVarType,
+    /// Length of the array, `Some(0)` if it could not be evaluated
+    pub array_len: Option<usize>,
+}
+
+/// Attributes, uniforms and structs declared in the preprocessed source of a program
+#[derive(Debug, Default, Clone)]
+pub struct ShaderReflection {
+    pub attributes: Vec<ShaderVar>,
+    pub uniforms: Vec<ShaderVar>,
+    pub structs: HashMap<String, Vec<ShaderVar>>,
+    /// Members of the uniform blocks in declaration order, they are also in `uniforms`
+    pub blocks: HashMap<String, Vec<ShaderVar>>,
+}
+
+const QUALIFIERS: &[&str] = &[
+    "const", "highp", "mediump", "lowp", "flat", "smooth", "centroid", "invariant"
+];
+
+fn tokenize(code: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = code.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().map(|&c| c != '\n').unwrap_or(false) {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                while let Some(c) = chars.next() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '#' => {
+                // Directives left by the preprocessor, e.g. #version and #extension
+                while chars.peek().map(|&c| c != '\n').unwrap_or(false) {
+                    chars.next();
+                }
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(word);
+            }
+            c if c.is_whitespace() => (),
+            c => tokens.push(c.to_string()),
+        }
+    }
+
+    tokens
+}
+
+/// Parse `type name[N], name2;` into variables
+fn parse_decl(tokens: &[String]) -> Vec<ShaderVar> {
+    let tokens: Vec<&String> = tokens
+        .iter()
+        .filter(|t| !QUALIFIERS.contains(&t.as_str()))
+        .collect();
+
+    let mut vars = Vec::new();
+    let ty = match tokens.first() {
+        Some(ty) => ShaderVarType::from_name(ty),
+        None => return vars,
+    };
+
+    let mut i = 1;
+    while i < tokens.len() {
+        let name = tokens[i].clone();
+        let mut array_len = None;
+        i += 1;
+
+        if i < tokens.len() && tokens[i] == "[" {
+            let len = tokens.get(i + 1).and_then(|t| t.parse().ok());
+            array_len = Some(len.unwrap_or(0));
+
+            while i < tokens.len() && tokens[i] != "]" {
+                i += 1;
+            }
+            i += 1;
+        }
+
+        vars.push(ShaderVar {
+            name,
+            ty: ty.clone(),
+            array_len,
+        });
+
+        // skip to next declarator
+        while i < tokens.len() && tokens[i] != "," {
+            i += 1;
+        }
+        i += 1;
+    }
+
+    vars
+}
+
+/// Parse the members between braces, `start` is the position of `{`
+/// Return the members and the position after `}`
+fn parse_members(tokens: &[String], start: usize) -> (Vec<ShaderVar>, usize) {
+    let mut members = Vec::new();
+    let mut i = start + 1;
+    let mut begin = i;
+
+    while i < tokens.len() && tokens[i] != "}" {
+        if tokens[i] == ";" {
+            members.extend(parse_decl(&tokens[begin..i]));
+            begin = i + 1;
+        }
+        i += 1;
+    }
+
+    (members, i + 1)
+}
+
+fn skip_block(tokens: &[String], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+
+    while i < tokens.len() {
+        match tokens[i].as_str() {
+            "{" => depth += 1,
+            "}" => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    i
+}
+
+/// Remove `layout(...)` qualifiers
+fn strip_layout(stmt: &[String]) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut i = 0;
+
+    while i < stmt.len() {
+        if stmt[i] == "layout" {
+            while i < stmt.len() && stmt[i] != ")" {
+                i += 1;
+            }
+        } else {
+            result.push(stmt[i].clone());
+        }
+        i += 1;
+    }
+
+    result
+}
+
+impl ShaderReflection {
+    pub fn parse(kind: ShaderKind, code: &str) -> ShaderReflection {
+        let tokens = tokenize(code);
+        let mut r = ShaderReflection::default();
+
+        let mut i = 0;
+        let mut begin = 0;
+
+        while i < tokens.len() {
+            match tokens[i].as_str() {
+                ";" => {
+                    let stmt = strip_layout(&tokens[begin..i]);
+                    r.parse_statement(kind, &stmt);
+                    begin = i + 1;
+                    i += 1;
+                }
+                "{" => {
+                    let stmt = strip_layout(&tokens[begin..i]);
+                    let first = stmt.first().map(|s| s.as_str());
+
+                    match (first, stmt.get(1)) {
+                        (Some("struct"), Some(name)) => {
+                            let (members, next) = parse_members(&tokens, i);
+                            r.structs.insert(name.clone(), members);
+                            i = next;
+                        }
+                        (Some("uniform"), Some(name)) => {
+                            // uniform block, members are in global scope
+                            let (members, next) = parse_members(&tokens, i);
+                            r.blocks.insert(name.clone(), members.clone());
+                            r.uniforms.extend(members);
+                            i = next;
+                        }
+                        _ => {
+                            // function body
+                            i = skip_block(&tokens, i);
+                            begin = i;
+                            continue;
+                        }
+                    }
+
+                    // skip instance names of struct and block declarations
+                    while i < tokens.len() && tokens[i] != ";" {
+                        i += 1;
+                    }
+                    i += 1;
+                    begin = i;
+                }
+                _ => i += 1,
+            }
+        }
+
+        r.resolve_structs();
+        r
+    }
+
+    fn parse_statement(&mut self, kind: ShaderKind, stmt: &[String]) {
+        let first = match stmt.first() {
+            Some(first) => first.as_str(),
+            None => return,
+        };
+
+        match (first, kind) {
+            ("uniform", _) => self.uniforms.extend(parse_decl(&stmt[1..])),
+            ("attribute", ShaderKind::Vertex) | ("in", ShaderKind::Vertex) => {
+                self.attributes.extend(parse_decl(&stmt[1..]))
+            }
+            _ => (),
+        }
+    }
+
+    fn resolve_structs(&mut self) {
+        let structs = &self.structs;
+        let resolve = |v: &mut ShaderVar| {
+            if let ShaderVarType::Other(ref name) = v.ty.clone() {
+                if structs.contains_key(name) {
+                    v.ty = ShaderVarType::Struct(name.clone());
+                }
+            }
+        };
+
+        for u in self.uniforms.iter_mut() {
+            resolve(u);
+        }
+
+        let names: Vec<String> = self.structs.keys().cloned().collect();
+        let mut resolved = HashMap::new();
+        for name in names {
+            let mut members = self.structs[&name].clone();
+            for m in members.iter_mut() {
+                if let ShaderVarType::Other(ref ty) = m.ty.clone() {
+                    if self.structs.contains_key(ty) {
+                        m.ty = ShaderVarType::Struct(ty.clone());
+                    }
+                }
+            }
+            resolved.insert(name, members);
+        }
+        self.structs = resolved;
+    }
+
+    /// Merge the declarations of another stage of the same program
+    pub fn merge(&mut self, other: ShaderReflection) {
+        self.attributes.extend(other.attributes);
+
+        for u in other.uniforms {
+            if !self.uniforms.iter().any(|v| v.name == u.name) {
+                self.uniforms.push(u);
+            }
+        }
+
+        self.structs.extend(other.structs);
+        self.blocks.extend(other.blocks);
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&ShaderVar> {
+        self.attributes.iter().find(|v| v.name == name)
+    }
+
+    /// Find an uniform by the name used to set it, e.g. `uPointLights[0].position`
+    pub fn uniform(&self, name: &str) -> Option<&ShaderVar> {
+        let mut parts = name.split('.');
+        fn strip_index(s: &str) -> &str {
+            s.split('[').next().unwrap_or("")
+        }
+
+        let root = strip_index(parts.next()?);
+        let mut var = self.uniforms.iter().find(|v| v.name == root)?;
+
+        for part in parts {
+            let member = strip_index(part);
+            var = match var.ty {
+                ShaderVarType::Struct(ref s) => {
+                    self.structs.get(s)?.iter().find(|v| v.name == member)?
+                }
+                _ => return None,
+            };
+        }
+
+        Some(var)
+    }
+
+    /// Whether the uniform set with `name` is a member of the uniform block `block`
+    pub fn in_block(&self, block: &str, name: &str) -> bool {
+        let root = name.split(|c| c == '[' || c == '.').next().unwrap_or("");
+
+        self.blocks
+            .get(block)
+            .map(|members| members.iter().any(|v| v.name == root))
+            .unwrap_or(false)
+    }
+
+    pub fn samplers(&self) -> Vec<&ShaderVar> {
+        self.uniforms.iter().filter(|v| v.ty.is_sampler()).collect()
+    }
+}
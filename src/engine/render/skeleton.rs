@@ -0,0 +1,186 @@
+use engine::core::{Aabb, Component, GameObject};
+use engine::render::MeshData;
+use math::*;
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use uni_app;
+
+/// Joints uploaded per draw, same as UNI_MAX_JOINTS in "unrust/skinning.glsl"
+pub const MAX_JOINTS: usize = 64;
+
+lazy_static! {
+    /// Names of the `uJointMatrices` elements, formatted once instead of for each draw
+    pub(crate) static ref JOINT_MATRIX_UNIFORMS: Vec<String> = (0..MAX_JOINTS)
+        .map(|i| format!("uJointMatrices[{}]", i))
+        .collect();
+}
+
+/// Joint hierarchy of skinned meshes, each joint is a game object of the `SceneTree`
+///
+/// Joints are animated by moving their game objects, their parents are part of the pose.
+#[derive(Component)]
+pub struct Skeleton {
+    joints: Vec<Weak<RefCell<GameObject>>>,
+    /// From the space of the mesh in bind pose to the space of each joint
+    inverse_bind_matrices: Vec<Matrix4f>,
+}
+
+impl Skeleton {
+    /// Only the first `MAX_JOINTS` joints deform the meshes, a warning is printed for the others
+    pub fn new(
+        joints: &[Rc<RefCell<GameObject>>],
+        inverse_bind_matrices: Vec<Matrix4f>,
+    ) -> Skeleton {
+        debug_assert_eq!(joints.len(), inverse_bind_matrices.len());
+
+        if joints.len() > MAX_JOINTS {
+            uni_app::App::print(format!(
+                "Skeleton has {} joints, only the first {} deform the skinned meshes\n",
+                joints.len(),
+                MAX_JOINTS
+            ));
+        }
+
+        Skeleton {
+            joints: joints.iter().map(Rc::downgrade).collect(),
+            inverse_bind_matrices,
+        }
+    }
+
+    /// Skeleton whose bind pose is the current pose of the joints,
+    /// for a mesh in the space of `mesh_m`
+    pub fn from_current_pose(joints: &[Rc<RefCell<GameObject>>], mesh_m: &Matrix4f) -> Skeleton {
+        let inverse_bind_matrices = joints
+            .iter()
+            .map(|j| {
+                let m = j.borrow().transform.as_global_matrix();
+                m.invert().unwrap_or(Matrix4::identity()) * mesh_m
+            })
+            .collect();
+
+        Skeleton::new(joints, inverse_bind_matrices)
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn joint(&self, i: usize) -> Option<Rc<RefCell<GameObject>>> {
+        self.joints.get(i).and_then(|j| j.upgrade())
+    }
+
+    pub fn inverse_bind_matrix(&self, i: usize) -> Matrix4f {
+        self.inverse_bind_matrices[i]
+    }
+
+    /// From the space of the mesh in bind pose to its space in the current pose,
+    /// for a mesh in the space of `mesh_m`
+    ///
+    /// Removed joints keep the bind pose.
+    pub fn joint_matrices(&self, mesh_m: &Matrix4f) -> Vec<Matrix4f> {
+        let inv_mesh_m = mesh_m.invert().unwrap_or(Matrix4::identity());
+
+        self.joints
+            .iter()
+            .zip(self.inverse_bind_matrices.iter())
+            .map(|(joint, inv_bind)| match joint.upgrade() {
+                Some(joint) => {
+                    // The joint may be the borrowed game object of the mesh
+                    let joint_m = match joint.try_borrow() {
+                        Ok(joint) => joint.transform.as_global_matrix(),
+                        Err(_) => return Matrix4::identity(),
+                    };
+
+                    inv_mesh_m * joint_m * inv_bind
+                }
+                None => Matrix4::identity(),
+            })
+            .collect()
+    }
+}
+
+/// Mesh of the same game object deformed by the joints of a `Skeleton` on the gpu
+///
+/// Its vertices need `joints` and `weights`, and its programs the "unrust/skinning.glsl"
+/// include. The joint matrices are computed by the engine once per frame, before the
+/// first render pass, and up to `MAX_JOINTS` are uploaded per draw.
+#[derive(Component)]
+pub struct SkinnedMesh {
+    /// Component of a `Skeleton`, which can be shared by many meshes
+    pub skeleton: Arc<Component>,
+
+    /// Bind pose bounds of the vertices influenced by each joint
+    joint_bounds: Vec<Aabb>,
+    joint_matrices: Vec<Matrix4f>,
+    bounds: Option<Aabb>,
+    /// Frame of the engine in which `joint_matrices` were computed
+    updated_frame: Option<u64>,
+}
+
+impl SkinnedMesh {
+    /// `data` is the bind pose of the mesh, used to compute the animated bounds
+    pub fn new(skeleton: Arc<Component>, data: &MeshData) -> SkinnedMesh {
+        let mut joint_bounds = Vec::new();
+
+        if let (&Some(ref joints), &Some(ref weights)) = (&data.joints, &data.weights) {
+            for (i, v) in data.vertices.chunks(3).enumerate() {
+                let p = Vector3::new(v[0], v[1], v[2]);
+
+                for k in 0..4 {
+                    if weights[i * 4 + k] <= 0.0 {
+                        continue;
+                    }
+
+                    let j = joints[i * 4 + k] as usize;
+                    if joint_bounds.len() <= j {
+                        joint_bounds.resize(j + 1, Aabb::empty());
+                    }
+
+                    joint_bounds[j].merge_point(&p);
+                }
+            }
+        }
+
+        SkinnedMesh {
+            skeleton,
+            joint_bounds,
+            joint_matrices: Vec::new(),
+            bounds: None,
+            updated_frame: None,
+        }
+    }
+
+    /// Palette of the last update, see `Skeleton::joint_matrices`
+    pub fn joint_matrices(&self) -> &[Matrix4f] {
+        &self.joint_matrices
+    }
+
+    /// Bounds of the animated pose in the space of the mesh
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    /// Pose of the skeleton, computed once per `frame` such that all passes render the same
+    pub(crate) fn update(&mut self, mesh_m: &Matrix4f, frame: u64) {
+        if self.updated_frame == Some(frame) {
+            return;
+        }
+        self.updated_frame = Some(frame);
+
+        let mut matrices = match self.skeleton.try_as::<Skeleton>() {
+            Some(skeleton) => skeleton.borrow().joint_matrices(mesh_m),
+            None => Vec::new(),
+        };
+        // The extra joints were reported by Skeleton::new
+        matrices.truncate(MAX_JOINTS);
+
+        let mut bounds = Aabb::empty();
+        for (aabb, m) in self.joint_bounds.iter().zip(matrices.iter()) {
+            bounds.merge(&aabb.transform(m));
+        }
+
+        self.bounds = if bounds.is_empty() { None } else { Some(bounds) };
+        self.joint_matrices = matrices;
+    }
+}
@@ -3,18 +3,24 @@ use uni_gl::*;
 
 use image::{RgbImage, RgbaImage};
 
-use engine::asset::{Asset, AssetResult, AssetSystem, FileFuture, LoadableAsset, Resource, DDS};
+use engine::asset::{Asset, AssetError, AssetResult, AssetSystem, DDSImage, FileFuture,
+                    LoadableAsset, Resource, DDS};
+use engine::context::ToGLState;
+use engine::render::{CompressedFormat, CompressedImage, DepthTest, ShaderVarType};
 use std::cell::{Cell, RefCell};
 use std::path::Path;
 use std::rc::Rc;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum TextureFiltering {
     Nearest,
+    /// Trilinear filtering when the texture has mipmaps
     Linear,
+    /// Linear filtering with the nearest mipmap
+    Bilinear,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum TextureWrap {
     Repeat,
     ClampToEdge,
@@ -27,18 +33,66 @@ pub enum TextureImage {
     Rgb(RgbImage),
     DXT1(DDS),
     DXT5(DDS),
+    /// BC4, BC5, BC7 from DDS files and ETC2, ASTC from KTX files
+    Compressed(CompressedImage),
+}
+
+/// RGB float voxels, x varying fastest, e.g. a color grading LUT.
+/// Uploaded as RGB16F, which is filterable in WebGL2 and GL3
+#[derive(Debug, Clone)]
+pub struct TextureVolume {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub data: Vec<f32>,
 }
 
 #[derive(Debug)]
 pub enum TextureAttachment {
     Color0,
+    /// RGBA 32 bit floats, always sampled with nearest filtering.
+    /// Requires float textures (OES_texture_float or WebGL2 with EXT_color_buffer_float)
+    Color0Float,
+    /// RGBA 16 bit floats, filterable.
+    /// Requires desktop GL, or WebGL2 with EXT_color_buffer_float
+    Color0HalfFloat,
     Depth,
 }
 
+impl TextureAttachment {
+    /// Whether the GL context can render to this attachment
+    pub fn is_supported(&self, gl: &WebGLRenderingContext) -> bool {
+        let has = |name: &str| gl.get_extension(name).is_some();
+
+        match *self {
+            TextureAttachment::Color0 => true,
+            TextureAttachment::Color0Float => {
+                !uni_gl::IS_GL_ES || if gl.is_webgl2 {
+                    has("EXT_color_buffer_float")
+                } else {
+                    has("OES_texture_float") && has("WEBGL_color_buffer_float")
+                }
+            }
+            TextureAttachment::Color0HalfFloat => {
+                !uni_gl::IS_GL_ES || (gl.is_webgl2 && has("EXT_color_buffer_float"))
+            }
+            TextureAttachment::Depth => {
+                !uni_gl::IS_GL_ES || gl.is_webgl2 || has("WEBGL_depth_texture")
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 enum TextureKind {
     Image(Resource<TextureImage>),
     CubeMap([Resource<TextureImage>; 6]),
+    /// One image per layer, or a sprite sheet split in `grid` columns and rows
+    Texture2DArray {
+        layers: Vec<Resource<TextureImage>>,
+        grid: Option<(u32, u32)>,
+    },
+    Texture3D(Resource<TextureVolume>),
     RenderTexture {
         size: (u32, u32),
         attach: TextureAttachment,
@@ -52,13 +106,35 @@ pub struct Texture {
     pub wrap_v: Cell<TextureWrap>,
     pub wrap_w: Cell<Option<TextureWrap>>,
 
+    /// Generate (or upload the stored) mipmaps
+    ///
+    /// Enabling them after the upload generates them from the base level, which is not
+    /// possible for compressed textures uploaded without their mipmaps.
+    pub mipmaps: Cell<bool>,
+    /// Max anisotropy level, 1.0 to disable anisotropic filtering,
+    /// clamped to the max of the GL context
+    pub anisotropy: Cell<f32>,
+    /// Desktop GL only, as GLES and WebGL have no lod bias sampler parameter
+    pub lod_bias: Cell<f32>,
+    /// Min and max lod, WebGL2 / GL3 only
+    pub lod_range: Cell<Option<(f32, f32)>>,
+    /// Compare function of depth textures sampled with shadow samplers, WebGL2 / GL3 only
+    pub compare: Cell<Option<DepthTest>>,
+
     gl_state: RefCell<Option<TextureGLState>>,
     kind: TextureKind,
+    /// File reported in upload errors, empty for generated textures
+    path: RefCell<String>,
 }
 
 pub enum TextureAsset {
     Single(Resource<TextureImage>),
     Cube([Resource<TextureImage>; 6]),
+    Array {
+        layers: Vec<Resource<TextureImage>>,
+        grid: Option<(u32, u32)>,
+    },
+    Volume(Resource<TextureVolume>),
 }
 
 impl From<TextureImage> for TextureAsset {
@@ -67,6 +143,95 @@ impl From<TextureImage> for TextureAsset {
     }
 }
 
+impl From<Vec<TextureImage>> for TextureAsset {
+    fn from(layers: Vec<TextureImage>) -> TextureAsset {
+        TextureAsset::Array {
+            layers: layers.into_iter().map(Resource::new).collect(),
+            grid: None,
+        }
+    }
+}
+
+impl From<TextureVolume> for TextureAsset {
+    fn from(volume: TextureVolume) -> TextureAsset {
+        TextureAsset::Volume(Resource::new(volume))
+    }
+}
+
+/// How the files of a texture are laid out, from the conventions of its name
+enum TextureLayout {
+    Single,
+    /// `sky_cubemap.png` loads `sky_right.png`, `sky_left.png`, ...
+    Cube,
+    /// `ground_array4.png` loads `ground_0.png` to `ground_3.png`
+    Array(u32),
+    /// `tiles_array8x4.png` splits `tiles.png` in 8 columns and 4 rows
+    Sheet(u32, u32),
+    /// `.cube` 3D LUT files
+    Volume,
+}
+
+fn texture_files(fname: &str) -> (TextureLayout, Vec<String>) {
+    let path = Path::new(fname);
+    let ext = path.extension();
+    let stem = path.file_stem();
+    let parent = path.parent();
+    let parent = parent.map_or("".to_string(), |p| p.to_str().unwrap().to_string() + "/");
+
+    if ext.is_none() || stem.is_none() {
+        return (TextureLayout::Single, vec![fname.to_string()]);
+    }
+
+    let ext = ext.unwrap().to_str().unwrap();
+    let stem = stem.unwrap().to_str().unwrap();
+    let tag = "_cubemap";
+
+    if ext.to_lowercase() == "cube" {
+        return (TextureLayout::Volume, vec![fname.to_string()]);
+    }
+
+    if stem.to_lowercase().ends_with(tag) {
+        let f = (&stem[..stem.len() - tag.len()]).to_string();
+        let faces = ["right", "left", "top", "bottom", "front", "back"];
+
+        return (
+            TextureLayout::Cube,
+            faces
+                .iter()
+                .map(|face| format!("{}{}_{}.{}", &parent, &f, face, ext))
+                .collect(),
+        );
+    }
+
+    if let Some(pos) = stem.to_lowercase().rfind("_array") {
+        let f = &stem[..pos];
+        let spec: Vec<Option<u32>> = stem[pos + "_array".len()..]
+            .split('x')
+            .map(|n| n.parse().ok())
+            .collect();
+
+        match spec.as_slice() {
+            &[Some(n)] if n > 0 => {
+                return (
+                    TextureLayout::Array(n),
+                    (0..n)
+                        .map(|i| format!("{}{}_{}.{}", &parent, f, i, ext))
+                        .collect(),
+                );
+            }
+            &[Some(cols), Some(rows)] if cols > 0 && rows > 0 => {
+                return (
+                    TextureLayout::Sheet(cols, rows),
+                    vec![format!("{}{}.{}", &parent, f, ext)],
+                );
+            }
+            _ => (),
+        }
+    }
+
+    (TextureLayout::Single, vec![fname.to_string()])
+}
+
 impl Asset for Texture {
     type Resource = TextureAsset;
 
@@ -77,17 +242,59 @@ impl Asset for Texture {
                 wrap_u: Cell::new(TextureWrap::ClampToEdge),
                 wrap_v: Cell::new(TextureWrap::ClampToEdge),
                 wrap_w: Cell::new(None),
+                mipmaps: Cell::new(true),
+                anisotropy: Cell::new(1.0),
+                lod_bias: Cell::new(0.0),
+                lod_range: Cell::new(None),
+                compare: Cell::new(None),
                 gl_state: RefCell::new(None),
+                path: RefCell::new(String::new()),
                 kind: TextureKind::Image(res),
             }),
 
             TextureAsset::Cube(res) => Rc::new(Texture {
                 filtering: Cell::new(TextureFiltering::Linear),
                 gl_state: RefCell::new(None),
+                path: RefCell::new(String::new()),
                 kind: TextureKind::CubeMap(res),
                 wrap_u: Cell::new(TextureWrap::ClampToEdge),
                 wrap_v: Cell::new(TextureWrap::ClampToEdge),
                 wrap_w: Cell::new(Some(TextureWrap::ClampToEdge)),
+                mipmaps: Cell::new(true),
+                anisotropy: Cell::new(1.0),
+                lod_bias: Cell::new(0.0),
+                lod_range: Cell::new(None),
+                compare: Cell::new(None),
+            }),
+
+            TextureAsset::Array { layers, grid } => Rc::new(Texture {
+                filtering: Cell::new(TextureFiltering::Linear),
+                gl_state: RefCell::new(None),
+                path: RefCell::new(String::new()),
+                kind: TextureKind::Texture2DArray { layers, grid },
+                wrap_u: Cell::new(TextureWrap::ClampToEdge),
+                wrap_v: Cell::new(TextureWrap::ClampToEdge),
+                wrap_w: Cell::new(None),
+                mipmaps: Cell::new(true),
+                anisotropy: Cell::new(1.0),
+                lod_bias: Cell::new(0.0),
+                lod_range: Cell::new(None),
+                compare: Cell::new(None),
+            }),
+
+            TextureAsset::Volume(res) => Rc::new(Texture {
+                filtering: Cell::new(TextureFiltering::Linear),
+                gl_state: RefCell::new(None),
+                path: RefCell::new(String::new()),
+                kind: TextureKind::Texture3D(res),
+                wrap_u: Cell::new(TextureWrap::ClampToEdge),
+                wrap_v: Cell::new(TextureWrap::ClampToEdge),
+                wrap_w: Cell::new(Some(TextureWrap::ClampToEdge)),
+                mipmaps: Cell::new(false),
+                anisotropy: Cell::new(1.0),
+                lod_bias: Cell::new(0.0),
+                lod_range: Cell::new(None),
+                compare: Cell::new(None),
             }),
         };
     }
@@ -96,70 +303,135 @@ impl Asset for Texture {
 impl LoadableAsset for Texture {
     fn load<T: AssetSystem + Clone + 'static>(
         asys: &T,
+        name: &str,
         mut files: Vec<FileFuture>,
     ) -> Self::Resource {
-        if files.len() == 6 {
-            TextureAsset::Cube([
+        let name = name.replace('*', &asys.texture_variant());
+
+        match texture_files(&name).0 {
+            TextureLayout::Cube => TextureAsset::Cube([
                 Self::load_resource::<TextureImage, T>(asys.clone(), files.remove(0)),
                 Self::load_resource::<TextureImage, T>(asys.clone(), files.remove(0)),
                 Self::load_resource::<TextureImage, T>(asys.clone(), files.remove(0)),
                 Self::load_resource::<TextureImage, T>(asys.clone(), files.remove(0)),
                 Self::load_resource::<TextureImage, T>(asys.clone(), files.remove(0)),
                 Self::load_resource::<TextureImage, T>(asys.clone(), files.remove(0)),
-            ])
-        } else {
-            TextureAsset::Single(Self::load_resource::<TextureImage, T>(
+            ]),
+            TextureLayout::Array(_) => TextureAsset::Array {
+                layers: files
+                    .into_iter()
+                    .map(|f| Self::load_resource::<TextureImage, T>(asys.clone(), f))
+                    .collect(),
+                grid: None,
+            },
+            TextureLayout::Sheet(cols, rows) => TextureAsset::Array {
+                layers: vec![
+                    Self::load_resource::<TextureImage, T>(asys.clone(), files.remove(0)),
+                ],
+                grid: Some((cols, rows)),
+            },
+            TextureLayout::Volume => TextureAsset::Volume(Self::load_resource::<
+                TextureVolume,
+                T,
+            >(asys.clone(), files.remove(0))),
+            TextureLayout::Single => TextureAsset::Single(Self::load_resource::<TextureImage, T>(
                 asys.clone(),
                 files.remove(0),
-            ))
+            )),
         }
     }
 
     fn gather<T: AssetSystem>(asys: &T, fname: &str) -> Vec<FileFuture> {
-        let path = Path::new(fname);
-        let ext = path.extension();
-        let stem = path.file_stem();
-        let parent = path.parent();
-        let parent = parent.map_or("".to_string(), |p| p.to_str().unwrap().to_string() + "/");
-
-        if ext.is_none() || stem.is_none() {
-            return vec![asys.new_file(fname)];
-        }
+        let fname = fname.replace('*', &asys.texture_variant());
 
-        let ext = ext.unwrap().to_str().unwrap();
-        let stem = stem.unwrap().to_str().unwrap();
-        let tag = "_cubemap";
-
-        if stem.to_lowercase().ends_with(tag) {
-            let f = (&stem[..stem.len() - tag.len()]).to_string();
-            return vec![
-                asys.new_file(&format!("{}{}_right.{}", &parent, &f, ext)),
-                asys.new_file(&format!("{}{}_left.{}", &parent, &f, ext)),
-                asys.new_file(&format!("{}{}_top.{}", &parent, &f, ext)),
-                asys.new_file(&format!("{}{}_bottom.{}", &parent, &f, ext)),
-                asys.new_file(&format!("{}{}_front.{}", &parent, &f, ext)),
-                asys.new_file(&format!("{}{}_back.{}", &parent, &f, ext)),
-            ];
+        texture_files(&fname)
+            .1
+            .iter()
+            .map(|f| asys.new_file(f))
+            .collect()
+    }
+
+    fn reload(&self, r: Self::Resource) {
+        match (&self.kind, r) {
+            (&TextureKind::Image(ref res), TextureAsset::Single(new_res)) => res.reload(new_res),
+            (&TextureKind::CubeMap(ref res), TextureAsset::Cube(new_res)) => {
+                let [a, b, c, d, e, f] = new_res;
+                for (res, new_res) in res.iter().zip(vec![a, b, c, d, e, f]) {
+                    res.reload(new_res);
+                }
+            }
+            (
+                &TextureKind::Texture2DArray { ref layers, .. },
+                TextureAsset::Array {
+                    layers: new_layers, ..
+                },
+            ) => {
+                for (res, new_res) in layers.iter().zip(new_layers) {
+                    res.reload(new_res);
+                }
+            }
+            (&TextureKind::Texture3D(ref res), TextureAsset::Volume(new_res)) => {
+                res.reload(new_res)
+            }
+            _ => return,
         }
 
-        vec![asys.new_file(fname)]
+        // Upload again in next prepare
+        self.gl_state.replace(None);
     }
 }
 
+/// Sampling parameters applied to the GL texture
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct SamplerState {
+    filtering: TextureFiltering,
+    mipmaps: bool,
+    wrap_u: TextureWrap,
+    wrap_v: TextureWrap,
+    wrap_w: Option<TextureWrap>,
+    anisotropy: f32,
+    lod_bias: f32,
+    lod_range: Option<(f32, f32)>,
+    compare: Option<DepthTest>,
+}
+
 #[derive(Debug)]
 struct TextureGLState {
     tex: WebGLTexture,
     size: (u32, u32),
+    target: uni_gl::TextureKind,
+    /// Whether the mipmaps are sampled
+    has_mipmap: bool,
+    /// Whether the mip levels are in the GL texture
+    mip_levels: bool,
+    /// Whether the mip levels can be generated from the base level, e.g. not compressed
+    can_generate_mipmap: bool,
+    force_nearest_filtering: bool,
+    /// Max of EXT_texture_filter_anisotropic, `None` without the extension
+    max_anisotropy: Option<f32>,
+
+    /// Last applied sampler, compared with the texture options in each bind
+    sampler: SamplerState,
 }
 
 impl Texture {
+    pub(crate) fn set_path(&self, path: &str) {
+        *self.path.borrow_mut() = path.to_owned();
+    }
+
     pub fn new_render_texture(width: u32, height: u32, attach: TextureAttachment) -> Rc<Self> {
         Rc::new(Texture {
             filtering: Cell::new(TextureFiltering::Linear),
             gl_state: RefCell::new(None),
+            path: RefCell::new(String::new()),
             wrap_u: Cell::new(TextureWrap::ClampToEdge),
             wrap_v: Cell::new(TextureWrap::ClampToEdge),
             wrap_w: Cell::new(None),
+            mipmaps: Cell::new(true),
+            anisotropy: Cell::new(1.0),
+            lod_bias: Cell::new(0.0),
+            lod_range: Cell::new(None),
+            compare: Cell::new(None),
             kind: TextureKind::RenderTexture {
                 size: (width, height),
                 attach: attach,
@@ -171,21 +443,53 @@ impl Texture {
         self.gl_state.borrow().as_ref().map(|s| s.size)
     }
 
+    /// The sampler type of the shader uniforms this texture can be bound to
+    pub fn sampler_type(&self) -> ShaderVarType {
+        match self.kind {
+            TextureKind::CubeMap(_) => ShaderVarType::SamplerCube,
+            TextureKind::Texture2DArray { .. } => ShaderVarType::Sampler2DArray,
+            TextureKind::Texture3D(_) => ShaderVarType::Sampler3D,
+            _ => ShaderVarType::Sampler2D,
+        }
+    }
+
     pub fn bind(&self, gl: &WebGLRenderingContext, unit: u32) -> AssetResult<()> {
         self.prepare(gl, unit)?;
 
-        let state_option = self.gl_state.borrow();
-        let state = state_option.as_ref().unwrap();
+        let mut state_option = self.gl_state.borrow_mut();
+        let state = state_option.as_mut().unwrap();
 
         gl.active_texture(unit);
-        match self.kind {
-            TextureKind::CubeMap(_) => gl.bind_texture_cube(&state.tex),
-            _ => gl.bind_texture(&state.tex),
+        bind_gl_texture(gl, state.target, &state.tex);
+
+        // Options changed after the upload
+        let sampler = self.sampler_state();
+        if state.sampler != sampler {
+            if state.sampler.mipmaps != sampler.mipmaps {
+                update_mipmaps(gl, state, sampler.mipmaps);
+            }
+
+            apply_sampler(gl, state, &sampler);
+            state.sampler = sampler;
         }
 
         Ok(())
     }
 
+    fn sampler_state(&self) -> SamplerState {
+        SamplerState {
+            filtering: self.filtering.get(),
+            mipmaps: self.mipmaps.get(),
+            wrap_u: self.wrap_u.get(),
+            wrap_v: self.wrap_v.get(),
+            wrap_w: self.wrap_w.get(),
+            anisotropy: self.anisotropy.get(),
+            lod_bias: self.lod_bias.get(),
+            lod_range: self.lod_range.get(),
+            compare: self.compare.get(),
+        }
+    }
+
     pub fn bind_with_frame_buffer(&self, gl: &WebGLRenderingContext, unit: u32) -> AssetResult<()> {
         self.prepare(gl, unit)?;
 
@@ -193,14 +497,13 @@ impl Texture {
         let state = state_option.as_ref().unwrap();
 
         gl.active_texture(unit);
-        match self.kind {
-            TextureKind::CubeMap(_) => gl.bind_texture_cube(&state.tex),
-            _ => gl.bind_texture(&state.tex),
-        }
+        bind_gl_texture(gl, state.target, &state.tex);
 
         if let TextureKind::RenderTexture { ref attach, .. } = self.kind {
             match attach {
-                &TextureAttachment::Color0 => {
+                &TextureAttachment::Color0
+                | &TextureAttachment::Color0Float
+                | &TextureAttachment::Color0HalfFloat => {
                     bind_to_framebuffer(gl, &state.tex, Buffers::ColorAttachment0);
                 }
                 &TextureAttachment::Depth => {
@@ -220,11 +523,10 @@ impl Texture {
 
         let new_state = Some(texture_bind_buffer(
             gl,
-            &self.filtering.get(),
-            self.wrap_u.get(),
-            self.wrap_v.get(),
-            self.wrap_w.get(),
+            &self.sampler_state(),
+            self.mipmaps.get(),
             &self.kind,
+            &self.path.borrow(),
             unit,
         )?);
 
@@ -234,6 +536,15 @@ impl Texture {
     }
 }
 
+fn bind_gl_texture(gl: &WebGLRenderingContext, target: uni_gl::TextureKind, tex: &WebGLTexture) {
+    match target {
+        uni_gl::TextureKind::TextureCubeMap => gl.bind_texture_cube(tex),
+        uni_gl::TextureKind::Texture2dArray => gl.bind_texture_2d_array(tex),
+        uni_gl::TextureKind::Texture3d => gl.bind_texture_3d(tex),
+        _ => gl.bind_texture(tex),
+    }
+}
+
 fn bind_to_framebuffer(gl: &WebGLRenderingContext, tex: &WebGLTexture, buffer: Buffers) {
     gl.framebuffer_texture2d(
         Buffers::Framebuffer,
@@ -260,22 +571,22 @@ fn bind_to_framebuffer(gl: &WebGLRenderingContext, tex: &WebGLTexture, buffer: B
 
 fn texture_bind_buffer(
     gl: &WebGLRenderingContext,
-    texfilter: &TextureFiltering,
-    wrap_u: TextureWrap,
-    wrap_v: TextureWrap,
-    wrap_w: Option<TextureWrap>,
+    sampler: &SamplerState,
+    mipmaps: bool,
     kind: &TextureKind,
+    path: &str,
     unit: u32,
 ) -> AssetResult<TextureGLState> {
-    let mut gl_tex_kind: uni_gl::TextureKind = uni_gl::TextureKind::Texture2d;
+    let mut target = uni_gl::TextureKind::Texture2d;
     let mut force_nearest_filtering = false;
 
-    let (tex, size, has_midmap) = match kind {
+    let (tex, size, has_midmap, can_generate_mipmap) = match kind {
         &TextureKind::Image(ref img_res) => {
             let teximg = img_res.try_into()?;
             let tex = gl.create_texture();
             let size: (u32, u32);
             let has_midmap;
+            let can_generate_mipmap;
 
             gl.active_texture(unit);
             gl.bind_texture(&tex);
@@ -293,8 +604,11 @@ fn texture_bind_buffer(
                         &*img,                       // data
                     );
 
-                    gl.generate_mipmap();
-                    has_midmap = true;
+                    if mipmaps {
+                        gl.generate_mipmap();
+                    }
+                    has_midmap = mipmaps;
+                    can_generate_mipmap = true;
                 }
                 TextureImage::Rgb(img) => {
                     size = (img.width(), img.height());
@@ -308,52 +622,51 @@ fn texture_bind_buffer(
                         &*img,                       // data
                     );
 
-                    gl.generate_mipmap();
-                    has_midmap = true;
+                    if mipmaps {
+                        gl.generate_mipmap();
+                    }
+                    has_midmap = mipmaps;
+                    can_generate_mipmap = true;
                 }
 
                 TextureImage::DXT1(dds) => {
                     size = (dds.images[0].width, dds.images[0].height);
-
-                    let format = if dds.has_alpha {
-                        TextureCompression::RgbaDxt1
-                    } else {
-                        TextureCompression::RgbDxt1
-                    };
-
-                    for (lvl, img) in dds.images.iter().enumerate() {
-                        gl.compressed_tex_image2d(
-                            TextureBindPoint::Texture2d,
-                            lvl as u8,
-                            format,
-                            img.width as u16,
-                            img.height as u16,
-                            &img.data,
-                        );
-                    }
-
-                    has_midmap = dds.images.len() > 1;
+                    has_midmap = upload_compressed(
+                        gl,
+                        path,
+                        TextureBindPoint::Texture2d,
+                        dxt1_format(&dds),
+                        mip_images(&dds, mipmaps),
+                    )?;
+                    can_generate_mipmap = false;
                 }
 
                 TextureImage::DXT5(dds) => {
                     size = (dds.images[0].width, dds.images[0].height);
+                    has_midmap = upload_compressed(
+                        gl,
+                        path,
+                        TextureBindPoint::Texture2d,
+                        CompressedFormat::Dxt5,
+                        mip_images(&dds, mipmaps),
+                    )?;
+                    can_generate_mipmap = false;
+                }
 
-                    for (lvl, img) in dds.images.iter().enumerate() {
-                        gl.compressed_tex_image2d(
-                            TextureBindPoint::Texture2d,
-                            lvl as u8,
-                            TextureCompression::RgbaDxt5,
-                            img.width as u16,
-                            img.height as u16,
-                            &img.data,
-                        );
-                    }
-
-                    has_midmap = dds.images.len() > 1;
+                TextureImage::Compressed(img) => {
+                    size = (img.images[0].width, img.images[0].height);
+                    has_midmap = upload_compressed(
+                        gl,
+                        path,
+                        TextureBindPoint::Texture2d,
+                        img.format,
+                        compressed_mip_images(&img, mipmaps),
+                    )?;
+                    can_generate_mipmap = false;
                 }
             }
 
-            (tex, size, has_midmap)
+            (tex, size, has_midmap, can_generate_mipmap)
         }
         &TextureKind::CubeMap(ref img_res) => {
             let mut imgs = Vec::new();
@@ -416,53 +729,151 @@ fn texture_bind_buffer(
 
                     &TextureImage::DXT1(ref dds) => {
                         size = (dds.images[0].width, dds.images[0].height);
-
-                        for (lvl, img) in dds.images.iter().enumerate() {
-                            gl.compressed_tex_image2d(
-                                bindpoints[i],
-                                lvl as u8,
-                                TextureCompression::RgbaDxt1,
-                                img.width as u16,
-                                img.height as u16,
-                                &img.data,
-                            );
-                        }
-
-                        has_midmap = dds.images.len() > 1;
+                        has_midmap = upload_compressed(
+                            gl,
+                            path,
+                            bindpoints[i],
+                            dxt1_format(dds),
+                            mip_images(dds, mipmaps),
+                        )?;
                     }
 
                     &TextureImage::DXT5(ref dds) => {
                         size = (dds.images[0].width, dds.images[0].height);
+                        has_midmap = upload_compressed(
+                            gl,
+                            path,
+                            bindpoints[i],
+                            CompressedFormat::Dxt5,
+                            mip_images(dds, mipmaps),
+                        )?;
+                    }
 
-                        for (lvl, img) in dds.images.iter().enumerate() {
-                            gl.compressed_tex_image2d(
-                                bindpoints[i],
-                                lvl as u8,
-                                TextureCompression::RgbaDxt5,
-                                img.width as u16,
-                                img.height as u16,
-                                &img.data,
-                            );
-                        }
-
-                        has_midmap = dds.images.len() > 1;
+                    &TextureImage::Compressed(ref img) => {
+                        size = (img.images[0].width, img.images[0].height);
+                        has_midmap = upload_compressed(
+                            gl,
+                            path,
+                            bindpoints[i],
+                            img.format,
+                            compressed_mip_images(img, mipmaps),
+                        )?;
                     }
                 }
             }
 
-            if need_gen_mipmap {
+            if need_gen_mipmap && mipmaps {
                 gl.generate_mipmap_cube();
                 has_midmap = true;
             }
 
-            gl_tex_kind = uni_gl::TextureKind::TextureCubeMap;
+            target = uni_gl::TextureKind::TextureCubeMap;
+
+            (tex, size, has_midmap, need_gen_mipmap)
+        }
+
+        &TextureKind::Texture2DArray {
+            ref layers,
+            grid,
+        } => {
+            require_webgl2(gl, "Texture arrays", path)?;
+
+            // Test if all resources are ready.
+            for res in layers.iter() {
+                res.try_borrow()?;
+            }
+
+            let mut images = Vec::new();
+            for res in layers.iter() {
+                images.push(rgba_pixels(res.try_into()?, path)?);
+            }
+
+            if let Some((cols, rows)) = grid {
+                images = split_sheet(images.remove(0), cols, rows, path)?;
+            }
+
+            let size = (images[0].0, images[0].1);
+            let mut data = Vec::new();
+
+            for (w, h, mut pixels) in images.iter().cloned() {
+                if (w, h) != size {
+                    return Err(AssetError::InvalidFormat {
+                        path: path.to_owned(),
+                        len: pixels.len(),
+                        reason: format!(
+                            "Layers of a texture array must have the same size, {:?} != {:?}",
+                            (w, h),
+                            size
+                        ),
+                    });
+                }
+
+                data.append(&mut pixels);
+            }
+
+            let tex = gl.create_texture();
+            gl.active_texture(unit);
+            gl.bind_texture_2d_array(&tex);
+            gl.tex_image3d(
+                TextureBindPoint::Texture2dArray, // target
+                0,                                // level
+                size.0 as u16,                    // width
+                size.1 as u16,                    // height
+                images.len() as u16,              // depth
+                PixelFormat::Rgba,                // format
+                PixelType::UnsignedByte,          // type
+                &data,                            // data
+            );
+
+            if mipmaps {
+                gl.generate_mipmap_2d_array();
+            }
+
+            target = uni_gl::TextureKind::Texture2dArray;
 
-            (tex, size, has_midmap)
+            (tex, size, mipmaps, true)
+        }
+
+        &TextureKind::Texture3D(ref res) => {
+            require_webgl2(gl, "3D textures", path)?;
+
+            let volume = res.try_into()?;
+            let tex = gl.create_texture();
+
+            gl.active_texture(unit);
+            gl.bind_texture_3d(&tex);
+
+            // RGB rows of odd sizes, e.g. 33 for LUTs, are not 4 bytes aligned
+            gl.pixel_storei(PixelStorageMode::UnpackAlignment, 1);
+            gl.tex_image3d(
+                TextureBindPoint::Texture3d, // target
+                0,                           // level
+                volume.width as u16,         // width
+                volume.height as u16,        // height
+                volume.depth as u16,         // depth
+                PixelFormat::Rgb,            // format
+                PixelType::HalfFloat,        // type
+                &half_floats(&volume.data),  // data
+            );
+            gl.pixel_storei(PixelStorageMode::UnpackAlignment, 4);
+
+            if mipmaps {
+                gl.generate_mipmap_3d();
+            }
+
+            target = uni_gl::TextureKind::Texture3d;
+
+            (tex, (volume.width, volume.height), mipmaps, true)
         }
 
         &TextureKind::RenderTexture { size, ref attach } => {
             let (fmt, data_type) = match attach {
                 &TextureAttachment::Color0 => (PixelFormat::Rgba, PixelType::UnsignedByte),
+                &TextureAttachment::Color0Float => {
+                    force_nearest_filtering = true;
+                    (PixelFormat::Rgba, PixelType::Float)
+                }
+                &TextureAttachment::Color0HalfFloat => (PixelFormat::Rgba, PixelType::HalfFloat),
                 &TextureAttachment::Depth => {
                     force_nearest_filtering = true;
                     (PixelFormat::DepthComponent, PixelType::UnsignedShort)
@@ -482,31 +893,267 @@ fn texture_bind_buffer(
                 &[],                         // data
             );
 
-            (tex, size, false)
+            (tex, size, false, false)
+        }
+    };
+
+    let mut state = TextureGLState {
+        tex,
+        size,
+        target,
+        has_mipmap: has_midmap,
+        mip_levels: has_midmap,
+        can_generate_mipmap,
+        force_nearest_filtering,
+        max_anisotropy: max_anisotropy(gl),
+        sampler: *sampler,
+    };
+
+    apply_sampler(gl, &mut state, sampler);
+
+    //unbind_texture(gl, kind);
+
+    Ok(state)
+}
+
+/// Max anisotropy level of EXT_texture_filter_anisotropic, `None` without the extension
+fn max_anisotropy(gl: &WebGLRenderingContext) -> Option<f32> {
+    let supported =
+        !uni_gl::IS_GL_ES || gl.get_extension("EXT_texture_filter_anisotropic").is_some();
+    if !supported {
+        return None;
+    }
+
+    Some(gl.get_parameter_f32(Parameter::MaxTextureMaxAnisotropy))
+}
+
+/// Generate the mipmaps enabled after the upload, or stop sampling them,
+/// the texture is bound to the active unit
+fn update_mipmaps(gl: &WebGLRenderingContext, state: &mut TextureGLState, mipmaps: bool) {
+    if mipmaps && !state.mip_levels && state.can_generate_mipmap {
+        match state.target {
+            uni_gl::TextureKind::TextureCubeMap => gl.generate_mipmap_cube(),
+            uni_gl::TextureKind::Texture2dArray => gl.generate_mipmap_2d_array(),
+            uni_gl::TextureKind::Texture3d => gl.generate_mipmap_3d(),
+            _ => gl.generate_mipmap(),
+        }
+
+        state.mip_levels = true;
+    }
+
+    state.has_mipmap = mipmaps && state.mip_levels;
+}
+
+fn require_webgl2(gl: &WebGLRenderingContext, what: &str, path: &str) -> AssetResult<()> {
+    if gl.is_webgl2 {
+        return Ok(());
+    }
+
+    Err(AssetError::InvalidFormat {
+        path: path.to_owned(),
+        len: 0,
+        reason: format!("{} require WebGL2", what),
+    })
+}
+
+/// Base level of an image as RGBA8, compressed images are decompressed
+fn rgba_pixels(img: TextureImage, path: &str) -> AssetResult<(u32, u32, Vec<u8>)> {
+    let (format, base) = match img {
+        TextureImage::Rgba(img) => return Ok((img.width(), img.height(), img.into_raw())),
+        TextureImage::Rgb(img) => {
+            let pixels = img.pixels()
+                .flat_map(|p| vec![p.data[0], p.data[1], p.data[2], 255])
+                .collect();
+            return Ok((img.width(), img.height(), pixels));
         }
+        TextureImage::DXT1(dds) => (dxt1_format(&dds), dds.images[0].clone()),
+        TextureImage::DXT5(dds) => (CompressedFormat::Dxt5, dds.images[0].clone()),
+        TextureImage::Compressed(img) => (img.format, img.images[0].clone()),
     };
 
-    let mut filtering: (i32, i32) = match texfilter {
-        &TextureFiltering::Nearest => (
+    let pixels = decompress_level(format, &base, path)?;
+    Ok((base.width, base.height, pixels))
+}
+
+fn decompress_level(format: CompressedFormat, img: &DDSImage, path: &str) -> AssetResult<Vec<u8>> {
+    if img.data.len() < format.level_size(img.width, img.height) {
+        return Err(AssetError::InvalidFormat {
+            path: path.to_owned(),
+            len: img.data.len(),
+            reason: format!("{:?} level of {}x{} is truncated", format, img.width, img.height),
+        });
+    }
+
+    format
+        .decompress(img.width, img.height, &img.data)
+        .ok_or_else(|| AssetError::UnsupportedFormat {
+            path: path.to_owned(),
+            format,
+        })
+}
+
+/// Split a sprite sheet in layers, from left to right and top to bottom
+fn split_sheet(
+    sheet: (u32, u32, Vec<u8>),
+    cols: u32,
+    rows: u32,
+    path: &str,
+) -> AssetResult<Vec<(u32, u32, Vec<u8>)>> {
+    let (w, h, pixels) = sheet;
+    let (tw, th) = (w / cols, h / rows);
+
+    if tw == 0 || th == 0 {
+        return Err(AssetError::InvalidFormat {
+            path: path.to_owned(),
+            len: pixels.len(),
+            reason: format!("Sprite sheet {}x{} is smaller than {}x{} tiles", w, h, cols, rows),
+        });
+    }
+
+    let mut layers = Vec::new();
+    for row in 0..rows {
+        for col in 0..cols {
+            let mut tile = Vec::with_capacity((tw * th * 4) as usize);
+            for y in row * th..(row + 1) * th {
+                let start = ((y * w + col * tw) * 4) as usize;
+                tile.extend_from_slice(&pixels[start..start + (tw * 4) as usize]);
+            }
+            layers.push((tw, th, tile));
+        }
+    }
+
+    Ok(layers)
+}
+
+/// Little endian half floats, rounded to nearest
+fn half_floats(data: &[f32]) -> Vec<u8> {
+    let half = |v: f32| -> u16 {
+        let b = v.to_bits();
+        let sign = ((b >> 16) & 0x8000) as u16;
+        let exp = ((b >> 23) & 0xff) as i32 - 127 + 15;
+        let mant = b & 0x7f_ffff;
+
+        if exp >= 31 {
+            // Overflow, infinity and NaN
+            return sign | if exp == 128 + 15 && mant != 0 { 0x7e00 } else { 0x7c00 };
+        }
+
+        if exp <= 0 {
+            // Subnormal or zero
+            if exp < -10 {
+                return sign;
+            }
+            let m = mant | 0x80_0000;
+            let shift = (14 - exp) as u32;
+            return sign | ((m >> shift) + ((m >> (shift - 1)) & 1)) as u16;
+        }
+
+        sign | (((exp as u32) << 10 | mant >> 13) + ((mant >> 12) & 1)) as u16
+    };
+
+    let mut bytes = Vec::with_capacity(data.len() * 2);
+    for &v in data {
+        let h = half(v);
+        bytes.push(h as u8);
+        bytes.push((h >> 8) as u8);
+    }
+    bytes
+}
+
+/// Only the base level is uploaded when mipmaps are disabled
+fn mip_images(dds: &DDS, mipmaps: bool) -> &[DDSImage] {
+    if mipmaps {
+        &dds.images
+    } else {
+        &dds.images[..1]
+    }
+}
+
+fn compressed_mip_images(img: &CompressedImage, mipmaps: bool) -> &[DDSImage] {
+    if mipmaps {
+        &img.images
+    } else {
+        &img.images[..1]
+    }
+}
+
+fn dxt1_format(dds: &DDS) -> CompressedFormat {
+    if dds.has_alpha {
+        CompressedFormat::Dxt1Alpha
+    } else {
+        CompressedFormat::Dxt1
+    }
+}
+
+/// Upload compressed levels, or decompress them to RGBA if the context
+/// does not support the format. Return whether mipmaps were uploaded
+fn upload_compressed(
+    gl: &WebGLRenderingContext,
+    path: &str,
+    target: TextureBindPoint,
+    format: CompressedFormat,
+    images: &[DDSImage],
+) -> AssetResult<bool> {
+    let supported = format.is_supported(gl);
+
+    for (lvl, img) in images.iter().enumerate() {
+        if supported {
+            gl.compressed_tex_image2d(
+                target,
+                lvl as u8,
+                format.to_gl(),
+                img.width as u16,
+                img.height as u16,
+                &img.data,
+            );
+            continue;
+        }
+
+        let rgba = decompress_level(format, img, path)?;
+
+        gl.tex_image2d(
+            target,                  // target
+            lvl as u8,               // level
+            img.width as u16,        // width
+            img.height as u16,       // height
+            PixelFormat::Rgba,       // format
+            PixelType::UnsignedByte, // type
+            &rgba,                   // data
+        );
+    }
+
+    Ok(images.len() > 1)
+}
+
+/// Apply the sampler parameters to the texture bound to the active unit
+fn apply_sampler(
+    gl: &WebGLRenderingContext,
+    state: &mut TextureGLState,
+    sampler: &SamplerState,
+) {
+    let gl_tex_kind = state.target;
+
+    let mut filtering: (i32, i32) = match (sampler.filtering, state.has_mipmap) {
+        (TextureFiltering::Nearest, _) => (
             TextureMinFilter::Nearest as i32,
             TextureMagFilter::Nearest as i32,
         ),
-        _ => {
-            if has_midmap {
-                (
-                    TextureMinFilter::LinearMipmapLinear as i32,
-                    TextureMagFilter::Linear as i32,
-                )
-            } else {
-                (
-                    TextureMinFilter::Linear as i32,
-                    TextureMagFilter::Linear as i32,
-                )
-            }
-        }
+        (TextureFiltering::Linear, true) => (
+            TextureMinFilter::LinearMipmapLinear as i32,
+            TextureMagFilter::Linear as i32,
+        ),
+        (TextureFiltering::Bilinear, true) => (
+            TextureMinFilter::LinearMipmapNearest as i32,
+            TextureMagFilter::Linear as i32,
+        ),
+        (_, false) => (
+            TextureMinFilter::Linear as i32,
+            TextureMagFilter::Linear as i32,
+        ),
     };
 
-    if force_nearest_filtering {
+    // Depth textures can only be filtered by the hardware comparison
+    if state.force_nearest_filtering && sampler.compare.is_none() {
         filtering = (
             TextureMinFilter::Nearest as i32,
             TextureMagFilter::Nearest as i32,
@@ -525,25 +1172,66 @@ fn texture_bind_buffer(
     gl.tex_parameteri(
         gl_tex_kind,
         TextureParameter::TextureWrapS,
-        to_gl_wrap(wrap_u),
+        to_gl_wrap(sampler.wrap_u),
     );
     gl.tex_parameteri(
         gl_tex_kind,
         TextureParameter::TextureWrapT,
-        to_gl_wrap(wrap_v),
+        to_gl_wrap(sampler.wrap_v),
     );
 
-    if let &TextureKind::CubeMap(..) = kind {
-        if let Some(wrap_w) = wrap_w {
+    match (state.target, sampler.wrap_w) {
+        (uni_gl::TextureKind::TextureCubeMap, Some(wrap_w))
+        | (uni_gl::TextureKind::Texture3d, Some(wrap_w)) => {
             gl.tex_parameteri(
                 gl_tex_kind,
                 TextureParameter::TextureWrapR,
                 to_gl_wrap(wrap_w),
             );
         }
+        _ => (),
     }
 
-    //unbind_texture(gl, kind);
+    // EXT_texture_filter_anisotropic, also set at 1.0 to disable it again
+    if let Some(max) = state.max_anisotropy {
+        gl.tex_parameterf(
+            gl_tex_kind,
+            TextureParameter::TextureMaxAnisotropy,
+            sampler.anisotropy.max(1.0).min(max),
+        );
+    }
 
-    Ok(TextureGLState { tex, size })
+    if !uni_gl::IS_GL_ES {
+        gl.tex_parameterf(
+            gl_tex_kind,
+            TextureParameter::TextureLodBias,
+            sampler.lod_bias,
+        );
+    }
+
+    if gl.is_webgl2 {
+        let (min_lod, max_lod) = sampler.lod_range.unwrap_or((-1000.0, 1000.0));
+        gl.tex_parameterf(gl_tex_kind, TextureParameter::TextureMinLod, min_lod);
+        gl.tex_parameterf(gl_tex_kind, TextureParameter::TextureMaxLod, max_lod);
+
+        match sampler.compare {
+            Some(func) => {
+                gl.tex_parameteri(
+                    gl_tex_kind,
+                    TextureParameter::TextureCompareMode,
+                    TextureCompareMode::CompareRefToTexture as i32,
+                );
+                gl.tex_parameteri(
+                    gl_tex_kind,
+                    TextureParameter::TextureCompareFunc,
+                    func.as_gl_state() as i32,
+                );
+            }
+            None => gl.tex_parameteri(
+                gl_tex_kind,
+                TextureParameter::TextureCompareMode,
+                TextureCompareMode::None as i32,
+            ),
+        }
+    }
 }
@@ -0,0 +1,770 @@
+use engine::asset::DDSImage;
+use uni_gl;
+use uni_gl::{TextureCompression, WebGLRenderingContext};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CompressedFormat {
+    /// BC1
+    Dxt1,
+    /// BC1 with 1 bit alpha
+    Dxt1Alpha,
+    /// BC3
+    Dxt5,
+    /// Single channel, e.g. roughness or height maps
+    Bc4,
+    /// Two channels, e.g. normal maps
+    Bc5,
+    Bc7,
+    Etc2Rgb,
+    Etc2RgbA1,
+    Etc2Rgba,
+    /// Block width and height
+    Astc(u8, u8),
+}
+
+/// All mip levels of a compressed texture, the first one is the base level
+#[derive(Debug, Clone)]
+pub struct CompressedImage {
+    pub format: CompressedFormat,
+    pub images: Vec<DDSImage>,
+}
+
+impl CompressedFormat {
+    pub fn block_size(&self) -> (u32, u32) {
+        match *self {
+            CompressedFormat::Astc(w, h) => (w as u32, h as u32),
+            _ => (4, 4),
+        }
+    }
+
+    pub fn block_bytes(&self) -> u32 {
+        match *self {
+            CompressedFormat::Dxt1
+            | CompressedFormat::Dxt1Alpha
+            | CompressedFormat::Bc4
+            | CompressedFormat::Etc2Rgb
+            | CompressedFormat::Etc2RgbA1 => 8,
+            _ => 16,
+        }
+    }
+
+    /// Size in bytes of a level of `width` x `height` pixels
+    pub fn level_size(&self, width: u32, height: u32) -> usize {
+        let (bw, bh) = self.block_size();
+        let blocks = ((width + bw - 1) / bw) * ((height + bh - 1) / bh);
+        (blocks * self.block_bytes()) as usize
+    }
+
+    /// Whether the GL context can sample this format without decompression
+    pub fn is_supported(&self, gl: &WebGLRenderingContext) -> bool {
+        let has = |name: &str| gl.get_extension(name).is_some();
+
+        match *self {
+            CompressedFormat::Dxt1 | CompressedFormat::Dxt1Alpha | CompressedFormat::Dxt5 => {
+                !uni_gl::IS_GL_ES || has("WEBGL_compressed_texture_s3tc")
+            }
+            CompressedFormat::Bc4 | CompressedFormat::Bc5 => {
+                !uni_gl::IS_GL_ES || has("EXT_texture_compression_rgtc")
+            }
+            CompressedFormat::Bc7 => !uni_gl::IS_GL_ES || has("EXT_texture_compression_bptc"),
+            CompressedFormat::Etc2Rgb
+            | CompressedFormat::Etc2RgbA1
+            | CompressedFormat::Etc2Rgba => has("WEBGL_compressed_texture_etc"),
+            CompressedFormat::Astc(..) => has("WEBGL_compressed_texture_astc"),
+        }
+    }
+
+    /// Substituted for `*` in texture names, e.g. `rock.*.ktx` loads `rock.astc.ktx`
+    /// on a context supporting ASTC. DXT is the fallback as it can be decompressed.
+    ///
+    /// ASTC has no CPU decoder, an ASTC texture loaded on a context which can not
+    /// sample it fails with `AssetError::UnsupportedFormat`
+    pub fn texture_variant(gl: &WebGLRenderingContext) -> &'static str {
+        let variants = [
+            (CompressedFormat::Astc(4, 4), "astc"),
+            (CompressedFormat::Bc7, "bc7"),
+            (CompressedFormat::Etc2Rgba, "etc2"),
+            (CompressedFormat::Dxt5, "dxt"),
+        ];
+
+        variants
+            .iter()
+            .find(|&&(format, _)| format.is_supported(gl))
+            .map_or("dxt", |&(_, name)| name)
+    }
+
+    pub(crate) fn to_gl(&self) -> TextureCompression {
+        match *self {
+            CompressedFormat::Dxt1 => TextureCompression::RgbDxt1,
+            CompressedFormat::Dxt1Alpha => TextureCompression::RgbaDxt1,
+            CompressedFormat::Dxt5 => TextureCompression::RgbaDxt5,
+            CompressedFormat::Bc4 => TextureCompression::RedRgtc1,
+            CompressedFormat::Bc5 => TextureCompression::RedGreenRgtc2,
+            CompressedFormat::Bc7 => TextureCompression::RgbaBptcUnorm,
+            CompressedFormat::Etc2Rgb => TextureCompression::Rgb8Etc2,
+            CompressedFormat::Etc2RgbA1 => TextureCompression::Rgb8PunchthroughAlpha1Etc2,
+            CompressedFormat::Etc2Rgba => TextureCompression::Rgba8Etc2Eac,
+            CompressedFormat::Astc(w, h) => TextureCompression::RgbaAstc(w, h),
+        }
+    }
+
+    /// Decompress a level to RGBA8, `None` for ASTC which has no CPU decoder,
+    /// or if `data` is shorter than `level_size`
+    pub fn decompress(&self, width: u32, height: u32, data: &[u8]) -> Option<Vec<u8>> {
+        let decode_block: fn(&[u8], &mut [[u8; 4]; 16]) = match *self {
+            CompressedFormat::Dxt1 => decode_bc1_opaque,
+            CompressedFormat::Dxt1Alpha => decode_bc1,
+            CompressedFormat::Dxt5 => decode_bc3,
+            CompressedFormat::Bc4 => decode_bc4_block,
+            CompressedFormat::Bc5 => decode_bc5_block,
+            CompressedFormat::Bc7 => decode_bc7,
+            CompressedFormat::Etc2Rgb => decode_etc2_rgb,
+            CompressedFormat::Etc2RgbA1 => decode_etc2_rgb_a1,
+            CompressedFormat::Etc2Rgba => decode_etc2_rgba,
+            CompressedFormat::Astc(..) => return None,
+        };
+
+        let block_bytes = self.block_bytes() as usize;
+        let blocks_x = ((width + 3) / 4) as usize;
+        let blocks_y = ((height + 3) / 4) as usize;
+
+        if data.len() < blocks_x * blocks_y * block_bytes {
+            return None;
+        }
+
+        let mut rgba = vec![0u8; (width * height * 4) as usize];
+        let mut pixels = [[0u8; 4]; 16];
+
+        for by in 0..blocks_y {
+            for bx in 0..blocks_x {
+                let offset = (by * blocks_x + bx) * block_bytes;
+                decode_block(&data[offset..offset + block_bytes], &mut pixels);
+
+                for y in 0..4 {
+                    for x in 0..4 {
+                        let (px, py) = (bx * 4 + x, by * 4 + y);
+                        if px >= width as usize || py >= height as usize {
+                            continue;
+                        }
+
+                        let dst = (py * width as usize + px) * 4;
+                        rgba[dst..dst + 4].copy_from_slice(&pixels[y * 4 + x]);
+                    }
+                }
+            }
+        }
+
+        Some(rgba)
+    }
+}
+
+fn u16_le(b: &[u8]) -> u16 {
+    b[0] as u16 | (b[1] as u16) << 8
+}
+
+fn u64_le(b: &[u8]) -> u64 {
+    (0..8).fold(0, |v, i| v | (b[i] as u64) << (i * 8))
+}
+
+fn u64_be(b: &[u8]) -> u64 {
+    (0..8).fold(0, |v, i| v << 8 | b[i] as u64)
+}
+
+fn rgb565(c: u16) -> [i32; 3] {
+    let r = ((c >> 11) & 0x1f) as i32;
+    let g = ((c >> 5) & 0x3f) as i32;
+    let b = (c & 0x1f) as i32;
+    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+}
+
+/// Pixels are in row major order, `force_4` is used by the color block of BC3
+fn decode_bc1_color(block: &[u8], out: &mut [[u8; 4]; 16], alpha: bool, force_4: bool) {
+    let c0 = u16_le(&block[0..]);
+    let c1 = u16_le(&block[2..]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+
+    let mut colors = [[0u8; 4]; 4];
+    for i in 0..3 {
+        colors[0][i] = a[i] as u8;
+        colors[1][i] = b[i] as u8;
+
+        if c0 > c1 || force_4 {
+            colors[2][i] = ((2 * a[i] + b[i]) / 3) as u8;
+            colors[3][i] = ((a[i] + 2 * b[i]) / 3) as u8;
+        } else {
+            colors[2][i] = ((a[i] + b[i]) / 2) as u8;
+            colors[3][i] = 0;
+        }
+    }
+
+    colors[0][3] = 255;
+    colors[1][3] = 255;
+    colors[2][3] = 255;
+    colors[3][3] = if c0 <= c1 && !force_4 && alpha { 0 } else { 255 };
+
+    let indices = u64_le(&[block[4], block[5], block[6], block[7], 0, 0, 0, 0]);
+    for i in 0..16 {
+        out[i] = colors[((indices >> (i * 2)) & 0x3) as usize];
+    }
+}
+
+fn decode_bc1(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_bc1_color(block, out, true, false);
+}
+
+fn decode_bc1_opaque(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_bc1_color(block, out, false, false);
+}
+
+/// Decode a BC4 channel block (also the alpha block of BC3)
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    let (r0, r1) = (block[0] as i32, block[1] as i32);
+
+    let mut values = [0i32; 8];
+    values[0] = r0;
+    values[1] = r1;
+
+    if r0 > r1 {
+        for i in 1..7 {
+            values[i + 1] = ((7 - i as i32) * r0 + i as i32 * r1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            values[i + 1] = ((5 - i as i32) * r0 + i as i32 * r1) / 5;
+        }
+        values[6] = 0;
+        values[7] = 255;
+    }
+
+    let bits = u64_le(&[
+        block[2], block[3], block[4], block[5], block[6], block[7], 0, 0
+    ]);
+
+    let mut out = [0u8; 16];
+    for i in 0..16 {
+        out[i] = values[((bits >> (i * 3)) & 0x7) as usize] as u8;
+    }
+    out
+}
+
+fn decode_bc3(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    let alpha = decode_bc4(&block[0..8]);
+    decode_bc1_color(&block[8..16], out, false, true);
+
+    for i in 0..16 {
+        out[i][3] = alpha[i];
+    }
+}
+
+fn decode_bc4_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    let r = decode_bc4(block);
+
+    for i in 0..16 {
+        out[i] = [r[i], r[i], r[i], 255];
+    }
+}
+
+fn decode_bc5_block(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    let r = decode_bc4(&block[0..8]);
+    let g = decode_bc4(&block[8..16]);
+
+    for i in 0..16 {
+        out[i] = [r[i], g[i], 0, 255];
+    }
+}
+
+const ETC1_MODIFIERS: [[i32; 2]; 8] = [
+    [2, 8],
+    [5, 17],
+    [9, 29],
+    [13, 42],
+    [18, 60],
+    [24, 80],
+    [33, 106],
+    [47, 183],
+];
+
+const ETC2_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+fn clamp8(v: i32) -> u8 {
+    v.max(0).min(255) as u8
+}
+
+fn bits(v: u64, hi: u32, lo: u32) -> i32 {
+    ((v >> lo) & ((1 << (hi - lo + 1)) - 1)) as i32
+}
+
+fn ext4(v: i32) -> i32 {
+    v * 17
+}
+
+fn ext5(v: i32) -> i32 {
+    (v << 3) | (v >> 2)
+}
+
+fn decode_etc2_rgb(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_etc2(block, out, false);
+}
+
+fn decode_etc2_rgb_a1(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_etc2(block, out, true);
+}
+
+/// ETC2 RGB block, the pixel indices are in column major order.
+/// With `punchthrough` alpha, bit 33 is the opaque flag instead of the differential flag
+/// and pixels with index 2 of a non opaque block are transparent black
+fn decode_etc2(block: &[u8], out: &mut [[u8; 4]; 16], punchthrough: bool) {
+    let v = u64_be(block);
+
+    // index of pixel (x, y) is x * 4 + y
+    let index = |x: usize, y: usize| {
+        let i = (x * 4 + y) as u32;
+        (bits(v, i + 16, i + 16) << 1) | bits(v, i, i)
+    };
+
+    let mut write = |f: &Fn(usize, usize) -> Option<[i32; 3]>| {
+        for y in 0..4 {
+            for x in 0..4 {
+                out[y * 4 + x] = match f(x, y) {
+                    Some(c) => [clamp8(c[0]), clamp8(c[1]), clamp8(c[2]), 255],
+                    None => [0; 4],
+                };
+            }
+        }
+    };
+
+    // The individual mode is not available with punchthrough alpha
+    let diff = punchthrough || bits(v, 33, 33) == 1;
+    let transparent = punchthrough && bits(v, 33, 33) == 0;
+    let paint_at = |paint: &[[i32; 3]; 4], x: usize, y: usize| match index(x, y) {
+        2 if transparent => None,
+        i => Some(paint[i as usize]),
+    };
+
+    let (base0, base1) = if !diff {
+        (
+            [ext4(bits(v, 63, 60)), ext4(bits(v, 55, 52)), ext4(bits(v, 47, 44))],
+            [ext4(bits(v, 59, 56)), ext4(bits(v, 51, 48)), ext4(bits(v, 43, 40))],
+        )
+    } else {
+        let r = bits(v, 63, 59);
+        let g = bits(v, 55, 51);
+        let b = bits(v, 47, 43);
+        let signed3 = |d: i32| if d >= 4 { d - 8 } else { d };
+
+        let r2 = r + signed3(bits(v, 58, 56));
+        let g2 = g + signed3(bits(v, 50, 48));
+        let b2 = b + signed3(bits(v, 42, 40));
+
+        if r2 < 0 || r2 > 31 {
+            // T mode
+            let c0 = [
+                ext4((bits(v, 60, 59) << 2) | bits(v, 57, 56)),
+                ext4(bits(v, 55, 52)),
+                ext4(bits(v, 51, 48)),
+            ];
+            let c1 = [
+                ext4(bits(v, 47, 44)),
+                ext4(bits(v, 43, 40)),
+                ext4(bits(v, 39, 36)),
+            ];
+            let d = ETC2_DISTANCES[((bits(v, 35, 34) << 1) | bits(v, 32, 32)) as usize];
+
+            let paint = [
+                c0,
+                [c1[0] + d, c1[1] + d, c1[2] + d],
+                c1,
+                [c1[0] - d, c1[1] - d, c1[2] - d],
+            ];
+            write(&|x, y| paint_at(&paint, x, y));
+            return;
+        }
+
+        if g2 < 0 || g2 > 31 {
+            // H mode
+            let r0 = bits(v, 62, 59);
+            let g0 = (bits(v, 58, 56) << 1) | bits(v, 52, 52);
+            let b0 = (bits(v, 51, 51) << 3) | bits(v, 49, 47);
+            let r1 = bits(v, 46, 43);
+            let g1 = bits(v, 42, 39);
+            let b1 = bits(v, 38, 35);
+
+            let order = ((r0 << 8) | (g0 << 4) | b0) >= ((r1 << 8) | (g1 << 4) | b1);
+            let di = (bits(v, 34, 34) << 2) | (bits(v, 32, 32) << 1) | order as i32;
+            let d = ETC2_DISTANCES[di as usize];
+
+            let c0 = [ext4(r0), ext4(g0), ext4(b0)];
+            let c1 = [ext4(r1), ext4(g1), ext4(b1)];
+            let paint = [
+                [c0[0] + d, c0[1] + d, c0[2] + d],
+                [c0[0] - d, c0[1] - d, c0[2] - d],
+                [c1[0] + d, c1[1] + d, c1[2] + d],
+                [c1[0] - d, c1[1] - d, c1[2] - d],
+            ];
+            write(&|x, y| paint_at(&paint, x, y));
+            return;
+        }
+
+        if b2 < 0 || b2 > 31 {
+            // Planar mode
+            let ext6 = |c: i32| (c << 2) | (c >> 4);
+            let ext7 = |c: i32| (c << 1) | (c >> 6);
+
+            let o = [
+                ext6(bits(v, 62, 57)),
+                ext7((bits(v, 56, 56) << 6) | bits(v, 54, 49)),
+                ext6((bits(v, 48, 48) << 5) | (bits(v, 44, 43) << 3) | bits(v, 41, 39)),
+            ];
+            let h = [
+                ext6((bits(v, 38, 34) << 1) | bits(v, 32, 32)),
+                ext7(bits(v, 31, 25)),
+                ext6(bits(v, 24, 19)),
+            ];
+            let vv = [
+                ext6(bits(v, 18, 13)),
+                ext7(bits(v, 12, 6)),
+                ext6(bits(v, 5, 0)),
+            ];
+
+            write(&|x, y| {
+                let (x, y) = (x as i32, y as i32);
+                let c = |i: usize| (x * (h[i] - o[i]) + y * (vv[i] - o[i]) + 4 * o[i] + 2) >> 2;
+                Some([c(0), c(1), c(2)])
+            });
+            return;
+        }
+
+        (
+            [ext5(r), ext5(g), ext5(b)],
+            [ext5(r2), ext5(g2), ext5(b2)],
+        )
+    };
+
+    let tables = [bits(v, 39, 37) as usize, bits(v, 36, 34) as usize];
+    let flip = bits(v, 32, 32) == 1;
+
+    write(&|x, y| {
+        let sub = if flip { (y >= 2) as usize } else { (x >= 2) as usize };
+        let base = if sub == 0 { base0 } else { base1 };
+        let m = ETC1_MODIFIERS[tables[sub]];
+        let d = match (index(x, y), transparent) {
+            (0, true) => 0,
+            (2, true) => return None,
+            (0, false) => m[0],
+            (1, _) => m[1],
+            (2, false) => -m[0],
+            _ => -m[1],
+        };
+        Some([base[0] + d, base[1] + d, base[2] + d])
+    });
+}
+
+fn decode_etc2_rgba(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    decode_etc2_rgb(&block[8..16], out);
+
+    // EAC alpha block
+    let v = u64_be(&block[0..8]);
+    let base = bits(v, 63, 56);
+    let multiplier = bits(v, 55, 52);
+    let table = EAC_MODIFIERS[bits(v, 51, 48) as usize];
+
+    for x in 0..4 {
+        for y in 0..4 {
+            let i = (x * 4 + y) as u32;
+            let idx = bits(v, 47 - i * 3, 45 - i * 3) as usize;
+            out[y * 4 + x][3] = clamp8(base + table[idx] * multiplier);
+        }
+    }
+}
+
+/// Subsets, partition bits, rotation bits, index selection bits, color bits, alpha bits,
+/// endpoint p-bits, shared p-bits, index bits and secondary index bits of the BC7 modes
+const BC7_MODES: [[u32; 10]; 8] = [
+    [3, 4, 0, 0, 4, 0, 1, 0, 3, 0],
+    [2, 6, 0, 0, 6, 0, 0, 1, 3, 0],
+    [3, 6, 0, 0, 5, 0, 0, 0, 2, 0],
+    [2, 6, 0, 0, 7, 0, 1, 0, 2, 0],
+    [1, 0, 2, 1, 5, 6, 0, 0, 2, 3],
+    [1, 0, 2, 0, 7, 8, 0, 0, 2, 2],
+    [1, 0, 0, 0, 7, 7, 1, 0, 4, 0],
+    [2, 6, 0, 0, 5, 5, 1, 0, 2, 0],
+];
+
+const BC7_WEIGHTS_2: [i32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [i32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [i32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Two subset partitions, bit i is the subset of pixel i
+const BC7_PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80,
+    0xc800, 0xffec, 0xfe80, 0xe800, 0xffe8, 0xff00, 0xfff0, 0xf000,
+    0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c,
+    0xaaaa, 0xf0f0, 0x5a5a, 0x33cc, 0x3c3c, 0x55aa, 0x9696, 0xa55a,
+    0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c,
+    0x9336, 0x9cc6, 0x817e, 0xe718, 0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+const BC7_PARTITIONS_3: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2],
+    [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0],
+    [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2],
+    [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0],
+    [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0],
+    [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1],
+    [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1],
+    [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2],
+    [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2],
+    [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1],
+    [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+/// Anchor pixel of the second subset of the two subset partitions
+const BC7_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6,
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// Anchor pixels of the second and third subsets of the three subset partitions
+const BC7_ANCHORS_3: [[u8; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3,
+        3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15,
+        8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15,
+        3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8,
+        15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8,
+        15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8,
+        15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+/// Little endian bit stream of a block, read from the lowest bit
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read(&mut self, n: u32) -> i32 {
+        let mut v = 0;
+        for i in 0..n {
+            let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+            v |= (bit as i32) << i;
+            self.pos += 1;
+        }
+        v
+    }
+}
+
+fn bc7_subset(subsets: u32, partition: usize, pixel: usize) -> usize {
+    match subsets {
+        2 => ((BC7_PARTITIONS_2[partition] >> pixel) & 1) as usize,
+        3 => BC7_PARTITIONS_3[partition][pixel] as usize,
+        _ => 0,
+    }
+}
+
+/// Anchor pixels store their index without its highest bit, which is always 0
+fn bc7_is_anchor(subsets: u32, partition: usize, pixel: usize) -> bool {
+    pixel == 0 || match subsets {
+        2 => BC7_ANCHORS_2[partition] as usize == pixel,
+        3 => {
+            BC7_ANCHORS_3[0][partition] as usize == pixel
+                || BC7_ANCHORS_3[1][partition] as usize == pixel
+        }
+        _ => false,
+    }
+}
+
+fn bc7_interpolate(e0: i32, e1: i32, index: i32, bits: u32) -> u8 {
+    let w = match bits {
+        2 => BC7_WEIGHTS_2[index as usize],
+        3 => BC7_WEIGHTS_3[index as usize],
+        _ => BC7_WEIGHTS_4[index as usize],
+    };
+    (((64 - w) * e0 + w * e1 + 32) >> 6) as u8
+}
+
+fn decode_bc7(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    let mode = match (0..8).find(|&m| block[0] & (1 << m) != 0) {
+        Some(mode) => mode,
+        None => {
+            // Reserved mode, decoded as transparent black
+            *out = [[0; 4]; 16];
+            return;
+        }
+    };
+
+    let m = BC7_MODES[mode];
+    let (subsets, color_bits, alpha_bits, index_bits, index2_bits) = (m[0], m[4], m[5], m[8], m[9]);
+
+    let mut r = BitReader {
+        data: block,
+        pos: mode + 1,
+    };
+    let partition = r.read(m[1]) as usize;
+    let rotation = r.read(m[2]);
+    let index_selection = r.read(m[3]);
+
+    // Endpoints of each subset, channel by channel
+    let endpoints = (subsets * 2) as usize;
+    let mut e = [[0i32; 4]; 6];
+    for c in 0..3 {
+        for i in 0..endpoints {
+            e[i][c] = r.read(color_bits);
+        }
+    }
+    for i in 0..endpoints {
+        e[i][3] = r.read(alpha_bits);
+    }
+
+    let mut bits = [color_bits, color_bits, color_bits, alpha_bits];
+    if m[6] != 0 || m[7] != 0 {
+        // Unique p-bit per endpoint, or shared by the endpoints of a subset
+        let mut p = [0i32; 6];
+        for i in 0..endpoints {
+            p[i] = if m[6] != 0 || i % 2 == 0 { r.read(1) } else { p[i - 1] };
+        }
+
+        for i in 0..endpoints {
+            for c in 0..4 {
+                e[i][c] = (e[i][c] << 1) | p[i];
+            }
+        }
+
+        for b in bits.iter_mut() {
+            *b += 1;
+        }
+    }
+
+    for i in 0..endpoints {
+        for c in 0..4 {
+            e[i][c] = if alpha_bits == 0 && c == 3 {
+                255
+            } else {
+                let v = e[i][c] << (8 - bits[c]);
+                v | (v >> bits[c])
+            };
+        }
+    }
+
+    let mut indices = [0i32; 16];
+    for i in 0..16 {
+        let anchor = bc7_is_anchor(subsets, partition, i);
+        indices[i] = r.read(index_bits - anchor as u32);
+    }
+
+    let mut indices2 = [0i32; 16];
+    if index2_bits > 0 {
+        for i in 0..16 {
+            indices2[i] = r.read(index2_bits - (i == 0) as u32);
+        }
+    }
+
+    for i in 0..16 {
+        let s = bc7_subset(subsets, partition, i);
+        let (e0, e1) = (e[s * 2], e[s * 2 + 1]);
+
+        // Modes 4 and 5 have separate color and alpha indices, swapped by the index selection
+        let (color, alpha) = if index2_bits == 0 {
+            ((indices[i], index_bits), (indices[i], index_bits))
+        } else if index_selection == 0 {
+            ((indices[i], index_bits), (indices2[i], index2_bits))
+        } else {
+            ((indices2[i], index2_bits), (indices[i], index_bits))
+        };
+
+        let mut px = [0u8; 4];
+        for c in 0..3 {
+            px[c] = bc7_interpolate(e0[c], e1[c], color.0, color.1);
+        }
+        px[3] = bc7_interpolate(e0[3], e1[3], alpha.0, alpha.1);
+
+        match rotation {
+            1 => px.swap(0, 3),
+            2 => px.swap(1, 3),
+            3 => px.swap(2, 3),
+            _ => (),
+        }
+
+        out[i] = px;
+    }
+}
@@ -0,0 +1,183 @@
+use math::{Matrix4, Vector2, Vector3, Vector4};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use uni_gl::{BufferKind, DrawMode, WebGLBuffer, WebGLRenderingContext};
+
+/// Binding point reserved for the per-frame uniform block
+pub const FRAME_BLOCK_BINDING: u32 = 0;
+
+/// Name of the per-frame uniform block, see "unrust/frame_data.glsl"
+pub const FRAME_BLOCK_NAME: &'static str = "FrameData";
+
+/// Binding point reserved for the uniform block packed from the params of a material
+pub const MATERIAL_BLOCK_BINDING: u32 = 1;
+
+/// Name of the uniform block packed from the params of a material
+pub const MATERIAL_BLOCK_NAME: &'static str = "MaterialData";
+
+/// Serialize values following the std140 layout rules
+///
+/// Reference: OpenGL 4.5 spec, 7.6.2.2 Standard Uniform Block Layout
+#[derive(Default, Debug)]
+pub struct Std140Writer {
+    data: Vec<u8>,
+}
+
+impl Std140Writer {
+    pub fn new() -> Std140Writer {
+        Std140Writer::default()
+    }
+
+    fn align(&mut self, n: usize) {
+        while self.data.len() % n != 0 {
+            self.data.push(0);
+        }
+    }
+
+    fn push_f32s(&mut self, values: &[f32]) {
+        for v in values.iter() {
+            let bits = v.to_bits();
+            self.data.extend_from_slice(&[
+                bits as u8,
+                (bits >> 8) as u8,
+                (bits >> 16) as u8,
+                (bits >> 24) as u8,
+            ]);
+        }
+    }
+
+    pub fn write_f32(&mut self, v: f32) -> &mut Self {
+        self.align(4);
+        self.push_f32s(&[v]);
+        self
+    }
+
+    pub fn write_i32(&mut self, v: i32) -> &mut Self {
+        self.align(4);
+        self.push_f32s(&[f32::from_bits(v as u32)]);
+        self
+    }
+
+    pub fn write_bool(&mut self, v: bool) -> &mut Self {
+        self.write_i32(v as i32)
+    }
+
+    pub fn write_vec2(&mut self, v: Vector2<f32>) -> &mut Self {
+        self.align(8);
+        self.push_f32s(&[v.x, v.y]);
+        self
+    }
+
+    pub fn write_vec3(&mut self, v: Vector3<f32>) -> &mut Self {
+        self.align(16);
+        self.push_f32s(&[v.x, v.y, v.z]);
+        self
+    }
+
+    pub fn write_vec4(&mut self, v: Vector4<f32>) -> &mut Self {
+        self.align(16);
+        self.push_f32s(&[v.x, v.y, v.z, v.w]);
+        self
+    }
+
+    pub fn write_mat4(&mut self, m: Matrix4<f32>) -> &mut Self {
+        let m: &[f32; 16] = m.as_ref();
+
+        self.align(16);
+        self.push_f32s(m);
+        self
+    }
+
+    /// Structs and array elements start and end at 16 bytes boundaries
+    pub fn write_struct<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Std140Writer),
+    {
+        self.align(16);
+        f(self);
+        self.align(16);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        self.align(16);
+        self.data
+    }
+}
+
+/// A uniform buffer object shared by all programs which declare a block with the same name
+///
+/// Only available on WebGL2 / GL3, programs should fall back to plain uniforms otherwise.
+pub struct UniformBuffer {
+    name: &'static str,
+    binding: u32,
+
+    data: RefCell<Vec<u8>>,
+    dirty: Cell<bool>,
+    gl_state: RefCell<Option<WebGLBuffer>>,
+}
+
+impl fmt::Debug for UniformBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UniformBuffer")
+            .field("name", &self.name)
+            .field("binding", &self.binding)
+            .field("size", &self.data.borrow().len())
+            .finish()
+    }
+}
+
+impl UniformBuffer {
+    pub fn new(name: &'static str, binding: u32) -> UniformBuffer {
+        UniformBuffer {
+            name,
+            binding,
+            data: RefCell::new(Vec::new()),
+            dirty: Cell::new(true),
+            gl_state: RefCell::new(None),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn binding(&self) -> u32 {
+        self.binding
+    }
+
+    /// Replace the content of the buffer, it will be uploaded in next bind
+    pub fn update(&self, writer: Std140Writer) {
+        let bytes = writer.into_bytes();
+
+        if *self.data.borrow() != bytes {
+            self.data.replace(bytes);
+            self.dirty.set(true);
+        }
+    }
+
+    pub fn bind(&self, gl: &WebGLRenderingContext) {
+        let mut state = self.gl_state.borrow_mut();
+
+        if state.is_none() {
+            *state = Some(gl.create_buffer());
+            self.dirty.set(true);
+        }
+
+        let buffer = state.as_ref().unwrap();
+
+        if self.dirty.get() {
+            gl.bind_buffer(BufferKind::Uniform, buffer);
+            gl.buffer_data(BufferKind::Uniform, &self.data.borrow(), DrawMode::Dynamic);
+            gl.unbind_buffer(BufferKind::Uniform);
+
+            self.dirty.set(false);
+        }
+
+        gl.bind_buffer_base(BufferKind::Uniform, self.binding, buffer);
+    }
+}
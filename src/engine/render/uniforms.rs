@@ -139,6 +139,13 @@ impl UniformCache {
         }
     }
 
+    /// Forget all values and locations, e.g. when the program is linked again
+    pub fn clear(&self) {
+        self.uniform_entries.borrow_mut().clear();
+        self.pending_entries.borrow_mut().clear();
+        self.uniform_map.borrow_mut().clear();
+    }
+
     pub fn commit(&self, gl: &WebGLRenderingContext, prog: &WebGLProgram) {
         {
             let mut pending = self.pending_entries.borrow_mut();
@@ -24,6 +24,9 @@ extern crate unrust_derive;
 #[macro_use]
 extern crate bitflags;
 
+#[macro_use]
+extern crate lazy_static;
+
 #[cfg(feature = "flame_it")]
 extern crate flame;
 
@@ -7,10 +7,34 @@ use std::collections::BTreeSet;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::cell::Cell;
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::SystemTime;
+#[cfg(not(target_arch = "wasm32"))]
+use uni_app;
+
+/// Seconds between two checks of the modification time of opened files
+#[cfg(not(target_arch = "wasm32"))]
+const WATCH_INTERVAL: f64 = 0.5;
+
 // unrust engine support different file system.
 #[derive(Default)]
 pub struct AppFileSystem {
     loading_files: Rc<RefCell<BTreeSet<String>>>,
+
+    /// Opened files with their path and modification time
+    #[cfg(not(target_arch = "wasm32"))]
+    watched_files: RefCell<HashMap<String, (String, Option<SystemTime>)>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    last_watch: Cell<f64>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn modified_time(path: &str) -> Option<SystemTime> {
+    ::std::fs::metadata(path).and_then(|m| m.modified()).ok()
 }
 
 pub struct AppFile(String, fs::File, Rc<RefCell<BTreeSet<String>>>);
@@ -34,6 +58,19 @@ impl Future for AppFileReader {
     }
 }
 
+impl AppFileSystem {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn watch(&self, filename: &str, path: &str) {
+        self.watched_files.borrow_mut().insert(
+            filename.to_string(),
+            (path.to_string(), modified_time(path)),
+        );
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn watch(&self, _filename: &str, _path: &str) {}
+}
+
 impl FileSystem for AppFileSystem {
     type File = AppFile;
 
@@ -52,6 +89,7 @@ impl FileSystem for AppFileSystem {
         match f {
             Err(e) => Box::new(future::err(e)),
             Ok(file) => {
+                self.watch(filename, &abs_filename);
                 self.loading_files.borrow_mut().insert(filename.to_string());
                 Box::new(AppFileReader(Some(AppFile(
                     filename.into(),
@@ -69,6 +107,29 @@ impl FileSystem for AppFileSystem {
             .map(|s| s.clone())
             .collect()
     }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn modified_files(&self) -> Vec<String> {
+        let now = uni_app::now();
+        if now - self.last_watch.get() < WATCH_INTERVAL {
+            return Vec::new();
+        }
+        self.last_watch.set(now);
+
+        let mut modified = Vec::new();
+
+        let mut watched = self.watched_files.borrow_mut();
+
+        for (filename, entry) in watched.iter_mut() {
+            let new_time = modified_time(&entry.0);
+            if new_time.is_some() && new_time != entry.1 {
+                entry.1 = new_time;
+                modified.push(filename.clone());
+            }
+        }
+
+        modified
+    }
 }
 
 impl File for AppFile {
@@ -4,7 +4,7 @@ use std::rc::Rc;
 use std::sync::Arc;
 
 use engine::{
-    AssetSystem, Camera, ClearOption, Component, ComponentBased, ComponentType, Engine, GameObject,
+    AssetSystem, Camera, Component, ComponentBased, ComponentType, Engine, GameObject,
     IEngine, SceneTree,
 };
 use world::app_fs::AppEngine;
@@ -349,7 +349,7 @@ impl<'a> World {
 
     #[cfg_attr(feature = "flame_it", flame)]
     fn render(&mut self) {
-        self.engine.render(ClearOption::default());
+        self.engine.render_cameras();
     }
 
     pub fn run_frame<'b: 'a>(&mut self, app: *mut App) {
@@ -5,10 +5,28 @@
 #define texture2D texture
 out vec4 FragColor;
 
+// Keywords:
+// NORMAL_MAP: shade in tangent space with uMaterial.normal_map, the mesh needs tangents
+// ALPHA_TEST: discard the fragments with an alpha under uAlphaCutoff
+// SHADOWS: receive the shadows rendered by ShadowPass
+
 #define UNI_POINT_LIGHTS 4
 
-#include "unrust/phong_light.glsl"
+#include "unrust/frame_data.glsl"
+#include "unrust/ssao_utils.glsl"
+#include "unrust/fog.glsl"
+
+#ifdef SHADOWS
 #include "unrust/shadow_utils.glsl"
+#else
+float ShadowCalculation(vec3 worldPos, vec3 worldNormal, vec3 normal, vec3 lightDir) {
+    return 1.0;
+}
+
+float LocalShadowCalculation(int light, vec3 lightPos, vec3 worldPos, vec3 worldNormal) {
+    return 1.0;
+}
+#endif
 
 struct Material {
     vec3 ambient;    
@@ -21,8 +39,11 @@ struct Material {
     float shininess;
     float transparent;
     sampler2D mask_tex;
-};
 
+#ifdef NORMAL_MAP
+    sampler2D normal_map;
+#endif
+};
 
 struct MaterialColor {
     vec3 ambient;
@@ -30,25 +51,68 @@ struct MaterialColor {
     vec3 specular;
 };
 
-
-uniform vec3 uViewPos;
 uniform Material uMaterial;
 
-varying vec3 vFragPos;
-varying vec2 vTexCoords;       
-varying vec3 vNormal; 
-                      
+#ifdef ALPHA_TEST
+uniform float uAlphaCutoff;
+#endif
 
-// Lights
-uniform DirectionalLight uDirectionalLight;
-uniform PointLight uPointLights[UNI_POINT_LIGHTS];
+varying vec3 vFragPos;
+varying vec3 vNormal;
+varying vec3 vWorldNormal;
+varying vec2 vTexCoords;
+
+#ifdef NORMAL_MAP
+varying vec3 vDirectionalLightDirTgt;
+varying vec3 vPointLightPointsTgt[UNI_POINT_LIGHTS];
+varying vec3 vViewDirTgt;
+varying vec3 vFragPosTgt;
+
+// Shade with the vertex normals instead
+uniform bool uNoNormalMap;
+
+/* 
+    X: -1 to +1 :  Red: 0 to 255
+    Y: -1 to +1 :  Green: 0 to 255
+  Z: 0 to -1 :  Blue: 128 to 255
+  */
+
+vec3 decode_normalmap(vec3 n) {    
+    return vec3( (n.xy * 2.0 - vec2(1.0, 1.0)),  n.z);
+}
+#endif
 
-vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir, MaterialColor color);
-vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir, MaterialColor color);
+// Lights are shaded in tangent space with NORMAL_MAP, and in world space otherwise
+vec3 CalcDirectionalLight(DirectionalLight light, vec3 lightDir, vec3 normal, vec3 viewDir, MaterialColor color, float lit);
+vec3 CalcPointLight(PointLight light, vec3 lightDir, vec3 normal, vec3 fragPos, vec3 viewDir, MaterialColor color, float shadow);
 
 void main(void) {
+    float alpha = uMaterial.transparent * texture2D(uMaterial.mask_tex, vTexCoords).r;
+
+#ifdef ALPHA_TEST
+    if (alpha < uAlphaCutoff) {
+        discard;
+    }
+#endif
+
+    vec3 worldNormal = normalize(vWorldNormal);
+
+#ifdef NORMAL_MAP
     vec3 norm = normalize(vNormal);
+
+    if(!uNoNormalMap) 
+    {
+        norm = texture2D(uMaterial.normal_map, vTexCoords ).rgb;
+        norm = normalize(decode_normalmap(norm));        
+    }
+
+    vec3 viewDir = normalize(vViewDirTgt);
+    vec3 dirLightDir = vDirectionalLightDirTgt;
+#else
+    vec3 norm = worldNormal;
     vec3 viewDir = normalize(uViewPos - vFragPos);
+    vec3 dirLightDir = uDirectionalLight.direction;
+#endif
 
     // Presample the color
     MaterialColor color;
@@ -59,24 +123,32 @@ void main(void) {
     color.specular = uMaterial.specular * vec3(texture2D(uMaterial.specular_tex, vTexCoords));
 
     // Directional Light
-    vec3 result = CalcDirectionalLight(uDirectionalLight, norm, viewDir, color);
+    vec3 result = CalcDirectionalLight(uDirectionalLight, dirLightDir, norm, viewDir, color, LightFactor(0));
     
     // Point Lights
-    for(int i = 0; i < UNI_POINT_LIGHTS; i++)
-        result += CalcPointLight(uPointLights[i], norm, vFragPos, viewDir, color);
+    for(int i = 0; i < UNI_POINT_LIGHTS; i++) {
+#ifdef NORMAL_MAP
+        vec3 lightDir = vPointLightPointsTgt[i] - vFragPosTgt;
+#else
+        vec3 lightDir = uPointLights[i].position - vFragPos;
+#endif
+        result += CalcPointLight(uPointLights[i], lightDir, norm, vFragPos, viewDir, color,
+            LocalShadowCalculation(i, uPointLights[i].position, vFragPos, worldNormal) * LightFactor(i + 1));
+    }
+
+    result = ApplyFog(result, uViewPos, vFragPos);
 
     // float gamma = 2.2;    
     // gl_FragColor = vec4(pow(result, vec3(1.0/gamma)), uMaterial.transparent);           
-    gl_FragColor = vec4(result, uMaterial.transparent * texture2D(uMaterial.mask_tex, vTexCoords).r );           
-    //gl_FragColor = vec4(0.0, 0.0, 1.0, 1.0);
+    gl_FragColor = vec4(result, alpha);
 }
 
-vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir, MaterialColor color)
+vec3 CalcDirectionalLight(DirectionalLight light, vec3 lightDir, vec3 normal, vec3 viewDir, MaterialColor color, float lit)
 {
     // Ambient
-    vec3 ambient = light.ambient * color.ambient;
+    vec3 ambient = light.ambient * color.ambient * AmbientOcclusion();
 
-    vec3 lightDir = normalize(-light.direction);  
+    lightDir = normalize(-lightDir);
     float diff = max(dot(normal, lightDir), 0.0);
     vec3 diffuse = light.diffuse * diff * color.diffuse;
 
@@ -85,15 +157,15 @@ vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir, Mat
     vec3 halfwayDir = normalize(lightDir + viewDir);  
     float spec = pow(max(dot(normal, halfwayDir), 0.0), uMaterial.shininess);
     
-    vec3 specular = light.specular * spec *  color.specular;
-    float shadow = ShadowCalculation(vFragPos, normal, normal, lightDir);
+    vec3 specular = light.specular * spec * color.specular;
+    float shadow = ShadowCalculation(vFragPos, normalize(vWorldNormal), normal, lightDir) * lit;
 
     return ambient + (diffuse + specular) * shadow;
 }
 
-vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir, MaterialColor color)
+vec3 CalcPointLight(PointLight light, vec3 lightDir, vec3 normal, vec3 fragPos, vec3 viewDir, MaterialColor color, float shadow)
 {
-    vec3 lightDir = normalize(light.position - fragPos);
+    lightDir = normalize(lightDir);
     
     // diffuse shading
     float diff = max(dot(normal, lightDir), 0.0);
@@ -106,12 +178,12 @@ vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir, M
     // attenuation
     float distance = length(light.position - fragPos);
     float d = (light.constant + light.linear * distance + light.quadratic * (distance * distance));
-    float attenuation = 1.0 / max(d, 0.001);
+    float attenuation = SpotFactor(light, fragPos) / max(d, 0.001);
     
     // combine results
-    vec3 ambient = light.ambient * color.ambient;
+    vec3 ambient = light.ambient * color.ambient * AmbientOcclusion();
     vec3 diffuse = light.diffuse * diff * color.diffuse;
     vec3 specular = light.specular * spec * color.specular;
     
-    return (ambient + diffuse + specular) * attenuation * light.rate;        
+    return (ambient + (diffuse + specular) * shadow) * attenuation * light.rate;        
 }
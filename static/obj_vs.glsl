@@ -1,24 +1,62 @@
 #define USE_GLSL_300ES
 
-//#ifndef GL_ES
 #define attribute in
 #define varying out
-//#endif
+
+// Keywords:
+// NORMAL_MAP: shade in tangent space with uMaterial.normal_map, the mesh needs tangents
+
+#define UNI_POINT_LIGHTS 4
 
 #include "unrust/default_uniforms.glsl"
+#include "unrust/skinning.glsl"
+#include "unrust/morph.glsl"
 
 attribute vec3 aVertexPosition;
 attribute vec3 aVertexNormal;
 attribute vec2 aTextureCoord;
+#ifdef NORMAL_MAP
+attribute vec3 aVertexTangent;
+#endif
 
 varying vec3 vFragPos;
 varying vec3 vNormal;
+varying vec3 vWorldNormal;
 varying vec2 vTexCoords;
 
+#ifdef NORMAL_MAP
+varying vec3 vDirectionalLightDirTgt;
+varying vec3 vPointLightPointsTgt[UNI_POINT_LIGHTS];
+varying vec3 vViewDirTgt;
+varying vec3 vFragPosTgt;
+#endif
+
 void main(void) {
-    vFragPos = vec3(uMMatrix * vec4(aVertexPosition, 1.0));            
-    vNormal = mat3(uNMatrix) * aVertexNormal;
+    mat4 skin = SkinMatrix();
+    vec4 position = skin * vec4(MorphPosition(aVertexPosition), 1.0);
+    vec3 normal = mat3(skin) * aVertexNormal;
+
+    vFragPos = vec3(uMMatrix * position);
+    vWorldNormal = mat3(uNMatrix) * normal;
     vTexCoords = aTextureCoord;
 
-    gl_Position = uPMatrix * uMVMatrix * vec4(aVertexPosition, 1.0);
+#ifdef NORMAL_MAP
+    vec3 T = normalize(mat3(uMMatrix) * mat3(skin) * aVertexTangent);
+    vec3 N = normalize(vWorldNormal);
+    vec3 B = cross(T, N);
+    mat3 TBN = transpose(mat3(T, B, N));
+
+    vNormal = TBN * normal;
+    vViewDirTgt = TBN * normalize(uViewPos - vFragPos);
+    vDirectionalLightDirTgt = TBN * uDirectionalLight.direction;
+
+    for(int i = 0; i < UNI_POINT_LIGHTS; i++) {
+        vPointLightPointsTgt[i] = TBN * uPointLights[i].position;
+    }
+    vFragPosTgt = TBN * vFragPos;
+#else
+    vNormal = vWorldNormal;
+#endif
+
+    gl_Position = uPMatrix * uMVMatrix * position;
 }
@@ -4,6 +4,8 @@
 #endif
 
 #include "unrust/default_uniforms.glsl"
+#include "unrust/skinning.glsl"
+#include "unrust/morph.glsl"
 
 attribute vec3 aVertexPosition;
 attribute vec3 aVertexNormal;
@@ -14,9 +16,12 @@ varying vec3 vNormal;
 varying vec2 vTexCoords;
 
 void main(void) {
-    vFragPos = vec3(uMMatrix * vec4(aVertexPosition, 1.0));            
-    vNormal = mat3(uNMatrix) * aVertexNormal;
+    mat4 skin = SkinMatrix();
+    vec4 position = skin * vec4(MorphPosition(aVertexPosition), 1.0);
+
+    vFragPos = vec3(uMMatrix * position);
+    vNormal = mat3(uNMatrix) * mat3(skin) * aVertexNormal;
     vTexCoords = aTextureCoord;
 
-    gl_Position = uPMatrix * uMVMatrix * vec4(aVertexPosition, 1.0);
+    gl_Position = uPMatrix * uMVMatrix * position;
 }
@@ -1,4 +1,5 @@
+#include "unrust/frame_data.glsl"
+
 uniform mat4 uMVMatrix;
-uniform mat4 uPMatrix;
 uniform mat4 uNMatrix;
 uniform mat4 uMMatrix;
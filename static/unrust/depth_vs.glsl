@@ -0,0 +1,14 @@
+#ifndef GL_ES
+#define attribute in
+#define varying out
+#endif
+
+#include "unrust/default_uniforms.glsl"
+#include "unrust/skinning.glsl"
+#include "unrust/morph.glsl"
+
+attribute vec3 aVertexPosition;
+
+void main(void) {
+    gl_Position = uPMatrix * uMVMatrix * SkinMatrix() * vec4(MorphPosition(aVertexPosition), 1.0);
+}
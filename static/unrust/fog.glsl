@@ -0,0 +1,71 @@
+// Scene-wide distance and height fog, set by the engine from the Fog component
+
+#if (!defined(GL_ES) || defined(USE_GLSL_300ES)) && !defined(textureCube)
+#define textureCube texture
+#endif
+
+// uFog is in the per-frame data
+#include "unrust/frame_data.glsl"
+
+// Bound to a reserved texture unit for the whole pass
+uniform samplerCube uFogSky;
+
+vec3 FogColor(vec3 viewDir)
+{
+    if (uFog.sky) {
+        return textureCube(uFogSky, viewDir).rgb;
+    }
+
+    return uFog.color;
+}
+
+// Average density along the ray from the eye, relative to the density at uFog.height
+float FogHeightFactor(vec3 eye, vec3 fragPos)
+{
+    if (uFog.height_falloff <= 0.0) {
+        return 1.0;
+    }
+
+    float k = uFog.height_falloff * (fragPos.y - eye.y);
+    float average = abs(k) > 0.001 ? (1.0 - exp(-k)) / k : 1.0;
+
+    return exp(-uFog.height_falloff * (eye.y - uFog.height)) * average;
+}
+
+float FogFactor(vec3 eye, vec3 fragPos)
+{
+    float d = length(fragPos - eye) * FogHeightFactor(eye, fragPos);
+
+    if (uFog.mode == 1) {
+        return clamp((d - uFog.start) / max(uFog.end - uFog.start, 0.0001), 0.0, 1.0);
+    } else if (uFog.mode == 2) {
+        return 1.0 - exp(-uFog.density * d);
+    } else if (uFog.mode == 3) {
+        float dd = uFog.density * d;
+        return 1.0 - exp(-dd * dd);
+    }
+
+    return 0.0;
+}
+
+vec3 ApplyFog(vec3 color, vec3 eye, vec3 fragPos)
+{
+    if (uFog.mode == 0) {
+        return color;
+    }
+
+    vec3 viewDir = normalize(fragPos - eye);
+    return mix(color, FogColor(viewDir), FogFactor(eye, fragPos));
+}
+
+// Fog of a skybox in direction dir, at the horizon only
+vec3 ApplySkyFog(vec3 color, vec3 dir)
+{
+    if (uFog.mode == 0) {
+        return color;
+    }
+
+    dir = normalize(dir);
+    float fog = uFog.skybox * (1.0 - smoothstep(0.0, 0.3, dir.y));
+    return mix(color, FogColor(dir), fog);
+}
@@ -0,0 +1,83 @@
+// Per-frame data shared by all programs, set by the engine once for each camera.
+// It is a uniform block on WebGL2 / GL3, and plain uniforms on WebGL1.
+
+#ifndef UNI_FRAME_DATA
+#define UNI_FRAME_DATA
+
+#include "unrust/phong_light.glsl"
+
+#ifndef UNI_POINT_LIGHTS
+#define UNI_POINT_LIGHTS 4
+#endif
+
+#if !defined(GL_ES) || defined(USE_GLSL_300ES)
+#define UNI_FRAME_BLOCK
+#endif
+
+// Scene-wide fog, see "unrust/fog.glsl"
+struct Fog {
+    // 0: disabled, 1: linear, 2: exponential, 3: exponential squared
+    int mode;
+    vec3 color;
+    float start;
+    float end;
+    float density;
+    float height;
+    float height_falloff;
+    float skybox;
+    // Sample uFogSky instead of color
+    bool sky;
+};
+
+#ifdef UNI_FRAME_BLOCK
+layout(std140) uniform FrameData {
+    mat4 uPVMatrix;
+    mat4 uVMatrix;
+    mat4 uPMatrix;
+    vec3 uViewPos;
+    float uTime;
+
+    DirectionalLight uDirectionalLight;
+    PointLight uPointLights[UNI_POINT_LIGHTS];
+
+    Fog uFog;
+};
+
+// Lights culled by the layer of the object, bit 0 for uDirectionalLight,
+// bit (i + 1) for uPointLights[i]
+uniform highp int uLightMask;
+
+bool isLightEnabled(int i) {
+    return (uLightMask & (1 << i)) != 0;
+}
+#else
+uniform mat4 uPVMatrix;
+uniform mat4 uVMatrix;
+uniform mat4 uPMatrix;
+uniform vec3 uViewPos;
+uniform float uTime;
+
+uniform DirectionalLight uDirectionalLight;
+uniform PointLight uPointLights[UNI_POINT_LIGHTS];
+
+uniform Fog uFog;
+
+// Culled lights are bound without diffuse and specular instead
+bool isLightEnabled(int i) {
+    return true;
+}
+#endif
+
+// 0 for the diffuse and specular terms of culled lights, ambient is always kept
+float LightFactor(int i) {
+    return isLightEnabled(i) ? 1.0 : 0.0;
+}
+
+// View matrix without translation, for skyboxes
+mat4 SkyboxViewMatrix() {
+    mat4 v = uVMatrix;
+    v[3] = vec4(0.0, 0.0, 0.0, 1.0);
+    return v;
+}
+
+#endif
@@ -0,0 +1,10 @@
+#define USE_GLSL_300ES
+
+#ifdef GL_ES
+#define varying in
+#define gl_FragColor FragColor
+#define texture2D texture
+out vec4 FragColor;
+#endif
+
+#include "unrust/particle_fs.glsl"
@@ -0,0 +1,135 @@
+#ifndef GL_ES
+#define varying in
+#define gl_FragColor FragColor
+#define texture2D texture
+out vec4 FragColor;
+#endif
+
+// One texel per particle, in two passes over ping-pong float textures:
+// with SIM_VELOCITY it writes (velocity, lifetime), otherwise (position, age).
+// Both passes spawn the same particles, the random numbers only depend on the index and seed.
+
+#define PI 3.14159265
+
+varying vec2 vTexCoords;
+
+uniform sampler2D uPositions;
+uniform sampler2D uVelocities;
+// Velocities written by the velocity pass of this frame
+uniform sampler2D uNewVelocities;
+
+uniform float uSide;
+uniform float uMaxParticles;
+// Particles [start, start + count) modulo uMaxParticles are spawned this frame
+uniform float uEmitStart;
+uniform float uEmitCount;
+// Random in [0, 1) each frame
+uniform float uSeed;
+uniform float uDeltaTime;
+// Kill all particles which are not spawned this frame
+uniform bool uReset;
+
+// 0: point, 1: sphere, 2: cone
+uniform int uShape;
+// sphere: (radius, 0), cone: (cos angle, radius)
+uniform vec2 uShapeParams;
+// (min, max)
+uniform vec2 uSpeed;
+uniform vec2 uLifetime;
+// In the simulation space
+uniform vec3 uGravity;
+uniform float uDrag;
+// From the space of the game object to the simulation space
+uniform mat4 uSpawnMatrix;
+
+// Inputs are kept small, sin loses its precision on large arguments
+float random(float index, float n) {
+    vec2 p = vec2(index / uMaxParticles, uSeed + n * 0.1);
+    return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+vec3 randomUnitVector(float index, float n) {
+    float z = random(index, n) * 2.0 - 1.0;
+    float phi = random(index, n + 1.0) * 2.0 * PI;
+    float r = sqrt(max(1.0 - z * z, 0.0));
+
+    return vec3(r * cos(phi), r * sin(phi), z);
+}
+
+// Same shapes as ParticleEmitter::spawn
+void spawn(float index, out vec3 position, out vec3 velocity, out float lifetime) {
+    vec3 dir = randomUnitVector(index, 0.0);
+    position = vec3(0.0);
+
+    if (uShape == 1) {
+        position = dir * uShapeParams.x * pow(random(index, 2.0), 1.0 / 3.0);
+    } else if (uShape == 2) {
+        float cosT = 1.0 - random(index, 3.0) * (1.0 - uShapeParams.x);
+        float sinT = sqrt(max(1.0 - cosT * cosT, 0.0));
+        float phi = random(index, 4.0) * 2.0 * PI;
+
+        float r = uShapeParams.y * sqrt(random(index, 5.0));
+        float discPhi = random(index, 6.0) * 2.0 * PI;
+
+        position = vec3(r * cos(discPhi), 0.0, r * sin(discPhi));
+        dir = vec3(sinT * cos(phi), cosT, sinT * sin(phi));
+    }
+
+    float speed = mix(uSpeed.x, uSpeed.y, random(index, 7.0));
+    lifetime = max(mix(uLifetime.x, uLifetime.y, random(index, 8.0)), 0.001);
+
+    position = vec3(uSpawnMatrix * vec4(position, 1.0));
+    velocity = vec3(uSpawnMatrix * vec4(dir * speed, 0.0));
+}
+
+bool isSpawned(float index) {
+    float offset = mod(index - uEmitStart + uMaxParticles, uMaxParticles);
+    return offset < uEmitCount;
+}
+
+void main(void) {
+    vec2 texel = floor(vTexCoords * uSide);
+    float index = texel.y * uSide + texel.x;
+
+    if (index >= uMaxParticles) {
+        gl_FragColor = vec4(0.0);
+        return;
+    }
+
+    if (isSpawned(index)) {
+        vec3 position;
+        vec3 velocity;
+        float lifetime;
+        spawn(index, position, velocity, lifetime);
+
+#ifdef SIM_VELOCITY
+        gl_FragColor = vec4(velocity, lifetime);
+#else
+        gl_FragColor = vec4(position, 0.0);
+#endif
+        return;
+    }
+
+    if (uReset) {
+        // age >= lifetime, dead
+        gl_FragColor = vec4(0.0);
+        return;
+    }
+
+    vec4 pos = texture2D(uPositions, vTexCoords);
+    vec4 vel = texture2D(uVelocities, vTexCoords);
+
+#ifdef SIM_VELOCITY
+    if (pos.w < vel.w) {
+        vel.xyz = (vel.xyz + uGravity * uDeltaTime) * max(1.0 - uDrag * uDeltaTime, 0.0);
+    }
+    gl_FragColor = vel;
+#else
+    vec4 newVel = texture2D(uNewVelocities, vTexCoords);
+    if (pos.w < newVel.w) {
+        pos.xyz += newVel.xyz * uDeltaTime;
+        pos.w += uDeltaTime;
+    }
+    gl_FragColor = pos;
+#endif
+}
@@ -0,0 +1,105 @@
+#define USE_GLSL_300ES
+
+#define attribute in
+#define varying out
+#define texture2D texture
+
+#include "unrust/default_uniforms.glsl"
+
+#define RAMP_SIZE 64.0
+
+// Corners of the particle bounds, only used for culling
+attribute vec3 aVertexPosition;
+// xy: corner of the quad, from -1 to 1
+attribute vec3 aVertexNormal;
+
+varying vec3 vFragPos;
+varying vec2 vTexCoords;
+varying vec4 vColor;
+
+// (position, age) and (velocity, lifetime) written by gpu_particle_sim
+uniform sampler2D uPositions;
+uniform sampler2D uVelocities;
+uniform float uSide;
+uniform float uMaxParticles;
+// Otherwise particles are in the space of the game object
+uniform bool uWorldSpace;
+
+// Over the normalized age, row 0: color, row 1: size / uMaxSize
+uniform sampler2D uRamp;
+uniform float uMaxSize;
+
+uniform vec3 uCameraRight;
+uniform vec3 uCameraUp;
+uniform bool uStretched;
+// (length scale, speed scale)
+uniform vec2 uStretch;
+// (columns, rows, cycles), no animation when cycles is 0
+uniform vec3 uSheet;
+
+vec2 sheetUv(vec2 uv, float t) {
+    if (uSheet.z <= 0.0) {
+        return uv;
+    }
+
+    float frames = max(uSheet.x * uSheet.y, 1.0);
+    float frame = mod(floor(t * uSheet.z * frames), frames);
+    float col = mod(frame, uSheet.x);
+    // Loaded images are flipped, so the first row is at the top of the uv space
+    float row = uSheet.y - 1.0 - floor(frame / uSheet.x);
+
+    return (vec2(col, row) + uv) / uSheet.xy;
+}
+
+void main(void) {
+    // One instance of the quad per particle
+    float index = float(gl_InstanceID);
+    vec2 uv = (vec2(mod(index, uSide), floor(index / uSide)) + 0.5) / uSide;
+
+    vec4 pos = texture2D(uPositions, uv);
+    vec4 vel = texture2D(uVelocities, uv);
+
+    vFragPos = vec3(0.0);
+    vTexCoords = vec2(0.0);
+    vColor = vec4(0.0);
+
+    if (index >= uMaxParticles || pos.w >= vel.w) {
+        // Dead, outside of the clip volume
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        return;
+    }
+
+    float t = pos.w / vel.w;
+    float rampU = (t * (RAMP_SIZE - 1.0) + 0.5) / RAMP_SIZE;
+    float halfSize = texture2D(uRamp, vec2(rampU, 0.75)).r * uMaxSize * 0.5;
+
+    vec3 center = pos.xyz;
+    vec3 velocity = vel.xyz;
+    if (!uWorldSpace) {
+        center = vec3(uMMatrix * vec4(center, 1.0));
+        velocity = vec3(uMMatrix * vec4(velocity, 0.0));
+    }
+
+    vec2 corner = aVertexNormal.xy;
+    vec3 side = uCameraRight * halfSize;
+    vec3 offset = uCameraUp * halfSize * corner.y;
+
+    if (uStretched) {
+        float speed = length(velocity);
+        vec3 axis = speed > 0.0001 ? velocity / speed : uCameraUp;
+
+        vec3 s = cross(axis, uViewPos - center);
+        if (dot(s, s) > 0.0) {
+            side = normalize(s) * halfSize;
+        }
+
+        float back = halfSize * uStretch.x + speed * uStretch.y;
+        offset = axis * corner.y * (corner.y < 0.0 ? back : halfSize);
+    }
+
+    vFragPos = center + side * corner.x + offset;
+    vTexCoords = sheetUv(corner * 0.5 + 0.5, t);
+    vColor = texture2D(uRamp, vec2(rampU, 0.25));
+
+    gl_Position = uPVMatrix * vec4(vFragPos, 1.0);
+}
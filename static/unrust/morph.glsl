@@ -0,0 +1,18 @@
+// Morph targets of a mesh, included in vertex shaders before skinning.
+// Each target holds per vertex position offsets, see `MeshData::morph_targets`.
+
+attribute vec3 aMorphTarget0;
+attribute vec3 aMorphTarget1;
+attribute vec3 aMorphTarget2;
+attribute vec3 aMorphTarget3;
+
+// Set per surface by `MeshSurface::set_morph_weight`, and bound back to zero once drawn
+uniform float uMorphWeights[4];
+
+vec3 MorphPosition(vec3 position) {
+    return position
+        + uMorphWeights[0] * aMorphTarget0
+        + uMorphWeights[1] * aMorphTarget1
+        + uMorphWeights[2] * aMorphTarget2
+        + uMorphWeights[3] * aMorphTarget3;
+}
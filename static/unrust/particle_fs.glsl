@@ -0,0 +1,30 @@
+#ifndef GL_ES
+#define varying in
+#define gl_FragColor FragColor
+#define texture2D texture
+out vec4 FragColor;
+#endif
+
+#include "unrust/frame_data.glsl"
+#include "unrust/fog.glsl"
+
+varying vec3 vFragPos;
+varying vec2 vTexCoords;
+varying vec4 vColor;
+
+uniform sampler2D uTexture;
+// Without texture, particles are soft discs
+uniform bool uHasTexture;
+
+void main(void) {
+    vec4 color = vColor;
+
+    if (uHasTexture) {
+        color *= texture2D(uTexture, vTexCoords);
+    } else {
+        float r = length(vTexCoords * 2.0 - 1.0);
+        color.a *= 1.0 - smoothstep(0.5, 1.0, r);
+    }
+
+    gl_FragColor = vec4(ApplyFog(color.rgb, uViewPos, vFragPos), color.a);
+}
@@ -0,0 +1,22 @@
+#ifndef GL_ES
+#define attribute in
+#define varying out
+#endif
+
+#include "unrust/default_uniforms.glsl"
+
+attribute vec3 aVertexPosition;
+attribute vec2 aTextureCoord;
+attribute vec4 aVertexColor;
+
+varying vec3 vFragPos;
+varying vec2 vTexCoords;
+varying vec4 vColor;
+
+void main(void) {
+    vFragPos = vec3(uMMatrix * vec4(aVertexPosition, 1.0));
+    vTexCoords = aTextureCoord;
+    vColor = aVertexColor;
+
+    gl_Position = uPMatrix * uMVMatrix * vec4(aVertexPosition, 1.0);
+}
@@ -1,3 +1,6 @@
+#ifndef UNI_PHONG_LIGHT
+#define UNI_PHONG_LIGHT
+
 struct DirectionalLight {
     vec3 direction;
   
@@ -18,4 +21,18 @@ struct PointLight {
     vec3 specular;
 
     float rate;
-};
\ No newline at end of file
+
+    // Spot lights only, cosines of the cone half angles
+    vec3 direction;
+    float cutoff;
+    float outer_cutoff;
+};
+
+// 1 inside the inner cone, fading to 0 at the outer cone, always 1 for point lights
+float SpotFactor(PointLight light, vec3 fragPos)
+{
+    float theta = dot(normalize(fragPos - light.position), light.direction);
+    return clamp((theta - light.outer_cutoff) / max(light.cutoff - light.outer_cutoff, 0.0001), 0.0, 1.0);
+}
+
+#endif
@@ -6,46 +6,46 @@ out vec4 FragColor;
 
 #define UNI_POINT_LIGHTS 4
 
-#include "unrust/phong_light.glsl"
+#include "unrust/frame_data.glsl"
 #include "unrust/shadow_utils.glsl"
+#include "unrust/ssao_utils.glsl"
+#include "unrust/fog.glsl"
 
 struct Material {
     sampler2D diffuse;
     float shininess;
 };
 
-uniform vec3 uViewPos;
 uniform Material uMaterial;
 
 in vec3 vFragPos;
 in vec2 vTexCoords;       
 in vec3 vNormal;       
 
-// Lights
-uniform DirectionalLight uDirectionalLight;
-uniform PointLight uPointLights[UNI_POINT_LIGHTS];
-
-vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir);
-vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir);
+vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir, float lit);
+vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir, float shadow);
 
 void main(void) {
     vec3 norm = normalize(vNormal);
     vec3 viewDir = normalize(uViewPos - vFragPos);
 
     // Directional Light
-    vec3 result = CalcDirectionalLight(uDirectionalLight, norm, viewDir);
+    vec3 result = CalcDirectionalLight(uDirectionalLight, norm, viewDir, LightFactor(0));
     
     // Point Lights
     for(int i = 0; i < UNI_POINT_LIGHTS; i++)
-        result += CalcPointLight(uPointLights[i], norm, vFragPos, viewDir);
+        result += CalcPointLight(uPointLights[i], norm, vFragPos, viewDir,
+            LocalShadowCalculation(i, uPointLights[i].position, vFragPos, norm) * LightFactor(i + 1));
+
+    result = ApplyFog(result, uViewPos, vFragPos);
 
     gl_FragColor = vec4(result, 1.0);           
 }
 
-vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir)
+vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir, float lit)
 {
     // diffuse
-    vec3 ambient = light.ambient * vec3(texture2D(uMaterial.diffuse, vTexCoords));
+    vec3 ambient = light.ambient * vec3(texture2D(uMaterial.diffuse, vTexCoords)) * AmbientOcclusion();
 
     vec3 lightDir = normalize(-light.direction);  
     float diff = max(dot(normal, lightDir), 0.0);
@@ -56,12 +56,12 @@ vec3 CalcDirectionalLight(DirectionalLight light, vec3 normal, vec3 viewDir)
     float spec = pow(max(dot(viewDir, reflectDir), 0.0), uMaterial.shininess);
     vec3 specular = light.specular * spec; 
 
-    float shadow = ShadowCalculation(vFragPos, normal, normal, lightDir);
+    float shadow = ShadowCalculation(vFragPos, normal, normal, lightDir) * lit;
 
     return ambient + (diffuse + specular) * shadow;
 }
 
-vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir)
+vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir, float shadow)
 {
     vec3 lightDir = normalize(light.position - fragPos);
     
@@ -74,10 +74,10 @@ vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir)
     // attenuation
     float distance = length(light.position - fragPos);
     float d = (light.constant + light.linear * distance + light.quadratic * (distance * distance));
-    float attenuation = 1.0 / max(d, 0.001);
+    float attenuation = SpotFactor(light, fragPos) / max(d, 0.001);
     
     // combine results
-    vec3 ambient = light.ambient * vec3(texture2D(uMaterial.diffuse, vTexCoords));
+    vec3 ambient = light.ambient * vec3(texture2D(uMaterial.diffuse, vTexCoords)) * AmbientOcclusion();
     vec3 diffuse = light.diffuse * diff * vec3(texture2D(uMaterial.diffuse, vTexCoords));
     vec3 specular = light.specular * spec;
     
@@ -85,5 +85,5 @@ vec3 CalcPointLight(PointLight light, vec3 normal, vec3 fragPos, vec3 viewDir)
     diffuse *= attenuation;
     specular *= attenuation;
     
-    return (ambient + diffuse + specular) * light.rate;        
+    return (ambient + (diffuse + specular) * shadow) * light.rate;        
 }
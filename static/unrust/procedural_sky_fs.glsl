@@ -0,0 +1,89 @@
+#ifndef GL_ES
+#define varying in
+#define gl_FragColor FragColor
+out vec4 FragColor;
+#endif
+
+// Preetham, Shirley, Smits: "A Practical Analytic Model for Daylight"
+// The coefficients only depend on the sun and the turbidity, they are computed by SkyModel in
+// src/actors/procedural_sky.rs, which also bakes the same sky on the cpu.
+
+varying vec3 vTexCoords;
+
+// Normalized direction to the sun
+uniform vec3 uSunDirection;
+// Perez distribution coefficients A to E, of Y, x and y
+uniform vec3 uPerez[5];
+// Zenith Yxy divided by the distribution at the zenith
+uniform vec3 uSkyZenith;
+// Angular radius of the sun disk in radians
+uniform float uSunRadius;
+uniform float uSunIntensity;
+uniform float uExposure;
+
+// Haze close to the horizon
+uniform vec3 uFogColor;
+uniform float uFogDensity;
+uniform float uFogHeightFalloff;
+
+#include "unrust/fog.glsl"
+
+// Perez distribution in a direction of view zenith angle theta and sun angle gamma
+vec3 Perez(float cosTheta, float cosGamma)
+{
+    float gamma = acos(clamp(cosGamma, -1.0, 1.0));
+
+    return (1.0 + uPerez[0] * exp(uPerez[1] / cosTheta))
+        * (1.0 + uPerez[2] * exp(uPerez[3] * gamma) + uPerez[4] * cosGamma * cosGamma);
+}
+
+// Yxy of the sky in a direction of view zenith angle theta and sun angle gamma
+vec3 SkyYxy(float cosTheta, float cosGamma)
+{
+    return Perez(cosTheta, cosGamma) * uSkyZenith;
+}
+
+vec3 YxyToRGB(vec3 Yxy)
+{
+    float Y = Yxy.x;
+    float X = Yxy.y / Yxy.z * Y;
+    float Z = (1.0 - Yxy.y - Yxy.z) / Yxy.z * Y;
+
+    return vec3(
+        3.2406 * X - 1.5372 * Y - 0.4986 * Z,
+        -0.9689 * X + 1.8758 * Y + 0.0415 * Z,
+        0.0557 * X - 0.2040 * Y + 1.0570 * Z
+    );
+}
+
+void main()
+{
+    vec3 dir = normalize(vTexCoords);
+    vec3 sun = normalize(uSunDirection);
+
+    // Below the horizon is the same as the horizon
+    float cosTheta = max(dir.y, 0.01);
+    float cosGamma = dot(dir, sun);
+
+    vec3 color = max(YxyToRGB(SkyYxy(cosTheta, cosGamma)), vec3(0.0));
+
+    // Sun disk, reddened close to the horizon, fading out up to 1.2 times its radius
+    float disk = smoothstep(cos(uSunRadius * 1.2), cos(uSunRadius), cosGamma);
+    vec3 sunColor = mix(vec3(1.0, 0.4, 0.1), vec3(1.0, 0.95, 0.85), smoothstep(0.0, 0.3, sun.y));
+    color += disk * sunColor * uSunIntensity;
+
+    // Night falls when the sun sets
+    color *= smoothstep(-0.1, 0.05, sun.y);
+
+    // Tone mapping and gamma
+    color = 1.0 - exp(-color * uExposure);
+    color = pow(color, vec3(1.0 / 2.2));
+
+    float fog = clamp(uFogDensity * exp(-max(dir.y, 0.0) * uFogHeightFalloff), 0.0, 1.0);
+    color = mix(color, uFogColor, fog);
+
+    // Scene fog on top of the haze of the sky
+    color = ApplySkyFog(color, dir);
+
+    gl_FragColor = vec4(color, 1.0);
+}
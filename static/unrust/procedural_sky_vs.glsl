@@ -0,0 +1,15 @@
+#ifndef GL_ES
+#define attribute in
+#define varying out
+#endif
+
+#include "unrust/frame_data.glsl"
+
+attribute vec3 aVertexPosition;
+varying vec3 vTexCoords;
+
+void main()
+{
+    vTexCoords = aVertexPosition;
+    gl_Position = (uPMatrix * SkyboxViewMatrix() * vec4(aVertexPosition, 1.0)).xyww;
+}
@@ -7,3 +7,24 @@ struct ShadowMap {
     vec2 viewport_scale;
     float tex_size;
 };
+
+// Bias and strength of the shadows of a light
+struct ShadowSettings {
+    float depth_bias;
+    float normal_bias;
+    float strength;
+};
+
+// A tile of the local light shadow atlas, one per spot light and 6 per point light
+struct LocalShadowFace {
+    mat4 light_matrix;
+    // xy: offset, zw: scale in the atlas
+    vec4 tile;
+};
+
+// Faces of the light in uPointLights[i], num_faces is 0 without shadows
+struct LocalShadow {
+    int first_face;
+    int num_faces;
+    ShadowSettings settings;
+};
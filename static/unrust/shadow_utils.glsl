@@ -1,8 +1,52 @@
 #include "unrust/shadow_map.glsl"
+#include "unrust/shadow_vsm.glsl"
+
+// Filtering is selected by the keywords set by ShadowPass:
+// SHADOW_HARDWARE, SHADOW_PCF, SHADOW_PCSS or SHADOW_VSM,
+// a 3x3 grid PCF is used without any of them.
+// Local lights are only rendered in depth, so PCSS and VSM fall back to the 3x3 grid for them.
+// With SHADOW_VSM, VSM_PACKED is set when the moments are packed in RGBA8.
+
+#ifndef UNI_POINT_LIGHTS
+#define UNI_POINT_LIGHTS 4
+#endif
+
+#define UNI_LOCAL_SHADOW_FACES 16
+#define MAX_SHADOW_SAMPLES 16
 
 uniform bool uShadowEnabled;
 uniform ShadowMap uShadowMap[4];
+uniform ShadowSettings uShadowSettings;
+// Fraction at the end of a cascade blended with the next one
+uniform float uShadowCascadeBlend;
+
+uniform LocalShadow uLocalShadows[UNI_POINT_LIGHTS];
+uniform LocalShadowFace uLocalShadowFaces[UNI_LOCAL_SHADOW_FACES];
+uniform vec2 uLocalShadowTexelSize;
+
+// Sampler2DShadow needs GLSL 1.30 or GLSL ES 3.00, which has no default precision for it
+#if defined(SHADOW_HARDWARE) && defined(UNI_GLSL3)
+#define HARDWARE_SHADOW_COMPARE
+uniform highp sampler2DShadow uShadowMapTexture;
+uniform highp sampler2DShadow uLocalShadowTexture;
+#else
 uniform sampler2D uShadowMapTexture;
+uniform sampler2D uLocalShadowTexture;
+#endif
+
+#ifdef SHADOW_VSM
+uniform sampler2D uShadowMomentsTexture;
+uniform float uShadowMinVariance;
+uniform float uShadowLightBleeding;
+#endif
+
+// PCF and PCSS kernel
+uniform vec2 uPoissonDisk[MAX_SHADOW_SAMPLES];
+uniform int uShadowSamples;
+// PCF kernel radius in texels
+uniform float uShadowFilterRadius;
+// PCSS light size, in uv of a cascade
+uniform float uShadowLightSize;
 
 float ndc_z() {
     return ((2.0 * gl_FragCoord.z - gl_DepthRange.near - gl_DepthRange.far) /
@@ -18,7 +62,7 @@ vec2 get_shadow_offsets(vec3 N, vec3 L) {
 
 vec3 LightSpacePosition(int index, vec3 worldPos, vec3 worldNormal, vec2 bias_offset)
 {
-    float normal_bias = 0.02;
+    float normal_bias = uShadowSettings.normal_bias;
 
     vec4 posLightSpace = uShadowMap[index].light_matrix * vec4(worldPos + worldNormal * normal_bias * bias_offset.x, 1.0);
     vec3 projCoordsNDC = posLightSpace.xyz / posLightSpace.w;
@@ -27,54 +71,229 @@ vec3 LightSpacePosition(int index, vec3 worldPos, vec3 worldNormal, vec2 bias_of
     return projCoordsNDC * 0.5 + 0.5;
 }
 
-int ShadowIndex() {
+// The cascade of this fragment, blend is the weight of the next cascade
+int ShadowIndex(out float blend) {
     float nz = ndc_z();
     int i3 = 3 * int(nz > uShadowMap[3].range.x);
     int i2 = max(i3, 2 * int(nz > uShadowMap[2].range.x));
-    
-    return max(i2, 1 * int(nz > uShadowMap[1].range.x));
+    int index = max(i2, 1 * int(nz > uShadowMap[1].range.x));
+
+    blend = 0.0;
+
+    // Unused cascades have an empty range
+    if (index < 3 && uShadowMap[index + 1].range.x < uShadowMap[index + 1].range.y) {
+        vec2 range = uShadowMap[index].range;
+        float t = (nz - range.x) / max(range.y - range.x, 0.000001);
+        float start = 1.0 - uShadowCascadeBlend;
+
+        blend = clamp((t - start) / max(uShadowCascadeBlend, 0.000001), 0.0, 1.0);
+    }
+
+    return index;
 }
 
-float ShadowCalculation(vec3 worldPos, vec3 worldNormal, vec3 normal, vec3 lightDir)
+// Lit fraction of a single depth comparison, bilinear 2x2 with hardware comparison
+#ifdef HARDWARE_SHADOW_COMPARE
+float CompareShadowMap(vec2 uv, float depth)
 {
-    if (!uShadowEnabled) {
-        return 1.0;
-    }
-    
-    float constant_bias = 0.5;
-    float slope_bias = 3.0;
-    float normal_bias = 0.02;
+    return texture(uShadowMapTexture, vec3(uv, depth));
+}
 
-    vec2 bias_offset = get_shadow_offsets(normal, lightDir);    
-    int index = ShadowIndex();
-            
-    vec3 projCoords = LightSpacePosition(index, worldPos, worldNormal, bias_offset);
-    float currentDepth = projCoords.z;    
-    float texelSize = uShadowMap[index].tex_size;
+float CompareLocalShadow(vec2 uv, float depth)
+{
+    return texture(uLocalShadowTexture, vec3(uv, depth));
+}
+#else
+float CompareShadowMap(vec2 uv, float depth)
+{
+    return float(depth <= texture2D(uShadowMapTexture, uv).r);
+}
+
+float CompareLocalShadow(vec2 uv, float depth)
+{
+    return float(depth <= texture2D(uLocalShadowTexture, uv).r);
+}
+#endif
 
-    float shadow = 0.0;
-    float bias = constant_bias * texelSize * (constant_bias + slope_bias * bias_offset.y);
+// uv, texel and bounds are in texture space
+float GridPCF(sampler2D map, vec2 uv, float depth, vec2 texel, vec2 uvMin, vec2 uvMax)
+{
+    float lit = 0.0;
 
     for(int x = -1; x <= 1; ++x)
     {
         for(int y = -1; y <= 1; ++y)
         {
-            vec2 offset = vec2(x, y)* texelSize;
-            vec2 boundProj = projCoords.xy + offset;
-            vec2 adjBoundProj = uShadowMap[index].viewport_offset + boundProj * uShadowMap[index].viewport_scale;
-
-            float pcfDepth = texture2D(uShadowMapTexture, adjBoundProj).r;
-            float partShadow = float(currentDepth - bias > pcfDepth);
-            
-            partShadow = float(boundProj.x >= 0.0 && boundProj.x <= 1.0 &&
-                boundProj.y >= 0.0 && boundProj.y <= 1.0) * partShadow;
-            
-            shadow += partShadow;
-        }       
+            vec2 sampleUV = clamp(uv + vec2(x, y) * texel, uvMin, uvMax);
+            lit += float(depth <= texture2D(map, sampleUV).r);
+        }
+    }
+
+    return lit / 9.0;
+}
+
+float PoissonPCF(sampler2D map, vec2 uv, float depth, vec2 radius, vec2 uvMin, vec2 uvMax)
+{
+    float lit = 0.0;
+    float count = 0.0;
+
+    for (int i = 0; i < MAX_SHADOW_SAMPLES; i++) {
+        if (i >= uShadowSamples) {
+            break;
+        }
+
+        vec2 sampleUV = clamp(uv + uPoissonDisk[i] * radius, uvMin, uvMax);
+        lit += float(depth <= texture2D(map, sampleUV).r);
+        count += 1.0;
     }
-    
-    shadow /= 9.0;
 
-    return (1.0 - shadow);
+    return lit / max(count, 1.0);
 }
 
+// Percentage-closer soft shadows, the penumbra grows with the receiver to blocker distance
+float PCSS(sampler2D map, vec2 uv, float depth, vec2 lightSize, vec2 texel, vec2 uvMin, vec2 uvMax)
+{
+    float blockers = 0.0;
+    float count = 0.0;
+
+    for (int i = 0; i < MAX_SHADOW_SAMPLES; i++) {
+        if (i >= uShadowSamples) {
+            break;
+        }
+
+        vec2 sampleUV = clamp(uv + uPoissonDisk[i] * lightSize, uvMin, uvMax);
+        float d = texture2D(map, sampleUV).r;
+
+        if (d < depth) {
+            blockers += d;
+            count += 1.0;
+        }
+    }
+
+    if (count == 0.0) {
+        return 1.0;
+    }
+
+    float blocker = blockers / count;
+    float penumbra = clamp((depth - blocker) / max(blocker, 0.0001), 0.0, 1.0);
+
+    return PoissonPCF(map, uv, depth, max(lightSize * penumbra, texel), uvMin, uvMax);
+}
+
+// Lit fraction in the cascade `index`
+float CascadeShadow(int index, vec3 worldPos, vec3 worldNormal, vec2 bias_offset)
+{
+    vec3 projCoords = LightSpacePosition(index, worldPos, worldNormal, bias_offset);
+    float texelSize = uShadowMap[index].tex_size;
+    float bias = uShadowSettings.depth_bias * texelSize * (1.0 + 6.0 * bias_offset.y);
+    float currentDepth = projCoords.z - bias;
+
+    if (any(lessThan(projCoords.xy, vec2(0.0))) || any(greaterThan(projCoords.xy, vec2(1.0)))) {
+        return 1.0;
+    }
+
+    // from the cascade to the texture space
+    vec2 offset = uShadowMap[index].viewport_offset;
+    vec2 scale = uShadowMap[index].viewport_scale;
+    vec2 uv = offset + projCoords.xy * scale;
+    vec2 texel = texelSize * scale;
+    vec2 uvMin = offset + texel;
+    vec2 uvMax = offset + scale - texel;
+
+#if defined(SHADOW_VSM)
+    vec2 moments = UnpackMoments(texture2D(uShadowMomentsTexture, uv));
+    float lit = ChebyshevUpperBound(moments, currentDepth, uShadowMinVariance, uShadowLightBleeding);
+#elif defined(SHADOW_PCSS)
+    float lit = PCSS(uShadowMapTexture, uv, currentDepth, uShadowLightSize * scale, texel, uvMin, uvMax);
+#elif defined(SHADOW_HARDWARE)
+    float lit = CompareShadowMap(uv, currentDepth);
+#elif defined(SHADOW_PCF)
+    // texelSize is 2 texels of the cascade
+    vec2 radius = uShadowFilterRadius * 0.5 * texel;
+    float lit = PoissonPCF(uShadowMapTexture, uv, currentDepth, radius, uvMin, uvMax);
+#else
+    float lit = GridPCF(uShadowMapTexture, uv, currentDepth, texel, uvMin, uvMax);
+#endif
+
+    return lit;
+}
+
+float ShadowCalculation(vec3 worldPos, vec3 worldNormal, vec3 normal, vec3 lightDir)
+{
+    if (!uShadowEnabled) {
+        return 1.0;
+    }
+
+    vec2 bias_offset = get_shadow_offsets(normal, lightDir);
+    float blend;
+    int index = ShadowIndex(blend);
+
+    float lit = CascadeShadow(index, worldPos, worldNormal, bias_offset);
+
+    if (blend > 0.0) {
+        float next = CascadeShadow(index + 1, worldPos, worldNormal, bias_offset);
+        lit = mix(lit, next, blend);
+    }
+
+    return mix(1.0, lit, uShadowSettings.strength);
+}
+
+// Cube faces are ordered +X, -X, +Y, -Y, +Z, -Z
+int CubeFace(vec3 dir)
+{
+    vec3 a = abs(dir);
+
+    if (a.x >= a.y && a.x >= a.z) {
+        return dir.x > 0.0 ? 0 : 1;
+    }
+    if (a.y >= a.z) {
+        return dir.y > 0.0 ? 2 : 3;
+    }
+    return dir.z > 0.0 ? 4 : 5;
+}
+
+float LocalShadowCalculation(int light, vec3 lightPos, vec3 worldPos, vec3 worldNormal)
+{
+    LocalShadow s = uLocalShadows[light];
+
+    if (s.num_faces == 0) {
+        return 1.0;
+    }
+
+    int face = s.first_face;
+    if (s.num_faces == 6) {
+        face += CubeFace(worldPos - lightPos);
+    }
+
+    float normal_bias = s.settings.normal_bias * length(worldPos - lightPos);
+
+    vec4 posLightSpace = uLocalShadowFaces[face].light_matrix * vec4(worldPos + worldNormal * normal_bias, 1.0);
+    vec3 projCoords = posLightSpace.xyz / posLightSpace.w * 0.5 + 0.5;
+
+    if (posLightSpace.w <= 0.0 || any(lessThan(projCoords, vec3(0.0))) || any(greaterThan(projCoords, vec3(1.0)))) {
+        return 1.0;
+    }
+
+    vec4 tile = uLocalShadowFaces[face].tile;
+    vec2 texel = uLocalShadowTexelSize;
+
+    // a texel of the atlas, in depth units of a tile
+    float bias = s.settings.depth_bias * 4.0 * texel.x;
+    float currentDepth = projCoords.z - bias;
+
+    // Keep the kernel inside the tile
+    vec2 uv = tile.xy + projCoords.xy * tile.zw;
+    vec2 tileMin = tile.xy + texel;
+    vec2 tileMax = tile.xy + tile.zw - texel;
+
+#if defined(SHADOW_HARDWARE)
+    float lit = CompareLocalShadow(clamp(uv, tileMin, tileMax), currentDepth);
+#elif defined(SHADOW_PCF)
+    vec2 radius = uShadowFilterRadius * texel;
+    float lit = PoissonPCF(uLocalShadowTexture, uv, currentDepth, radius, tileMin, tileMax);
+#else
+    float lit = GridPCF(uLocalShadowTexture, uv, currentDepth, texel, tileMin, tileMax);
+#endif
+
+    return mix(1.0, lit, s.settings.strength);
+}
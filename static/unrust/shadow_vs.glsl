@@ -4,12 +4,16 @@
 #endif
 
 #include "unrust/default_uniforms.glsl"
+#include "unrust/skinning.glsl"
+#include "unrust/morph.glsl"
 
 attribute vec3 aVertexPosition;
 uniform mat4 uShadowMatrix;            
 
 void main(void) {
-    vec4 pos = uShadowMatrix * uMMatrix * vec4(aVertexPosition, 1.0);    
+    vec4 pos = uShadowMatrix * uMMatrix * SkinMatrix() * vec4(MorphPosition(aVertexPosition), 1.0);    
+#ifndef LOCAL_SHADOW
     pos.z *= pos.w;
+#endif
     gl_Position = pos;
 }
\ No newline at end of file
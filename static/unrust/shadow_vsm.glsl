@@ -0,0 +1,41 @@
+// Variance shadow map moments, (depth, depth^2), in the red and green channels
+// of half float textures, or with VSM_PACKED stored with 16 bits each in RGBA8 textures
+
+#ifdef VSM_PACKED
+vec4 PackMoments(vec2 moments)
+{
+    vec4 packed = vec4(moments.x, fract(moments.x * 255.0), moments.y, fract(moments.y * 255.0));
+    packed.xz -= packed.yw / 255.0;
+
+    return packed;
+}
+
+vec2 UnpackMoments(vec4 packed)
+{
+    return vec2(packed.x + packed.y / 255.0, packed.z + packed.w / 255.0);
+}
+#else
+vec4 PackMoments(vec2 moments)
+{
+    return vec4(moments, 0.0, 1.0);
+}
+
+vec2 UnpackMoments(vec4 moments)
+{
+    return moments.xy;
+}
+#endif
+
+// Upper bound of the lit fraction, with the light bleeding below `bleeding` cut off
+float ChebyshevUpperBound(vec2 moments, float depth, float minVariance, float bleeding)
+{
+    if (depth <= moments.x) {
+        return 1.0;
+    }
+
+    float variance = max(moments.y - moments.x * moments.x, minVariance);
+    float d = depth - moments.x;
+    float p = variance / (variance + d * d);
+
+    return clamp((p - bleeding) / (1.0 - bleeding), 0.0, 1.0);
+}
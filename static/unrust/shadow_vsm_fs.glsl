@@ -0,0 +1,52 @@
+#ifndef GL_ES
+#define varying in
+#define gl_FragColor FragColor
+#define texture2D texture
+out vec4 FragColor;
+#endif
+
+#include "unrust/shadow_vsm.glsl"
+
+#define MAX_BLUR_RADIUS 8
+
+varying vec2 vTexCoords;
+
+// The shadow depth map with VSM_FROM_DEPTH, packed moments otherwise
+uniform sampler2D uSource;
+// Offset between two samples along the blur direction
+uniform vec2 uBlurStep;
+uniform int uBlurRadius;
+// Number of shadow map tiles in a row of the texture
+uniform float uTiles;
+
+vec2 SampleMoments(vec2 uv)
+{
+#ifdef VSM_FROM_DEPTH
+    float depth = texture2D(uSource, uv).r;
+    return vec2(depth, depth * depth);
+#else
+    return UnpackMoments(texture2D(uSource, uv));
+#endif
+}
+
+void main()
+{
+    // Keep the samples in the tile of this fragment
+    vec2 tileMin = floor(vTexCoords * uTiles) / uTiles;
+    vec2 tileMax = tileMin + vec2(1.0 / uTiles) - abs(uBlurStep);
+
+    vec2 moments = vec2(0.0);
+    float count = 0.0;
+
+    for (int i = -MAX_BLUR_RADIUS; i <= MAX_BLUR_RADIUS; i++) {
+        if (i < -uBlurRadius || i > uBlurRadius) {
+            continue;
+        }
+
+        vec2 uv = clamp(vTexCoords + float(i) * uBlurStep, tileMin, tileMax);
+        moments += SampleMoments(uv);
+        count += 1.0;
+    }
+
+    gl_FragColor = PackMoments(moments / count);
+}
@@ -0,0 +1,26 @@
+// Linear blend skinning of a SkinnedMesh, included in vertex shaders.
+// The joint matrices go from the bind pose to the animated pose, in the space of the mesh.
+
+// The palette takes 4 uniform vectors per joint, a shader may define a lower UNI_MAX_JOINTS
+// before the include, the matrices of the extra joints are then ignored.
+#ifndef UNI_MAX_JOINTS
+#define UNI_MAX_JOINTS 64
+#endif
+
+attribute vec4 aVertexJoints;
+attribute vec4 aVertexWeights;
+
+uniform mat4 uJointMatrices[UNI_MAX_JOINTS];
+// Set by the engine for each draw, false for meshes without skeleton
+uniform bool uSkinned;
+
+mat4 SkinMatrix() {
+    if (!uSkinned) {
+        return mat4(1.0);
+    }
+
+    return aVertexWeights.x * uJointMatrices[int(aVertexJoints.x)]
+        + aVertexWeights.y * uJointMatrices[int(aVertexJoints.y)]
+        + aVertexWeights.z * uJointMatrices[int(aVertexJoints.z)]
+        + aVertexWeights.w * uJointMatrices[int(aVertexJoints.w)];
+}
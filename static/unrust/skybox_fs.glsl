@@ -8,7 +8,10 @@ out vec4 FragColor;
 varying vec3 vTexCoords;
 uniform samplerCube uSkybox;
 
+#include "unrust/fog.glsl"
+
 void main()
 {    
-    gl_FragColor = textureCube(uSkybox, vTexCoords);
+    vec4 color = textureCube(uSkybox, vTexCoords);
+    gl_FragColor = vec4(ApplySkyFog(color.rgb, vTexCoords), color.a);
 }
\ No newline at end of file
@@ -3,7 +3,7 @@
 #define varying out
 #endif
 
-uniform mat4 uPVSkyboxMatrix;
+#include "unrust/frame_data.glsl"
 
 attribute vec3 aVertexPosition;
 varying vec3 vTexCoords;
@@ -11,5 +11,5 @@ varying vec3 vTexCoords;
 void main()
 {
     vTexCoords = aVertexPosition;
-    gl_Position = (uPVSkyboxMatrix * vec4(aVertexPosition, 1.0)).xyww;
+    gl_Position = (uPMatrix * SkyboxViewMatrix() * vec4(aVertexPosition, 1.0)).xyww;
 }         
\ No newline at end of file
@@ -0,0 +1,33 @@
+#ifndef GL_ES
+#define varying in
+#define gl_FragColor FragColor
+#define texture2D texture
+out vec4 FragColor;
+#endif
+
+#define MAX_BLUR_RADIUS 4
+
+varying vec2 vTexCoords;
+
+uniform sampler2D uSource;
+// Offset between two samples along the blur direction
+uniform vec2 uBlurStep;
+uniform int uBlurRadius;
+
+void main()
+{
+    float ao = 0.0;
+    float count = 0.0;
+
+    for (int i = -MAX_BLUR_RADIUS; i <= MAX_BLUR_RADIUS; i++) {
+        if (i < -uBlurRadius || i > uBlurRadius) {
+            continue;
+        }
+
+        ao += texture2D(uSource, vTexCoords + float(i) * uBlurStep).r;
+        count += 1.0;
+    }
+
+    ao /= count;
+    gl_FragColor = vec4(ao, ao, ao, 1.0);
+}
@@ -0,0 +1,94 @@
+#ifndef GL_ES
+#define varying in
+#define gl_FragColor FragColor
+#define texture2D texture
+out vec4 FragColor;
+#endif
+
+#define MAX_SSAO_SAMPLES 32
+
+varying vec2 vTexCoords;
+
+// Depth of the camera
+uniform sampler2D uDepthMap;
+uniform vec2 uTexelSize;
+
+uniform mat4 uProjection;
+uniform mat4 uInvProjection;
+
+// Hemisphere around +z, denser near the center
+uniform vec3 uSsaoKernel[MAX_SSAO_SAMPLES];
+uniform int uSsaoSamples;
+uniform float uSsaoRadius;
+uniform float uSsaoBias;
+uniform float uSsaoIntensity;
+
+vec3 ViewPosition(vec2 uv)
+{
+    float depth = texture2D(uDepthMap, uv).r;
+    vec4 view = uInvProjection * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+
+    return view.xyz / view.w;
+}
+
+// Pick the difference with the closest neighbor, to keep the normals of edges
+vec3 MinDiff(vec3 p, vec3 a, vec3 b)
+{
+    vec3 da = a - p;
+    vec3 db = p - b;
+
+    return abs(da.z) < abs(db.z) ? da : db;
+}
+
+float Hash(vec2 p)
+{
+    return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+void main()
+{
+    if (texture2D(uDepthMap, vTexCoords).r >= 1.0) {
+        gl_FragColor = vec4(1.0);
+        return;
+    }
+
+    vec3 p = ViewPosition(vTexCoords);
+
+    // Normals are reconstructed from the depth of the neighbors
+    vec2 dx = vec2(uTexelSize.x, 0.0);
+    vec2 dy = vec2(0.0, uTexelSize.y);
+    vec3 ddx = MinDiff(p, ViewPosition(vTexCoords + dx), ViewPosition(vTexCoords - dx));
+    vec3 ddy = MinDiff(p, ViewPosition(vTexCoords + dy), ViewPosition(vTexCoords - dy));
+    vec3 normal = normalize(cross(ddx, ddy));
+
+    // Rotate the kernel randomly per pixel, the noise is removed by the blur
+    float angle = Hash(gl_FragCoord.xy) * 6.2831853;
+    vec3 randomVec = vec3(cos(angle), sin(angle), 0.0);
+    vec3 tangent = normalize(randomVec - normal * dot(randomVec, normal));
+    vec3 bitangent = cross(normal, tangent);
+    mat3 TBN = mat3(tangent, bitangent, normal);
+
+    float occlusion = 0.0;
+    float count = 0.0;
+
+    for (int i = 0; i < MAX_SSAO_SAMPLES; i++) {
+        if (i >= uSsaoSamples) {
+            break;
+        }
+
+        vec3 s = p + TBN * uSsaoKernel[i] * uSsaoRadius;
+
+        vec4 offset = uProjection * vec4(s, 1.0);
+        vec2 uv = offset.xy / offset.w * 0.5 + 0.5;
+        float sceneZ = ViewPosition(uv).z;
+
+        // Ignore occluders far outside the radius
+        float range = smoothstep(0.0, 1.0, uSsaoRadius / max(abs(p.z - sceneZ), 0.0001));
+
+        occlusion += float(sceneZ >= s.z + uSsaoBias) * range;
+        count += 1.0;
+    }
+
+    float ao = clamp(1.0 - uSsaoIntensity * occlusion / max(count, 1.0), 0.0, 1.0);
+    gl_FragColor = vec4(ao, ao, ao, 1.0);
+}
@@ -0,0 +1,16 @@
+// Screen space ambient occlusion of the camera, set by SsaoPass
+
+// Set by the engine for each camera, false for the cameras without occlusion
+uniform bool uSsaoEnabled;
+uniform sampler2D uSsaoTexture;
+// Viewport of the camera in pixels, xy: offset, zw: size
+uniform vec4 uSsaoViewport;
+
+float AmbientOcclusion()
+{
+    if (!uSsaoEnabled) {
+        return 1.0;
+    }
+
+    return texture2D(uSsaoTexture, (gl_FragCoord.xy - uSsaoViewport.xy) / uSsaoViewport.zw).r;
+}
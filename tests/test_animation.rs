@@ -0,0 +1,250 @@
+extern crate unrust;
+
+use std::rc::Rc;
+use unrust::actors::{AnimationClip, AnimationLayer, AnimationState, Animator, ClipError,
+                     Condition, Curve, CurveError, FloatProperty, Track, TrackProperty,
+                     Transition};
+use unrust::engine::{GameObject, Material, MaterialParam, Mesh};
+use unrust::math::*;
+use unrust::world::{Handle, World, WorldBuilder};
+
+fn headless_world() -> World {
+    WorldBuilder::new("Headless")
+        .with_headless(true)
+        .with_size((64, 64))
+        .build()
+}
+
+/// Clip moving the target 0 along x from `from` to `to` in 1 second
+fn move_clip(from: f32, to: f32) -> Rc<AnimationClip> {
+    Rc::new(AnimationClip::new(vec![Track {
+        target: 0,
+        property: TrackProperty::Translation(Curve::linear(
+            Vector3::new(from, 0.0, 0.0),
+            Vector3::new(to, 0.0, 0.0),
+        )),
+    }]).unwrap())
+}
+
+fn translation_x(go: &Handle<GameObject>) -> f32 {
+    go.borrow().transform.local().disp.x
+}
+
+fn assert_near(a: f32, b: f32) {
+    assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+}
+
+#[test]
+fn test_curve_evaluate() {
+    let curve: Curve<f32> = Curve::new(vec![(1.0, 10.0), (0.0, 0.0), (3.0, 20.0)]);
+
+    assert_eq!(curve.keys()[0], (0.0, 0.0));
+    assert_eq!(curve.end_time(), 3.0);
+
+    assert_near(curve.evaluate(-1.0), 0.0);
+    assert_near(curve.evaluate(0.5), 5.0);
+    assert_near(curve.evaluate(1.0), 10.0);
+    assert_near(curve.evaluate(2.0), 15.0);
+    assert_near(curve.evaluate(4.0), 20.0);
+
+    assert_near(Curve::constant(7.0f32).evaluate(100.0), 7.0);
+}
+
+#[test]
+fn test_curve_invalid_keys() {
+    assert_eq!(Curve::<f32>::new(Vec::new()).err(), Some(CurveError::NoKeys));
+    assert_eq!(
+        Curve::new(vec![(0.0, 1.0), (::std::f32::INFINITY, 2.0)]).err(),
+        Some(CurveError::InvalidTime(::std::f32::INFINITY))
+    );
+}
+
+#[test]
+fn test_clip_duration() {
+    let clip = AnimationClip::new(vec![
+        Track {
+            target: 0,
+            property: TrackProperty::Translation(Curve::constant(Vector3::new(1.0, 2.0, 3.0))),
+        },
+        Track {
+            target: 1,
+            property: TrackProperty::Scale(Curve::new(vec![
+                (0.0, Vector3::new(1.0, 1.0, 1.0)),
+                (2.5, Vector3::new(2.0, 2.0, 2.0)),
+            ]).unwrap()),
+        },
+    ]).unwrap();
+
+    assert_eq!(clip.duration, 2.5);
+}
+
+#[test]
+fn test_clip_invalid_tracks() {
+    let clip = AnimationClip::new(vec![Track {
+        target: 0,
+        property: TrackProperty::Translation(Curve::new(vec![(-1.0, Vector3::zero())]).unwrap()),
+    }]);
+    assert_eq!(
+        clip.err(),
+        Some(ClipError::NegativeTime {
+            target: 0,
+            time: -1.0,
+        })
+    );
+
+    let clip = AnimationClip::new(vec![Track {
+        target: 2,
+        property: TrackProperty::Float(FloatProperty::MorphWeight(4), Curve::constant(1.0)),
+    }]);
+    assert_eq!(
+        clip.err(),
+        Some(ClipError::InvalidMorphTarget {
+            target: 2,
+            index: 4,
+        })
+    );
+}
+
+#[test]
+fn test_morph_weight_track() {
+    let mut world = headless_world();
+    let go = world.new_game_object();
+    {
+        let db = world.asset_system();
+        let mut mesh = Mesh::new();
+        mesh.add_surface(
+            db.new_mesh_buffer("cube"),
+            Material::new(db.new_program("phong")),
+        );
+        go.borrow_mut().add_component(mesh);
+    }
+
+    let clip = AnimationClip::new(vec![Track {
+        target: 0,
+        property: TrackProperty::Float(FloatProperty::MorphWeight(1), Curve::linear(0.0, 1.0)),
+    }]).unwrap();
+
+    let mut animator = Animator::new(&[go.clone()]);
+    animator.add_layer(AnimationLayer::new(vec![
+        AnimationState::new("morph", Rc::new(clip)),
+    ]));
+    animator.advance(0.5);
+
+    let go = go.borrow();
+    let (mesh, _) = go.find_component::<Mesh>().unwrap();
+    assert_eq!(
+        mesh.surfaces[0].properties().get("uMorphWeights[1]"),
+        Some(&MaterialParam::Float(0.5))
+    );
+}
+
+#[test]
+fn test_clip_sampling() {
+    let mut world = headless_world();
+    let go = world.new_game_object();
+
+    let mut animator = Animator::new(&[go.clone()]);
+    let mut state = AnimationState::new("move", move_clip(0.0, 10.0));
+    state.looping = false;
+    animator.add_layer(AnimationLayer::new(vec![state]));
+
+    animator.advance(0.25);
+    assert_near(translation_x(&go), 2.5);
+
+    // Clips which do not loop hold their last keys
+    animator.advance(1.0);
+    assert_near(translation_x(&go), 10.0);
+}
+
+#[test]
+fn test_cross_fade_weights() {
+    let mut world = headless_world();
+    let go = world.new_game_object();
+
+    let mut animator = Animator::new(&[go.clone()]);
+    animator.add_layer(AnimationLayer::new(vec![
+        AnimationState::new("a", move_clip(0.0, 0.0)),
+        AnimationState::new("b", move_clip(10.0, 10.0)),
+    ]));
+
+    animator.advance(0.1);
+    assert_near(translation_x(&go), 0.0);
+
+    // A quarter of the way from "a" to "b"
+    animator.cross_fade(0, "b", 1.0);
+    animator.advance(0.25);
+    assert_near(translation_x(&go), 2.5);
+    assert_eq!(animator.layers[0].current_state(), Some("b"));
+
+    animator.advance(0.5);
+    assert_near(translation_x(&go), 7.5);
+
+    // The fade is over
+    animator.advance(0.5);
+    assert_near(translation_x(&go), 10.0);
+}
+
+#[test]
+fn test_layer_weight() {
+    let mut world = headless_world();
+    let go = world.new_game_object();
+
+    let mut animator = Animator::new(&[go.clone()]);
+    animator.add_layer(AnimationLayer::new(vec![
+        AnimationState::new("a", move_clip(4.0, 4.0)),
+    ]));
+
+    let mut layer = AnimationLayer::new(vec![AnimationState::new("b", move_clip(8.0, 8.0))]);
+    layer.weight = 0.5;
+    animator.add_layer(layer);
+
+    animator.advance(0.1);
+    assert_near(translation_x(&go), 6.0);
+}
+
+#[test]
+fn test_state_machine_transitions() {
+    let mut world = headless_world();
+    let go = world.new_game_object();
+
+    let mut idle = AnimationState::new("idle", move_clip(0.0, 0.0));
+    let mut to_jump = Transition::new("jump", 0.0);
+    to_jump.conditions.push(Condition::Trigger("jump".to_string()));
+    let mut to_run = Transition::new("run", 0.0);
+    to_run.conditions.push(Condition::Greater("speed".to_string(), 0.5));
+    idle.transitions = vec![to_jump, to_run];
+
+    // Back to idle when the clip is over
+    let mut jump = AnimationState::new("jump", move_clip(0.0, 1.0));
+    let mut to_idle = Transition::new("idle", 0.0);
+    to_idle.exit_time = Some(1.0);
+    jump.transitions.push(to_idle);
+
+    let run = AnimationState::new("run", move_clip(0.0, 0.0));
+
+    let mut animator = Animator::new(&[go.clone()]);
+    animator.add_layer(AnimationLayer::new(vec![idle, jump, run]));
+
+    animator.advance(0.1);
+    assert_eq!(animator.layers[0].current_state(), Some("idle"));
+
+    // Missing and failing parameters never pass
+    animator.set_float("speed", 0.2);
+    animator.advance(0.1);
+    assert_eq!(animator.layers[0].current_state(), Some("idle"));
+
+    animator.set_trigger("jump");
+    animator.advance(0.1);
+    assert_eq!(animator.layers[0].current_state(), Some("jump"));
+
+    animator.advance(0.5);
+    assert_eq!(animator.layers[0].current_state(), Some("jump"));
+
+    // The trigger was consumed by the transition, so idle stays
+    animator.advance(0.6);
+    assert_eq!(animator.layers[0].current_state(), Some("idle"));
+
+    animator.set_float("speed", 1.0);
+    animator.advance(0.1);
+    assert_eq!(animator.layers[0].current_state(), Some("run"));
+}
@@ -0,0 +1,111 @@
+extern crate unrust;
+
+use std::rc::Rc;
+use unrust::engine::{Material, MaterialParam, Mesh};
+use unrust::math::*;
+use unrust::world::{World, WorldBuilder};
+
+#[test]
+fn test_mesh_clone_properties() {
+    let world = WorldBuilder::new("Headless")
+        .with_headless(true)
+        .with_size((64, 64))
+        .build();
+
+    let db = world.asset_system();
+
+    let material = Material::new(db.new_program("phong"));
+    material.set("uMaterial.shininess", 32.0);
+
+    let mut mesh = Mesh::new();
+    mesh.add_surface(db.new_mesh_buffer("cube"), material);
+    mesh.surfaces[0].set_property("uMaterial.shininess", 8.0);
+
+    let other = mesh.clone();
+    other.surfaces[0].set_property("uMaterial.shininess", 64.0);
+    other.surfaces[0].set_property("uMaterial.ambient", 1.0);
+
+    assert_eq!(
+        mesh.surfaces[0].properties().get("uMaterial.shininess"),
+        Some(&MaterialParam::Float(8.0))
+    );
+    assert!(mesh.surfaces[0].properties().get("uMaterial.ambient").is_none());
+
+    assert_eq!(
+        other.surfaces[0].properties().get("uMaterial.shininess"),
+        Some(&MaterialParam::Float(64.0))
+    );
+
+    other.surfaces[0].clear_properties();
+    assert_eq!(mesh.surfaces[0].properties().len(), 1);
+}
+
+#[test]
+fn test_mesh_restore_properties() {
+    let world = WorldBuilder::new("Headless")
+        .with_headless(true)
+        .with_size((64, 64))
+        .build();
+
+    let db = world.asset_system();
+
+    let material = Rc::new(Material::new(db.new_program("phong")));
+    material.set("uMaterial.shininess", 32.0);
+
+    // Both surfaces share the material and its program, only the first one overrides
+    let mut mesh = Mesh::new();
+    mesh.add_surface(db.new_mesh_buffer("cube"), material.clone());
+    mesh.add_surface(db.new_mesh_buffer("cube"), material.clone());
+    mesh.surfaces[0].set_property("uMaterial.shininess", 8.0);
+    mesh.surfaces[0].set_property("uMaterial.diffuse", db.new_texture("tex_r.dds"));
+
+    assert!(mesh.surfaces[1].properties().is_empty());
+
+    // What is bound after the first surface is drawn, before the second one
+    let restored = material.restored_properties(&mesh.surfaces[0].properties());
+
+    assert_eq!(restored.len(), 2);
+    assert_eq!(
+        restored.get("uMaterial.shininess"),
+        Some(&MaterialParam::Float(32.0))
+    );
+    // Not set by the material, the sampler is reset to unit 0
+    assert_eq!(
+        restored.get("uMaterial.diffuse"),
+        Some(&MaterialParam::Int(0))
+    );
+}
+
+#[test]
+fn test_mesh_bounds_generation() {
+    let mut world = WorldBuilder::new("Headless")
+        .with_headless(true)
+        .with_size((64, 64))
+        .build();
+
+    let generation = |world: &World| world.engine().bounds_generation(&world.root().tree());
+
+    let go = world.new_game_object();
+    let g = generation(&world);
+    assert_eq!(generation(&world), g);
+
+    let mut mesh = Mesh::new();
+    {
+        let db = world.asset_system();
+        mesh.add_surface(db.new_mesh_buffer("cube"), Material::new(db.new_program("phong")));
+    }
+    go.borrow_mut().add_component(mesh);
+    let g_mesh = generation(&world);
+    assert!(g_mesh != g);
+
+    let mut local = go.borrow().transform.local();
+    local.disp = Vector3::new(1.0, 0.0, 0.0);
+    go.borrow_mut().transform.set_local(local);
+    let g_moved = generation(&world);
+    assert!(g_moved != g_mesh);
+
+    let go = go.borrow();
+    let (mesh, _) = go.find_component::<Mesh>().unwrap();
+    mesh.invalidate_bounds();
+    assert!(generation(&world) != g_moved);
+}
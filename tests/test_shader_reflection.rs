@@ -0,0 +1,140 @@
+extern crate unrust;
+
+use std::collections::HashMap;
+use unrust::engine::{PreprocessedShaderCode, ShaderKeywords, ShaderKind, ShaderReflection,
+                     ShaderVarType};
+
+/// Preprocess a fragment shader with `keywords` defined
+fn preprocess(code: &str, keywords: &[&str]) -> String {
+    let code = match PreprocessedShaderCode::new(ShaderKind::Fragment, code, &HashMap::new()) {
+        Ok(code) => code,
+        Err(_) => panic!("Fail to preprocess shader"),
+    };
+
+    let keywords: ShaderKeywords = keywords.iter().map(|k| k.to_string()).collect();
+    match code.with_keywords(&keywords) {
+        Ok(code) => code.as_string().clone(),
+        Err(_) => panic!("Fail to preprocess shader with {:?}", keywords),
+    }
+}
+
+fn names(r: &ShaderReflection) -> Vec<&str> {
+    r.uniforms.iter().map(|v| v.name.as_str()).collect()
+}
+
+#[test]
+fn test_reflection_arrays() {
+    let r = ShaderReflection::parse(
+        ShaderKind::Vertex,
+        "attribute vec3 aVertexPosition;
+        in vec2 aTextureCoord;
+        uniform vec3 uColors[4], uSingle;
+        uniform highp float uWeights[MAX_WEIGHTS];",
+    );
+
+    assert!(r.attribute("aVertexPosition").is_some());
+    assert!(r.attribute("aTextureCoord").is_some());
+    assert_eq!(names(&r), vec!["uColors", "uSingle", "uWeights"]);
+
+    let colors = r.uniform("uColors[2]").unwrap();
+    assert_eq!(colors.ty, ShaderVarType::Vec3);
+    assert_eq!(colors.array_len, Some(4));
+
+    let single = r.uniform("uSingle").unwrap();
+    assert_eq!(single.ty, ShaderVarType::Vec3);
+    assert_eq!(single.array_len, None);
+
+    // The length could not be evaluated without preprocessing
+    assert_eq!(r.uniform("uWeights").unwrap().array_len, Some(0));
+}
+
+#[test]
+fn test_reflection_structs() {
+    let r = ShaderReflection::parse(
+        ShaderKind::Fragment,
+        "struct Light {
+            vec3 position;
+            float range;
+        };
+
+        struct Lights {
+            Light items[2];
+            int count;
+        };
+
+        uniform Lights uLights;
+        uniform Light uPointLights[4];
+
+        layout(std140) uniform MaterialData {
+            vec4 uTint;
+            float uRoughness;
+        };
+
+        void main() {
+            float uLocal = uLights.items[0].range;
+        }",
+    );
+
+    assert_eq!(
+        names(&r),
+        vec!["uLights", "uPointLights", "uTint", "uRoughness"]
+    );
+    assert_eq!(
+        r.uniform("uLights").unwrap().ty,
+        ShaderVarType::Struct("Lights".to_string())
+    );
+    assert_eq!(
+        r.uniform("uLights.items[1].range").unwrap().ty,
+        ShaderVarType::Float
+    );
+    assert_eq!(
+        r.uniform("uPointLights[3].position").unwrap().ty,
+        ShaderVarType::Vec3
+    );
+    assert!(r.uniform("uLights.missing").is_none());
+    assert!(r.uniform("uTint.x").is_none());
+
+    assert!(r.in_block("MaterialData", "uTint"));
+    assert!(r.in_block("MaterialData", "uRoughness"));
+    assert!(!r.in_block("MaterialData", "uLights"));
+}
+
+#[test]
+fn test_reflection_comments() {
+    let r = ShaderReflection::parse(
+        ShaderKind::Fragment,
+        "// uniform float uLineComment;
+        /* uniform float uBlockComment;
+           uniform vec2 uStillComment; */
+        uniform /* inline */ float uReal; // trailing
+        #define uDirective 1",
+    );
+
+    assert_eq!(names(&r), vec!["uReal"]);
+    assert_eq!(r.uniform("uReal").unwrap().ty, ShaderVarType::Float);
+}
+
+#[test]
+fn test_reflection_variants() {
+    let code = "
+#ifdef NORMAL_MAP
+uniform sampler2D uNormalMap;
+#else
+uniform vec3 uFlatNormal;
+#endif
+uniform vec3 uLightColors[3];
+void main() {}
+";
+
+    let r = ShaderReflection::parse(ShaderKind::Fragment, &preprocess(code, &[]));
+    assert_eq!(names(&r), vec!["uFlatNormal", "uLightColors"]);
+    assert_eq!(r.samplers().len(), 0);
+
+    let r = ShaderReflection::parse(ShaderKind::Fragment, &preprocess(code, &["NORMAL_MAP"]));
+    assert_eq!(names(&r), vec!["uNormalMap", "uLightColors"]);
+    assert_eq!(
+        r.uniform("uNormalMap").unwrap().ty,
+        ShaderVarType::Sampler2D
+    );
+    assert_eq!(r.samplers().len(), 1);
+}
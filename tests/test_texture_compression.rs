@@ -0,0 +1,457 @@
+extern crate unrust;
+
+use unrust::engine::{AssetError, CompressedFormat, KTXReader};
+
+/// Decompress a single 4x4 block, pixels are in row major order
+fn decode(format: CompressedFormat, block: &[u8]) -> Vec<[u8; 4]> {
+    let rgba = format.decompress(4, 4, block).unwrap();
+    rgba.chunks(4).map(|p| [p[0], p[1], p[2], p[3]]).collect()
+}
+
+fn row(pixels: &[[u8; 4]], y: usize) -> Vec<[u8; 4]> {
+    pixels[y * 4..y * 4 + 4].to_vec()
+}
+
+/// Pack `(lowest bit, value)` fields in a big endian ETC2 / EAC block
+fn etc_block(fields: &[(u32, u64)]) -> Vec<u8> {
+    let v = fields.iter().fold(0u64, |v, &(lsb, field)| v | field << lsb);
+    (0..8).map(|i| (v >> (56 - i * 8)) as u8).collect()
+}
+
+/// Set the 2 bits index of pixel (x, y) in an ETC2 block
+fn etc_index(x: u32, y: u32, index: u64) -> Vec<(u32, u64)> {
+    let i = x * 4 + y;
+    vec![(i + 16, index >> 1), (i, index & 1)]
+}
+
+/// Pack `(bit count, value)` fields from the lowest bit of a BC7 block
+fn bc7_block(fields: &[(u32, u32)]) -> Vec<u8> {
+    let mut block = vec![0u8; 16];
+    let mut pos = 0;
+    for &(n, v) in fields {
+        for i in 0..n {
+            block[pos / 8] |= (((v >> i) & 1) as u8) << (pos % 8);
+            pos += 1;
+        }
+    }
+    assert_eq!(pos, 128);
+    block
+}
+
+/// BC3 / BC4 indices 0 to 7 for pixels 0 to 7 and 8 to 15
+const BC4_INDICES: [u8; 6] = [0x88, 0xc6, 0xfa, 0x88, 0xc6, 0xfa];
+
+#[test]
+fn test_bc1_four_colors() {
+    // red and blue endpoints, indices 0, 1, 2, 3 on each row
+    let block = [0x00, 0xf8, 0x1f, 0x00, 0xe4, 0xe4, 0xe4, 0xe4];
+
+    for &format in [CompressedFormat::Dxt1, CompressedFormat::Dxt1Alpha].iter() {
+        let pixels = decode(format, &block);
+        for y in 0..4 {
+            assert_eq!(
+                row(&pixels, y),
+                vec![[255, 0, 0, 255], [0, 0, 255, 255], [170, 0, 85, 255], [85, 0, 170, 255]]
+            );
+        }
+    }
+}
+
+#[test]
+fn test_bc1_three_colors() {
+    // color0 <= color1, the last color is transparent with 1 bit alpha
+    let block = [0x00, 0x40, 0x00, 0x80, 0xe4, 0xe4, 0xe4, 0xe4];
+
+    let pixels = decode(CompressedFormat::Dxt1Alpha, &block);
+    assert_eq!(
+        row(&pixels, 0),
+        vec![[66, 0, 0, 255], [132, 0, 0, 255], [99, 0, 0, 255], [0, 0, 0, 0]]
+    );
+
+    let pixels = decode(CompressedFormat::Dxt1, &block);
+    assert_eq!(pixels[3], [0, 0, 0, 255]);
+}
+
+#[test]
+fn test_bc3() {
+    let mut block = vec![70, 0];
+    block.extend_from_slice(&BC4_INDICES);
+    // the color block always has 4 colors, even when color0 <= color1
+    block.extend_from_slice(&[0x00, 0x40, 0x00, 0x80, 0xe4, 0xe4, 0xe4, 0xe4]);
+
+    let pixels = decode(CompressedFormat::Dxt5, &block);
+    assert_eq!(
+        row(&pixels, 0),
+        vec![[66, 0, 0, 70], [132, 0, 0, 0], [88, 0, 0, 60], [110, 0, 0, 50]]
+    );
+    assert_eq!(
+        row(&pixels, 1),
+        vec![[66, 0, 0, 40], [132, 0, 0, 30], [88, 0, 0, 20], [110, 0, 0, 10]]
+    );
+}
+
+#[test]
+fn test_bc4() {
+    // r0 <= r1, 4 interpolated values followed by 0 and 255
+    let mut block = vec![0, 50];
+    block.extend_from_slice(&BC4_INDICES);
+
+    let pixels = decode(CompressedFormat::Bc4, &block);
+    let values: Vec<u8> = pixels.iter().map(|p| p[0]).collect();
+    assert_eq!(
+        values,
+        vec![0, 50, 10, 20, 30, 40, 0, 255, 0, 50, 10, 20, 30, 40, 0, 255]
+    );
+    assert!(pixels.iter().all(|p| p[1] == p[0] && p[2] == p[0] && p[3] == 255));
+}
+
+#[test]
+fn test_bc5() {
+    // r0 > r1 in the red channel with 6 interpolated values
+    let mut block = vec![70, 0];
+    block.extend_from_slice(&BC4_INDICES);
+    block.extend_from_slice(&[0, 50]);
+    block.extend_from_slice(&BC4_INDICES);
+
+    let pixels = decode(CompressedFormat::Bc5, &block);
+    assert_eq!(
+        row(&pixels, 0),
+        vec![[70, 0, 0, 255], [0, 50, 0, 255], [60, 10, 0, 255], [50, 20, 0, 255]]
+    );
+    assert_eq!(
+        row(&pixels, 1),
+        vec![[40, 30, 0, 255], [30, 40, 0, 255], [20, 0, 0, 255], [10, 255, 0, 255]]
+    );
+}
+
+#[test]
+fn test_bc7_mode6() {
+    // single subset, RGBA endpoints 0 and 127 with p-bits 0 and 1, pixel i has index i
+    let mut fields = vec![(7, 0x40)];
+    for _ in 0..4 {
+        fields.extend_from_slice(&[(7, 0), (7, 127)]);
+    }
+    fields.extend_from_slice(&[(1, 0), (1, 1), (3, 0)]);
+    for i in 1..16 {
+        fields.push((4, i));
+    }
+
+    let pixels = decode(CompressedFormat::Bc7, &bc7_block(&fields));
+    let ramp = [
+        0, 16, 36, 52, 68, 84, 104, 120, 135, 151, 171, 187, 203, 219, 239, 255,
+    ];
+    for i in 0..16 {
+        assert_eq!(pixels[i], [ramp[i]; 4]);
+    }
+}
+
+#[test]
+fn test_bc7_mode1_partition() {
+    // partition 13 puts the two last rows in subset 1, whose anchor is pixel 15.
+    // Subset 0 goes from black to white with p-bit 0, subset 1 is red with p-bit 1
+    let mut fields = vec![(2, 0x2), (6, 13)];
+    for &e in [[0, 63, 63, 63], [0, 63, 0, 0], [0, 63, 0, 0]].iter() {
+        fields.extend(e.iter().map(|&v| (6, v)));
+    }
+    fields.extend_from_slice(&[(1, 0), (1, 1), (2, 0)]);
+    for i in 1..15 {
+        fields.push((3, if i < 8 { i } else { 0 }));
+    }
+    fields.push((2, 0));
+
+    let pixels = decode(CompressedFormat::Bc7, &bc7_block(&fields));
+    let ramp = [0, 36, 71, 107, 146, 182, 217, 253];
+    for i in 0..8 {
+        assert_eq!(pixels[i], [ramp[i], ramp[i], ramp[i], 255]);
+    }
+    for i in 8..16 {
+        assert_eq!(pixels[i], [255, 2, 2, 255]);
+    }
+}
+
+#[test]
+fn test_bc7_mode5_rotation() {
+    // rotation 1 swaps red and alpha, the alpha index of pixel i is i % 4 and
+    // the color indices are 3, except for the anchor pixel 0 which has no high bit
+    let mut fields = vec![(6, 0x20), (2, 1)];
+    for _ in 0..3 {
+        fields.extend_from_slice(&[(7, 0), (7, 127)]);
+    }
+    fields.extend_from_slice(&[(8, 0), (8, 255)]);
+    fields.push((1, 1));
+    for _ in 1..16 {
+        fields.push((2, 3));
+    }
+    fields.push((1, 0));
+    for i in 1..16 {
+        fields.push((2, i % 4));
+    }
+
+    let pixels = decode(CompressedFormat::Bc7, &bc7_block(&fields));
+    assert_eq!(
+        row(&pixels, 0),
+        vec![[0, 84, 84, 84], [84, 255, 255, 255], [171, 255, 255, 255], [255, 255, 255, 255]]
+    );
+}
+
+/// Differential block with flip, bases (16, 8, 0) and (15, 11, 0) and tables 1 and 2
+fn etc2_differential(opaque: u64, indices: &[(u32, u32, u64)]) -> Vec<u8> {
+    let mut fields = vec![
+        (59, 16),
+        (56, 7),
+        (51, 8),
+        (48, 3),
+        (37, 1),
+        (34, 2),
+        (33, opaque),
+        (32, 1),
+    ];
+    for &(x, y, index) in indices {
+        fields.extend(etc_index(x, y, index));
+    }
+    etc_block(&fields)
+}
+
+/// T mode block, column x has index x
+fn etc2_t_mode(opaque: u64) -> Vec<u8> {
+    // r = 0 and dr = -3 overflows, r1 = 1 is split in bits 60-59 and 57-56
+    let mut fields = vec![
+        (56, 0b101),
+        (52, 2),
+        (48, 3),
+        (44, 8),
+        (40, 8),
+        (36, 8),
+        (34, 1),
+        (33, opaque),
+        (32, 1),
+    ];
+    for x in 0..4 {
+        for y in 0..4 {
+            fields.extend(etc_index(x, y, x as u64));
+        }
+    }
+    etc_block(&fields)
+}
+
+#[test]
+fn test_etc2_individual() {
+    // bases (8, 8, 8) and (4, 4, 4) as 4 bits, tables 0 and 7
+    let mut fields = vec![
+        (60, 8),
+        (56, 4),
+        (52, 8),
+        (48, 4),
+        (44, 8),
+        (40, 4),
+        (34, 7),
+    ];
+    fields.extend(etc_index(3, 0, 3));
+    fields.extend(etc_index(2, 1, 1));
+    fields.extend(etc_index(0, 3, 2));
+
+    let pixels = decode(CompressedFormat::Etc2Rgb, &etc_block(&fields));
+    assert_eq!(
+        row(&pixels, 0),
+        vec![[138, 138, 138, 255], [138, 138, 138, 255], [115, 115, 115, 255], [0, 0, 0, 255]]
+    );
+    assert_eq!(pixels[1 * 4 + 2], [251, 251, 251, 255]);
+    assert_eq!(pixels[3 * 4 + 0], [134, 134, 134, 255]);
+}
+
+#[test]
+fn test_etc2_differential() {
+    let pixels = decode(
+        CompressedFormat::Etc2Rgb,
+        &etc2_differential(1, &[(1, 3, 3)]),
+    );
+
+    // flipped, the sub blocks are the two top and the two bottom rows
+    assert_eq!(row(&pixels, 0), vec![[137, 71, 5, 255]; 4]);
+    assert_eq!(row(&pixels, 1), vec![[137, 71, 5, 255]; 4]);
+    assert_eq!(
+        row(&pixels, 3),
+        vec![[132, 99, 9, 255], [94, 61, 0, 255], [132, 99, 9, 255], [132, 99, 9, 255]]
+    );
+}
+
+#[test]
+fn test_etc2_t_mode() {
+    let pixels = decode(CompressedFormat::Etc2Rgb, &etc2_t_mode(1));
+    let paint = [
+        [17, 34, 51, 255],
+        [152, 152, 152, 255],
+        [136, 136, 136, 255],
+        [120, 120, 120, 255],
+    ];
+
+    for y in 0..4 {
+        assert_eq!(row(&pixels, y), paint.to_vec());
+    }
+}
+
+#[test]
+fn test_etc2_h_mode() {
+    // g = 0 and dg = -3 overflows, colors (4, 6, 2) and (10, 10, 10) with distance 23
+    let mut fields = vec![
+        (59, 4),
+        (56, 3),
+        (50, 1),
+        (47, 0b010),
+        (43, 10),
+        (39, 10),
+        (35, 10),
+        (34, 1),
+        (33, 1),
+    ];
+    for x in 0..4 {
+        for y in 0..4 {
+            fields.extend(etc_index(x, y, x as u64));
+        }
+    }
+
+    let pixels = decode(CompressedFormat::Etc2Rgb, &etc_block(&fields));
+    assert_eq!(
+        row(&pixels, 2),
+        vec![[91, 125, 57, 255], [45, 79, 11, 255], [193, 193, 193, 255], [147, 147, 147, 255]]
+    );
+}
+
+#[test]
+fn test_etc2_planar() {
+    // b = 0 and db = -4 overflows, origin (32, 64, 32), horizontal black and vertical white
+    let fields = [
+        (57, 32),
+        (56, 1),
+        (48, 1),
+        (42, 1),
+        (33, 1),
+        (13, 63),
+        (6, 127),
+        (0, 63),
+    ];
+
+    let pixels = decode(CompressedFormat::Etc2Rgb, &etc_block(&fields));
+    assert_eq!(pixels[0], [130, 129, 130, 255]);
+    assert_eq!(pixels[3], [33, 32, 33, 255]);
+    assert_eq!(pixels[12], [224, 224, 224, 255]);
+    assert_eq!(pixels[15], [126, 127, 126, 255]);
+    assert_eq!(pixels[2 * 4 + 1], [160, 160, 160, 255]);
+}
+
+#[test]
+fn test_etc2_punchthrough() {
+    // opaque blocks decode as ETC2 RGB
+    let block = etc2_differential(1, &[(1, 3, 3)]);
+    assert_eq!(
+        decode(CompressedFormat::Etc2RgbA1, &block),
+        decode(CompressedFormat::Etc2Rgb, &block)
+    );
+
+    // index 0 has no modifier and index 2 is transparent in non opaque blocks
+    let pixels = decode(
+        CompressedFormat::Etc2RgbA1,
+        &etc2_differential(0, &[(1, 3, 3), (2, 3, 2), (3, 3, 1)]),
+    );
+    assert_eq!(row(&pixels, 0), vec![[132, 66, 0, 255]; 4]);
+    assert_eq!(
+        row(&pixels, 3),
+        vec![[123, 90, 0, 255], [94, 61, 0, 255], [0, 0, 0, 0], [152, 119, 29, 255]]
+    );
+
+    let pixels = decode(CompressedFormat::Etc2RgbA1, &etc2_t_mode(0));
+    assert_eq!(
+        row(&pixels, 1),
+        vec![[17, 34, 51, 255], [152, 152, 152, 255], [0, 0, 0, 0], [120, 120, 120, 255]]
+    );
+}
+
+#[test]
+fn test_eac_alpha() {
+    // base 128, multiplier 2 and table 0, pixel (x, y) has index (x * 4 + y) % 8
+    let mut fields = vec![(56, 128), (52, 2)];
+    for i in 0..16 {
+        fields.push((45 - i * 3, (i % 8) as u64));
+    }
+
+    let mut block = etc_block(&fields);
+    block.extend(etc2_differential(1, &[]));
+
+    let pixels = decode(CompressedFormat::Etc2Rgba, &block);
+    let alpha = [122, 116, 110, 98, 132, 138, 144, 156];
+    for y in 0..4 {
+        for x in 0..4 {
+            assert_eq!(pixels[y * 4 + x][3], alpha[(x * 4 + y) % 8]);
+        }
+    }
+    assert_eq!(&pixels[0][..3], &[137, 71, 5]);
+}
+
+#[test]
+fn test_decompress_unsupported() {
+    assert_eq!(CompressedFormat::Astc(4, 4).decompress(4, 4, &[0; 16]), None);
+    assert_eq!(CompressedFormat::Dxt1.decompress(8, 4, &[0; 8]), None);
+    assert!(CompressedFormat::Dxt1.decompress(5, 3, &[0; 16]).is_some());
+}
+
+fn u32_le(v: u32) -> Vec<u8> {
+    (0..4).map(|i| (v >> (i * 8)) as u8).collect()
+}
+
+/// KTX 1 file of a single level of 4x4 DXT1
+fn ktx_dxt1(level: &[u8]) -> Vec<u8> {
+    let mut buff = b"\xABKTX 11\xBB\r\n\x1A\n".to_vec();
+    // endianness, gl type, type size, gl format, internal format, base internal format,
+    // width, height, depth, array elements, faces, levels and key values length
+    let header = [0x04030201, 0, 1, 0, 0x83F0, 0x1907, 4, 4, 0, 0, 1, 1, 0];
+    for &v in header.iter() {
+        buff.extend(u32_le(v));
+    }
+
+    buff.extend(u32_le(level.len() as u32));
+    buff.extend_from_slice(level);
+    buff
+}
+
+#[test]
+fn test_ktx_header() {
+    let block = [0x00, 0xf8, 0x1f, 0x00, 0xe4, 0xe4, 0xe4, 0xe4];
+
+    let img = KTXReader::read(ktx_dxt1(&block), &"test.ktx".to_string()).unwrap();
+    assert_eq!(img.format, CompressedFormat::Dxt1);
+    assert_eq!(img.images.len(), 1);
+    assert_eq!((img.images[0].width, img.images[0].height), (4, 4));
+    assert_eq!(img.images[0].data, block.to_vec());
+
+    match KTXReader::read(ktx_dxt1(&block[..4]), &"short.ktx".to_string()) {
+        Err(AssetError::InvalidFormat { .. }) => (),
+        r => panic!("Unexpected result {:?}", r.map(|img| img.format)),
+    }
+}
+
+#[test]
+fn test_ktx2_header() {
+    let block = [0x00, 0xf8, 0x1f, 0x00, 0xe4, 0xe4, 0xe4, 0xe4];
+
+    let mut buff = b"\xABKTX 20\xBB\r\n\x1A\n".to_vec();
+    // vk format (BC1_RGB_UNORM), type size, width, height, depth, layers, faces, levels
+    // and supercompression, followed by the dfd, kvd and sgd offsets and lengths
+    let header = [131, 1, 4, 4, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    for &v in header.iter() {
+        buff.extend(u32_le(v));
+    }
+
+    // level offset, length and uncompressed length
+    let offset = buff.len() as u32 + 24;
+    for &v in [offset, 0, 8, 0, 8, 0].iter() {
+        buff.extend(u32_le(v));
+    }
+    buff.extend_from_slice(&block);
+
+    let img = KTXReader::read(buff, &"test.ktx2".to_string()).unwrap();
+    assert_eq!(img.format, CompressedFormat::Dxt1);
+    assert_eq!(img.images[0].data, block.to_vec());
+
+    let pixels = decode(img.format, &img.images[0].data);
+    assert_eq!(pixels[1], [0, 0, 255, 255]);
+}